@@ -0,0 +1,3 @@
+-- Remove concept designations
+
+ALTER TABLE concepts DROP COLUMN IF EXISTS designations;
@@ -0,0 +1,3 @@
+-- Store concept designations (including language-specific definitions)
+
+ALTER TABLE concepts ADD COLUMN designations JSONB;
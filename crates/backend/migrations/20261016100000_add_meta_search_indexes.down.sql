@@ -0,0 +1,5 @@
+-- Remove meta search indexes
+
+DROP INDEX IF EXISTS idx_code_systems_meta;
+DROP INDEX IF EXISTS idx_value_sets_meta;
+DROP INDEX IF EXISTS idx_concept_maps_meta;
@@ -0,0 +1,5 @@
+-- Index resource meta for _tag, _profile and _security searches
+
+CREATE INDEX idx_code_systems_meta ON code_systems USING gin((content -> 'meta') jsonb_path_ops);
+CREATE INDEX idx_value_sets_meta ON value_sets USING gin((content -> 'meta') jsonb_path_ops);
+CREATE INDEX idx_concept_maps_meta ON concept_maps USING gin((content -> 'meta') jsonb_path_ops);
@@ -0,0 +1,5 @@
+-- Remove url/version unique indexes
+
+DROP INDEX IF EXISTS idx_code_systems_url_version_key;
+DROP INDEX IF EXISTS idx_value_sets_url_version_key;
+DROP INDEX IF EXISTS idx_concept_maps_url_version_key;
@@ -0,0 +1,36 @@
+-- Enforce url/version uniqueness including unversioned resources (UNIQUE(url, version)
+-- treats NULL versions as distinct), so imports can rely on ON CONFLICT DO NOTHING
+
+-- Earlier racing imports may have left duplicate unversioned rows. They are not deleted
+-- here: the migration fails and lists them, so they can be reviewed and removed with the
+-- CLI `dedupe` command (which keeps the oldest row of each url) before migrating again.
+DO $$
+DECLARE
+    duplicates TEXT;
+BEGIN
+    SELECT string_agg(format('%s %s (%s rows)', resource_type, url, copies), E'\n  ')
+    INTO duplicates
+    FROM (
+        SELECT 'CodeSystem' AS resource_type, url, COUNT(*) AS copies
+        FROM code_systems WHERE version IS NULL GROUP BY url HAVING COUNT(*) > 1
+        UNION ALL
+        SELECT 'ValueSet', url, COUNT(*)
+        FROM value_sets WHERE version IS NULL GROUP BY url HAVING COUNT(*) > 1
+        UNION ALL
+        SELECT 'ConceptMap', url, COUNT(*)
+        FROM concept_maps WHERE version IS NULL GROUP BY url HAVING COUNT(*) > 1
+        ORDER BY 1, 2
+    ) AS duplicated;
+
+    IF duplicates IS NOT NULL THEN
+        RAISE EXCEPTION E'Duplicate unversioned resources:\n  %', duplicates
+            USING HINT = 'Run `term-squid-cli dedupe --dry-run` to review them and '
+                         '`term-squid-cli dedupe` to keep only the oldest row of each url, '
+                         'then migrate again.';
+    END IF;
+END
+$$;
+
+CREATE UNIQUE INDEX idx_code_systems_url_version_key ON code_systems(url, COALESCE(version, ''));
+CREATE UNIQUE INDEX idx_value_sets_url_version_key ON value_sets(url, COALESCE(version, ''));
+CREATE UNIQUE INDEX idx_concept_maps_url_version_key ON concept_maps(url, COALESCE(version, ''));
@@ -0,0 +1,3 @@
+-- Drop import history
+
+DROP TABLE IF EXISTS import_history;
@@ -0,0 +1,16 @@
+-- Record each package import run for auditing
+
+CREATE TABLE import_history (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    package_name VARCHAR(255) NOT NULL,
+    package_version VARCHAR(100) NOT NULL,
+    registry VARCHAR(512),
+    resources INTEGER NOT NULL,
+    imported INTEGER NOT NULL,
+    skipped INTEGER NOT NULL,
+    errors INTEGER NOT NULL,
+    dry_run BOOLEAN NOT NULL,
+    imported_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_import_history_imported_at ON import_history(imported_at DESC);
@@ -0,0 +1,3 @@
+-- Drop the CodeSystem change notification trigger
+DROP TRIGGER IF EXISTS code_systems_changed ON code_systems;
+DROP FUNCTION IF EXISTS notify_code_systems_changed();
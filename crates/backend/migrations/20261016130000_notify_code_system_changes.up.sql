@@ -0,0 +1,11 @@
+-- Notify listeners (the server's capability cache) whenever CodeSystems change
+CREATE OR REPLACE FUNCTION notify_code_systems_changed() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_notify('code_systems_changed', TG_OP);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER code_systems_changed
+    AFTER INSERT OR UPDATE OR DELETE OR TRUNCATE ON code_systems
+    FOR EACH STATEMENT EXECUTE FUNCTION notify_code_systems_changed();
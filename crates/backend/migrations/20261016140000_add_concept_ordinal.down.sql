@@ -0,0 +1,4 @@
+-- Remove concept ordinals
+
+DROP INDEX IF EXISTS idx_concepts_ordinal;
+ALTER TABLE concepts DROP COLUMN IF EXISTS ordinal;
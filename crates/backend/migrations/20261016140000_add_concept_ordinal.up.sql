@@ -0,0 +1,16 @@
+-- Preserve the position of each concept in its CodeSystem, so ordered systems expand in order
+
+ALTER TABLE concepts ADD COLUMN ordinal INTEGER;
+
+-- Concepts imported before this migration keep the previous code order
+UPDATE concepts c
+SET ordinal = numbered.ordinal
+FROM (
+    SELECT id, (ROW_NUMBER() OVER (PARTITION BY code_system_id ORDER BY code) - 1)::INTEGER AS ordinal
+    FROM concepts
+) numbered
+WHERE c.id = numbered.id;
+
+ALTER TABLE concepts ALTER COLUMN ordinal SET NOT NULL;
+
+CREATE INDEX idx_concepts_ordinal ON concepts(code_system_id, ordinal, code);
@@ -0,0 +1,3 @@
+-- Drop CodeSystem supplement links
+
+DROP TABLE IF EXISTS code_system_supplements;
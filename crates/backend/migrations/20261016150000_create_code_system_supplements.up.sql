@@ -0,0 +1,17 @@
+-- Link CodeSystem supplements to the base CodeSystem named by their `supplements` canonical
+
+CREATE TABLE code_system_supplements (
+    supplement_id UUID PRIMARY KEY REFERENCES code_systems(id) ON DELETE CASCADE,
+    base_url TEXT NOT NULL,
+    -- NULL when the canonical names no version, i.e. the supplement applies to every version
+    base_version TEXT
+);
+
+CREATE INDEX idx_code_system_supplements_base ON code_system_supplements(base_url, base_version);
+
+-- Link supplements imported before this migration
+INSERT INTO code_system_supplements (supplement_id, base_url, base_version)
+SELECT id, split_part(content->>'supplements', '|', 1),
+       NULLIF(split_part(content->>'supplements', '|', 2), '')
+FROM code_systems
+WHERE content->>'supplements' IS NOT NULL;
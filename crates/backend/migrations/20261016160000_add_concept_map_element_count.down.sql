@@ -0,0 +1,4 @@
+-- Drop the ConceptMap element count
+
+ALTER TABLE concept_maps DROP COLUMN IF EXISTS element_count;
+DROP FUNCTION IF EXISTS concept_map_element_count(JSONB);
@@ -0,0 +1,13 @@
+-- Store the number of mapping elements (group.element entries) of each ConceptMap
+
+CREATE FUNCTION concept_map_element_count(content JSONB) RETURNS INTEGER AS $$
+    SELECT COALESCE(SUM(jsonb_array_length(g->'element')), 0)::INTEGER
+    FROM jsonb_array_elements(
+        CASE WHEN jsonb_typeof(content->'group') = 'array' THEN content->'group' ELSE '[]' END
+    ) g
+    WHERE jsonb_typeof(g->'element') = 'array'
+$$ LANGUAGE SQL IMMUTABLE;
+
+ALTER TABLE concept_maps ADD COLUMN element_count INTEGER NOT NULL DEFAULT 0;
+
+UPDATE concept_maps SET element_count = concept_map_element_count(content);
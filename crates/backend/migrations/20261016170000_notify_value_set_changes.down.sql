@@ -0,0 +1,4 @@
+-- Drop the ValueSet change notification triggers
+DROP TRIGGER IF EXISTS value_set_expansions_changed ON value_set_expansions;
+DROP TRIGGER IF EXISTS value_sets_changed ON value_sets;
+DROP FUNCTION IF EXISTS notify_value_sets_changed();
@@ -0,0 +1,16 @@
+-- Notify listeners (the server's expansion cache) whenever ValueSets or their stored
+-- expansions change
+CREATE OR REPLACE FUNCTION notify_value_sets_changed() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_notify('value_sets_changed', TG_OP);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER value_sets_changed
+    AFTER INSERT OR UPDATE OR DELETE OR TRUNCATE ON value_sets
+    FOR EACH STATEMENT EXECUTE FUNCTION notify_value_sets_changed();
+
+CREATE TRIGGER value_set_expansions_changed
+    AFTER INSERT OR UPDATE OR DELETE OR TRUNCATE ON value_set_expansions
+    FOR EACH STATEMENT EXECUTE FUNCTION notify_value_sets_changed();
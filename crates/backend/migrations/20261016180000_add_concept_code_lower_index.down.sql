@@ -0,0 +1,3 @@
+-- Remove the lower-cased concept code index
+
+DROP INDEX IF EXISTS idx_concepts_code_lower;
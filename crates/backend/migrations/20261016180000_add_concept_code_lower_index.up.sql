@@ -0,0 +1,3 @@
+-- Index lower-cased codes for concept lookups in case-insensitive CodeSystems
+
+CREATE INDEX idx_concepts_code_lower ON concepts (code_system_id, lower(code));
@@ -0,0 +1,58 @@
+use axum::{
+    extract::{Request, State},
+    http::header,
+    middleware::{self, Next},
+    response::Response,
+    routing::post,
+    Router,
+};
+use std::sync::Arc;
+
+use super::operations::{
+    precompute_expansion, reindex_code_system, reindex_code_systems, reindex_concept_map,
+    reindex_concept_maps, reindex_value_set, reindex_value_sets,
+};
+use crate::error::AppError;
+use crate::store::TerminologyStore;
+
+/// Admin endpoints, gated behind the configured bearer token
+pub fn admin_routes(admin_token: Option<String>) -> Router<Arc<dyn TerminologyStore>> {
+    Router::new()
+        .route(
+            "/ValueSet/{id}/$precompute-expansion",
+            post(precompute_expansion),
+        )
+        .route("/CodeSystem/$reindex", post(reindex_code_systems))
+        .route("/CodeSystem/{id}/$reindex", post(reindex_code_system))
+        .route("/ValueSet/$reindex", post(reindex_value_sets))
+        .route("/ValueSet/{id}/$reindex", post(reindex_value_set))
+        .route("/ConceptMap/$reindex", post(reindex_concept_maps))
+        .route("/ConceptMap/{id}/$reindex", post(reindex_concept_map))
+        .route_layer(middleware::from_fn_with_state(
+            admin_token.map(Arc::<str>::from),
+            require_admin_token,
+        ))
+}
+
+async fn require_admin_token(
+    State(admin_token): State<Option<Arc<str>>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let admin_token = admin_token
+        .ok_or_else(|| AppError::Forbidden("Admin endpoints are disabled".to_string()))?;
+
+    let provided = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+
+    if provided != Some(&*admin_token) {
+        return Err(AppError::Unauthorized(
+            "Missing or invalid admin token".to_string(),
+        ));
+    }
+
+    Ok(next.run(request).await)
+}
@@ -0,0 +1,196 @@
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    extract::Request,
+    http::{header, HeaderMap, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use http_body::{Frame, SizeHint};
+use std::collections::VecDeque;
+use std::future::poll_fn;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Bytes of each body written to the log; longer bodies are cut off
+const MAX_LOGGED_BODY_BYTES: usize = 8 * 1024;
+
+/// Logs request and response bodies at DEBUG, for diagnosing clients (`LOG_BODIES=true`).
+///
+/// Only bodies of a known length are logged, and only their first
+/// [`MAX_LOGGED_BODY_BYTES`] are read ahead of the handler or client; streamed bodies, such
+/// as `$everything` exports, pass through untouched. The `Authorization` header is redacted.
+pub async fn log_bodies(request: Request, next: Next) -> Response {
+    let (parts, body) = request.into_parts();
+    let (body, logged) = buffer(body).await;
+    tracing::debug!(
+        method = %parts.method,
+        uri = %parts.uri,
+        headers = ?redacted(&parts.headers),
+        body = %logged,
+        "request body"
+    );
+    let method = parts.method.clone();
+    let uri = parts.uri.clone();
+
+    let response = next.run(Request::from_parts(parts, body)).await;
+
+    let (parts, body) = response.into_parts();
+    let (body, logged) = buffer(body).await;
+    tracing::debug!(
+        method = %method,
+        uri = %uri,
+        status = %parts.status,
+        body = %logged,
+        "response body"
+    );
+
+    Response::from_parts(parts, body)
+}
+
+/// Read the first [`MAX_LOGGED_BODY_BYTES`] of a body of known length, returning an
+/// equivalent body and its log text. The rest of the body is passed on unread.
+async fn buffer(mut body: Body) -> (Body, String) {
+    let Some(size) = body.size_hint().exact() else {
+        return (body, "<streamed, not logged>".to_string());
+    };
+
+    let mut read = VecDeque::new();
+    let mut read_bytes = 0;
+    while read_bytes < MAX_LOGGED_BODY_BYTES {
+        match poll_fn(|cx| Pin::new(&mut body).poll_frame(cx)).await {
+            Some(Ok(frame)) => {
+                read_bytes += frame.data_ref().map_or(0, |data| data.len());
+                read.push_back(frame);
+            }
+            Some(Err(e)) => return (Body::empty(), format!("<unreadable: {e}>")),
+            None => break,
+        }
+    }
+
+    let shown: Vec<u8> = read
+        .iter()
+        .filter_map(|frame| frame.data_ref())
+        .flatten()
+        .take(MAX_LOGGED_BODY_BYTES)
+        .copied()
+        .collect();
+    let mut logged = String::from_utf8_lossy(&shown).into_owned();
+    if size > MAX_LOGGED_BODY_BYTES as u64 {
+        logged.push_str(&format!("... ({size} bytes total)"));
+    }
+
+    (Body::new(Replayed { read, rest: body }), logged)
+}
+
+/// A body whose first frames were already read for logging: they are sent again ahead of
+/// the unread rest, keeping the body's size hint
+struct Replayed {
+    read: VecDeque<Frame<Bytes>>,
+    rest: Body,
+}
+
+impl HttpBody for Replayed {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Self::Error>>> {
+        match self.read.pop_front() {
+            Some(frame) => Poll::Ready(Some(Ok(frame))),
+            None => Pin::new(&mut self.rest).poll_frame(cx),
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.read.is_empty() && self.rest.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        let read = self
+            .read
+            .iter()
+            .filter_map(|frame| frame.data_ref())
+            .map(|data| data.len() as u64)
+            .sum::<u64>();
+        let rest = self.rest.size_hint();
+        let mut hint = SizeHint::new();
+        hint.set_lower(rest.lower() + read);
+        if let Some(upper) = rest.upper() {
+            hint.set_upper(upper + read);
+        }
+        hint
+    }
+}
+
+fn redacted(headers: &HeaderMap) -> HeaderMap {
+    let mut headers = headers.clone();
+    if headers.contains_key(header::AUTHORIZATION) {
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_static("[redacted]"),
+        );
+    }
+    headers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::stream;
+    use http_body_util::BodyExt;
+
+    fn content(size: usize) -> Vec<u8> {
+        (0..size).map(|i| b'a' + (i % 26) as u8).collect()
+    }
+
+    /// `content` of known length arriving in 1 KiB frames
+    fn framed(content: &[u8]) -> Body {
+        let read = content
+            .chunks(1024)
+            .map(|chunk| Frame::data(Bytes::copy_from_slice(chunk)))
+            .collect();
+        Body::new(Replayed {
+            read,
+            rest: Body::empty(),
+        })
+    }
+
+    #[tokio::test]
+    async fn small_bodies_are_logged_whole() {
+        let (body, logged) = buffer(Body::from("{\"resourceType\":\"Parameters\"}")).await;
+        assert_eq!(logged, "{\"resourceType\":\"Parameters\"}");
+        assert_eq!(body.size_hint().exact(), Some(29));
+        let bytes = body.collect().await.unwrap().to_bytes();
+        assert_eq!(bytes, "{\"resourceType\":\"Parameters\"}");
+    }
+
+    #[tokio::test]
+    async fn large_bodies_are_cut_off_and_passed_on_whole() {
+        let size = 20 * 1024 + 5;
+        let expected = content(size);
+        let (body, logged) = buffer(framed(&expected)).await;
+        assert_eq!(
+            logged,
+            format!(
+                "{}... ({size} bytes total)",
+                String::from_utf8_lossy(&expected[..MAX_LOGGED_BODY_BYTES])
+            )
+        );
+        assert_eq!(body.size_hint().exact(), Some(size as u64));
+        assert_eq!(body.collect().await.unwrap().to_bytes(), expected);
+    }
+
+    #[tokio::test]
+    async fn streamed_bodies_are_not_read() {
+        let frames = content(20 * 1024)
+            .chunks(1024)
+            .map(|chunk| Ok::<_, axum::Error>(Frame::data(Bytes::copy_from_slice(chunk))))
+            .collect::<Vec<_>>();
+        let streamed = Body::new(http_body_util::StreamBody::new(stream::iter(frames)));
+        let (body, logged) = buffer(streamed).await;
+        assert_eq!(logged, "<streamed, not logged>");
+        assert_eq!(body.collect().await.unwrap().to_bytes().len(), 20 * 1024);
+    }
+}
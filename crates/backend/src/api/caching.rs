@@ -0,0 +1,105 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::terminology::{Cacheable, ResolvedVersion};
+
+/// Hands the resolved version to [`cache_headers`] as a response extension
+impl<T: Serialize> IntoResponse for Cacheable<T> {
+    fn into_response(self) -> Response {
+        match self.resolved {
+            Some(resolved) => (Extension(resolved), Json(self.body)).into_response(),
+            None => Json(self.body).into_response(),
+        }
+    }
+}
+
+/// Adds `Cache-Control` and `ETag` headers to successful GET operation responses.
+/// Results whose resource and dependencies are all pinned versions are publicly cacheable
+/// for `max_age` seconds; results computed from any latest version must be revalidated.
+/// The ETag covers every resource version the result was computed from.
+pub async fn cache_headers(State(max_age): State<u64>, request: Request, next: Next) -> Response {
+    if request.method() != Method::GET {
+        return next.run(request).await;
+    }
+
+    let uri = request.uri().clone();
+    let accept_language = request.headers().get(header::ACCEPT_LANGUAGE).cloned();
+    let if_none_match = request.headers().get(header::IF_NONE_MATCH).cloned();
+
+    let mut response = next.run(request).await;
+    if !response.status().is_success() {
+        return response;
+    }
+
+    let Some(resolved) = response.extensions().get::<ResolvedVersion>().cloned() else {
+        return response;
+    };
+
+    let etag = HeaderValue::from_str(&entity_tag(
+        &resolved,
+        uri.path_and_query().map_or(uri.path(), |p| p.as_str()),
+        accept_language.as_ref().map(|l| l.as_bytes()),
+    ))
+    .expect("hex ETag is a valid header value");
+
+    let cache_control = if resolved.fully_pinned() {
+        HeaderValue::from_str(&format!("public, max-age={max_age}"))
+            .expect("numeric max-age is a valid header value")
+    } else {
+        HeaderValue::from_static("no-cache")
+    };
+
+    if if_none_match
+        .as_ref()
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| matches_entity_tag(value, &etag))
+    {
+        response = StatusCode::NOT_MODIFIED.into_response();
+    }
+
+    let headers = response.headers_mut();
+    headers.insert(header::CACHE_CONTROL, cache_control);
+    headers.insert(header::ETAG, etag);
+    // Displays may be localized from the Accept-Language header
+    headers.insert(header::VARY, HeaderValue::from_static("Accept-Language"));
+
+    response
+}
+
+/// A strong ETag hashing, with a hash that is stable across builds, every resource version
+/// the result was computed from, the request path and query, and the `Accept-Language`
+fn entity_tag(resolved: &ResolvedVersion, uri: &str, accept_language: Option<&[u8]>) -> String {
+    let mut hasher = Sha256::new();
+    // Each field is length-prefixed so that adjacent fields cannot run into each other
+    let mut field = |bytes: &[u8]| {
+        hasher.update((bytes.len() as u64).to_be_bytes());
+        hasher.update(bytes);
+    };
+    for resolved in std::iter::once(resolved).chain(&resolved.dependencies) {
+        field(resolved.url.as_bytes());
+        field(resolved.version.as_deref().unwrap_or_default().as_bytes());
+        field(resolved.updated_at.to_rfc3339().as_bytes());
+    }
+    field(uri.as_bytes());
+    field(accept_language.unwrap_or_default());
+
+    let digest = hasher.finalize();
+    let hex: String = digest[..16].iter().map(|b| format!("{b:02x}")).collect();
+    format!("\"{hex}\"")
+}
+
+/// Whether an `If-None-Match` header (`*` or a comma-separated list of possibly weak
+/// `W/"..."` tags) matches `etag`, comparing weakly as RFC 9110 requires for it
+fn matches_entity_tag(if_none_match: &str, etag: &HeaderValue) -> bool {
+    let etag = etag.to_str().unwrap_or_default();
+    if_none_match.split(',').map(str::trim).any(|candidate| {
+        candidate == "*" || candidate.strip_prefix("W/").unwrap_or(candidate) == etag
+    })
+}
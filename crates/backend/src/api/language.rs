@@ -0,0 +1,115 @@
+use axum::{
+    extract::FromRequestParts,
+    http::{header, request::Parts},
+};
+use std::convert::Infallible;
+use std::sync::Arc;
+
+/// Server-wide display language used when a request does not ask for one
+#[derive(Debug, Clone, Default)]
+pub struct DefaultDisplayLanguage(pub Option<Arc<str>>);
+
+/// The display language implied by the request itself: the preferred `Accept-Language`
+/// tag, falling back to the server default. An explicit `displayLanguage` parameter
+/// overrides both, see [`RequestLanguage::resolve`].
+#[derive(Debug, Clone)]
+pub struct RequestLanguage(pub Option<String>);
+
+impl RequestLanguage {
+    /// Apply the precedence `displayLanguage` parameter > `Accept-Language` > server default
+    pub fn resolve(self, display_language: Option<&str>) -> Option<String> {
+        display_language.map(str::to_string).or(self.0)
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for RequestLanguage {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let accepted = parts
+            .headers
+            .get(header::ACCEPT_LANGUAGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(preferred_language);
+
+        let language = accepted.or_else(|| {
+            parts
+                .extensions
+                .get::<DefaultDisplayLanguage>()
+                .and_then(|default| default.0.as_deref().map(str::to_string))
+        });
+
+        Ok(Self(language))
+    }
+}
+
+/// The highest-weighted concrete tag of an `Accept-Language` header
+fn preferred_language(header: &str) -> Option<String> {
+    header
+        .split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.split(';');
+            let tag = parts.next()?.trim();
+            let weight = parts
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .and_then(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            (!tag.is_empty() && tag != "*" && weight > 0.0).then_some((tag, weight))
+        })
+        // Keep the first of equally weighted tags, as listed by the client
+        .fold(None, |best: Option<(&str, f32)>, candidate| match best {
+            Some(best) if best.1 >= candidate.1 => Some(best),
+            _ => Some(candidate),
+        })
+        .map(|(tag, _)| tag.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Request;
+
+    /// The language a request resolves to, given its header, the server default and the
+    /// `displayLanguage` parameter
+    async fn resolve(
+        accept_language: Option<&str>,
+        default: Option<&str>,
+        display_language: Option<&str>,
+    ) -> Option<String> {
+        let mut request =
+            Request::builder().extension(DefaultDisplayLanguage(default.map(Into::into)));
+        if let Some(accept_language) = accept_language {
+            request = request.header(header::ACCEPT_LANGUAGE, accept_language);
+        }
+        let (mut parts, ()) = request.body(()).unwrap().into_parts();
+        let Ok(language) = RequestLanguage::from_request_parts(&mut parts, &()).await;
+        language.resolve(display_language)
+    }
+
+    #[tokio::test]
+    async fn display_language_parameter_overrides_header_and_default() {
+        let language = resolve(Some("de"), Some("fr"), Some("nl")).await;
+        assert_eq!(language.as_deref(), Some("nl"));
+        let language = resolve(None, Some("fr"), Some("nl")).await;
+        assert_eq!(language.as_deref(), Some("nl"));
+    }
+
+    #[tokio::test]
+    async fn accept_language_overrides_default() {
+        let language = resolve(Some("de;q=0.5, es"), Some("fr"), None).await;
+        assert_eq!(language.as_deref(), Some("es"));
+    }
+
+    #[tokio::test]
+    async fn default_applies_only_when_the_request_gives_no_language() {
+        assert_eq!(resolve(None, Some("fr"), None).await.as_deref(), Some("fr"));
+        // A header without a usable tag does not select a language either
+        assert_eq!(
+            resolve(Some("*, de;q=0"), Some("fr"), None)
+                .await
+                .as_deref(),
+            Some("fr")
+        );
+        assert_eq!(resolve(None, None, None).await, None);
+    }
+}
@@ -1,32 +1,83 @@
+mod admin;
+mod body_log;
+pub mod caching;
+pub mod language;
 mod operations;
 mod parameters;
+mod rate_limit;
 mod resources;
 
+use crate::config::Config;
+use crate::fhir_version::FhirVersion;
 use crate::store::TerminologyStore;
-use axum::{extract::State, routing::get, Json, Router};
+use crate::terminology::ExpansionCache;
+use admin::admin_routes;
+use axum::{
+    extract::State,
+    http::StatusCode,
+    middleware,
+    response::{IntoResponse, Response},
+    routing::get,
+    Extension, Json, Router,
+};
 use operations::*;
+use rate_limit::{rate_limiter, RateLimit};
 use resources::*;
 use serde_json::{json, Value};
 use std::sync::Arc;
 
-pub fn create_router(store: Arc<dyn TerminologyStore>) -> Router {
-    // Create version-specific routers
-    let version_router = create_version_router();
+pub use resources::CapabilityCache;
 
-    Router::new()
+pub fn create_router(
+    store: Arc<dyn TerminologyStore>,
+    config: &Config,
+    capabilities: CapabilityCache,
+    expansions: ExpansionCache,
+) -> Router {
+    // One rate limit budget per client, whichever version's base URL it calls
+    let rate_limit = rate_limiter(
+        RateLimit {
+            per_second: config.rate_limit_per_second,
+            burst: config.rate_limit_burst,
+        },
+        config.admin_token.as_deref(),
+    );
+
+    let router = Router::new()
         // System endpoints (non-versioned)
         .route("/health", get(health_check))
-        .route("/stats", get(get_stats))
-        // R4 versioned endpoints
-        .nest("/r4", version_router.clone())
-        // R5 versioned endpoints
-        .nest("/r5", version_router.clone())
-        // R6 versioned endpoints
-        .nest("/r6", version_router)
+        .route("/readyz", get(readiness_check))
+        .route("/stats", get(get_stats));
+
+    // Each version-specific base URL gets a router built for the release it serves
+    FhirVersion::ALL
+        .into_iter()
+        .fold(router, |router, version| {
+            let version_router = create_version_router(
+                version,
+                config,
+                capabilities.clone(),
+                expansions.clone(),
+                &rate_limit,
+            );
+            let version_router = if config.log_bodies {
+                version_router.layer(middleware::from_fn(body_log::log_bodies))
+            } else {
+                version_router
+            };
+            router.nest(version.path_prefix(), version_router)
+        })
         .with_state(store)
 }
 
-fn create_version_router() -> Router<Arc<dyn TerminologyStore>> {
+/// The FHIR endpoints for one release; handlers see `version` as an `Extension`
+fn create_version_router(
+    version: FhirVersion,
+    config: &Config,
+    capabilities: CapabilityCache,
+    expansions: ExpansionCache,
+    rate_limit: &impl Fn(Router<Arc<dyn TerminologyStore>>) -> Router<Arc<dyn TerminologyStore>>,
+) -> Router<Arc<dyn TerminologyStore>> {
     Router::new()
         // Capability endpoints
         .route("/metadata", get(capability_statement))
@@ -36,7 +87,40 @@ fn create_version_router() -> Router<Arc<dyn TerminologyStore>> {
         .merge(valueset_routes())
         .merge(conceptmap_routes())
         // Operation endpoints
-        .merge(operation_routes())
+        .merge(rate_limit(operation_routes(
+            config.cache_max_age,
+            config.default_display_language.as_deref(),
+            expansions,
+        )))
+        // Admin endpoints
+        .merge(admin_routes(config.admin_token.clone()))
+        .layer(Extension(SearchLimits {
+            max_count: config.max_search_count,
+        }))
+        .layer(Extension(capabilities))
+        .layer(Extension(version))
+}
+
+/// Channel notified by a trigger whenever rows of `code_systems` change, including
+/// writes made by the CLI
+pub const CODE_SYSTEMS_CHANGED_CHANNEL: &str = "code_systems_changed";
+
+/// Channel notified by triggers whenever rows of `value_sets` or `value_set_expansions`
+/// change
+pub const VALUE_SETS_CHANGED_CHANNEL: &str = "value_sets_changed";
+
+/// Drop what a change notification on `channel` makes stale: the cached expansions on
+/// every change, and the cached TerminologyCapabilities on CodeSystem changes. `None`
+/// stands for a listener error, after which notifications may have been lost, so both go.
+pub async fn invalidate_caches(
+    channel: Option<&str>,
+    capabilities: &CapabilityCache,
+    expansions: &ExpansionCache,
+) {
+    expansions.invalidate();
+    if channel != Some(VALUE_SETS_CHANGED_CHANNEL) {
+        capabilities.invalidate().await;
+    }
 }
 
 async fn health_check() -> Json<Value> {
@@ -47,14 +131,259 @@ async fn health_check() -> Json<Value> {
     }))
 }
 
+/// Ready when the database holds every required table and all migrations are applied
+async fn readiness_check(State(store): State<Arc<dyn TerminologyStore>>) -> Response {
+    match store.check_schema().await {
+        Ok(problems) if problems.is_empty() => Json(json!({ "status": "ready" })).into_response(),
+        Ok(problems) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({ "status": "degraded", "problems": problems })),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({ "status": "unavailable", "problems": [e.to_string()] })),
+        )
+            .into_response(),
+    }
+}
+
 async fn get_stats(State(store): State<Arc<dyn TerminologyStore>>) -> Json<Value> {
     let code_systems_count = store.count_code_systems().await.unwrap_or(0);
     let value_sets_count = store.count_value_sets().await.unwrap_or(0);
     let concept_maps_count = store.count_concept_maps().await.unwrap_or(0);
+    let recent_imports = store.recent_imports(10).await.unwrap_or_default();
+    let closure_coverage = store.closure_coverage().await.unwrap_or_default();
+    let systems_without_closure = closure_coverage
+        .iter()
+        .filter(|c| c.closure_rows == 0)
+        .count();
+    let closure_systems: Vec<Value> = closure_coverage
+        .iter()
+        .map(|c| {
+            json!({
+                "url": c.url,
+                "version": c.version,
+                "has_closure": c.closure_rows > 0,
+                "closure_rows": c.closure_rows
+            })
+        })
+        .collect();
 
     Json(json!({
         "code_systems": code_systems_count,
         "value_sets": value_sets_count,
-        "concept_maps": concept_maps_count
+        "concept_maps": concept_maps_count,
+        "recent_imports": recent_imports,
+        "closure": {
+            "systems_without_closure": systems_without_closure,
+            "code_systems": closure_systems
+        }
     }))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+    use axum::body::Body;
+    use axum::extract::ConnectInfo;
+    use axum::http::{header, Request};
+    use http_body_util::BodyExt;
+    use std::net::SocketAddr;
+    use tower::ServiceExt;
+
+    fn config() -> Config {
+        Config {
+            database_url: String::new(),
+            server_host: "127.0.0.1".into(),
+            server_port: 0,
+            admin_token: None,
+            cache_max_age: 3600,
+            default_display_language: None,
+            max_search_count: 1000,
+            rate_limit_per_second: 0,
+            rate_limit_burst: 0,
+            log_bodies: false,
+            closure_query_timeout_ms: 0,
+            expand_total_limit: 0,
+            strict_canonicals: false,
+            expansion_cache_concepts: 0,
+        }
+    }
+
+    fn router_with(config: Config) -> Router {
+        create_router(
+            Arc::new(MemoryStore::default()),
+            &config,
+            CapabilityCache::default(),
+            ExpansionCache::new(0),
+        )
+    }
+
+    fn router() -> Router {
+        router_with(config())
+    }
+
+    async fn get(router: &Router, uri: &str) -> (StatusCode, Value) {
+        let request = Request::get(uri).body(Body::empty()).unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    #[tokio::test]
+    async fn each_base_url_reports_its_own_fhir_version() {
+        let router = router();
+        // Fetched twice so the cached statement of one release is not served for another
+        for _ in 0..2 {
+            for (uri, fhir_version) in [
+                ("/r4/metadata", "4.0.1"),
+                ("/r5/metadata", "5.0.0"),
+                ("/r6/metadata", "6.0.0-ballot2"),
+            ] {
+                let (status, body) = get(&router, uri).await;
+                assert_eq!(status, StatusCode::OK);
+                assert_eq!(body["resourceType"], "CapabilityStatement");
+                assert_eq!(body["fhirVersion"], fhir_version, "{uri}");
+            }
+        }
+    }
+
+    async fn post(router: &Router, uri: &str, body: Value) -> (StatusCode, Value) {
+        let request = Request::post(uri)
+            .header("content-type", "application/fhir+json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    fn diagnostics(outcome: &Value) -> Vec<&str> {
+        outcome["issue"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|issue| issue["diagnostics"].as_str().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn every_missing_parameter_is_reported() {
+        let router = router();
+        let (status, outcome) = get(&router, "/r4/CodeSystem/$lookup").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            diagnostics(&outcome),
+            ["system parameter required", "code parameter required"]
+        );
+
+        let (status, outcome) = get(&router, "/r4/CodeSystem/$subsumes?codeA=a").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            diagnostics(&outcome),
+            ["system parameter required", "codeB parameter required"]
+        );
+
+        let parameters = json!({ "resourceType": "Parameters", "parameter": [] });
+        let (status, outcome) = post(&router, "/r4/CodeSystem/$lookup", parameters).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            diagnostics(&outcome),
+            ["system parameter required", "code parameter required"]
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_base_urls_are_not_found() {
+        let (status, _) = get(&router(), "/r3/metadata").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn operations_are_throttled_per_client_and_reads_are_not() {
+        let router = router_with(Config {
+            rate_limit_per_second: 1,
+            rate_limit_burst: 3,
+            ..config()
+        });
+        let from = |uri: &str, ip: [u8; 4]| {
+            let mut request = Request::get(uri).body(Body::empty()).unwrap();
+            request
+                .extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from((ip, 40000))));
+            router.clone().oneshot(request)
+        };
+        let lookup = "/r4/CodeSystem/$lookup?system=http://example.org&code=a";
+
+        for _ in 0..3 {
+            let response = from(lookup, [10, 0, 0, 1]).await.unwrap();
+            assert_ne!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        }
+        let throttled = from(lookup, [10, 0, 0, 1]).await.unwrap();
+        assert_eq!(throttled.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(throttled.headers().contains_key(header::RETRY_AFTER));
+        let body = throttled.into_body().collect().await.unwrap().to_bytes();
+        let outcome: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(outcome["resourceType"], "OperationOutcome");
+        assert_eq!(outcome["issue"][0]["code"], "throttled");
+
+        // Another client has its own bucket, and reads are not limited
+        let other = from(lookup, [10, 0, 0, 2]).await.unwrap();
+        assert_ne!(other.status(), StatusCode::TOO_MANY_REQUESTS);
+        for _ in 0..5 {
+            let read = from("/r4/CodeSystem/missing", [10, 0, 0, 1]).await.unwrap();
+            assert_ne!(read.status(), StatusCode::TOO_MANY_REQUESTS);
+        }
+    }
+
+    #[tokio::test]
+    async fn code_system_changes_invalidate_the_cached_system_list() {
+        let capabilities = CapabilityCache::default();
+        let expansions = ExpansionCache::new(0);
+        let installed = |urls: &[&str]| {
+            let store = urls.iter().fold(MemoryStore::default(), |store, url| {
+                store.with_code_system(json!({ "url": url }))
+            });
+            create_router(
+                Arc::new(store),
+                &config(),
+                capabilities.clone(),
+                expansions.clone(),
+            )
+        };
+        let systems = |router: Router| async move {
+            let (status, body) = get(&router, "/r4/TerminologyCapabilities").await;
+            assert_eq!(status, StatusCode::OK);
+            body["codeSystem"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|system| system["uri"].as_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(systems(installed(&["http://a"])).await, ["http://a"]);
+        // The store now holds another system, but the list is served from the cache
+        let changed = installed(&["http://a", "http://b"]);
+        assert_eq!(systems(changed.clone()).await, ["http://a"]);
+
+        invalidate_caches(Some(VALUE_SETS_CHANGED_CHANNEL), &capabilities, &expansions).await;
+        assert_eq!(systems(changed.clone()).await, ["http://a"]);
+
+        invalidate_caches(
+            Some(CODE_SYSTEMS_CHANGED_CHANNEL),
+            &capabilities,
+            &expansions,
+        )
+        .await;
+        assert_eq!(systems(changed).await, ["http://a", "http://b"]);
+
+        // A listener error may have lost notifications, so the list is rebuilt as well
+        invalidate_caches(None, &capabilities, &expansions).await;
+        assert_eq!(systems(installed(&["http://b"])).await, ["http://b"]);
+    }
+}
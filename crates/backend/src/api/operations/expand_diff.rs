@@ -0,0 +1,125 @@
+use axum::{extract::State, Json};
+use std::collections::HashSet;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::error::AppError;
+use crate::models::ValueSet;
+use crate::store::TerminologyStore;
+use crate::terminology::{compose_expansion, Coding, Parameter, ParameterIssues, Parameters};
+
+/// POST /ValueSet/$expand-diff with Parameters body
+///
+/// Each side is identified either by `urlA`/`urlB` (optionally with
+/// `valueSetVersionA`/`valueSetVersionB`) or by `idA`/`idB`.
+pub async fn expand_diff(
+    State(store): State<Arc<dyn TerminologyStore>>,
+    params: Parameters,
+) -> Result<Json<Parameters>, AppError> {
+    let mut issues = ParameterIssues::default();
+    let side_a = resolve_side(&store, &params, "A", &mut issues).await?;
+    let side_b = resolve_side(&store, &params, "B", &mut issues).await?;
+    let (Some(value_set_a), Some(value_set_b)) = (side_a, side_b) else {
+        return Err(issues.into());
+    };
+
+    let entries_a = compose_expansion(&store, &value_set_a).await?;
+    let entries_b = compose_expansion(&store, &value_set_b).await?;
+
+    let keys_a: HashSet<_> = entries_a.iter().filter_map(coding_key).collect();
+    let keys_b: HashSet<_> = entries_b.iter().filter_map(coding_key).collect();
+
+    let added: Vec<_> = entries_b
+        .iter()
+        .filter(|entry| coding_key(entry).is_some_and(|key| !keys_a.contains(&key)))
+        .filter_map(entry_coding)
+        .collect();
+    let removed: Vec<_> = entries_a
+        .iter()
+        .filter(|entry| coding_key(entry).is_some_and(|key| !keys_b.contains(&key)))
+        .filter_map(entry_coding)
+        .collect();
+
+    let mut result = vec![
+        Parameter::integer("addedCount", added.len() as i64),
+        Parameter::integer("removedCount", removed.len() as i64),
+    ];
+    for (name, codings) in [("added", added), ("removed", removed)] {
+        if !codings.is_empty() {
+            let parts = codings
+                .into_iter()
+                .map(|coding| Parameter::coding("coding", coding))
+                .collect();
+            result.push(Parameter::part(name, parts));
+        }
+    }
+
+    Ok(Json(Parameters::with_parameters(result)))
+}
+
+/// Look up one side of the comparison, recording missing or malformed inputs in `issues`
+async fn resolve_side(
+    store: &Arc<dyn TerminologyStore>,
+    params: &Parameters,
+    suffix: &str,
+    issues: &mut ParameterIssues,
+) -> Result<Option<ValueSet>, AppError> {
+    let url_name = format!("url{suffix}");
+    let id_name = format!("id{suffix}");
+
+    let url = params
+        .get_uri(&url_name)
+        .or_else(|| params.get_string(&url_name));
+    let id = params.get_string(&id_name);
+
+    match (url, id) {
+        (Some(url), None) => {
+            let version = params.get_string(&format!("valueSetVersion{suffix}"));
+            let value_set = store
+                .get_value_set(url, version)
+                .await?
+                .ok_or_else(|| AppError::NotFound(format!("ValueSet '{url}' not found")))?;
+            Ok(Some(value_set))
+        }
+        (None, Some(id)) => {
+            let Ok(id) = Uuid::parse_str(id) else {
+                issues.invalid(format!("{id_name} must be a ValueSet id, got '{id}'"));
+                return Ok(None);
+            };
+            let value_set = store
+                .get_value_set_by_id(&id)
+                .await?
+                .ok_or_else(|| AppError::NotFound(format!("ValueSet {id} not found")))?;
+            Ok(Some(value_set))
+        }
+        (Some(_), Some(_)) => {
+            issues.invalid(format!("only one of {url_name} and {id_name} may be given"));
+            Ok(None)
+        }
+        (None, None) => {
+            issues.invalid(format!("{url_name} or {id_name} parameter required"));
+            Ok(None)
+        }
+    }
+}
+
+/// The system+code identity of an expansion entry
+fn coding_key(entry: &serde_json::Value) -> Option<(String, String)> {
+    Some((
+        entry.get("system")?.as_str()?.to_string(),
+        entry.get("code")?.as_str()?.to_string(),
+    ))
+}
+
+fn entry_coding(entry: &serde_json::Value) -> Option<Coding> {
+    let (system, code) = coding_key(entry)?;
+    let mut coding = Coding::new(system, code);
+    coding.version = entry
+        .get("version")
+        .and_then(|v| v.as_str())
+        .map(str::to_string);
+    if let Some(display) = entry.get("display").and_then(|d| d.as_str()) {
+        coding = coding.with_display(display);
+    }
+    Some(coding)
+}
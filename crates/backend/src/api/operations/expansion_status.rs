@@ -0,0 +1,49 @@
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::error::AppError;
+use crate::store::TerminologyStore;
+use crate::terminology::{Parameter, Parameters};
+
+/// GET /ValueSet/{id}/$expansion-status: whether a stored expansion exists, when it was
+/// computed, and whether it is stale because the ValueSet or a CodeSystem it references
+/// was updated afterwards
+pub async fn expansion_status(
+    State(store): State<Arc<dyn TerminologyStore>>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Parameters>, AppError> {
+    let value_set = store
+        .get_value_set_by_id(&id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("ValueSet {id} not found")))?;
+
+    let Some(status) = store.get_expansion_status(&id).await? else {
+        return Ok(Json(Parameters::with_parameters(vec![Parameter::boolean(
+            "exists", false,
+        )])));
+    };
+
+    let value_set_changed = value_set.updated_at > status.computed_at;
+    let mut result = vec![
+        Parameter::boolean("exists", true),
+        Parameter::date_time("computed", status.computed_at.to_rfc3339()),
+        Parameter::boolean(
+            "stale",
+            value_set_changed || !status.newer_code_systems.is_empty(),
+        ),
+        Parameter::boolean("valueSetChanged", value_set_changed),
+    ];
+    for (url, version) in status.newer_code_systems {
+        let canonical = match version {
+            Some(version) => format!("{url}|{version}"),
+            None => url,
+        };
+        result.push(Parameter::uri("changedCodeSystem", canonical));
+    }
+
+    Ok(Json(Parameters::with_parameters(result)))
+}
@@ -0,0 +1,90 @@
+use axum::{extract::State, Json};
+use std::sync::Arc;
+
+use crate::error::AppError;
+use crate::store::TerminologyStore;
+use crate::terminology::{
+    self, Parameter, ParameterIssues, ParameterValue, Parameters, PropertyCondition,
+};
+
+/// POST /CodeSystem/$find-matches with Parameters body: `system`, optional `version`, and
+/// one or more `property` parameters with `code`, `value` and an optional `comparator`
+pub async fn find_matches_post(
+    State(store): State<Arc<dyn TerminologyStore>>,
+    params: Parameters,
+) -> Result<Json<Parameters>, AppError> {
+    let mut issues = ParameterIssues::default();
+    let system = issues.require(
+        "system",
+        params
+            .get_uri("system")
+            .or_else(|| params.get_string("system")),
+    );
+    let version = params.get_string("version");
+
+    let properties: Vec<&Parameter> = params
+        .parameter
+        .iter()
+        .flatten()
+        .filter(|p| p.name == "property")
+        .collect();
+    if properties.is_empty() {
+        issues.invalid("property parameter required");
+    }
+    let conditions: Vec<PropertyCondition> = properties
+        .into_iter()
+        .filter_map(|property| property_condition(&mut issues, property))
+        .collect();
+
+    let Some(system) = system else {
+        return Err(issues.into());
+    };
+    issues.check()?;
+
+    terminology::find_matches(store, system, version, &conditions)
+        .await
+        .map(Json)
+}
+
+/// The `code`, `value` and `comparator` parts of a `property` parameter
+fn property_condition<'a>(
+    issues: &mut ParameterIssues,
+    property: &'a Parameter,
+) -> Option<PropertyCondition<'a>> {
+    let part = |name: &str| {
+        property
+            .part
+            .iter()
+            .flatten()
+            .find(|p| p.name == name)
+            .and_then(|p| p.value.as_ref())
+    };
+    let text = |value: &'a ParameterValue| match value {
+        ParameterValue::ValueCode(s) | ParameterValue::ValueString(s) => Some(s.as_str()),
+        _ => None,
+    };
+
+    let code = issues.require("property.code", part("code").and_then(text))?;
+    let value = match part("value") {
+        Some(
+            ParameterValue::ValueCode(s)
+            | ParameterValue::ValueString(s)
+            | ParameterValue::ValueDateTime(s)
+            | ParameterValue::ValueUri(s),
+        ) => s.clone(),
+        Some(ParameterValue::ValueInteger(i)) => i.to_string(),
+        Some(ParameterValue::ValueDecimal(d)) => d.to_string(),
+        Some(ParameterValue::ValueBoolean(b)) => b.to_string(),
+        Some(ParameterValue::ValueCoding(coding)) => coding.code.clone().unwrap_or_default(),
+        _ => {
+            issues.invalid(format!("property '{code}' requires a value"));
+            return None;
+        }
+    };
+
+    Some(PropertyCondition {
+        code,
+        value,
+        comparator: part("comparator").and_then(text),
+    })
+}
@@ -1,22 +1,48 @@
 mod expand;
+mod expand_diff;
+mod expansion_status;
+mod find_matches;
 mod lookup;
+mod precompute;
+mod reindex;
 mod subsumes;
 mod translate;
 mod validate;
+mod versions;
 
 pub use expand::*;
+pub use expand_diff::*;
+pub use expansion_status::*;
+pub use find_matches::*;
 pub use lookup::*;
+pub use precompute::*;
+pub use reindex::*;
 pub use subsumes::*;
 pub use translate::*;
 pub use validate::*;
+pub use versions::*;
 
+use crate::api::caching::cache_headers;
+use crate::api::language::DefaultDisplayLanguage;
 use crate::store::TerminologyStore;
-use axum::{routing::get, Router};
+use crate::terminology::ExpansionCache;
+use axum::{
+    middleware,
+    routing::{get, post},
+    Extension, Router,
+};
 use std::sync::Arc;
 
-pub fn operation_routes() -> Router<Arc<dyn TerminologyStore>> {
-    Router::new()
-        // CodeSystem operations
+/// Operation routes. Read-only `$lookup`, `$expand` and `$validate-code` GET responses
+/// carry caching headers with the given `max-age` for pinned versions, and fall back to
+/// `default_display_language` when the request does not select a display language.
+/// `$expand` serves and fills `expansions`.
+pub fn operation_routes(
+    cache_max_age: u64,
+    default_display_language: Option<&str>,
+    expansions: ExpansionCache,
+) -> Router<Arc<dyn TerminologyStore>> {
+    let cacheable = Router::new()
         .route("/CodeSystem/$lookup", get(lookup_get).post(lookup_post))
         .route(
             "/CodeSystem/{id}/$lookup",
@@ -30,15 +56,6 @@ pub fn operation_routes() -> Router<Arc<dyn TerminologyStore>> {
             "/CodeSystem/{id}/$validate-code",
             get(validate_code_cs_instance_get).post(validate_code_cs_instance_post),
         )
-        .route(
-            "/CodeSystem/$subsumes",
-            get(subsumes_get).post(subsumes_post),
-        )
-        .route(
-            "/CodeSystem/{id}/$subsumes",
-            get(subsumes_instance_get).post(subsumes_instance_post),
-        )
-        // ValueSet operations
         .route("/ValueSet/$expand", get(expand_get).post(expand_post))
         .route(
             "/ValueSet/{id}/$expand",
@@ -52,6 +69,31 @@ pub fn operation_routes() -> Router<Arc<dyn TerminologyStore>> {
             "/ValueSet/{id}/$validate-code",
             get(validate_code_vs_instance_get).post(validate_code_vs_instance_post),
         )
+        .route_layer(middleware::from_fn_with_state(cache_max_age, cache_headers))
+        .layer(Extension(DefaultDisplayLanguage(
+            default_display_language.map(Into::into),
+        )))
+        .layer(Extension(expansions));
+
+    Router::new()
+        .merge(cacheable)
+        // CodeSystem operations
+        .route(
+            "/CodeSystem/$subsumes",
+            get(subsumes_get).post(subsumes_post),
+        )
+        .route(
+            "/CodeSystem/{id}/$subsumes",
+            get(subsumes_instance_get).post(subsumes_instance_post),
+        )
+        .route("/CodeSystem/$find-matches", post(find_matches_post))
+        .route(
+            "/CodeSystem/$versions",
+            get(versions_get).post(versions_post),
+        )
+        // ValueSet operations
+        .route("/ValueSet/$expand-diff", post(expand_diff))
+        .route("/ValueSet/{id}/$expansion-status", get(expansion_status))
         // ConceptMap operations
         .route(
             "/ConceptMap/$translate",
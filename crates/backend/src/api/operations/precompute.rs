@@ -0,0 +1,48 @@
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use serde_json::json;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::error::AppError;
+use crate::store::TerminologyStore;
+use crate::terminology::{compose_expansion, Parameter, Parameters};
+
+/// POST /ValueSet/{id}/$precompute-expansion
+pub async fn precompute_expansion(
+    State(store): State<Arc<dyn TerminologyStore>>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Parameters>, AppError> {
+    let value_set = store
+        .get_value_set_by_id(&id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("ValueSet {id} not found")))?;
+
+    let contains = compose_expansion(&store, &value_set).await?;
+    let total = contains.len();
+
+    let expansion = json!({
+        "identifier": format!("urn:uuid:{}", Uuid::new_v4()),
+        "timestamp": chrono::Utc::now().to_rfc3339(),
+        "total": total,
+        "contains": contains
+    });
+
+    let replaced = store
+        .store_value_set_expansion(&value_set.id, expansion)
+        .await?;
+
+    tracing::info!(
+        "Precomputed expansion for ValueSet '{}': {} codes (replaced: {})",
+        value_set.url,
+        total,
+        replaced
+    );
+
+    Ok(Json(Parameters::with_parameters(vec![
+        Parameter::integer("total", total as i64),
+        Parameter::boolean("replaced", replaced),
+    ])))
+}
@@ -0,0 +1,89 @@
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::error::AppError;
+use crate::models::ResourceType;
+use crate::store::TerminologyStore;
+use crate::terminology::{Parameter, Parameters};
+
+/// POST /CodeSystem/$reindex
+pub async fn reindex_code_systems(
+    State(store): State<Arc<dyn TerminologyStore>>,
+) -> Result<Json<Parameters>, AppError> {
+    perform_reindex(store, ResourceType::CodeSystem, None).await
+}
+
+/// POST /CodeSystem/{id}/$reindex
+pub async fn reindex_code_system(
+    State(store): State<Arc<dyn TerminologyStore>>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Parameters>, AppError> {
+    perform_reindex(store, ResourceType::CodeSystem, Some(id)).await
+}
+
+/// POST /ValueSet/$reindex
+pub async fn reindex_value_sets(
+    State(store): State<Arc<dyn TerminologyStore>>,
+) -> Result<Json<Parameters>, AppError> {
+    perform_reindex(store, ResourceType::ValueSet, None).await
+}
+
+/// POST /ValueSet/{id}/$reindex
+pub async fn reindex_value_set(
+    State(store): State<Arc<dyn TerminologyStore>>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Parameters>, AppError> {
+    perform_reindex(store, ResourceType::ValueSet, Some(id)).await
+}
+
+/// POST /ConceptMap/$reindex
+pub async fn reindex_concept_maps(
+    State(store): State<Arc<dyn TerminologyStore>>,
+) -> Result<Json<Parameters>, AppError> {
+    perform_reindex(store, ResourceType::ConceptMap, None).await
+}
+
+/// POST /ConceptMap/{id}/$reindex
+pub async fn reindex_concept_map(
+    State(store): State<Arc<dyn TerminologyStore>>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Parameters>, AppError> {
+    perform_reindex(store, ResourceType::ConceptMap, Some(id)).await
+}
+
+async fn perform_reindex(
+    store: Arc<dyn TerminologyStore>,
+    resource_type: ResourceType,
+    id: Option<Uuid>,
+) -> Result<Json<Parameters>, AppError> {
+    if let Some(id) = &id {
+        let exists = match resource_type {
+            ResourceType::CodeSystem => store.get_code_system_by_id(id).await?.is_some(),
+            ResourceType::ValueSet => store.get_value_set_by_id(id).await?.is_some(),
+            ResourceType::ConceptMap => store.get_concept_map_by_id(id).await?.is_some(),
+        };
+        if !exists {
+            return Err(AppError::NotFound(format!(
+                "{} {id} not found",
+                resource_type.as_str()
+            )));
+        }
+    }
+
+    let updated = store.reindex_resources(resource_type, id.as_ref()).await?;
+
+    tracing::info!(
+        "Reindexed {} resources: {} updated",
+        resource_type.as_str(),
+        updated
+    );
+
+    Ok(Json(Parameters::with_parameters(vec![Parameter::integer(
+        "updated",
+        updated as i64,
+    )])))
+}
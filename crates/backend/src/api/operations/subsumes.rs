@@ -2,13 +2,13 @@ use axum::{
     extract::{Path, Query, State},
     Json,
 };
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
 use std::sync::Arc;
 use uuid::Uuid;
 
-use crate::api::parameters::{Parameter, Parameters};
 use crate::error::AppError;
 use crate::store::TerminologyStore;
+use crate::terminology::{self, Parameter, ParameterIssues, Parameters, SubsumptionOutcome};
 
 #[derive(Debug, Deserialize)]
 pub struct SubsumesParams {
@@ -20,54 +20,35 @@ pub struct SubsumesParams {
     pub version: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(rename_all = "kebab-case")]
-#[allow(dead_code)]
-pub enum SubsumptionOutcome {
-    Equivalent,
-    Subsumes,
-    SubsumedBy,
-    NotSubsumed,
-}
-
 /// GET /CodeSystem/$subsumes?system=...&codeA=...&codeB=...
 pub async fn subsumes_get(
     State(store): State<Arc<dyn TerminologyStore>>,
     Query(params): Query<SubsumesParams>,
 ) -> Result<Json<Parameters>, AppError> {
-    let system = params
-        .system
-        .ok_or_else(|| AppError::BadRequest("system parameter required".to_string()))?;
-    let code_a = params
-        .code_a
-        .ok_or_else(|| AppError::BadRequest("codeA parameter required".to_string()))?;
-    let code_b = params
-        .code_b
-        .ok_or_else(|| AppError::BadRequest("codeB parameter required".to_string()))?;
-
-    perform_subsumes(store, &system, &code_a, &code_b, params.version.as_deref()).await
+    let mut issues = ParameterIssues::default();
+    let system = issues.require("system", params.system);
+    let code_a = issues.require("codeA", params.code_a);
+    let code_b = issues.require("codeB", params.code_b);
+    let (Some(system), Some(code_a), Some(code_b)) = (system, code_a, code_b) else {
+        return Err(issues.into());
+    };
+
+    terminology::subsumes(store, &system, &code_a, &code_b, params.version.as_deref())
+        .await
+        .map(outcome_parameters)
 }
 
 /// POST /CodeSystem/$subsumes with Parameters body
 pub async fn subsumes_post(
     State(store): State<Arc<dyn TerminologyStore>>,
-    Json(params): Json<Parameters>,
+    params: Parameters,
 ) -> Result<Json<Parameters>, AppError> {
-    let system = params
-        .get_string("system")
-        .or_else(|| params.get_uri("system"))
-        .ok_or_else(|| AppError::BadRequest("system parameter required".to_string()))?;
-    let code_a = params
-        .get_string("codeA")
-        .or_else(|| params.get_code("codeA"))
-        .ok_or_else(|| AppError::BadRequest("codeA parameter required".to_string()))?;
-    let code_b = params
-        .get_string("codeB")
-        .or_else(|| params.get_code("codeB"))
-        .ok_or_else(|| AppError::BadRequest("codeB parameter required".to_string()))?;
-    let version = params.get_string("version");
-
-    perform_subsumes(store, system, code_a, code_b, version).await
+    let inputs = SubsumesInputs::from_parameters(&params, true)?;
+    let system = inputs.system.unwrap_or_default();
+
+    terminology::subsumes(store, system, inputs.code_a, inputs.code_b, inputs.version)
+        .await
+        .map(outcome_parameters)
 }
 
 /// GET /CodeSystem/{id}/$subsumes?codeA=...&codeB=...
@@ -76,94 +57,140 @@ pub async fn subsumes_instance_get(
     Path(id): Path<Uuid>,
     Query(params): Query<SubsumesParams>,
 ) -> Result<Json<Parameters>, AppError> {
-    let code_a = params
-        .code_a
-        .ok_or_else(|| AppError::BadRequest("codeA parameter required".to_string()))?;
-    let code_b = params
-        .code_b
-        .ok_or_else(|| AppError::BadRequest("codeB parameter required".to_string()))?;
+    let mut issues = ParameterIssues::default();
+    let code_a = issues.require("codeA", params.code_a);
+    let code_b = issues.require("codeB", params.code_b);
+    let (Some(code_a), Some(code_b)) = (code_a, code_b) else {
+        return Err(issues.into());
+    };
 
     let code_system = store
         .get_code_system_by_id(&id)
         .await?
         .ok_or_else(|| AppError::NotFound(format!("CodeSystem {id} not found")))?;
 
-    perform_subsumes(store, &code_system.url, &code_a, &code_b, None).await
+    terminology::subsumes(
+        store,
+        &code_system.url,
+        &code_a,
+        &code_b,
+        code_system.version.as_deref(),
+    )
+    .await
+    .map(outcome_parameters)
 }
 
 /// POST /CodeSystem/{id}/$subsumes with Parameters body
 pub async fn subsumes_instance_post(
     State(store): State<Arc<dyn TerminologyStore>>,
     Path(id): Path<Uuid>,
-    Json(params): Json<Parameters>,
+    params: Parameters,
 ) -> Result<Json<Parameters>, AppError> {
-    let code_a = params
-        .get_string("codeA")
-        .or_else(|| params.get_code("codeA"))
-        .ok_or_else(|| AppError::BadRequest("codeA parameter required".to_string()))?;
-    let code_b = params
-        .get_string("codeB")
-        .or_else(|| params.get_code("codeB"))
-        .ok_or_else(|| AppError::BadRequest("codeB parameter required".to_string()))?;
+    let inputs = SubsumesInputs::from_parameters(&params, false)?;
 
     let code_system = store
         .get_code_system_by_id(&id)
         .await?
         .ok_or_else(|| AppError::NotFound(format!("CodeSystem {id} not found")))?;
 
-    perform_subsumes(store, &code_system.url, code_a, code_b, None).await
-}
-
-async fn perform_subsumes(
-    store: Arc<dyn TerminologyStore>,
-    system: &str,
-    code_a: &str,
-    code_b: &str,
-    version: Option<&str>,
-) -> Result<Json<Parameters>, AppError> {
-    // Get the CodeSystem
-    let code_system = store
-        .get_code_system(system, version)
-        .await?
-        .ok_or_else(|| AppError::NotFound(format!("CodeSystem '{system}' not found")))?;
-
-    // Check if both codes exist
-    let concept_a = store.get_concept(&code_system.id, code_a).await?;
-    let concept_b = store.get_concept(&code_system.id, code_b).await?;
-
-    if concept_a.is_none() {
-        return Err(AppError::NotFound(format!(
-            "Code '{code_a}' not found in system '{system}'"
-        )));
-    }
-
-    if concept_b.is_none() {
-        return Err(AppError::NotFound(format!(
-            "Code '{code_b}' not found in system '{system}'"
+    if let Some(system) = inputs.system.filter(|s| *s != code_system.url) {
+        return Err(AppError::BadRequest(format!(
+            "System '{system}' does not match CodeSystem '{}'",
+            code_system.url
         )));
     }
 
-    // Check for equivalence first
-    if code_a == code_b {
-        return Ok(Json(Parameters::with_parameters(vec![Parameter::code(
-            "outcome",
-            "equivalent",
-        )])));
-    }
+    terminology::subsumes(
+        store,
+        &code_system.url,
+        inputs.code_a,
+        inputs.code_b,
+        code_system.version.as_deref(),
+    )
+    .await
+    .map(outcome_parameters)
+}
 
-    // Query closure table for subsumption relationship
-    let outcome = store
-        .check_subsumption(&code_system.id, code_a, code_b)
-        .await?;
+/// `$subsumes` inputs from a Parameters body, given either as `codeA`/`codeB` plus `system`
+/// or as `codingA`/`codingB` Codings carrying their own system
+struct SubsumesInputs<'a> {
+    system: Option<&'a str>,
+    version: Option<&'a str>,
+    code_a: &'a str,
+    code_b: &'a str,
+}
 
-    let outcome_code = match outcome {
-        Some(true) => "subsumes",     // A subsumes B
-        Some(false) => "subsumed-by", // A is subsumed by B
-        None => "not-subsumed",       // No relationship
-    };
+impl<'a> SubsumesInputs<'a> {
+    /// Read the inputs, reporting every missing or conflicting parameter together.
+    /// `system` is only required when `require_system` is set (type-level invocation).
+    fn from_parameters(params: &'a Parameters, require_system: bool) -> Result<Self, AppError> {
+        let coding_a = params.get_coding("codingA");
+        let coding_b = params.get_coding("codingB");
+
+        let mut issues = ParameterIssues::default();
+        let code_a = issues.require(
+            "codeA or codingA",
+            params
+                .get_string("codeA")
+                .or_else(|| params.get_code("codeA"))
+                .or_else(|| coding_a.and_then(|c| c.code.as_deref())),
+        );
+        let code_b = issues.require(
+            "codeB or codingB",
+            params
+                .get_string("codeB")
+                .or_else(|| params.get_code("codeB"))
+                .or_else(|| coding_b.and_then(|c| c.code.as_deref())),
+        );
+
+        // Subsumption is only defined within one system, so every system given must agree
+        let explicit_system = params
+            .get_string("system")
+            .or_else(|| params.get_uri("system"));
+        let systems: Vec<&str> = [
+            explicit_system,
+            coding_a.and_then(|c| c.system.as_deref()),
+            coding_b.and_then(|c| c.system.as_deref()),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+
+        if let Some(other) = systems.iter().find(|s| **s != systems[0]) {
+            issues.invalid(format!(
+                "Cannot test subsumption across systems '{}' and '{other}'",
+                systems[0]
+            ));
+        }
+        if require_system {
+            issues.require("system", systems.first());
+        }
+
+        let (Some(code_a), Some(code_b)) = (code_a, code_b) else {
+            return Err(issues.into());
+        };
+        issues.check()?;
+
+        let version = params.get_string("version").or_else(|| {
+            [coding_a, coding_b]
+                .into_iter()
+                .flatten()
+                .find_map(|c| c.version.as_deref())
+        });
+
+        Ok(Self {
+            system: systems.first().copied(),
+            version,
+            code_a,
+            code_b,
+        })
+    }
+}
 
-    Ok(Json(Parameters::with_parameters(vec![Parameter::code(
+/// The `$subsumes` result: the outcome as a single `outcome` parameter
+fn outcome_parameters(outcome: SubsumptionOutcome) -> Json<Parameters> {
+    Json(Parameters::with_parameters(vec![Parameter::code(
         "outcome",
-        outcome_code,
-    )])))
+        outcome.code(),
+    )]))
 }
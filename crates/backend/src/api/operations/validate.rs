@@ -1,14 +1,13 @@
-use axum::{
-    extract::{Path, Query, State},
-    Json,
-};
+use axum::extract::{Path, Query, State};
 use serde::Deserialize;
 use std::sync::Arc;
 use uuid::Uuid;
 
-use crate::api::parameters::{Parameter, Parameters};
+use crate::api::language::RequestLanguage;
 use crate::error::AppError;
 use crate::store::TerminologyStore;
+use crate::terminology;
+use crate::terminology::{split_canonical, Cacheable, ParameterIssues, Parameters};
 
 #[derive(Debug, Deserialize)]
 pub struct ValidateCodeParams {
@@ -17,27 +16,46 @@ pub struct ValidateCodeParams {
     pub code: Option<String>,
     pub version: Option<String>,
     pub display: Option<String>,
+    #[serde(rename = "displayLanguage")]
+    pub display_language: Option<String>,
+    /// When false, an unknown system is an error instead of a `result=false` warning
+    #[serde(rename = "lenient-system")]
+    pub lenient_system: Option<bool>,
+    /// `system|version` to validate against when the ValueSet does not pin a version
+    #[serde(rename = "system-version")]
+    pub system_version: Option<String>,
+    /// Whether abstract (not selectable) codes are valid
+    #[serde(rename = "abstract")]
+    pub abstract_allowed: Option<bool>,
+    /// Take the system from the ValueSet's referenced systems when none is given
+    #[serde(rename = "inferSystem")]
+    pub infer_system: Option<bool>,
 }
 
 /// GET /CodeSystem/$validate-code?url=...&code=...
 pub async fn validate_code_cs_get(
     State(store): State<Arc<dyn TerminologyStore>>,
+    language: RequestLanguage,
     Query(params): Query<ValidateCodeParams>,
-) -> Result<Json<Parameters>, AppError> {
-    let system = params
-        .system
-        .or(params.url)
-        .ok_or_else(|| AppError::BadRequest("system or url parameter required".to_string()))?;
-    let code = params
-        .code
-        .ok_or_else(|| AppError::BadRequest("code parameter required".to_string()))?;
-
-    perform_validate_code(
+) -> Result<Cacheable<Parameters>, AppError> {
+    let mut issues = ParameterIssues::default();
+    let system = issues.require("system or url", params.system.or(params.url));
+    let code = issues.require("code", params.code);
+    let (Some(system), Some(code)) = (system, code) else {
+        return Err(issues.into());
+    };
+
+    terminology::validate_code(
         store,
         &system,
         &code,
         params.version.as_deref(),
         params.display.as_deref(),
+        language
+            .resolve(params.display_language.as_deref())
+            .as_deref(),
+        params.lenient_system.unwrap_or(true),
+        params.abstract_allowed.unwrap_or(false),
     )
     .await
 }
@@ -45,44 +63,73 @@ pub async fn validate_code_cs_get(
 /// POST /CodeSystem/$validate-code with Parameters body
 pub async fn validate_code_cs_post(
     State(store): State<Arc<dyn TerminologyStore>>,
-    Json(params): Json<Parameters>,
-) -> Result<Json<Parameters>, AppError> {
-    let system = params
-        .get_string("system")
-        .or_else(|| params.get_string("url"))
-        .or_else(|| params.get_uri("url"))
-        .ok_or_else(|| AppError::BadRequest("system or url parameter required".to_string()))?;
-    let code = params
-        .get_string("code")
-        .or_else(|| params.get_code("code"))
-        .ok_or_else(|| AppError::BadRequest("code parameter required".to_string()))?;
+    language: RequestLanguage,
+    params: Parameters,
+) -> Result<Cacheable<Parameters>, AppError> {
+    let mut issues = ParameterIssues::default();
+    let system = issues.require(
+        "system or url",
+        params
+            .get_string("system")
+            .or_else(|| params.get_string("url"))
+            .or_else(|| params.get_uri("url")),
+    );
+    let code = issues.require(
+        "code",
+        params
+            .get_string("code")
+            .or_else(|| params.get_code("code")),
+    );
+    let (Some(system), Some(code)) = (system, code) else {
+        return Err(issues.into());
+    };
     let version = params.get_string("version");
     let display = params.get_string("display");
+    let display_language = language.resolve(params.get_code("displayLanguage"));
+    let lenient_system = params.get_boolean("lenient-system").unwrap_or(true);
+    let abstract_allowed = params.get_boolean("abstract").unwrap_or(false);
 
-    perform_validate_code(store, system, code, version, display).await
+    terminology::validate_code(
+        store,
+        system,
+        code,
+        version,
+        display,
+        display_language.as_deref(),
+        lenient_system,
+        abstract_allowed,
+    )
+    .await
 }
 
 /// GET /CodeSystem/{id}/$validate-code?code=...
 pub async fn validate_code_cs_instance_get(
     State(store): State<Arc<dyn TerminologyStore>>,
+    language: RequestLanguage,
     Path(id): Path<Uuid>,
     Query(params): Query<ValidateCodeParams>,
-) -> Result<Json<Parameters>, AppError> {
-    let code = params
-        .code
-        .ok_or_else(|| AppError::BadRequest("code parameter required".to_string()))?;
+) -> Result<Cacheable<Parameters>, AppError> {
+    let mut issues = ParameterIssues::default();
+    let Some(code) = issues.require("code", params.code) else {
+        return Err(issues.into());
+    };
 
     let code_system = store
         .get_code_system_by_id(&id)
         .await?
         .ok_or_else(|| AppError::NotFound(format!("CodeSystem {id} not found")))?;
 
-    perform_validate_code(
+    terminology::validate_code(
         store,
         &code_system.url,
         &code,
-        None,
+        code_system.version.as_deref(),
         params.display.as_deref(),
+        language
+            .resolve(params.display_language.as_deref())
+            .as_deref(),
+        params.lenient_system.unwrap_or(true),
+        params.abstract_allowed.unwrap_or(false),
     )
     .await
 }
@@ -90,44 +137,75 @@ pub async fn validate_code_cs_instance_get(
 /// POST /CodeSystem/{id}/$validate-code with Parameters body
 pub async fn validate_code_cs_instance_post(
     State(store): State<Arc<dyn TerminologyStore>>,
+    language: RequestLanguage,
     Path(id): Path<Uuid>,
-    Json(params): Json<Parameters>,
-) -> Result<Json<Parameters>, AppError> {
-    let code = params
-        .get_string("code")
-        .or_else(|| params.get_code("code"))
-        .ok_or_else(|| AppError::BadRequest("code parameter required".to_string()))?;
+    params: Parameters,
+) -> Result<Cacheable<Parameters>, AppError> {
+    let mut issues = ParameterIssues::default();
+    let Some(code) = issues.require(
+        "code",
+        params
+            .get_string("code")
+            .or_else(|| params.get_code("code")),
+    ) else {
+        return Err(issues.into());
+    };
     let display = params.get_string("display");
+    let display_language = language.resolve(params.get_code("displayLanguage"));
+    let lenient_system = params.get_boolean("lenient-system").unwrap_or(true);
+    let abstract_allowed = params.get_boolean("abstract").unwrap_or(false);
 
     let code_system = store
         .get_code_system_by_id(&id)
         .await?
         .ok_or_else(|| AppError::NotFound(format!("CodeSystem {id} not found")))?;
 
-    perform_validate_code(store, &code_system.url, code, None, display).await
+    terminology::validate_code(
+        store,
+        &code_system.url,
+        code,
+        code_system.version.as_deref(),
+        display,
+        display_language.as_deref(),
+        lenient_system,
+        abstract_allowed,
+    )
+    .await
 }
 
 /// GET /ValueSet/$validate-code?url=...&code=...&system=...
 pub async fn validate_code_vs_get(
     State(store): State<Arc<dyn TerminologyStore>>,
+    language: RequestLanguage,
     Query(params): Query<ValidateCodeParams>,
-) -> Result<Json<Parameters>, AppError> {
-    let value_set_url = params
-        .url
-        .ok_or_else(|| AppError::BadRequest("url parameter required for ValueSet".to_string()))?;
-    let code = params
-        .code
-        .ok_or_else(|| AppError::BadRequest("code parameter required".to_string()))?;
-    let system = params.system.ok_or_else(|| {
-        AppError::BadRequest("system parameter required for ValueSet validation".to_string())
-    })?;
+) -> Result<Cacheable<Parameters>, AppError> {
+    let mut issues = ParameterIssues::default();
+    let value_set_url = issues.require("url", params.url);
+    let code = issues.require("code", params.code);
+    let system = optional_system(&mut issues, params.system, params.infer_system);
+    let (Some(value_set_url), Some(code), Some(system)) = (value_set_url, code, system) else {
+        return Err(issues.into());
+    };
+
+    let (value_set_url, value_set_version) = split_canonical(&value_set_url, None)?;
+    let value_set = store
+        .get_value_set(value_set_url, value_set_version)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("ValueSet '{value_set_url}' not found")))?;
 
-    perform_validate_code_valueset(
+    terminology::validate_code_in_value_set(
         store,
-        &value_set_url,
-        &system,
+        &value_set,
+        value_set_version.is_some(),
+        system.as_deref(),
         &code,
         params.display.as_deref(),
+        language
+            .resolve(params.display_language.as_deref())
+            .as_deref(),
+        params.lenient_system.unwrap_or(true),
+        params.abstract_allowed.unwrap_or(false),
+        params.system_version.as_deref(),
     )
     .await
 }
@@ -135,48 +213,87 @@ pub async fn validate_code_vs_get(
 /// POST /ValueSet/$validate-code with Parameters body
 pub async fn validate_code_vs_post(
     State(store): State<Arc<dyn TerminologyStore>>,
-    Json(params): Json<Parameters>,
-) -> Result<Json<Parameters>, AppError> {
-    let value_set_url = params
-        .get_string("url")
-        .or_else(|| params.get_uri("url"))
-        .ok_or_else(|| AppError::BadRequest("url parameter required for ValueSet".to_string()))?;
-    let code = params
-        .get_string("code")
-        .or_else(|| params.get_code("code"))
-        .ok_or_else(|| AppError::BadRequest("code parameter required".to_string()))?;
-    let system = params.get_string("system").ok_or_else(|| {
-        AppError::BadRequest("system parameter required for ValueSet validation".to_string())
-    })?;
+    language: RequestLanguage,
+    params: Parameters,
+) -> Result<Cacheable<Parameters>, AppError> {
+    let mut issues = ParameterIssues::default();
+    let value_set_url = issues.require(
+        "url",
+        params.get_string("url").or_else(|| params.get_uri("url")),
+    );
+    let code = issues.require(
+        "code",
+        params
+            .get_string("code")
+            .or_else(|| params.get_code("code")),
+    );
+    let system = optional_system(
+        &mut issues,
+        params.get_string("system"),
+        params.get_boolean("inferSystem"),
+    );
+    let (Some(value_set_url), Some(code), Some(system)) = (value_set_url, code, system) else {
+        return Err(issues.into());
+    };
     let display = params.get_string("display");
+    let display_language = language.resolve(params.get_code("displayLanguage"));
+    let lenient_system = params.get_boolean("lenient-system").unwrap_or(true);
+    let abstract_allowed = params.get_boolean("abstract").unwrap_or(false);
+    let system_version = system_version_param(&params);
+
+    let (value_set_url, value_set_version) = split_canonical(value_set_url, None)?;
+    let value_set = store
+        .get_value_set(value_set_url, value_set_version)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("ValueSet '{value_set_url}' not found")))?;
 
-    perform_validate_code_valueset(store, value_set_url, system, code, display).await
+    terminology::validate_code_in_value_set(
+        store,
+        &value_set,
+        value_set_version.is_some(),
+        system,
+        code,
+        display,
+        display_language.as_deref(),
+        lenient_system,
+        abstract_allowed,
+        system_version,
+    )
+    .await
 }
 
 /// GET /ValueSet/{id}/$validate-code?code=...&system=...
 pub async fn validate_code_vs_instance_get(
     State(store): State<Arc<dyn TerminologyStore>>,
+    language: RequestLanguage,
     Path(id): Path<Uuid>,
     Query(params): Query<ValidateCodeParams>,
-) -> Result<Json<Parameters>, AppError> {
-    let code = params
-        .code
-        .ok_or_else(|| AppError::BadRequest("code parameter required".to_string()))?;
-    let system = params.system.ok_or_else(|| {
-        AppError::BadRequest("system parameter required for ValueSet validation".to_string())
-    })?;
+) -> Result<Cacheable<Parameters>, AppError> {
+    let mut issues = ParameterIssues::default();
+    let code = issues.require("code", params.code);
+    let system = optional_system(&mut issues, params.system, params.infer_system);
+    let (Some(code), Some(system)) = (code, system) else {
+        return Err(issues.into());
+    };
 
     let value_set = store
         .get_value_set_by_id(&id)
         .await?
         .ok_or_else(|| AppError::NotFound(format!("ValueSet {id} not found")))?;
 
-    perform_validate_code_valueset(
+    terminology::validate_code_in_value_set(
         store,
-        &value_set.url,
-        &system,
+        &value_set,
+        true,
+        system.as_deref(),
         &code,
         params.display.as_deref(),
+        language
+            .resolve(params.display_language.as_deref())
+            .as_deref(),
+        params.lenient_system.unwrap_or(true),
+        params.abstract_allowed.unwrap_or(false),
+        params.system_version.as_deref(),
     )
     .await
 }
@@ -184,106 +301,68 @@ pub async fn validate_code_vs_instance_get(
 /// POST /ValueSet/{id}/$validate-code with Parameters body
 pub async fn validate_code_vs_instance_post(
     State(store): State<Arc<dyn TerminologyStore>>,
+    language: RequestLanguage,
     Path(id): Path<Uuid>,
-    Json(params): Json<Parameters>,
-) -> Result<Json<Parameters>, AppError> {
-    let code = params
-        .get_string("code")
-        .or_else(|| params.get_code("code"))
-        .ok_or_else(|| AppError::BadRequest("code parameter required".to_string()))?;
-    let system = params.get_string("system").ok_or_else(|| {
-        AppError::BadRequest("system parameter required for ValueSet validation".to_string())
-    })?;
+    params: Parameters,
+) -> Result<Cacheable<Parameters>, AppError> {
+    let mut issues = ParameterIssues::default();
+    let code = issues.require(
+        "code",
+        params
+            .get_string("code")
+            .or_else(|| params.get_code("code")),
+    );
+    let system = optional_system(
+        &mut issues,
+        params.get_string("system"),
+        params.get_boolean("inferSystem"),
+    );
+    let (Some(code), Some(system)) = (code, system) else {
+        return Err(issues.into());
+    };
     let display = params.get_string("display");
+    let display_language = language.resolve(params.get_code("displayLanguage"));
+    let lenient_system = params.get_boolean("lenient-system").unwrap_or(true);
+    let abstract_allowed = params.get_boolean("abstract").unwrap_or(false);
+    let system_version = system_version_param(&params);
 
     let value_set = store
         .get_value_set_by_id(&id)
         .await?
         .ok_or_else(|| AppError::NotFound(format!("ValueSet {id} not found")))?;
 
-    perform_validate_code_valueset(store, &value_set.url, system, code, display).await
+    terminology::validate_code_in_value_set(
+        store,
+        &value_set,
+        true,
+        system,
+        code,
+        display,
+        display_language.as_deref(),
+        lenient_system,
+        abstract_allowed,
+        system_version,
+    )
+    .await
 }
 
-async fn perform_validate_code(
-    store: Arc<dyn TerminologyStore>,
-    system: &str,
-    code: &str,
-    version: Option<&str>,
-    display: Option<&str>,
-) -> Result<Json<Parameters>, AppError> {
-    // Check if CodeSystem exists
-    let code_system = store.get_code_system(system, version).await?;
-    if code_system.is_none() {
-        return Ok(Json(Parameters::with_parameters(vec![
-            Parameter::boolean("result", false),
-            Parameter::string("message", format!("CodeSystem '{system}' not found")),
-        ])));
-    }
-
-    let code_system = code_system.unwrap();
-
-    // Check if code exists in the system
-    let concept = store.get_concept(&code_system.id, code).await?;
-
-    let is_valid = concept.is_some();
-    let mut result_params = vec![Parameter::boolean("result", is_valid)];
-
-    if is_valid {
-        let concept = concept.unwrap();
-
-        // Optionally validate display
-        if let Some(expected_display) = display {
-            if let Some(actual_display) = &concept.display {
-                if actual_display != expected_display {
-                    result_params.push(Parameter::string(
-                        "message",
-                        format!(
-                            "Display value '{expected_display}' does not match expected '{actual_display}'"
-                        ),
-                    ));
-                }
-            }
-        }
-
-        result_params.push(Parameter::string(
-            "display",
-            concept.display.unwrap_or_default(),
-        ));
-    } else {
-        result_params.push(Parameter::string(
-            "message",
-            format!("Code '{code}' not found in system '{system}'"),
-        ));
-    }
-
-    Ok(Json(Parameters::with_parameters(result_params)))
+/// The `system-version` parameter of a Parameters body, as a canonical, uri or string
+fn system_version_param(params: &Parameters) -> Option<&str> {
+    params
+        .get_canonical("system-version")
+        .or_else(|| params.get_uri("system-version"))
+        .or_else(|| params.get_string("system-version"))
 }
 
-async fn perform_validate_code_valueset(
-    store: Arc<dyn TerminologyStore>,
-    _value_set_url: &str,
-    system: &str,
-    code: &str,
-    display: Option<&str>,
-) -> Result<Json<Parameters>, AppError> {
-    // First validate the code exists in the specified system
-    let code_validation = perform_validate_code(store.clone(), system, code, None, display).await?;
-
-    let code_valid = code_validation.0.get_boolean("result").unwrap_or(false);
-
-    if !code_valid {
-        return Ok(code_validation);
+/// The `system` of a ValueSet validation, required unless `inferSystem` is true
+fn optional_system<T>(
+    issues: &mut ParameterIssues,
+    system: Option<T>,
+    infer_system: Option<bool>,
+) -> Option<Option<T>> {
+    if infer_system == Some(true) {
+        Some(system)
+    } else {
+        issues.require("system", system).map(Some)
     }
-
-    // TODO: Check if the ValueSet includes this code from this system
-    // For now, we'll just validate the code exists in the system
-    // Full implementation requires expanding the ValueSet and checking membership
-
-    Ok(Json(Parameters::with_parameters(vec![
-        Parameter::boolean("result", true),
-        Parameter::string(
-            "message",
-            "Code validation passed (ValueSet expansion not yet implemented)",
-        ),
-    ])))
 }
@@ -0,0 +1,67 @@
+use axum::{
+    extract::{Query, State},
+    Json,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+
+use crate::error::AppError;
+use crate::store::TerminologyStore;
+use crate::terminology::{Parameter, ParameterIssues, Parameters};
+
+#[derive(Debug, Deserialize)]
+pub struct VersionsParams {
+    pub url: Option<String>,
+}
+
+/// GET /CodeSystem/$versions?url=...
+pub async fn versions_get(
+    State(store): State<Arc<dyn TerminologyStore>>,
+    Query(params): Query<VersionsParams>,
+) -> Result<Json<Parameters>, AppError> {
+    let mut issues = ParameterIssues::default();
+    let Some(url) = issues.require("url", params.url) else {
+        return Err(issues.into());
+    };
+
+    perform_versions(store, &url).await
+}
+
+/// POST /CodeSystem/$versions with Parameters body
+pub async fn versions_post(
+    State(store): State<Arc<dyn TerminologyStore>>,
+    params: Parameters,
+) -> Result<Json<Parameters>, AppError> {
+    let mut issues = ParameterIssues::default();
+    let url = params.get_uri("url").or_else(|| params.get_string("url"));
+    let Some(url) = issues.require("url", url) else {
+        return Err(issues.into());
+    };
+
+    perform_versions(store, url).await
+}
+
+/// One `version` part per installed version of the CodeSystem, flagging the one used when a
+/// request names no version
+async fn perform_versions(
+    store: Arc<dyn TerminologyStore>,
+    url: &str,
+) -> Result<Json<Parameters>, AppError> {
+    let versions = store.list_versions(url).await?;
+    if versions.is_empty() {
+        return Err(AppError::UnknownCodeSystem(url.to_string()));
+    }
+
+    let mut result = vec![Parameter::uri("url", url)];
+    for (index, (version, updated_at)) in versions.into_iter().enumerate() {
+        let mut parts = Vec::new();
+        if let Some(version) = version {
+            parts.push(Parameter::string("version", version));
+        }
+        parts.push(Parameter::boolean("default", index == 0));
+        parts.push(Parameter::date_time("lastUpdated", updated_at.to_rfc3339()));
+        result.push(Parameter::part("version", parts));
+    }
+
+    Ok(Json(Parameters::with_parameters(result)))
+}
@@ -0,0 +1,108 @@
+use axum::{
+    extract::ConnectInfo,
+    http::{header, Request},
+    response::{IntoResponse, Response},
+    Router,
+};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tower_governor::{
+    governor::GovernorConfigBuilder, key_extractor::KeyExtractor, GovernorError, GovernorLayer,
+};
+
+use crate::error::AppError;
+
+/// Token bucket settings applied per client to operation endpoints
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    /// Sustained requests per second; `0` disables rate limiting
+    pub per_second: u32,
+    /// Requests a client may make in a burst before being throttled
+    pub burst: u32,
+}
+
+/// Identifies a client by its peer IP, or as the admin when it sends the configured admin
+/// token. Other bearer tokens are not verified, so they do not earn a bucket of their own;
+/// otherwise a client could send a fresh token with every request.
+#[derive(Debug, Clone)]
+struct ClientKeyExtractor {
+    admin_token: Option<Arc<str>>,
+}
+
+impl KeyExtractor for ClientKeyExtractor {
+    type Key = String;
+
+    fn extract<T>(&self, request: &Request<T>) -> Result<Self::Key, GovernorError> {
+        let token = request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+        if token.is_some() && token == self.admin_token.as_deref() {
+            return Ok("admin".to_string());
+        }
+
+        request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| format!("ip:{}", addr.ip()))
+            .ok_or(GovernorError::UnableToExtractKey)
+    }
+}
+
+/// A per-client throttle for routers: every route of a router it is applied to answers
+/// `429` with `Retry-After` once the client's bucket is empty. All routers it is applied to
+/// draw from the same buckets. Applying it leaves routers unchanged when disabled.
+/// Requests authenticated with `admin_token` share one bucket of their own.
+pub fn rate_limiter<S>(
+    limit: RateLimit,
+    admin_token: Option<&str>,
+) -> impl Fn(Router<S>) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let config = (limit.per_second > 0)
+        .then(|| {
+            GovernorConfigBuilder::default()
+                .period(Duration::from_secs(1) / limit.per_second)
+                .burst_size(limit.burst.max(1))
+                .key_extractor(ClientKeyExtractor {
+                    admin_token: admin_token.map(Arc::from),
+                })
+                .finish()
+        })
+        .flatten()
+        .map(Arc::new);
+
+    // Forget clients whose buckets have refilled so the key map does not grow unbounded
+    if let Some(config) = &config {
+        let limiter = config.limiter().clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(60));
+            loop {
+                interval.tick().await;
+                limiter.retain_recent();
+            }
+        });
+    }
+
+    move |router| match &config {
+        Some(config) => {
+            router.route_layer(GovernorLayer::new(config.clone()).error_handler(throttled))
+        }
+        None => router,
+    }
+}
+
+fn throttled(error: GovernorError) -> Response {
+    match error {
+        GovernorError::TooManyRequests { wait_time, .. } => {
+            AppError::TooManyRequests(wait_time).into_response()
+        }
+        GovernorError::UnableToExtractKey | GovernorError::Other { .. } => AppError::Internal(
+            anyhow::anyhow!("Unable to identify client for rate limiting"),
+        )
+        .into_response(),
+    }
+}
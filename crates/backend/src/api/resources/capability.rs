@@ -1,8 +1,103 @@
-use axum::Json;
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::header,
+    response::{IntoResponse, Response},
+    Extension,
+};
 use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use tokio::sync::RwLock;
 
-pub async fn capability_statement() -> Json<Value> {
-    Json(json!({
+use crate::error::AppError;
+use crate::fhir_version::FhirVersion;
+use crate::store::TerminologyStore;
+
+/// Serialized capability documents, probed on nearly every client connection.
+/// The CapabilityStatement is static per FHIR release; TerminologyCapabilities lists the installed
+/// CodeSystems and is rebuilt on the next request after [`CapabilityCache::invalidate`].
+#[derive(Clone, Default)]
+pub struct CapabilityCache {
+    capability_statement: Arc<RwLock<HashMap<FhirVersion, Bytes>>>,
+    /// The cached document and a generation bumped on every invalidation, so that a
+    /// document built from a system list read before an invalidation is not kept
+    terminology_capabilities: Arc<RwLock<(u64, Option<Bytes>)>>,
+}
+
+impl CapabilityCache {
+    /// Build both documents ahead of the first request
+    pub async fn warm(&self, store: &Arc<dyn TerminologyStore>) -> Result<(), AppError> {
+        for version in FhirVersion::ALL {
+            self.capability_statement(version).await;
+        }
+        self.terminology_capabilities(store).await?;
+        Ok(())
+    }
+
+    /// Drop the cached TerminologyCapabilities after the installed CodeSystems changed
+    pub async fn invalidate(&self) {
+        let mut cached = self.terminology_capabilities.write().await;
+        *cached = (cached.0 + 1, None);
+    }
+
+    async fn capability_statement(&self, version: FhirVersion) -> Bytes {
+        if let Some(body) = self.capability_statement.read().await.get(&version) {
+            return body.clone();
+        }
+
+        let body = Bytes::from(capability_statement_json(version).to_string());
+        self.capability_statement
+            .write()
+            .await
+            .insert(version, body.clone());
+        body
+    }
+
+    async fn terminology_capabilities(
+        &self,
+        store: &Arc<dyn TerminologyStore>,
+    ) -> Result<Bytes, AppError> {
+        let generation = {
+            let cached = self.terminology_capabilities.read().await;
+            if let Some(body) = &cached.1 {
+                return Ok(body.clone());
+            }
+            cached.0
+        };
+
+        let code_systems = store.list_code_system_versions().await?;
+        let body = Bytes::from(terminology_capabilities_json(&code_systems).to_string());
+
+        let mut cached = self.terminology_capabilities.write().await;
+        if cached.0 == generation {
+            cached.1 = Some(body.clone());
+        }
+
+        Ok(body)
+    }
+}
+
+fn json_response(body: Bytes) -> Response {
+    ([(header::CONTENT_TYPE, "application/json")], body).into_response()
+}
+
+pub async fn capability_statement(
+    Extension(cache): Extension<CapabilityCache>,
+    Extension(version): Extension<FhirVersion>,
+) -> Response {
+    json_response(cache.capability_statement(version).await)
+}
+
+pub async fn terminology_capabilities(
+    State(store): State<Arc<dyn TerminologyStore>>,
+    Extension(cache): Extension<CapabilityCache>,
+) -> Result<Response, AppError> {
+    Ok(json_response(cache.terminology_capabilities(&store).await?))
+}
+
+fn capability_statement_json(version: FhirVersion) -> Value {
+    json!({
         "resourceType": "CapabilityStatement",
         "status": "active",
         "date": "2025-09-30",
@@ -14,7 +109,7 @@ pub async fn capability_statement() -> Json<Value> {
         "implementation": {
             "description": "FHIR Terminology Service - PostgreSQL backed. Multi-version support: /r4, /r5, /r6"
         },
-        "fhirVersion": "4.0.1",
+        "fhirVersion": version.fhir_version(),
         "format": ["json"],
         "rest": [{
             "mode": "server",
@@ -69,11 +164,34 @@ pub async fn capability_statement() -> Json<Value> {
                 }
             ]
         }]
-    }))
+    })
 }
 
-pub async fn terminology_capabilities() -> Json<Value> {
-    Json(json!({
+/// TerminologyCapabilities listing each installed CodeSystem url with its versions
+fn terminology_capabilities_json(code_systems: &[(String, Option<String>)]) -> Value {
+    let mut versions: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for (url, version) in code_systems {
+        let entry = versions.entry(url).or_default();
+        if let Some(version) = version {
+            entry.push(version);
+        }
+    }
+
+    let code_system: Vec<Value> = versions
+        .into_iter()
+        .map(|(url, versions)| {
+            let mut entry = json!({ "uri": url });
+            if !versions.is_empty() {
+                entry["version"] = versions
+                    .into_iter()
+                    .map(|version| json!({ "code": version }))
+                    .collect();
+            }
+            entry
+        })
+        .collect();
+
+    json!({
         "resourceType": "TerminologyCapabilities",
         "status": "active",
         "date": "2025-09-30",
@@ -82,7 +200,7 @@ pub async fn terminology_capabilities() -> Json<Value> {
             "name": "term-squid",
             "version": env!("CARGO_PKG_VERSION")
         },
-        "codeSystem": [],
+        "codeSystem": code_system,
         "expansion": {
             "hierarchical": false,
             "paging": true
@@ -91,5 +209,5 @@ pub async fn terminology_capabilities() -> Json<Value> {
         "validateCode": {
             "translations": false
         }
-    }))
+    })
 }
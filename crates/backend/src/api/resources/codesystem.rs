@@ -1,30 +1,47 @@
 use axum::{
-    extract::{Path, Query, State},
+    body::{Body, Bytes},
+    extract::{OriginalUri, Path, Query, State},
+    http::header,
+    response::{IntoResponse, Response},
     routing::get,
-    Json, Router,
+    Extension, Json, Router,
 };
+use futures_util::{stream, StreamExt};
 use serde::Deserialize;
 use serde_json::Value;
 use std::sync::Arc;
 use uuid::Uuid;
 
+use super::{check_paging, read_response, self_link, version_headers, ReadQuery, SearchLimits};
 use crate::error::AppError;
-use crate::models::{CodeSystem, SearchParams};
+use crate::models::{Concept, ResourceType, SearchParams};
 use crate::store::TerminologyStore;
 
 pub fn codesystem_routes() -> Router<Arc<dyn TerminologyStore>> {
     Router::new()
         .route("/CodeSystem", get(search_codesystems))
-        .route("/CodeSystem/{id}", get(get_codesystem))
+        .route(
+            "/CodeSystem/{id}",
+            get(get_codesystem).head(head_codesystem),
+        )
+        .route("/CodeSystem/{id}/$everything", get(codesystem_everything))
 }
 
 #[derive(Debug, Deserialize)]
 struct SearchQuery {
+    /// Canonical url, optionally with a piped version (`url|version`)
     url: Option<String>,
+    version: Option<String>,
     name: Option<String>,
     status: Option<String>,
     #[serde(rename = "fhirVersion")]
     fhir_version: Option<String>,
+    #[serde(rename = "_tag")]
+    tag: Option<String>,
+    #[serde(rename = "_profile")]
+    profile: Option<String>,
+    #[serde(rename = "_security")]
+    security: Option<String>,
     #[serde(rename = "_count")]
     count: Option<i64>,
     #[serde(rename = "_offset")]
@@ -35,9 +52,13 @@ impl From<SearchQuery> for SearchParams {
     fn from(query: SearchQuery) -> Self {
         SearchParams {
             url: query.url,
+            version: query.version,
             name: query.name,
             status: query.status,
             fhir_version: query.fhir_version,
+            tag: query.tag,
+            profile: query.profile,
+            security: query.security,
             limit: query.count,
             offset: query.offset,
         }
@@ -47,7 +68,8 @@ impl From<SearchQuery> for SearchParams {
 async fn get_codesystem(
     State(store): State<Arc<dyn TerminologyStore>>,
     Path(id): Path<String>,
-) -> Result<Json<CodeSystem>, AppError> {
+    Query(query): Query<ReadQuery>,
+) -> Result<Response, AppError> {
     // Try to parse as UUID first, otherwise treat as URL
     let code_system = if let Ok(uuid) = Uuid::parse_str(&id) {
         store.get_code_system_by_id(&uuid).await?
@@ -56,17 +78,44 @@ async fn get_codesystem(
     };
 
     code_system
+        .map(|code_system| {
+            let updated_at = code_system.updated_at;
+            read_response(code_system, updated_at, |r| r.content.0, &query)
+        })
+        .ok_or_else(|| AppError::NotFound("CodeSystem not found".to_string()))
+}
+
+/// HEAD /CodeSystem/{id}: the read headers without loading the resource content
+async fn head_codesystem(
+    State(store): State<Arc<dyn TerminologyStore>>,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    let updated_at = if let Ok(uuid) = Uuid::parse_str(&id) {
+        store.exists_by_id(ResourceType::CodeSystem, &uuid).await?
+    } else {
+        store
+            .get_code_system(&id, None)
+            .await?
+            .map(|r| r.updated_at)
+    };
+
+    updated_at
+        .map(version_headers)
         .ok_or_else(|| AppError::NotFound("CodeSystem not found".to_string()))
-        .map(Json)
 }
 
 async fn search_codesystems(
     State(store): State<Arc<dyn TerminologyStore>>,
+    Extension(limits): Extension<SearchLimits>,
+    OriginalUri(uri): OriginalUri,
     Query(query): Query<SearchQuery>,
 ) -> Result<Json<Value>, AppError> {
+    check_paging(query.count, query.offset, limits)?;
+
     // Get total count first
     let total = store.count_code_systems().await?;
 
+    let link = self_link(&uri, query.count, query.offset);
     let params: SearchParams = query.into();
     let results = store.search_code_systems(&params).await?;
 
@@ -74,6 +123,7 @@ async fn search_codesystems(
     let bundle = serde_json::json!({
         "resourceType": "Bundle",
         "type": "searchset",
+        "link": [link],
         "total": total,
         "entry": results.iter().map(|cs| {
             serde_json::json!({
@@ -87,3 +137,109 @@ async fn search_codesystems(
 
     Ok(Json(bundle))
 }
+
+/// Concepts fetched from the store per streamed chunk of `$everything`
+const EVERYTHING_PAGE_SIZE: i64 = 1000;
+
+/// GET /CodeSystem/{id}/$everything
+///
+/// Streams the stored CodeSystem with its `concept` array rebuilt from the concepts
+/// table, so systems imported without inline concepts can still be exported in full.
+async fn codesystem_everything(
+    State(store): State<Arc<dyn TerminologyStore>>,
+    Path(id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    let code_system = store
+        .get_code_system_by_id(&id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("CodeSystem {id} not found")))?;
+
+    let mut content = code_system.content.0;
+    if let Some(object) = content.as_object_mut() {
+        object.remove("concept");
+    }
+
+    // Open the resource object so the concept array can be appended as it is read
+    let mut head = serde_json::to_string(&content).map_err(|e| AppError::Internal(e.into()))?;
+    head.pop();
+    if head.len() > 1 {
+        head.push(',');
+    }
+    head.push_str("\"concept\":[");
+
+    // State: (ordinal and code of the last concept written, no concept written yet, finished)
+    let pages = stream::unfold(
+        (None::<(i32, String)>, true, false),
+        move |(after, first, done)| {
+            let store = store.clone();
+            async move {
+                if done {
+                    return None;
+                }
+
+                let page = match store
+                    .get_concepts_page(
+                        &id,
+                        after
+                            .as_ref()
+                            .map(|(ordinal, code)| (*ordinal, code.as_str())),
+                        EVERYTHING_PAGE_SIZE,
+                    )
+                    .await
+                {
+                    Ok(page) => page,
+                    Err(e) => {
+                        tracing::error!("Failed to stream concepts for CodeSystem {id}: {e}");
+                        return Some((
+                            Err(std::io::Error::other(e.to_string())),
+                            (None, first, true),
+                        ));
+                    }
+                };
+
+                if page.is_empty() {
+                    return Some((Ok(Bytes::from_static(b"]}")), (None, first, true)));
+                }
+
+                let mut chunk = String::new();
+                for (i, concept) in page.iter().enumerate() {
+                    if !(first && i == 0) {
+                        chunk.push(',');
+                    }
+                    chunk.push_str(&concept_json(concept).to_string());
+                }
+
+                let last = page.last().map(|c| (c.ordinal, c.code.clone()));
+                Some((Ok(Bytes::from(chunk)), (last, false, false)))
+            }
+        },
+    );
+
+    let body = stream::iter([Ok::<_, std::io::Error>(Bytes::from(head))]).chain(pages);
+
+    Ok((
+        [(header::CONTENT_TYPE, "application/fhir+json")],
+        Body::from_stream(body),
+    )
+        .into_response())
+}
+
+/// Rebuild a CodeSystem `concept` entry from its stored row
+fn concept_json(concept: &Concept) -> Value {
+    let mut json = serde_json::json!({ "code": concept.code });
+
+    if let Some(display) = &concept.display {
+        json["display"] = Value::String(display.clone());
+    }
+    if let Some(definition) = &concept.definition {
+        json["definition"] = Value::String(definition.clone());
+    }
+    if let Some(properties) = concept.properties.as_ref().filter(|p| p.0.is_array()) {
+        json["property"] = properties.0.clone();
+    }
+    if let Some(designations) = &concept.designations {
+        json["designation"] = designations.0.clone();
+    }
+
+    json
+}
@@ -1,29 +1,42 @@
 use axum::{
-    extract::{Path, Query, State},
+    extract::{OriginalUri, Path, Query, State},
+    response::{IntoResponse, Response},
     routing::get,
-    Json, Router,
+    Extension, Json, Router,
 };
 use serde::Deserialize;
 use serde_json::Value;
 use std::sync::Arc;
 use uuid::Uuid;
 
+use super::{check_paging, read_response, self_link, version_headers, ReadQuery, SearchLimits};
 use crate::error::AppError;
-use crate::models::{ConceptMap, SearchParams};
+use crate::models::{ResourceType, SearchParams};
 use crate::store::TerminologyStore;
 
 pub fn conceptmap_routes() -> Router<Arc<dyn TerminologyStore>> {
     Router::new()
         .route("/ConceptMap", get(search_conceptmaps))
-        .route("/ConceptMap/{id}", get(get_conceptmap))
+        .route(
+            "/ConceptMap/{id}",
+            get(get_conceptmap).head(head_conceptmap),
+        )
 }
 
 #[derive(Debug, Deserialize)]
 struct SearchQuery {
+    /// Canonical url, optionally with a piped version (`url|version`)
     url: Option<String>,
+    version: Option<String>,
     status: Option<String>,
     #[serde(rename = "fhirVersion")]
     fhir_version: Option<String>,
+    #[serde(rename = "_tag")]
+    tag: Option<String>,
+    #[serde(rename = "_profile")]
+    profile: Option<String>,
+    #[serde(rename = "_security")]
+    security: Option<String>,
     #[serde(rename = "_count")]
     count: Option<i64>,
     #[serde(rename = "_offset")]
@@ -34,9 +47,13 @@ impl From<SearchQuery> for SearchParams {
     fn from(query: SearchQuery) -> Self {
         SearchParams {
             url: query.url,
+            version: query.version,
             name: None,
             status: query.status,
             fhir_version: query.fhir_version,
+            tag: query.tag,
+            profile: query.profile,
+            security: query.security,
             limit: query.count,
             offset: query.offset,
         }
@@ -46,7 +63,8 @@ impl From<SearchQuery> for SearchParams {
 async fn get_conceptmap(
     State(store): State<Arc<dyn TerminologyStore>>,
     Path(id): Path<String>,
-) -> Result<Json<ConceptMap>, AppError> {
+    Query(query): Query<ReadQuery>,
+) -> Result<Response, AppError> {
     let concept_map = if let Ok(uuid) = Uuid::parse_str(&id) {
         store.get_concept_map_by_id(&uuid).await?
     } else {
@@ -54,23 +72,51 @@ async fn get_conceptmap(
     };
 
     concept_map
+        .map(|concept_map| {
+            let updated_at = concept_map.updated_at;
+            read_response(concept_map, updated_at, |r| r.content.0, &query)
+        })
+        .ok_or_else(|| AppError::NotFound("ConceptMap not found".to_string()))
+}
+
+/// HEAD /ConceptMap/{id}: the read headers without loading the resource content
+async fn head_conceptmap(
+    State(store): State<Arc<dyn TerminologyStore>>,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    let updated_at = if let Ok(uuid) = Uuid::parse_str(&id) {
+        store.exists_by_id(ResourceType::ConceptMap, &uuid).await?
+    } else {
+        store
+            .get_concept_map(&id, None)
+            .await?
+            .map(|r| r.updated_at)
+    };
+
+    updated_at
+        .map(version_headers)
         .ok_or_else(|| AppError::NotFound("ConceptMap not found".to_string()))
-        .map(Json)
 }
 
 async fn search_conceptmaps(
     State(store): State<Arc<dyn TerminologyStore>>,
+    Extension(limits): Extension<SearchLimits>,
+    OriginalUri(uri): OriginalUri,
     Query(query): Query<SearchQuery>,
 ) -> Result<Json<Value>, AppError> {
+    check_paging(query.count, query.offset, limits)?;
+
     // Get total count first
     let total = store.count_concept_maps().await?;
 
+    let link = self_link(&uri, query.count, query.offset);
     let params: SearchParams = query.into();
     let results = store.search_concept_maps(&params).await?;
 
     let bundle = serde_json::json!({
         "resourceType": "Bundle",
         "type": "searchset",
+        "link": [link],
         "total": total,
         "entry": results.iter().map(|cm| {
             serde_json::json!({
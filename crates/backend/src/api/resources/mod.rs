@@ -3,7 +3,106 @@ pub mod codesystem;
 pub mod conceptmap;
 pub mod valueset;
 
-pub use capability::{capability_statement, terminology_capabilities};
+pub use capability::{capability_statement, terminology_capabilities, CapabilityCache};
 pub use codesystem::codesystem_routes;
 pub use conceptmap::conceptmap_routes;
 pub use valueset::valueset_routes;
+
+use axum::http::{header, HeaderName, HeaderValue, Uri};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::{Deserialize, Serialize};
+
+use crate::error::AppError;
+
+/// `ETag` and `Last-Modified` headers for a stored resource, shared by reads and `HEAD`
+fn version_headers(updated_at: chrono::DateTime<chrono::Utc>) -> [(HeaderName, HeaderValue); 2] {
+    let etag = format!("W/\"{}\"", updated_at.timestamp_micros());
+    let last_modified = updated_at.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+    [
+        (
+            header::ETAG,
+            HeaderValue::from_str(&etag).expect("numeric ETag is a valid header value"),
+        ),
+        (
+            header::LAST_MODIFIED,
+            HeaderValue::from_str(&last_modified).expect("HTTP date is a valid header value"),
+        ),
+    ]
+}
+
+/// Query parameters of a read by id
+#[derive(Debug, Deserialize)]
+pub struct ReadQuery {
+    /// Return the resource JSON exactly as stored instead of the stored record
+    #[serde(rename = "_raw")]
+    raw: Option<bool>,
+}
+
+/// The response to a read: the stored record with its columns, or with `_raw=true` only
+/// the untouched resource content
+fn read_response<T: Serialize>(
+    record: T,
+    updated_at: chrono::DateTime<chrono::Utc>,
+    content: impl FnOnce(T) -> serde_json::Value,
+    query: &ReadQuery,
+) -> Response {
+    let headers = version_headers(updated_at);
+    if query.raw.unwrap_or(false) {
+        (headers, Json(content(record))).into_response()
+    } else {
+        (headers, Json(record)).into_response()
+    }
+}
+
+/// Bounds applied to resource search paging parameters
+#[derive(Debug, Clone, Copy)]
+pub struct SearchLimits {
+    /// Largest accepted `_count`
+    pub max_count: i64,
+}
+
+/// The searchset Bundle's `self` link: the request with its paging replaced by the
+/// effective `_count` (absent when the search is unlimited) and `_offset`
+fn self_link(uri: &Uri, count: Option<i64>, offset: Option<i64>) -> serde_json::Value {
+    let mut query: Vec<String> = uri
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .filter(|pair| !matches!(pair.split('=').next(), Some("_count" | "_offset")))
+        .map(str::to_string)
+        .collect();
+    if let Some(count) = count {
+        query.push(format!("_count={count}"));
+    }
+    query.push(format!("_offset={}", offset.unwrap_or(0)));
+
+    serde_json::json!({
+        "relation": "self",
+        "url": format!("{}?{}", uri.path(), query.join("&"))
+    })
+}
+
+/// Reject a negative or oversized `_count` and a negative `_offset`
+fn check_paging(
+    count: Option<i64>,
+    offset: Option<i64>,
+    limits: SearchLimits,
+) -> Result<(), AppError> {
+    if let Some(count) = count {
+        if !(0..=limits.max_count).contains(&count) {
+            return Err(AppError::BadRequest(format!(
+                "_count must be between 0 and {}, got {count}",
+                limits.max_count
+            )));
+        }
+    }
+    if let Some(offset) = offset.filter(|o| *o < 0) {
+        return Err(AppError::BadRequest(format!(
+            "_offset must not be negative, got {offset}"
+        )));
+    }
+
+    Ok(())
+}
@@ -1,30 +1,40 @@
 use axum::{
-    extract::{Path, Query, State},
+    extract::{OriginalUri, Path, Query, State},
+    response::{IntoResponse, Response},
     routing::get,
-    Json, Router,
+    Extension, Json, Router,
 };
 use serde::Deserialize;
 use serde_json::Value;
 use std::sync::Arc;
 use uuid::Uuid;
 
+use super::{check_paging, read_response, self_link, version_headers, ReadQuery, SearchLimits};
 use crate::error::AppError;
-use crate::models::{SearchParams, ValueSet};
+use crate::models::{ResourceType, SearchParams};
 use crate::store::TerminologyStore;
 
 pub fn valueset_routes() -> Router<Arc<dyn TerminologyStore>> {
     Router::new()
         .route("/ValueSet", get(search_valuesets))
-        .route("/ValueSet/{id}", get(get_valueset))
+        .route("/ValueSet/{id}", get(get_valueset).head(head_valueset))
 }
 
 #[derive(Debug, Deserialize)]
 struct SearchQuery {
+    /// Canonical url, optionally with a piped version (`url|version`)
     url: Option<String>,
+    version: Option<String>,
     name: Option<String>,
     status: Option<String>,
     #[serde(rename = "fhirVersion")]
     fhir_version: Option<String>,
+    #[serde(rename = "_tag")]
+    tag: Option<String>,
+    #[serde(rename = "_profile")]
+    profile: Option<String>,
+    #[serde(rename = "_security")]
+    security: Option<String>,
     #[serde(rename = "_count")]
     count: Option<i64>,
     #[serde(rename = "_offset")]
@@ -35,9 +45,13 @@ impl From<SearchQuery> for SearchParams {
     fn from(query: SearchQuery) -> Self {
         SearchParams {
             url: query.url,
+            version: query.version,
             name: query.name,
             status: query.status,
             fhir_version: query.fhir_version,
+            tag: query.tag,
+            profile: query.profile,
+            security: query.security,
             limit: query.count,
             offset: query.offset,
         }
@@ -47,7 +61,8 @@ impl From<SearchQuery> for SearchParams {
 async fn get_valueset(
     State(store): State<Arc<dyn TerminologyStore>>,
     Path(id): Path<String>,
-) -> Result<Json<ValueSet>, AppError> {
+    Query(query): Query<ReadQuery>,
+) -> Result<Response, AppError> {
     let value_set = if let Ok(uuid) = Uuid::parse_str(&id) {
         store.get_value_set_by_id(&uuid).await?
     } else {
@@ -55,23 +70,48 @@ async fn get_valueset(
     };
 
     value_set
+        .map(|value_set| {
+            let updated_at = value_set.updated_at;
+            read_response(value_set, updated_at, |r| r.content.0, &query)
+        })
+        .ok_or_else(|| AppError::NotFound("ValueSet not found".to_string()))
+}
+
+/// HEAD /ValueSet/{id}: the read headers without loading the resource content
+async fn head_valueset(
+    State(store): State<Arc<dyn TerminologyStore>>,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    let updated_at = if let Ok(uuid) = Uuid::parse_str(&id) {
+        store.exists_by_id(ResourceType::ValueSet, &uuid).await?
+    } else {
+        store.get_value_set(&id, None).await?.map(|r| r.updated_at)
+    };
+
+    updated_at
+        .map(version_headers)
         .ok_or_else(|| AppError::NotFound("ValueSet not found".to_string()))
-        .map(Json)
 }
 
 async fn search_valuesets(
     State(store): State<Arc<dyn TerminologyStore>>,
+    Extension(limits): Extension<SearchLimits>,
+    OriginalUri(uri): OriginalUri,
     Query(query): Query<SearchQuery>,
 ) -> Result<Json<Value>, AppError> {
+    check_paging(query.count, query.offset, limits)?;
+
     // Get total count first
     let total = store.count_value_sets().await?;
 
+    let link = self_link(&uri, query.count, query.offset);
     let params: SearchParams = query.into();
     let results = store.search_value_sets(&params).await?;
 
     let bundle = serde_json::json!({
         "resourceType": "Bundle",
         "type": "searchset",
+        "link": [link],
         "total": total,
         "entry": results.iter().map(|vs| {
             serde_json::json!({
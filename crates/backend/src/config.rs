@@ -5,6 +5,32 @@ pub struct Config {
     pub database_url: String,
     pub server_host: String,
     pub server_port: u16,
+    /// Bearer token required by admin endpoints; admin endpoints are disabled when unset
+    pub admin_token: Option<String>,
+    /// `max-age` in seconds for cacheable operation results on pinned versions
+    pub cache_max_age: u64,
+    /// Display language for `$lookup`, `$expand` and `$validate-code` when the request
+    /// gives neither a `displayLanguage` parameter nor an `Accept-Language` header
+    pub default_display_language: Option<String>,
+    /// Largest `_count` accepted by resource searches
+    pub max_search_count: i64,
+    /// Sustained operation requests per second allowed per client; `0` disables the limit
+    pub rate_limit_per_second: u32,
+    /// Operation requests a client may burst above the sustained rate
+    pub rate_limit_burst: u32,
+    /// Log request and response bodies of API routes at DEBUG
+    pub log_bodies: bool,
+    /// Statement timeout in milliseconds for `$subsumes` closure table queries; on
+    /// timeout the hierarchy is walked through its is-a properties instead. `0` disables it
+    pub closure_query_timeout_ms: u64,
+    /// Largest CodeSystem whose concepts are counted for a paged `$expand` total; larger
+    /// ones omit `total`. `0` always counts
+    pub expand_total_limit: i64,
+    /// Match canonical URLs exactly as given instead of also trying their normalized form
+    pub strict_canonicals: bool,
+    /// Most concepts held by the in-memory cache of computed `$expand` results, counted
+    /// over all cached expansions. `0` disables the cache
+    pub expansion_cache_concepts: usize,
 }
 
 impl Config {
@@ -19,6 +45,37 @@ impl Config {
             server_port: std::env::var("SERVER_PORT")
                 .unwrap_or_else(|_| "8081".to_string())
                 .parse()?,
+            admin_token: std::env::var("ADMIN_TOKEN").ok().filter(|t| !t.is_empty()),
+            cache_max_age: std::env::var("CACHE_MAX_AGE")
+                .unwrap_or_else(|_| "3600".to_string())
+                .parse()?,
+            default_display_language: std::env::var("DEFAULT_DISPLAY_LANGUAGE")
+                .ok()
+                .filter(|l| !l.is_empty()),
+            max_search_count: std::env::var("MAX_SEARCH_COUNT")
+                .unwrap_or_else(|_| "1000".to_string())
+                .parse()?,
+            rate_limit_per_second: std::env::var("RATE_LIMIT_PER_SECOND")
+                .unwrap_or_else(|_| "50".to_string())
+                .parse()?,
+            rate_limit_burst: std::env::var("RATE_LIMIT_BURST")
+                .unwrap_or_else(|_| "100".to_string())
+                .parse()?,
+            log_bodies: std::env::var("LOG_BODIES")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()?,
+            closure_query_timeout_ms: std::env::var("CLOSURE_QUERY_TIMEOUT_MS")
+                .unwrap_or_else(|_| "2000".to_string())
+                .parse()?,
+            expand_total_limit: std::env::var("EXPAND_TOTAL_LIMIT")
+                .unwrap_or_else(|_| "1000000".to_string())
+                .parse()?,
+            strict_canonicals: std::env::var("STRICT_CANONICALS")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()?,
+            expansion_cache_concepts: std::env::var("EXPANSION_CACHE_CONCEPTS")
+                .unwrap_or_else(|_| "500000".to_string())
+                .parse()?,
         };
 
         Ok(config)
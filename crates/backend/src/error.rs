@@ -1,5 +1,5 @@
 use axum::{
-    http::StatusCode,
+    http::{header, HeaderValue, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
@@ -18,28 +18,176 @@ pub enum AppError {
 
     #[error("Bad request: {0}")]
     BadRequest(String),
+
+    /// Every problem found with an operation's input parameters, reported together
+    #[error("Invalid parameters: {}", .0.join("; "))]
+    InvalidParameters(Vec<String>),
+
+    /// The CodeSystem an operation refers to is not known to the server
+    #[error("CodeSystem '{0}' not found")]
+    UnknownCodeSystem(String),
+
+    /// The CodeSystem is known but does not define the code
+    #[error("Code '{code}' not found in system '{system}'")]
+    UnknownCode { system: String, code: String },
+
+    /// The client exceeded its rate limit; retry after the given number of seconds
+    #[error("Too many requests, retry after {0}s")]
+    TooManyRequests(u64),
+
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
 }
 
+/// System of the stable error codes in `issue.details.coding` of error OperationOutcomes
+pub const ERROR_CODE_SYSTEM: &str = "https://github.com/octofhir/term-squid/CodeSystem/error-code";
+
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        let (status, error_message) = match self {
-            AppError::Database(ref e) => {
+        // The issue type is FHIR's; the details coding is a stable code for clients to
+        // branch on, and the diagnostics are for people
+        let (status, issue_type, code) = match &self {
+            AppError::Database(e) => {
                 tracing::error!("Database error: {:?}", e);
-                (StatusCode::INTERNAL_SERVER_ERROR, "Database error")
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "exception",
+                    "database-error",
+                )
             }
-            AppError::Internal(ref e) => {
+            AppError::Internal(e) => {
                 tracing::error!("Internal error: {:?}", e);
-                (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+                (StatusCode::INTERNAL_SERVER_ERROR, "exception", "internal")
             }
-            AppError::NotFound(_) => (StatusCode::NOT_FOUND, "Resource not found"),
-            AppError::BadRequest(_) => (StatusCode::BAD_REQUEST, "Bad request"),
+            AppError::NotFound(_) => (StatusCode::NOT_FOUND, "not-found", "not-found"),
+            AppError::UnknownCodeSystem(_) => {
+                (StatusCode::NOT_FOUND, "not-found", "unknown-code-system")
+            }
+            AppError::UnknownCode { .. } => (StatusCode::NOT_FOUND, "not-found", "unknown-code"),
+            AppError::BadRequest(_) => (StatusCode::BAD_REQUEST, "invalid", "bad-request"),
+            AppError::InvalidParameters(_) => {
+                (StatusCode::BAD_REQUEST, "invalid", "invalid-parameters")
+            }
+            AppError::Unauthorized(_) => (StatusCode::UNAUTHORIZED, "login", "unauthorized"),
+            AppError::Forbidden(_) => (StatusCode::FORBIDDEN, "forbidden", "forbidden"),
+            AppError::TooManyRequests(_) => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "throttled",
+                "too-many-requests",
+            ),
+        };
+
+        // Unknown systems and codes are also told apart by a tx-issue-type and expression
+        let tx_issue = match &self {
+            AppError::UnknownCodeSystem(_) => Some(("not-found", "system")),
+            AppError::UnknownCode { .. } => Some(("invalid-code", "code")),
+            _ => None,
+        };
+
+        // One issue per problem with the input parameters, a single one otherwise
+        let problems = match &self {
+            AppError::InvalidParameters(problems) => problems.clone(),
+            other => vec![other.to_string()],
         };
+        let issues: Vec<_> = problems
+            .iter()
+            .map(|problem| {
+                let mut coding = vec![json!({ "system": ERROR_CODE_SYSTEM, "code": code })];
+                let mut issue = json!({
+                    "severity": "error",
+                    "code": issue_type,
+                    "diagnostics": problem
+                });
+                if let Some((tx_issue_type, expression)) = tx_issue {
+                    coding.push(json!({
+                        "system": "http://hl7.org/fhir/tools/CodeSystem/tx-issue-type",
+                        "code": tx_issue_type
+                    }));
+                    issue["expression"] = json!([expression]);
+                }
+                issue["details"] = json!({ "coding": coding, "text": problem });
+                issue
+            })
+            .collect();
 
-        let body = Json(json!({
-            "error": error_message,
-            "message": self.to_string(),
-        }));
+        let body = Json(json!({ "resourceType": "OperationOutcome", "issue": issues }));
+        let mut response = (status, body).into_response();
+        if let AppError::TooManyRequests(retry_after) = self {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::BodyExt;
+
+    async fn outcome(error: AppError) -> (StatusCode, serde_json::Value) {
+        let response = error.into_response();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn every_variant_carries_an_error_code_and_diagnostics() {
+        let errors = [
+            AppError::NotFound("x".into()),
+            AppError::BadRequest("x".into()),
+            AppError::InvalidParameters(vec!["x".into()]),
+            AppError::UnknownCodeSystem("http://example.org".into()),
+            AppError::UnknownCode {
+                system: "http://example.org".into(),
+                code: "x".into(),
+            },
+            AppError::TooManyRequests(3),
+            AppError::Unauthorized("x".into()),
+            AppError::Forbidden("x".into()),
+        ];
+        for error in errors {
+            let (_, body) = outcome(error).await;
+            let issue = &body["issue"][0];
+            assert_eq!(issue["details"]["coding"][0]["system"], ERROR_CODE_SYSTEM);
+            assert!(issue["diagnostics"].is_string(), "{body}");
+        }
+    }
+
+    #[tokio::test]
+    async fn invalid_parameters_report_one_issue_each() {
+        let error = AppError::InvalidParameters(vec!["a".into(), "b".into()]);
+        let (status, body) = outcome(error).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["issue"].as_array().unwrap().len(), 2);
+        assert_eq!(body["issue"][1]["details"]["text"], "b");
+        assert_eq!(
+            body["issue"][1]["details"]["coding"][0]["code"],
+            "invalid-parameters"
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_code_keeps_the_tx_issue_type() {
+        let error = AppError::UnknownCode {
+            system: "http://example.org".into(),
+            code: "x".into(),
+        };
+        let (status, body) = outcome(error).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let issue = &body["issue"][0];
+        assert_eq!(issue["details"]["coding"][1]["code"], "invalid-code");
+        assert_eq!(issue["expression"][0], "code");
+    }
 
-        (status, body).into_response()
+    #[test]
+    fn too_many_requests_sets_retry_after() {
+        let response = AppError::TooManyRequests(7).into_response();
+        assert_eq!(response.headers()[header::RETRY_AFTER], "7");
     }
 }
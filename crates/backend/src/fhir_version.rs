@@ -0,0 +1,87 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// A FHIR release served under its own base URL (`/r4`, `/r5`, `/r6`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FhirVersion {
+    R4,
+    R5,
+    R6,
+}
+
+impl FhirVersion {
+    /// Every served release, in mount order
+    pub const ALL: [FhirVersion; 3] = [FhirVersion::R4, FhirVersion::R5, FhirVersion::R6];
+
+    /// The release for a base URL segment such as `r4` (case-insensitive)
+    pub fn from_path_prefix(prefix: &str) -> Option<Self> {
+        let prefix = prefix.trim_start_matches('/');
+        Self::ALL
+            .into_iter()
+            .find(|version| version.path_prefix()[1..].eq_ignore_ascii_case(prefix))
+    }
+
+    /// The release for a `fhirVersion` such as `4.0.1` or `6.0.0-ballot2`, by major version
+    pub fn from_semver(version: &str) -> Option<Self> {
+        let major = version.split(['.', '-']).next()?;
+        Self::ALL
+            .into_iter()
+            .find(|candidate| candidate.major().to_string() == major)
+    }
+
+    /// The base URL the release is mounted under
+    pub fn path_prefix(self) -> &'static str {
+        match self {
+            FhirVersion::R4 => "/r4",
+            FhirVersion::R5 => "/r5",
+            FhirVersion::R6 => "/r6",
+        }
+    }
+
+    /// The `fhirVersion` reported in capability documents
+    pub fn fhir_version(self) -> &'static str {
+        match self {
+            FhirVersion::R4 => "4.0.1",
+            FhirVersion::R5 => "5.0.0",
+            FhirVersion::R6 => "6.0.0-ballot2",
+        }
+    }
+
+    pub fn major(self) -> u32 {
+        match self {
+            FhirVersion::R4 => 4,
+            FhirVersion::R5 => 5,
+            FhirVersion::R6 => 6,
+        }
+    }
+
+    /// The `$translate` match part carrying the mapping strength: R4 `equivalence`,
+    /// R5 onwards `relationship`
+    pub fn relationship_element(self) -> &'static str {
+        match self {
+            FhirVersion::R4 => "equivalence",
+            FhirVersion::R5 | FhirVersion::R6 => "relationship",
+        }
+    }
+}
+
+impl fmt::Display for FhirVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FhirVersion::R4 => "R4",
+            FhirVersion::R5 => "R5",
+            FhirVersion::R6 => "R6",
+        })
+    }
+}
+
+/// Parses a release name or path prefix (`R4`, `r5`) or a semver `fhirVersion` (`4.0.1`)
+impl FromStr for FhirVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_path_prefix(s)
+            .or_else(|| Self::from_semver(s))
+            .ok_or_else(|| format!("Unknown FHIR version '{s}'"))
+    }
+}
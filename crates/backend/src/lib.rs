@@ -0,0 +1,11 @@
+//! The term-squid terminology server: the HTTP API and its PostgreSQL store. The `backend`
+//! binary serves it; the CLI uses the store and the
+//! terminology operations directly.
+
+pub mod api;
+pub mod config;
+pub mod error;
+pub mod fhir_version;
+pub mod models;
+pub mod store;
+pub mod terminology;
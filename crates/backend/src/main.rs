@@ -1,20 +1,18 @@
-mod api;
-mod config;
-mod error;
-mod models;
-mod store;
-
 use anyhow::Result;
 use axum::{
     body::Body,
     http::{header, StatusCode, Uri},
     response::{IntoResponse, Response},
 };
-use config::Config;
 use rust_embed::Embed;
-use sqlx::postgres::PgPoolOptions;
+use sqlx::postgres::{PgListener, PgPoolOptions};
+use std::net::SocketAddr;
 use std::sync::Arc;
-use store::{PostgresStore, TerminologyStore};
+use std::time::Duration;
+use term_squid_backend::api;
+use term_squid_backend::config::Config;
+use term_squid_backend::store::{PostgresStore, TerminologyStore};
+use term_squid_backend::terminology::ExpansionCache;
 use tower_http::{compression::CompressionLayer, cors::CorsLayer, trace::TraceLayer};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
@@ -50,27 +48,110 @@ async fn main() -> Result<()> {
     sqlx::query("SELECT 1").execute(&pool).await?;
     tracing::info!("Database connection verified");
 
+    // Listen for CodeSystem and ValueSet changes before warming the caches so none are
+    // missed. The listener holds a connection of its own rather than one of the pool's.
+    let mut changes = PgListener::connect(&config.database_url).await?;
+    changes
+        .listen_all([
+            api::CODE_SYSTEMS_CHANGED_CHANNEL,
+            api::VALUE_SETS_CHANGED_CHANNEL,
+        ])
+        .await?;
+
     // Create store
-    let store: Arc<dyn TerminologyStore> = Arc::new(PostgresStore::new(pool));
+    let store: Arc<dyn TerminologyStore> = Arc::new(
+        PostgresStore::new(pool)
+            .with_closure_timeout(config.closure_query_timeout_ms)
+            .with_count_limit(config.expand_total_limit)
+            .with_strict_canonicals(config.strict_canonicals),
+    );
     tracing::info!("PostgreSQL store initialized");
 
+    // Refuse to start against a database that is missing tables or migrations
+    let problems = store.check_schema().await?;
+    if !problems.is_empty() {
+        for problem in &problems {
+            tracing::error!("Database schema problem: {}", problem);
+        }
+        anyhow::bail!(
+            "Database schema is incomplete ({}); run `just db-migrate`",
+            problems.join("; ")
+        );
+    }
+    tracing::info!("Database schema verified");
+
+    // Preload capability documents and keep them and the cached expansions in step with
+    // the installed CodeSystems and ValueSets
+    let capabilities = api::CapabilityCache::default();
+    capabilities.warm(&store).await?;
+    let expansions = ExpansionCache::new(config.expansion_cache_concepts);
+    tokio::spawn(invalidate_on_change(
+        changes,
+        capabilities.clone(),
+        expansions.clone(),
+    ));
+    tracing::info!("Capability caches warmed");
+
     // Build application router with embedded static files
-    let app = api::create_router(store).fallback(static_handler).layer(
-        tower::ServiceBuilder::new()
-            .layer(TraceLayer::new_for_http())
-            .layer(CompressionLayer::new())
-            .layer(CorsLayer::permissive()),
-    );
+    let app = api::create_router(store, &config, capabilities, expansions)
+        .fallback(static_handler)
+        .layer(
+            tower::ServiceBuilder::new()
+                .layer(TraceLayer::new_for_http())
+                .layer(CompressionLayer::new())
+                .layer(CorsLayer::permissive()),
+        );
 
     // Start server
     let listener = tokio::net::TcpListener::bind(&config.bind_address()).await?;
     tracing::info!("Server listening on {}", config.bind_address());
 
-    axum::serve(listener, app).await?;
+    // Connection info lets the rate limiter key clients by peer IP
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
 
     Ok(())
 }
 
+/// First wait before the change listener reconnects after an error; doubled on each
+/// further error up to [`MAX_LISTEN_RETRY_DELAY`]
+const LISTEN_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+const MAX_LISTEN_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Invalidate the caches made stale by each change notification, see
+/// [`api::invalidate_caches`]. Reconnects back off exponentially while the database stays
+/// unreachable.
+async fn invalidate_on_change(
+    mut listener: PgListener,
+    capabilities: api::CapabilityCache,
+    expansions: ExpansionCache,
+) {
+    let mut retry_delay = LISTEN_RETRY_DELAY;
+    loop {
+        match listener.recv().await {
+            Ok(notification) => {
+                retry_delay = LISTEN_RETRY_DELAY;
+                api::invalidate_caches(Some(notification.channel()), &capabilities, &expansions)
+                    .await;
+            }
+            Err(e) => {
+                tracing::warn!(
+                    "Change listener error, retrying in {:?}: {}",
+                    retry_delay,
+                    e
+                );
+                api::invalidate_caches(None, &capabilities, &expansions).await;
+                tokio::time::sleep(retry_delay).await;
+                retry_delay = (retry_delay * 2).min(MAX_LISTEN_RETRY_DELAY);
+            }
+        }
+    }
+}
+
 // Handler for serving embedded static files
 async fn static_handler(uri: Uri) -> impl IntoResponse {
     let path = uri.path().trim_start_matches('/');
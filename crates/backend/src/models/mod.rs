@@ -55,16 +55,173 @@ pub struct Concept {
     pub display: Option<String>,
     pub definition: Option<String>,
     pub properties: Option<Json<serde_json::Value>>,
+    pub designations: Option<Json<serde_json::Value>>,
+    /// Position of the concept in its CodeSystem's (flattened) concept list
+    pub ordinal: i32,
     pub created_at: chrono::DateTime<chrono::Utc>,
 }
 
+/// The latest stored (precomputed or imported) expansion of a ValueSet
+#[derive(Debug, Clone)]
+pub struct StoredExpansion {
+    pub contains: Vec<serde_json::Value>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// How current a ValueSet's stored expansion is
+#[derive(Debug, Clone)]
+pub struct ExpansionStatus {
+    pub computed_at: chrono::DateTime<chrono::Utc>,
+    /// `(url, version)` of the CodeSystems the ValueSet references, directly or through
+    /// included ValueSets, that were updated after the expansion was computed
+    pub newer_code_systems: Vec<(String, Option<String>)>,
+}
+
+/// How a `$find-matches` property condition compares a concept's property value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PropertyComparator {
+    Eq,
+    Gt,
+    Lt,
+    Ge,
+    Le,
+}
+
+impl PropertyComparator {
+    /// The comparator for a `comparator` code (`eq`, `gt`, `lt`, `ge`, `le`)
+    pub fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "eq" => Some(Self::Eq),
+            "gt" => Some(Self::Gt),
+            "lt" => Some(Self::Lt),
+            "ge" => Some(Self::Ge),
+            "le" => Some(Self::Le),
+            _ => None,
+        }
+    }
+
+    pub fn sql(self) -> &'static str {
+        match self {
+            Self::Eq => "=",
+            Self::Gt => ">",
+            Self::Lt => "<",
+            Self::Ge => ">=",
+            Self::Le => "<=",
+        }
+    }
+}
+
+/// What a property condition compares: the text of a `valueCode`, `valueString`,
+/// `valueBoolean` or `valueCoding.code`, a `valueInteger`/`valueDecimal` number, or a
+/// `valueDateTime`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PropertyValueKind {
+    Text,
+    Number,
+    DateTime,
+}
+
+/// A condition on a concept property, matched against `concepts.properties`
+#[derive(Debug, Clone)]
+pub struct PropertyFilter {
+    pub code: String,
+    pub kind: PropertyValueKind,
+    pub comparator: PropertyComparator,
+    pub value: String,
+}
+
+/// A hierarchy filter operator of a ValueSet `compose` filter on the `concept` property,
+/// evaluated against the closure table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HierarchyOperator {
+    /// The concept and its descendants
+    IsA,
+    /// The concept's descendants, without the concept itself
+    DescendentOf,
+    /// Every concept that is not the concept or one of its descendants
+    IsNotA,
+    /// The concept and its ancestors
+    Generalizes,
+}
+
+impl HierarchyOperator {
+    /// The operator for a filter `op` code (`is-a`, `descendent-of`, `is-not-a`,
+    /// `generalizes`)
+    pub fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "is-a" => Some(Self::IsA),
+            "descendent-of" => Some(Self::DescendentOf),
+            "is-not-a" => Some(Self::IsNotA),
+            "generalizes" => Some(Self::Generalizes),
+            _ => None,
+        }
+    }
+}
+
+/// A package import run recorded by the CLI
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct ImportRecord {
+    pub package_name: String,
+    pub package_version: String,
+    pub registry: Option<String>,
+    pub resources: i32,
+    pub imported: i32,
+    pub skipped: i32,
+    pub errors: i32,
+    pub dry_run: bool,
+    pub imported_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// How many closure table rows back subsumption testing for a CodeSystem
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct ClosureCoverage {
+    pub code_system_id: Uuid,
+    pub url: String,
+    pub version: Option<String>,
+    pub closure_rows: i64,
+}
+
+/// The canonical resource types held in their own tables
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResourceType {
+    CodeSystem,
+    ValueSet,
+    ConceptMap,
+}
+
+impl ResourceType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::CodeSystem => "CodeSystem",
+            Self::ValueSet => "ValueSet",
+            Self::ConceptMap => "ConceptMap",
+        }
+    }
+
+    pub fn table(&self) -> &'static str {
+        match self {
+            Self::CodeSystem => "code_systems",
+            Self::ValueSet => "value_sets",
+            Self::ConceptMap => "concept_maps",
+        }
+    }
+}
+
 // Search parameters
 #[derive(Debug, Default, Clone)]
 pub struct SearchParams {
+    /// Canonical url; a piped version (`url|version`) takes precedence over `version`
     pub url: Option<String>,
+    pub version: Option<String>,
     pub name: Option<String>,
     pub status: Option<String>,
     pub fhir_version: Option<String>,
+    /// `_tag` token (`system|code`, `system|`, `|code` or `code`) matched against `meta.tag`
+    pub tag: Option<String>,
+    /// `_profile` canonical matched against `meta.profile`
+    pub profile: Option<String>,
+    /// `_security` token matched against `meta.security`
+    pub security: Option<String>,
     pub limit: Option<i64>,
     pub offset: Option<i64>,
 }
@@ -0,0 +1,18 @@
+/// Normalize a canonical URL so that trivially different spellings match: trailing slashes
+/// are removed and the scheme and host lowercased, while the path, query and fragment keep
+/// their case. Canonicals without an authority (`urn:oid:...`) only lose trailing slashes.
+pub fn normalize_canonical(url: &str) -> String {
+    let trimmed = url.trim_end_matches('/');
+    let Some((scheme, rest)) = trimmed.split_once("://") else {
+        return trimmed.to_string();
+    };
+
+    let authority_end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    let (authority, tail) = rest.split_at(authority_end);
+    format!(
+        "{}://{}{}",
+        scheme.to_ascii_lowercase(),
+        authority.to_ascii_lowercase(),
+        tail
+    )
+}
@@ -0,0 +1,54 @@
+use serde_json::Value;
+use std::collections::HashSet;
+
+/// The FHIR concept property whose values are a concept's parents
+const PARENT_PROPERTY_URI: &str = "http://hl7.org/fhir/concept-properties#parent";
+
+/// Is-a property codes of systems that do not declare theirs with [`PARENT_PROPERTY_URI`]
+const DEFAULT_HIERARCHY_PROPERTIES: [(&str, &str); 1] = [
+    // SNOMED CT 116680003 |Is a|
+    ("http://snomed.info/sct", "116680003"),
+];
+
+/// The property codes naming a concept's parents in a CodeSystem stored under `url`:
+/// `parent`, `subsumedBy`, any property declared with the FHIR parent property uri, and the
+/// system's entry in [`DEFAULT_HIERARCHY_PROPERTIES`]
+pub fn hierarchy_properties<'a>(url: &str, code_system: &'a Value) -> HashSet<&'a str> {
+    let mut codes: HashSet<&str> = code_system
+        .get("property")
+        .and_then(|p| p.as_array())
+        .into_iter()
+        .flatten()
+        .filter(|p| p.get("uri").and_then(|u| u.as_str()) == Some(PARENT_PROPERTY_URI))
+        .filter_map(|p| p.get("code")?.as_str())
+        .collect();
+    codes.extend(["parent", "subsumedBy"]);
+    codes.extend(
+        DEFAULT_HIERARCHY_PROPERTIES
+            .iter()
+            .filter(|(system, _)| *system == url)
+            .map(|(_, code)| *code),
+    );
+    codes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn declared_and_default_hierarchy_properties() {
+        let code_system = json!({ "property": [
+            { "code": "isa", "uri": "http://hl7.org/fhir/concept-properties#parent" },
+            { "code": "other", "uri": "http://example.org/other" }
+        ] });
+        let snomed = hierarchy_properties("http://snomed.info/sct", &code_system);
+        assert_eq!(
+            snomed,
+            HashSet::from(["isa", "parent", "subsumedBy", "116680003"])
+        );
+        let other = hierarchy_properties("http://example.org", &code_system);
+        assert_eq!(other, HashSet::from(["isa", "parent", "subsumedBy"]));
+    }
+}
@@ -0,0 +1,672 @@
+//! An in-memory [`TerminologyStore`] for testing the terminology operations without a
+//! database. It holds the resources it is built with and answers the reads the operations
+//! make; writes and maintenance queries are not supported.
+
+use async_trait::async_trait;
+use serde_json::Value;
+use sqlx::types::Json;
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+use super::TerminologyStore;
+use crate::error::AppError;
+use crate::models::{
+    ClosureCoverage, CodeSystem, Concept, ConceptMap, ExpansionStatus, HierarchyOperator,
+    ImportRecord, PropertyFilter, ResourceType, SearchParams, StoredExpansion, ValueSet,
+};
+
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    code_systems: Vec<CodeSystem>,
+    /// Concepts of every CodeSystem, in CodeSystem order
+    concepts: Vec<Concept>,
+    /// Direct `(code_system_id, parent, child)` hierarchy edges
+    edges: Vec<(Uuid, String, String)>,
+    /// `(supplement, supplemented CodeSystem)` ids
+    supplements: Vec<(Uuid, Uuid)>,
+    value_sets: Vec<ValueSet>,
+    /// Stored expansions by ValueSet id
+    expansions: Vec<(Uuid, StoredExpansion)>,
+    concept_maps: Vec<ConceptMap>,
+}
+
+/// Resources added later are more recently updated, so they are the default version
+fn timestamp(sequence: usize) -> chrono::DateTime<chrono::Utc> {
+    chrono::DateTime::UNIX_EPOCH + chrono::Duration::seconds(sequence as i64)
+}
+
+fn text(content: &Value, key: &str) -> Option<String> {
+    content
+        .get(key)
+        .and_then(|v| v.as_str())
+        .map(str::to_string)
+}
+
+impl MemoryStore {
+    /// Add a CodeSystem resource. Its concepts, nested ones included, are stored with the
+    /// hierarchy given by nesting and by `parent` properties; a `supplements` CodeSystem is
+    /// linked to the CodeSystem it supplements.
+    pub fn with_code_system(mut self, content: Value) -> Self {
+        let id = Uuid::new_v4();
+        let updated_at = timestamp(self.code_systems.len() + self.value_sets.len());
+
+        let mut stack: Vec<(Option<String>, &Value)> = content
+            .get("concept")
+            .and_then(|c| c.as_array())
+            .into_iter()
+            .flatten()
+            .rev()
+            .map(|concept| (None, concept))
+            .collect();
+        while let Some((parent, concept)) = stack.pop() {
+            let code = text(concept, "code").expect("concept code");
+            let parents = concept
+                .get("property")
+                .and_then(|p| p.as_array())
+                .into_iter()
+                .flatten()
+                .filter(|p| p.get("code").and_then(|c| c.as_str()) == Some("parent"))
+                .filter_map(|p| text(p, "valueCode"));
+            for parent in parent.into_iter().chain(parents) {
+                self.edges.push((id, parent, code.clone()));
+            }
+            self.concepts.push(Concept {
+                id: Uuid::new_v4(),
+                code_system_id: id,
+                code: code.clone(),
+                display: text(concept, "display"),
+                definition: text(concept, "definition"),
+                properties: concept.get("property").cloned().map(Json),
+                designations: concept.get("designation").cloned().map(Json),
+                ordinal: self.concepts.len() as i32,
+                created_at: updated_at,
+            });
+            for child in concept
+                .get("concept")
+                .and_then(|c| c.as_array())
+                .into_iter()
+                .flatten()
+                .rev()
+            {
+                stack.push((Some(code.clone()), child));
+            }
+        }
+
+        if let Some(base) = content.get("supplements").and_then(|s| s.as_str()) {
+            let (url, version) = base
+                .split_once('|')
+                .map_or((base, None), |(u, v)| (u, Some(v)));
+            let supplemented = self
+                .code_systems
+                .iter()
+                .rev()
+                .find(|cs| cs.url == url && (version.is_none() || cs.version.as_deref() == version))
+                .expect("supplemented CodeSystem is added first");
+            self.supplements.push((id, supplemented.id));
+        }
+
+        self.code_systems.push(CodeSystem {
+            id,
+            url: text(&content, "url").expect("CodeSystem url"),
+            version: text(&content, "version"),
+            status: text(&content, "status").unwrap_or_else(|| "active".to_string()),
+            name: text(&content, "name"),
+            title: text(&content, "title"),
+            fhir_version: None,
+            content: Json(content),
+            created_at: updated_at,
+            updated_at,
+        });
+        self
+    }
+
+    pub fn with_value_set(mut self, content: Value) -> Self {
+        let updated_at = timestamp(self.code_systems.len() + self.value_sets.len());
+        self.value_sets.push(ValueSet {
+            id: Uuid::new_v4(),
+            url: text(&content, "url").expect("ValueSet url"),
+            version: text(&content, "version"),
+            status: text(&content, "status").unwrap_or_else(|| "active".to_string()),
+            name: text(&content, "name"),
+            title: text(&content, "title"),
+            fhir_version: None,
+            content: Json(content),
+            created_at: updated_at,
+            updated_at,
+        });
+        self
+    }
+
+    /// Store `contains` as the expansion of the ValueSet added last, as
+    /// `$precompute-expansion` would
+    pub fn with_expansion(mut self, contains: Value) -> Self {
+        let value_set = self.value_sets.last().expect("the ValueSet is added first");
+        let expansion = StoredExpansion {
+            contains: contains.as_array().cloned().expect("contains array"),
+            created_at: value_set.updated_at,
+        };
+        self.expansions.push((value_set.id, expansion));
+        self
+    }
+
+    pub fn with_concept_map(mut self, content: Value) -> Self {
+        let updated_at = timestamp(self.concept_maps.len());
+        self.concept_maps.push(ConceptMap {
+            id: Uuid::new_v4(),
+            url: text(&content, "url").expect("ConceptMap url"),
+            version: text(&content, "version"),
+            status: text(&content, "status").unwrap_or_else(|| "active".to_string()),
+            name: text(&content, "name"),
+            title: text(&content, "title"),
+            source_uri: None,
+            target_uri: None,
+            fhir_version: None,
+            content: Json(content),
+            created_at: updated_at,
+            updated_at,
+        });
+        self
+    }
+
+    /// The resource `url` at `version`, or the most recently updated one
+    fn select<'a, T>(
+        resources: &'a [T],
+        url: &str,
+        version: Option<&str>,
+        key: impl Fn(&T) -> (&str, Option<&str>),
+    ) -> Option<&'a T> {
+        resources.iter().rev().find(|resource| {
+            let (resource_url, resource_version) = key(resource);
+            resource_url == url && (version.is_none() || resource_version == version)
+        })
+    }
+
+    fn concepts_of(&self, code_system_id: &Uuid) -> impl Iterator<Item = &Concept> {
+        let code_system_id = *code_system_id;
+        self.concepts
+            .iter()
+            .filter(move |c| c.code_system_id == code_system_id)
+    }
+
+    fn case_sensitive(&self, code_system_id: &Uuid) -> bool {
+        self.code_systems
+            .iter()
+            .find(|cs| cs.id == *code_system_id)
+            .and_then(|cs| cs.content.get("caseSensitive").and_then(|c| c.as_bool()))
+            != Some(false)
+    }
+
+    /// `code` and every code below it, like the closure table's rows for `code`
+    fn descendants(&self, code_system_id: &Uuid, code: &str) -> HashSet<String> {
+        self.closure(code_system_id, code, |(_, parent, child)| (parent, child))
+    }
+
+    /// `code` and every code above it
+    fn ancestors(&self, code_system_id: &Uuid, code: &str) -> HashSet<String> {
+        self.closure(code_system_id, code, |(_, parent, child)| (child, parent))
+    }
+
+    fn closure(
+        &self,
+        code_system_id: &Uuid,
+        code: &str,
+        direction: impl Fn(&(Uuid, String, String)) -> (&String, &String),
+    ) -> HashSet<String> {
+        let mut reached = HashSet::from([code.to_string()]);
+        let mut pending = vec![code.to_string()];
+        while let Some(code) = pending.pop() {
+            for edge in self.edges.iter().filter(|e| e.0 == *code_system_id) {
+                let (from, to) = direction(edge);
+                if *from == code && reached.insert(to.clone()) {
+                    pending.push(to.clone());
+                }
+            }
+        }
+        reached
+    }
+}
+
+#[async_trait]
+impl TerminologyStore for MemoryStore {
+    async fn create_code_system(&self, _cs: CodeSystem) -> Result<CodeSystem, AppError> {
+        unimplemented!("the memory store is read-only")
+    }
+
+    async fn get_code_system(
+        &self,
+        url: &str,
+        version: Option<&str>,
+    ) -> Result<Option<CodeSystem>, AppError> {
+        let selected = Self::select(&self.code_systems, url, version, |cs| {
+            (&cs.url, cs.version.as_deref())
+        });
+        Ok(selected.cloned())
+    }
+
+    async fn get_code_system_by_id(&self, id: &Uuid) -> Result<Option<CodeSystem>, AppError> {
+        Ok(self.code_systems.iter().find(|cs| cs.id == *id).cloned())
+    }
+
+    async fn list_versions(
+        &self,
+        url: &str,
+    ) -> Result<Vec<(Option<String>, chrono::DateTime<chrono::Utc>)>, AppError> {
+        Ok(self
+            .code_systems
+            .iter()
+            .rev()
+            .filter(|cs| cs.url == url)
+            .map(|cs| (cs.version.clone(), cs.updated_at))
+            .collect())
+    }
+
+    async fn get_supplements(&self, code_system: &CodeSystem) -> Result<Vec<CodeSystem>, AppError> {
+        Ok(self
+            .supplements
+            .iter()
+            .filter(|(_, base)| *base == code_system.id)
+            .filter_map(|(supplement, _)| self.code_systems.iter().find(|cs| cs.id == *supplement))
+            .cloned()
+            .collect())
+    }
+
+    async fn update_code_system(&self, _cs: CodeSystem) -> Result<CodeSystem, AppError> {
+        unimplemented!("the memory store is read-only")
+    }
+
+    async fn delete_code_system(&self, _url: &str, _version: Option<&str>) -> Result<(), AppError> {
+        unimplemented!("the memory store is read-only")
+    }
+
+    async fn search_code_systems(
+        &self,
+        _params: &SearchParams,
+    ) -> Result<Vec<CodeSystem>, AppError> {
+        unimplemented!("resource search is not used by the terminology operations")
+    }
+
+    async fn create_value_set(&self, _vs: ValueSet) -> Result<ValueSet, AppError> {
+        unimplemented!("the memory store is read-only")
+    }
+
+    async fn get_value_set(
+        &self,
+        url: &str,
+        version: Option<&str>,
+    ) -> Result<Option<ValueSet>, AppError> {
+        let selected = Self::select(&self.value_sets, url, version, |vs| {
+            (&vs.url, vs.version.as_deref())
+        });
+        Ok(selected.cloned())
+    }
+
+    async fn get_value_set_as_of(
+        &self,
+        url: &str,
+        before: chrono::DateTime<chrono::Utc>,
+    ) -> Result<Option<ValueSet>, AppError> {
+        Ok(self
+            .value_sets
+            .iter()
+            .rev()
+            .find(|vs| vs.url == url && vs.updated_at < before)
+            .cloned())
+    }
+
+    async fn get_value_set_by_id(&self, id: &Uuid) -> Result<Option<ValueSet>, AppError> {
+        Ok(self.value_sets.iter().find(|vs| vs.id == *id).cloned())
+    }
+
+    async fn get_value_set_systems(
+        &self,
+        value_set_id: &Uuid,
+    ) -> Result<Vec<(String, Option<String>)>, AppError> {
+        let mut systems = Vec::new();
+        let mut visited = HashSet::new();
+        let mut pending = vec![*value_set_id];
+        while let Some(id) = pending.pop() {
+            if !visited.insert(id) {
+                continue;
+            }
+            let Some(value_set) = self.value_sets.iter().find(|vs| vs.id == id) else {
+                continue;
+            };
+            let includes = value_set
+                .content
+                .pointer("/compose/include")
+                .and_then(|i| i.as_array())
+                .into_iter()
+                .flatten();
+            for include in includes {
+                if let Some(system) = text(include, "system") {
+                    let entry = (system, text(include, "version"));
+                    if !systems.contains(&entry) {
+                        systems.push(entry);
+                    }
+                }
+                let nested = include
+                    .get("valueSet")
+                    .and_then(|v| v.as_array())
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|v| v.as_str());
+                for canonical in nested {
+                    let url = canonical.split('|').next().unwrap_or(canonical);
+                    pending.extend(
+                        self.value_sets
+                            .iter()
+                            .filter(|vs| vs.url == url)
+                            .map(|vs| vs.id),
+                    );
+                }
+            }
+        }
+        Ok(systems)
+    }
+
+    async fn update_value_set(&self, _vs: ValueSet) -> Result<ValueSet, AppError> {
+        unimplemented!("the memory store is read-only")
+    }
+
+    async fn delete_value_set(&self, _url: &str, _version: Option<&str>) -> Result<(), AppError> {
+        unimplemented!("the memory store is read-only")
+    }
+
+    async fn search_value_sets(&self, _params: &SearchParams) -> Result<Vec<ValueSet>, AppError> {
+        unimplemented!("resource search is not used by the terminology operations")
+    }
+
+    async fn create_concept_map(&self, _cm: ConceptMap) -> Result<ConceptMap, AppError> {
+        unimplemented!("the memory store is read-only")
+    }
+
+    async fn get_concept_map(
+        &self,
+        url: &str,
+        version: Option<&str>,
+    ) -> Result<Option<ConceptMap>, AppError> {
+        let selected = Self::select(&self.concept_maps, url, version, |cm| {
+            (&cm.url, cm.version.as_deref())
+        });
+        Ok(selected.cloned())
+    }
+
+    async fn get_concept_map_by_id(&self, id: &Uuid) -> Result<Option<ConceptMap>, AppError> {
+        Ok(self.concept_maps.iter().find(|cm| cm.id == *id).cloned())
+    }
+
+    async fn find_concept_maps_for_system(
+        &self,
+        system: &str,
+        reverse: bool,
+    ) -> Result<Vec<ConceptMap>, AppError> {
+        let key = if reverse { "target" } else { "source" };
+        Ok(self
+            .concept_maps
+            .iter()
+            .filter(|cm| {
+                cm.content
+                    .get("group")
+                    .and_then(|g| g.as_array())
+                    .into_iter()
+                    .flatten()
+                    .any(|group| group.get(key).and_then(|s| s.as_str()) == Some(system))
+            })
+            .cloned()
+            .collect())
+    }
+
+    async fn update_concept_map(&self, _cm: ConceptMap) -> Result<ConceptMap, AppError> {
+        unimplemented!("the memory store is read-only")
+    }
+
+    async fn delete_concept_map(&self, _url: &str, _version: Option<&str>) -> Result<(), AppError> {
+        unimplemented!("the memory store is read-only")
+    }
+
+    async fn search_concept_maps(
+        &self,
+        _params: &SearchParams,
+    ) -> Result<Vec<ConceptMap>, AppError> {
+        unimplemented!("resource search is not used by the terminology operations")
+    }
+
+    async fn list_code_system_versions(&self) -> Result<Vec<(String, Option<String>)>, AppError> {
+        let mut versions: Vec<_> = self
+            .code_systems
+            .iter()
+            .map(|cs| (cs.url.clone(), cs.version.clone()))
+            .collect();
+        versions.sort();
+        Ok(versions)
+    }
+
+    async fn check_schema(&self) -> Result<Vec<String>, AppError> {
+        Ok(Vec::new())
+    }
+
+    async fn count_code_systems(&self) -> Result<i64, AppError> {
+        Ok(self.code_systems.len() as i64)
+    }
+
+    async fn count_value_sets(&self) -> Result<i64, AppError> {
+        Ok(self.value_sets.len() as i64)
+    }
+
+    async fn count_concept_maps(&self) -> Result<i64, AppError> {
+        Ok(self.concept_maps.len() as i64)
+    }
+
+    async fn closure_coverage(&self) -> Result<Vec<ClosureCoverage>, AppError> {
+        unimplemented!("statistics are not used by the terminology operations")
+    }
+
+    async fn recent_imports(&self, _limit: i64) -> Result<Vec<ImportRecord>, AppError> {
+        Ok(Vec::new())
+    }
+
+    async fn get_concept(
+        &self,
+        code_system_id: &Uuid,
+        code: &str,
+    ) -> Result<Option<Concept>, AppError> {
+        let case_sensitive = self.case_sensitive(code_system_id);
+        let mut concepts = self.concepts_of(code_system_id);
+        Ok(concepts
+            .find(|c| c.code == code || (!case_sensitive && c.code.eq_ignore_ascii_case(code)))
+            .cloned())
+    }
+
+    async fn get_concepts_by_codes(
+        &self,
+        code_system_id: &Uuid,
+        codes: &[&str],
+    ) -> Result<HashMap<String, Concept>, AppError> {
+        let mut found = HashMap::new();
+        for code in codes {
+            if let Some(concept) = self.get_concept(code_system_id, code).await? {
+                found.insert(code.to_string(), concept);
+            }
+        }
+        Ok(found)
+    }
+
+    async fn get_concept_versions(
+        &self,
+        url: &str,
+        code: &str,
+    ) -> Result<Vec<(Option<String>, Option<String>)>, AppError> {
+        Ok(self
+            .code_systems
+            .iter()
+            .rev()
+            .filter(|cs| cs.url == url)
+            .filter_map(|cs| {
+                let concept = self.concepts_of(&cs.id).find(|c| c.code == code)?;
+                Some((cs.version.clone(), concept.display.clone()))
+            })
+            .collect())
+    }
+
+    async fn find_concepts(
+        &self,
+        _code_system_id: &Uuid,
+        _filters: &[PropertyFilter],
+        _limit: i64,
+    ) -> Result<Vec<Concept>, AppError> {
+        unimplemented!("property filters are not supported by the memory store")
+    }
+
+    async fn get_hierarchy_concepts(
+        &self,
+        code_system_id: &Uuid,
+        operator: HierarchyOperator,
+        code: &str,
+    ) -> Result<Vec<Concept>, AppError> {
+        let related = match operator {
+            HierarchyOperator::Generalizes => self.ancestors(code_system_id, code),
+            _ => self.descendants(code_system_id, code),
+        };
+        let selected = |concept: &&Concept| match operator {
+            HierarchyOperator::IsA | HierarchyOperator::Generalizes => {
+                related.contains(&concept.code)
+            }
+            HierarchyOperator::DescendentOf => {
+                concept.code != code && related.contains(&concept.code)
+            }
+            HierarchyOperator::IsNotA => !related.contains(&concept.code),
+        };
+        Ok(self
+            .concepts_of(code_system_id)
+            .filter(selected)
+            .cloned()
+            .collect())
+    }
+
+    async fn get_related_concepts(
+        &self,
+        code_system_id: &Uuid,
+        code: &str,
+        parents: bool,
+    ) -> Result<Vec<Concept>, AppError> {
+        let related: HashSet<&str> = self
+            .edges
+            .iter()
+            .filter(|(id, _, _)| id == code_system_id)
+            .filter_map(|(_, parent, child)| match parents {
+                true if child == code => Some(parent.as_str()),
+                false if parent == code => Some(child.as_str()),
+                _ => None,
+            })
+            .collect();
+        Ok(self
+            .concepts_of(code_system_id)
+            .filter(|c| related.contains(c.code.as_str()))
+            .cloned()
+            .collect())
+    }
+
+    async fn get_concepts(&self, code_system_id: &Uuid) -> Result<Vec<Concept>, AppError> {
+        Ok(self.concepts_of(code_system_id).cloned().collect())
+    }
+
+    async fn count_concepts(&self, code_system_id: &Uuid) -> Result<i64, AppError> {
+        Ok(self.concepts_of(code_system_id).count() as i64)
+    }
+
+    async fn count_concepts_bounded(&self, code_system_id: &Uuid) -> Result<Option<i64>, AppError> {
+        self.count_concepts(code_system_id).await.map(Some)
+    }
+
+    async fn get_concepts_range(
+        &self,
+        code_system_id: &Uuid,
+        offset: i64,
+        limit: i64,
+    ) -> Result<Vec<Concept>, AppError> {
+        Ok(self
+            .concepts_of(code_system_id)
+            .skip(offset as usize)
+            .take(limit as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn get_concepts_page(
+        &self,
+        code_system_id: &Uuid,
+        after: Option<(i32, &str)>,
+        limit: i64,
+    ) -> Result<Vec<Concept>, AppError> {
+        Ok(self
+            .concepts_of(code_system_id)
+            .filter(|c| {
+                after.is_none_or(|(ordinal, code)| (c.ordinal, c.code.as_str()) > (ordinal, code))
+            })
+            .take(limit as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn check_subsumption(
+        &self,
+        code_system_id: &Uuid,
+        code_a: &str,
+        code_b: &str,
+    ) -> Result<Option<bool>, AppError> {
+        if self.descendants(code_system_id, code_a).contains(code_b) {
+            Ok(Some(true))
+        } else if self.descendants(code_system_id, code_b).contains(code_a) {
+            Ok(Some(false))
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn get_value_set_expansion(
+        &self,
+        value_set_id: &Uuid,
+    ) -> Result<Option<StoredExpansion>, AppError> {
+        Ok(self
+            .expansions
+            .iter()
+            .find(|(id, _)| id == value_set_id)
+            .map(|(_, expansion)| expansion.clone()))
+    }
+
+    async fn store_value_set_expansion(
+        &self,
+        _value_set_id: &Uuid,
+        _expansion: Value,
+    ) -> Result<bool, AppError> {
+        unimplemented!("the memory store is read-only")
+    }
+
+    async fn get_expansion_status(
+        &self,
+        _value_set_id: &Uuid,
+    ) -> Result<Option<ExpansionStatus>, AppError> {
+        Ok(None)
+    }
+
+    async fn exists_by_id(
+        &self,
+        resource_type: ResourceType,
+        id: &Uuid,
+    ) -> Result<Option<chrono::DateTime<chrono::Utc>>, AppError> {
+        let updated_at = match resource_type {
+            ResourceType::CodeSystem => self.get_code_system_by_id(id).await?.map(|r| r.updated_at),
+            ResourceType::ValueSet => self.get_value_set_by_id(id).await?.map(|r| r.updated_at),
+            ResourceType::ConceptMap => self.get_concept_map_by_id(id).await?.map(|r| r.updated_at),
+        };
+        Ok(updated_at)
+    }
+
+    async fn reindex_resources(
+        &self,
+        _resource_type: ResourceType,
+        _id: Option<&Uuid>,
+    ) -> Result<u64, AppError> {
+        unimplemented!("the memory store is read-only")
+    }
+}
@@ -1,5 +1,13 @@
+mod canonical;
+mod hierarchy;
+#[cfg(test)]
+mod memory;
 mod postgres;
 mod traits;
 
+pub use canonical::normalize_canonical;
+pub use hierarchy::hierarchy_properties;
+#[cfg(test)]
+pub use memory::MemoryStore;
 pub use postgres::PostgresStore;
 pub use traits::TerminologyStore;
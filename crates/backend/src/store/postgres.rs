@@ -1,18 +1,256 @@
 use crate::error::AppError;
-use crate::models::{CodeSystem, Concept, ConceptMap, SearchParams, ValueSet};
-use crate::store::TerminologyStore;
+use crate::fhir_version::FhirVersion;
+use crate::models::{
+    ClosureCoverage, CodeSystem, Concept, ConceptMap, ExpansionStatus, HierarchyOperator,
+    ImportRecord, PropertyFilter, PropertyValueKind, ResourceType, SearchParams, StoredExpansion,
+    ValueSet,
+};
+use crate::store::{hierarchy_properties, normalize_canonical, TerminologyStore};
 use async_trait::async_trait;
-use sqlx::PgPool;
+use sqlx::{PgPool, Postgres, QueryBuilder};
+use std::collections::{HashMap, HashSet, VecDeque};
 use uuid::Uuid;
 
+/// Tables queried by the store, checked by [`TerminologyStore::check_schema`]
+const REQUIRED_TABLES: [&str; 8] = [
+    "code_systems",
+    "value_sets",
+    "concept_maps",
+    "concepts",
+    "closure_table",
+    "value_set_expansions",
+    "import_history",
+    "code_system_supplements",
+];
+
+/// Most concepts visited by the hierarchy property walk that replaces a timed out
+/// closure query
+const MAX_PARENT_WALK: usize = 1000;
+
+/// SQLSTATE of a statement cancelled by `statement_timeout`
+const QUERY_CANCELED: &str = "57014";
+
 /// PostgreSQL implementation of TerminologyStore
 pub struct PostgresStore {
     pool: PgPool,
+    /// Statement timeout for closure table queries in milliseconds; 0 for none
+    closure_timeout_ms: u64,
+    /// Most concepts counted for an expansion total; 0 to always count
+    count_limit: i64,
+    /// Match canonical URLs exactly as given, without [`normalize_canonical`]
+    strict_canonicals: bool,
 }
 
 impl PostgresStore {
     pub fn new(pool: PgPool) -> Self {
-        Self { pool }
+        Self {
+            pool,
+            closure_timeout_ms: 0,
+            count_limit: 0,
+            strict_canonicals: false,
+        }
+    }
+
+    /// Bound closure table queries by a statement timeout, falling back to walking the
+    /// hierarchy properties when it expires
+    pub fn with_closure_timeout(mut self, timeout_ms: u64) -> Self {
+        self.closure_timeout_ms = timeout_ms;
+        self
+    }
+
+    /// Stop counting a CodeSystem's concepts for an expansion total past `limit`
+    pub fn with_count_limit(mut self, limit: i64) -> Self {
+        self.count_limit = limit;
+        self
+    }
+
+    /// Match canonical URLs exactly instead of also trying their normalized form
+    pub fn with_strict_canonicals(mut self, strict: bool) -> Self {
+        self.strict_canonicals = strict;
+        self
+    }
+
+    /// The `url` values a canonical lookup matches: the URL as given and, unless canonicals
+    /// are strict, its normalized form, so rows stored before normalization still match
+    fn url_candidates(&self, url: &str) -> Vec<String> {
+        let mut urls = vec![url.to_string()];
+        if !self.strict_canonicals {
+            let normalized = normalize_canonical(url);
+            if normalized != url {
+                urls.push(normalized);
+            }
+        }
+        urls
+    }
+
+    /// Build the search query for a resource table. Every value, including the paging limits,
+    /// is a bound parameter, and `url` matches like other canonical lookups. `name` is only
+    /// filtered on when `filter_name` is set.
+    fn search_query<'a>(
+        &self,
+        table: &str,
+        params: &'a SearchParams,
+        filter_name: bool,
+    ) -> QueryBuilder<'a, Postgres> {
+        let mut query = QueryBuilder::new(format!("SELECT * FROM {table} WHERE 1=1"));
+
+        let (url, piped_version) = match params.url.as_deref().map(|url| url.split_once('|')) {
+            Some(Some((url, version))) => (Some(url), Some(version).filter(|v| !v.is_empty())),
+            _ => (params.url.as_deref(), None),
+        };
+        if let Some(url) = url {
+            let urls = self.url_candidates(url);
+            query.push(" AND url = ANY(").push_bind(urls).push(")");
+        }
+        if let Some(version) = piped_version.or(params.version.as_deref()) {
+            query.push(" AND version = ").push_bind(version);
+        }
+        if let Some(status) = &params.status {
+            query.push(" AND status = ").push_bind(status);
+        }
+        if let Some(name) = params.name.as_ref().filter(|_| filter_name) {
+            query
+                .push(" AND name ILIKE ")
+                .push_bind(format!("%{name}%"));
+        }
+        if let Some(fhir_version) = &params.fhir_version {
+            // Rows record either a release name (`R4`) or the resource's semver `fhirVersion`
+            match fhir_version.parse::<FhirVersion>() {
+                Ok(release) => {
+                    query
+                        .push(" AND (fhir_version = ")
+                        .push_bind(release.to_string())
+                        .push(" OR fhir_version LIKE ")
+                        .push_bind(format!("{}.%", release.major()))
+                        .push(")");
+                }
+                Err(_) => {
+                    query.push(" AND fhir_version = ").push_bind(fhir_version);
+                }
+            }
+        }
+        if let Some(meta) = meta_filter(params) {
+            query.push(" AND content -> 'meta' @> ").push_bind(meta);
+        }
+        // Containment cannot require a missing element, so `|code` tokens are checked apart
+        for (element, token) in [("tag", &params.tag), ("security", &params.security)] {
+            if let Some(code) = token.as_deref().and_then(|t| t.strip_prefix('|')) {
+                query
+                    .push(format!(
+                        " AND jsonb_path_exists(content -> 'meta' -> '{element}', \
+                         '$[*] ? (@.code == $code && !exists(@.system))', \
+                         jsonb_build_object('code', "
+                    ))
+                    .push_bind(code)
+                    .push("::text))");
+            }
+        }
+
+        query.push(" ORDER BY updated_at DESC");
+
+        if let Some(limit) = params.limit {
+            query.push(" LIMIT ").push_bind(limit);
+        }
+        if let Some(offset) = params.offset {
+            query.push(" OFFSET ").push_bind(offset);
+        }
+
+        query
+    }
+
+    /// Look up both directions of a subsumption in the closure table, under the configured
+    /// statement timeout
+    async fn closure_subsumption(
+        &self,
+        code_system_id: &Uuid,
+        code_a: &str,
+        code_b: &str,
+    ) -> Result<Option<bool>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        if self.closure_timeout_ms > 0 {
+            sqlx::query(&format!(
+                "SET LOCAL statement_timeout = {}",
+                self.closure_timeout_ms
+            ))
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        // Some(true) when A is an ancestor of B, Some(false) when B is an ancestor of A
+        let outcome: Option<bool> = sqlx::query_scalar(
+            "SELECT ancestor_code = $2 FROM closure_table
+             WHERE code_system_id = $1
+               AND ((ancestor_code = $2 AND descendant_code = $3)
+                 OR (ancestor_code = $3 AND descendant_code = $2))
+             ORDER BY ancestor_code = $2 DESC
+             LIMIT 1",
+        )
+        .bind(code_system_id)
+        .bind(code_a)
+        .bind(code_b)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(outcome)
+    }
+
+    /// Whether `ancestor` is reachable from `code` through the CodeSystem's
+    /// [`hierarchy_properties`], the ones the closure table is built from, visiting at most
+    /// [`MAX_PARENT_WALK`] concepts
+    async fn parent_walk(
+        &self,
+        code_system_id: &Uuid,
+        ancestor: &str,
+        code: &str,
+    ) -> Result<bool, AppError> {
+        let Some(code_system) = self.get_code_system_by_id(code_system_id).await? else {
+            return Ok(false);
+        };
+        let hierarchy = hierarchy_properties(&code_system.url, &code_system.content);
+
+        let mut visited = HashSet::new();
+        let mut pending = vec![code.to_string()];
+
+        while let Some(current) = pending.pop() {
+            if visited.len() >= MAX_PARENT_WALK {
+                tracing::warn!(
+                    "Parent walk from '{}' stopped after {} concepts",
+                    code,
+                    MAX_PARENT_WALK
+                );
+                break;
+            }
+            if !visited.insert(current.clone()) {
+                continue;
+            }
+            let Some(concept) = self.get_concept(code_system_id, &current).await? else {
+                continue;
+            };
+
+            let properties = concept.properties.as_ref().and_then(|p| p.as_array());
+            for property in properties.into_iter().flatten() {
+                let is_parent = property
+                    .get("code")
+                    .and_then(|c| c.as_str())
+                    .is_some_and(|c| hierarchy.contains(c));
+                if !is_parent {
+                    continue;
+                }
+                let parent = property
+                    .get("valueCode")
+                    .or_else(|| property.get("valueString"))
+                    .or_else(|| property.pointer("/valueCoding/code"))
+                    .and_then(|v| v.as_str());
+                match parent {
+                    Some(parent) if parent == ancestor => return Ok(true),
+                    Some(parent) => pending.push(parent.to_string()),
+                    None => {}
+                }
+            }
+        }
+
+        Ok(false)
     }
 }
 
@@ -45,12 +283,13 @@ impl TerminologyStore for PostgresStore {
         url: &str,
         version: Option<&str>,
     ) -> Result<Option<CodeSystem>, AppError> {
+        let urls = self.url_candidates(url);
         let result = match version {
             Some(v) => {
                 sqlx::query_as::<_, CodeSystem>(
-                    "SELECT * FROM code_systems WHERE url = $1 AND version = $2",
+                    "SELECT * FROM code_systems WHERE url = ANY($1) AND version = $2",
                 )
-                .bind(url)
+                .bind(&urls)
                 .bind(v)
                 .fetch_optional(&self.pool)
                 .await?
@@ -58,9 +297,10 @@ impl TerminologyStore for PostgresStore {
             None => {
                 // Get the most recent version if no version specified
                 sqlx::query_as::<_, CodeSystem>(
-                    "SELECT * FROM code_systems WHERE url = $1 ORDER BY updated_at DESC LIMIT 1",
+                    "SELECT * FROM code_systems WHERE url = ANY($1)
+                     ORDER BY updated_at DESC LIMIT 1",
                 )
-                .bind(url)
+                .bind(&urls)
                 .fetch_optional(&self.pool)
                 .await?
             }
@@ -69,6 +309,22 @@ impl TerminologyStore for PostgresStore {
         Ok(result)
     }
 
+    async fn list_versions(
+        &self,
+        url: &str,
+    ) -> Result<Vec<(Option<String>, chrono::DateTime<chrono::Utc>)>, AppError> {
+        // Same ordering as the default selection in get_code_system
+        let versions = sqlx::query_as(
+            "SELECT version, updated_at FROM code_systems WHERE url = ANY($1)
+             ORDER BY updated_at DESC",
+        )
+        .bind(self.url_candidates(url))
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(versions)
+    }
+
     async fn get_code_system_by_id(&self, id: &Uuid) -> Result<Option<CodeSystem>, AppError> {
         let result = sqlx::query_as::<_, CodeSystem>("SELECT * FROM code_systems WHERE id = $1")
             .bind(id)
@@ -78,6 +334,21 @@ impl TerminologyStore for PostgresStore {
         Ok(result)
     }
 
+    async fn get_supplements(&self, code_system: &CodeSystem) -> Result<Vec<CodeSystem>, AppError> {
+        let supplements = sqlx::query_as::<_, CodeSystem>(
+            "SELECT cs.* FROM code_system_supplements s
+             JOIN code_systems cs ON cs.id = s.supplement_id
+             WHERE s.base_url = ANY($1) AND (s.base_version IS NULL OR s.base_version = $2)
+             ORDER BY cs.url, cs.version",
+        )
+        .bind(self.url_candidates(&code_system.url))
+        .bind(&code_system.version)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(supplements)
+    }
+
     async fn update_code_system(&self, cs: CodeSystem) -> Result<CodeSystem, AppError> {
         let result = sqlx::query_as::<_, CodeSystem>(
             r#"
@@ -101,15 +372,15 @@ impl TerminologyStore for PostgresStore {
     async fn delete_code_system(&self, url: &str, version: Option<&str>) -> Result<(), AppError> {
         match version {
             Some(v) => {
-                sqlx::query("DELETE FROM code_systems WHERE url = $1 AND version = $2")
-                    .bind(url)
+                sqlx::query("DELETE FROM code_systems WHERE url = ANY($1) AND version = $2")
+                    .bind(self.url_candidates(url))
                     .bind(v)
                     .execute(&self.pool)
                     .await?;
             }
             None => {
-                sqlx::query("DELETE FROM code_systems WHERE url = $1")
-                    .bind(url)
+                sqlx::query("DELETE FROM code_systems WHERE url = ANY($1)")
+                    .bind(self.url_candidates(url))
                     .execute(&self.pool)
                     .await?;
             }
@@ -122,52 +393,11 @@ impl TerminologyStore for PostgresStore {
         &self,
         params: &SearchParams,
     ) -> Result<Vec<CodeSystem>, AppError> {
-        let mut query_str = "SELECT * FROM code_systems WHERE 1=1".to_string();
-        let mut param_count = 0;
-
-        if params.url.is_some() {
-            param_count += 1;
-            query_str.push_str(&format!(" AND url = ${param_count}"));
-        }
-        if params.status.is_some() {
-            param_count += 1;
-            query_str.push_str(&format!(" AND status = ${param_count}"));
-        }
-        if params.name.is_some() {
-            param_count += 1;
-            query_str.push_str(&format!(" AND name ILIKE ${param_count}"));
-        }
-        if params.fhir_version.is_some() {
-            param_count += 1;
-            query_str.push_str(&format!(" AND fhir_version = ${param_count}"));
-        }
-
-        query_str.push_str(" ORDER BY updated_at DESC");
-
-        if let Some(limit) = params.limit {
-            query_str.push_str(&format!(" LIMIT {limit}"));
-        }
-        if let Some(offset) = params.offset {
-            query_str.push_str(&format!(" OFFSET {offset}"));
-        }
-
-        // Build the query dynamically
-        let mut query = sqlx::query_as::<_, CodeSystem>(&query_str);
-
-        if let Some(ref url) = params.url {
-            query = query.bind(url);
-        }
-        if let Some(ref status) = params.status {
-            query = query.bind(status);
-        }
-        if let Some(ref name) = params.name {
-            query = query.bind(format!("%{name}%"));
-        }
-        if let Some(ref fhir_version) = params.fhir_version {
-            query = query.bind(fhir_version);
-        }
-
-        let results = query.fetch_all(&self.pool).await?;
+        let results = self
+            .search_query("code_systems", params, true)
+            .build_query_as::<CodeSystem>()
+            .fetch_all(&self.pool)
+            .await?;
 
         Ok(results)
     }
@@ -199,21 +429,23 @@ impl TerminologyStore for PostgresStore {
         url: &str,
         version: Option<&str>,
     ) -> Result<Option<ValueSet>, AppError> {
+        let urls = self.url_candidates(url);
         let result = match version {
             Some(v) => {
                 sqlx::query_as::<_, ValueSet>(
-                    "SELECT * FROM value_sets WHERE url = $1 AND version = $2",
+                    "SELECT * FROM value_sets WHERE url = ANY($1) AND version = $2",
                 )
-                .bind(url)
+                .bind(&urls)
                 .bind(v)
                 .fetch_optional(&self.pool)
                 .await?
             }
             None => {
                 sqlx::query_as::<_, ValueSet>(
-                    "SELECT * FROM value_sets WHERE url = $1 ORDER BY updated_at DESC LIMIT 1",
+                    "SELECT * FROM value_sets WHERE url = ANY($1)
+                     ORDER BY updated_at DESC LIMIT 1",
                 )
-                .bind(url)
+                .bind(&urls)
                 .fetch_optional(&self.pool)
                 .await?
             }
@@ -222,6 +454,24 @@ impl TerminologyStore for PostgresStore {
         Ok(result)
     }
 
+    async fn get_value_set_as_of(
+        &self,
+        url: &str,
+        before: chrono::DateTime<chrono::Utc>,
+    ) -> Result<Option<ValueSet>, AppError> {
+        let urls = self.url_candidates(url);
+        let value_set = sqlx::query_as::<_, ValueSet>(
+            "SELECT * FROM value_sets WHERE url = ANY($1) AND updated_at < $2
+             ORDER BY updated_at DESC LIMIT 1",
+        )
+        .bind(&urls)
+        .bind(before)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(value_set)
+    }
+
     async fn get_value_set_by_id(&self, id: &Uuid) -> Result<Option<ValueSet>, AppError> {
         let result = sqlx::query_as::<_, ValueSet>("SELECT * FROM value_sets WHERE id = $1")
             .bind(id)
@@ -231,6 +481,62 @@ impl TerminologyStore for PostgresStore {
         Ok(result)
     }
 
+    async fn get_value_set_systems(
+        &self,
+        value_set_id: &Uuid,
+    ) -> Result<Vec<(String, Option<String>)>, AppError> {
+        let value_set = self
+            .get_value_set_by_id(value_set_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("ValueSet {value_set_id} not found")))?;
+
+        let mut systems: Vec<(String, Option<String>)> = Vec::new();
+        // ValueSets already queued, so that include cycles terminate
+        let mut visited = HashSet::from([value_set.id]);
+        let mut pending = VecDeque::from([value_set]);
+
+        while let Some(value_set) = pending.pop_front() {
+            let includes = value_set
+                .content
+                .pointer("/compose/include")
+                .and_then(|i| i.as_array())
+                .cloned()
+                .unwrap_or_default();
+
+            for include in includes {
+                if let Some(system) = include.get("system").and_then(|s| s.as_str()) {
+                    let version = include.get("version").and_then(|v| v.as_str());
+                    if !systems
+                        .iter()
+                        .any(|(s, v)| s == system && v.as_deref() == version)
+                    {
+                        systems.push((system.to_string(), version.map(str::to_string)));
+                    }
+                }
+
+                let nested = include
+                    .get("valueSet")
+                    .and_then(|v| v.as_array())
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|canonical| canonical.as_str());
+                for canonical in nested {
+                    let (url, version) = match canonical.split_once('|') {
+                        Some((url, version)) => (url, Some(version)),
+                        None => (canonical, None),
+                    };
+                    if let Some(nested) = self.get_value_set(url, version).await? {
+                        if visited.insert(nested.id) {
+                            pending.push_back(nested);
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(systems)
+    }
+
     async fn update_value_set(&self, vs: ValueSet) -> Result<ValueSet, AppError> {
         let result = sqlx::query_as::<_, ValueSet>(
             r#"
@@ -254,15 +560,15 @@ impl TerminologyStore for PostgresStore {
     async fn delete_value_set(&self, url: &str, version: Option<&str>) -> Result<(), AppError> {
         match version {
             Some(v) => {
-                sqlx::query("DELETE FROM value_sets WHERE url = $1 AND version = $2")
-                    .bind(url)
+                sqlx::query("DELETE FROM value_sets WHERE url = ANY($1) AND version = $2")
+                    .bind(self.url_candidates(url))
                     .bind(v)
                     .execute(&self.pool)
                     .await?;
             }
             None => {
-                sqlx::query("DELETE FROM value_sets WHERE url = $1")
-                    .bind(url)
+                sqlx::query("DELETE FROM value_sets WHERE url = ANY($1)")
+                    .bind(self.url_candidates(url))
                     .execute(&self.pool)
                     .await?;
             }
@@ -272,51 +578,11 @@ impl TerminologyStore for PostgresStore {
     }
 
     async fn search_value_sets(&self, params: &SearchParams) -> Result<Vec<ValueSet>, AppError> {
-        let mut query_str = "SELECT * FROM value_sets WHERE 1=1".to_string();
-        let mut param_count = 0;
-
-        if params.url.is_some() {
-            param_count += 1;
-            query_str.push_str(&format!(" AND url = ${param_count}"));
-        }
-        if params.name.is_some() {
-            param_count += 1;
-            query_str.push_str(&format!(" AND name ILIKE ${param_count}"));
-        }
-        if params.status.is_some() {
-            param_count += 1;
-            query_str.push_str(&format!(" AND status = ${param_count}"));
-        }
-        if params.fhir_version.is_some() {
-            param_count += 1;
-            query_str.push_str(&format!(" AND fhir_version = ${param_count}"));
-        }
-
-        query_str.push_str(" ORDER BY updated_at DESC");
-
-        if let Some(limit) = params.limit {
-            query_str.push_str(&format!(" LIMIT {limit}"));
-        }
-        if let Some(offset) = params.offset {
-            query_str.push_str(&format!(" OFFSET {offset}"));
-        }
-
-        let mut query = sqlx::query_as::<_, ValueSet>(&query_str);
-
-        if let Some(ref url) = params.url {
-            query = query.bind(url);
-        }
-        if let Some(ref name) = params.name {
-            query = query.bind(format!("%{name}%"));
-        }
-        if let Some(ref status) = params.status {
-            query = query.bind(status);
-        }
-        if let Some(ref fhir_version) = params.fhir_version {
-            query = query.bind(fhir_version);
-        }
-
-        let results = query.fetch_all(&self.pool).await?;
+        let results = self
+            .search_query("value_sets", params, true)
+            .build_query_as::<ValueSet>()
+            .fetch_all(&self.pool)
+            .await?;
 
         Ok(results)
     }
@@ -326,8 +592,8 @@ impl TerminologyStore for PostgresStore {
     async fn create_concept_map(&self, cm: ConceptMap) -> Result<ConceptMap, AppError> {
         let result = sqlx::query_as::<_, ConceptMap>(
             r#"
-            INSERT INTO concept_maps (url, version, status, name, title, source_uri, target_uri, content, updated_at)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, NOW())
+            INSERT INTO concept_maps (url, version, status, name, title, source_uri, target_uri, content, element_count, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, concept_map_element_count($8), NOW())
             RETURNING *
             "#
         )
@@ -350,24 +616,27 @@ impl TerminologyStore for PostgresStore {
         url: &str,
         version: Option<&str>,
     ) -> Result<Option<ConceptMap>, AppError> {
-        let result =
-            match version {
-                Some(v) => {
-                    sqlx::query_as::<_, ConceptMap>(
-                        "SELECT * FROM concept_maps WHERE url = $1 AND version = $2",
-                    )
-                    .bind(url)
-                    .bind(v)
-                    .fetch_optional(&self.pool)
-                    .await?
-                }
-                None => sqlx::query_as::<_, ConceptMap>(
-                    "SELECT * FROM concept_maps WHERE url = $1 ORDER BY updated_at DESC LIMIT 1",
+        let urls = self.url_candidates(url);
+        let result = match version {
+            Some(v) => {
+                sqlx::query_as::<_, ConceptMap>(
+                    "SELECT * FROM concept_maps WHERE url = ANY($1) AND version = $2",
                 )
-                .bind(url)
+                .bind(&urls)
+                .bind(v)
                 .fetch_optional(&self.pool)
-                .await?,
-            };
+                .await?
+            }
+            None => {
+                sqlx::query_as::<_, ConceptMap>(
+                    "SELECT * FROM concept_maps WHERE url = ANY($1)
+                 ORDER BY updated_at DESC LIMIT 1",
+                )
+                .bind(&urls)
+                .fetch_optional(&self.pool)
+                .await?
+            }
+        };
 
         Ok(result)
     }
@@ -381,14 +650,34 @@ impl TerminologyStore for PostgresStore {
         Ok(result)
     }
 
+    async fn find_concept_maps_for_system(
+        &self,
+        system: &str,
+        reverse: bool,
+    ) -> Result<Vec<ConceptMap>, AppError> {
+        let key = if reverse { "target" } else { "source" };
+        let results = sqlx::query_as::<_, ConceptMap>(
+            "SELECT * FROM concept_maps
+             WHERE content -> 'group' @> jsonb_build_array(jsonb_build_object($1::text, $2::text))
+             ORDER BY url, version",
+        )
+        .bind(key)
+        .bind(system)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(results)
+    }
+
     async fn update_concept_map(&self, cm: ConceptMap) -> Result<ConceptMap, AppError> {
         let result = sqlx::query_as::<_, ConceptMap>(
             r#"
             UPDATE concept_maps 
-            SET status = $1, name = $2, title = $3, source_uri = $4, target_uri = $5, content = $6, updated_at = NOW()
+            SET status = $1, name = $2, title = $3, source_uri = $4, target_uri = $5, content = $6,
+                element_count = concept_map_element_count($6), updated_at = NOW()
             WHERE id = $7
             RETURNING *
-            "#
+            "#,
         )
         .bind(&cm.status)
         .bind(&cm.name)
@@ -406,15 +695,15 @@ impl TerminologyStore for PostgresStore {
     async fn delete_concept_map(&self, url: &str, version: Option<&str>) -> Result<(), AppError> {
         match version {
             Some(v) => {
-                sqlx::query("DELETE FROM concept_maps WHERE url = $1 AND version = $2")
-                    .bind(url)
+                sqlx::query("DELETE FROM concept_maps WHERE url = ANY($1) AND version = $2")
+                    .bind(self.url_candidates(url))
                     .bind(v)
                     .execute(&self.pool)
                     .await?;
             }
             None => {
-                sqlx::query("DELETE FROM concept_maps WHERE url = $1")
-                    .bind(url)
+                sqlx::query("DELETE FROM concept_maps WHERE url = ANY($1)")
+                    .bind(self.url_candidates(url))
                     .execute(&self.pool)
                     .await?;
             }
@@ -427,50 +716,67 @@ impl TerminologyStore for PostgresStore {
         &self,
         params: &SearchParams,
     ) -> Result<Vec<ConceptMap>, AppError> {
-        let mut query_str = "SELECT * FROM concept_maps WHERE 1=1".to_string();
-        let mut param_count = 0;
+        let results = self
+            .search_query("concept_maps", params, false)
+            .build_query_as::<ConceptMap>()
+            .fetch_all(&self.pool)
+            .await?;
 
-        if params.url.is_some() {
-            param_count += 1;
-            query_str.push_str(&format!(" AND url = ${param_count}"));
-        }
-        if params.status.is_some() {
-            param_count += 1;
-            query_str.push_str(&format!(" AND status = ${param_count}"));
-        }
-        if params.fhir_version.is_some() {
-            param_count += 1;
-            query_str.push_str(&format!(" AND fhir_version = ${param_count}"));
-        }
+        Ok(results)
+    }
 
-        query_str.push_str(" ORDER BY updated_at DESC");
+    // ========== Statistics ==========
 
-        if let Some(limit) = params.limit {
-            query_str.push_str(&format!(" LIMIT {limit}"));
-        }
-        if let Some(offset) = params.offset {
-            query_str.push_str(&format!(" OFFSET {offset}"));
-        }
+    async fn list_code_system_versions(&self) -> Result<Vec<(String, Option<String>)>, AppError> {
+        let code_systems =
+            sqlx::query_as("SELECT url, version FROM code_systems ORDER BY url, version")
+                .fetch_all(&self.pool)
+                .await?;
+
+        Ok(code_systems)
+    }
 
-        let mut query = sqlx::query_as::<_, ConceptMap>(&query_str);
+    async fn check_schema(&self) -> Result<Vec<String>, AppError> {
+        let mut problems = Vec::new();
 
-        if let Some(ref url) = params.url {
-            query = query.bind(url);
-        }
-        if let Some(ref status) = params.status {
-            query = query.bind(status);
-        }
-        if let Some(ref fhir_version) = params.fhir_version {
-            query = query.bind(fhir_version);
+        for table in REQUIRED_TABLES {
+            let exists: bool = sqlx::query_scalar("SELECT to_regclass($1) IS NOT NULL")
+                .bind(table)
+                .fetch_one(&self.pool)
+                .await?;
+            if !exists {
+                problems.push(format!("table '{table}' is missing"));
+            }
         }
 
-        let results = query.fetch_all(&self.pool).await?;
+        // Migrations are applied with `sqlx migrate run`, which records them in _sqlx_migrations
+        let expected = sqlx::migrate!("./migrations")
+            .iter()
+            .map(|m| m.version)
+            .max()
+            .unwrap_or_default();
+        let history: bool =
+            sqlx::query_scalar("SELECT to_regclass('_sqlx_migrations') IS NOT NULL")
+                .fetch_one(&self.pool)
+                .await?;
+        if history {
+            let applied: Option<i64> =
+                sqlx::query_scalar("SELECT MAX(version) FROM _sqlx_migrations WHERE success")
+                    .fetch_one(&self.pool)
+                    .await?;
+            if applied.unwrap_or_default() < expected {
+                problems.push(format!(
+                    "migrations are outdated: applied {}, expected {expected}",
+                    applied.map_or("none".to_string(), |v| v.to_string())
+                ));
+            }
+        } else {
+            problems.push("migration history table '_sqlx_migrations' is missing".to_string());
+        }
 
-        Ok(results)
+        Ok(problems)
     }
 
-    // ========== Statistics ==========
-
     async fn count_code_systems(&self) -> Result<i64, AppError> {
         let result: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM code_systems")
             .fetch_one(&self.pool)
@@ -495,6 +801,36 @@ impl TerminologyStore for PostgresStore {
         Ok(result.0)
     }
 
+    async fn closure_coverage(&self) -> Result<Vec<ClosureCoverage>, AppError> {
+        let coverage = sqlx::query_as::<_, ClosureCoverage>(
+            "SELECT cs.id AS code_system_id, cs.url, cs.version,
+                    COALESCE(ct.closure_rows, 0) AS closure_rows
+             FROM code_systems cs
+             LEFT JOIN (
+                 SELECT code_system_id, COUNT(*) AS closure_rows
+                 FROM closure_table GROUP BY code_system_id
+             ) ct ON ct.code_system_id = cs.id
+             ORDER BY cs.url, cs.version",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(coverage)
+    }
+
+    async fn recent_imports(&self, limit: i64) -> Result<Vec<ImportRecord>, AppError> {
+        let imports = sqlx::query_as::<_, ImportRecord>(
+            "SELECT package_name, package_version, registry, resources, imported, skipped, errors,
+                    dry_run, imported_at
+             FROM import_history ORDER BY imported_at DESC LIMIT $1",
+        )
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(imports)
+    }
+
     async fn get_concept(
         &self,
         code_system_id: &uuid::Uuid,
@@ -507,68 +843,542 @@ impl TerminologyStore for PostgresStore {
         .bind(code)
         .fetch_optional(&self.pool)
         .await?;
+        if concept.is_some() {
+            return Ok(concept);
+        }
+
+        // Only CodeSystems declaring `caseSensitive: false` match codes in another case;
+        // the lowest code wins should several differ only by case
+        let concept = sqlx::query_as::<_, Concept>(
+            "SELECT c.* FROM concepts c
+             JOIN code_systems cs ON cs.id = c.code_system_id
+             WHERE c.code_system_id = $1 AND lower(c.code) = lower($2)
+               AND cs.content->'caseSensitive' = 'false'::jsonb
+             ORDER BY c.code
+             LIMIT 1",
+        )
+        .bind(code_system_id)
+        .bind(code)
+        .fetch_optional(&self.pool)
+        .await?;
 
         Ok(concept)
     }
 
-    async fn check_subsumption(
+    async fn get_concepts_by_codes(
         &self,
-        code_system_id: &uuid::Uuid,
-        code_a: &str,
-        code_b: &str,
-    ) -> Result<Option<bool>, AppError> {
-        // Check if code_a subsumes code_b (A is ancestor of B)
-        let subsumes: Option<(bool,)> = sqlx::query_as(
-            "SELECT TRUE FROM closure_table
-             WHERE code_system_id = $1 AND ancestor_code = $2 AND descendant_code = $3",
+        code_system_id: &Uuid,
+        codes: &[&str],
+    ) -> Result<HashMap<String, Concept>, AppError> {
+        let lowered: Vec<String> = codes.iter().map(|code| code.to_lowercase()).collect();
+        let stored = sqlx::query_as::<_, Concept>(
+            "SELECT c.* FROM concepts c
+             JOIN code_systems cs ON cs.id = c.code_system_id
+             WHERE c.code_system_id = $1
+               AND (c.code = ANY($2)
+                 OR (cs.content->'caseSensitive' = 'false'::jsonb
+                     AND lower(c.code) = ANY($3)))
+             ORDER BY c.code",
         )
         .bind(code_system_id)
-        .bind(code_a)
-        .bind(code_b)
-        .fetch_optional(&self.pool)
+        .bind(codes)
+        .bind(&lowered)
+        .fetch_all(&self.pool)
         .await?;
 
-        if subsumes.is_some() {
-            return Ok(Some(true));
+        // As in `get_concept`: an exact match first, else the lowest code differing in case
+        let mut exact = HashMap::new();
+        let mut by_lowercase = HashMap::new();
+        for concept in &stored {
+            exact.insert(concept.code.as_str(), concept);
+            by_lowercase
+                .entry(concept.code.to_lowercase())
+                .or_insert(concept);
         }
+        let concepts = codes
+            .iter()
+            .zip(&lowered)
+            .filter_map(|(code, lowered)| {
+                let concept = exact.get(code).or_else(|| by_lowercase.get(lowered))?;
+                Some((code.to_string(), (*concept).clone()))
+            })
+            .collect();
+        Ok(concepts)
+    }
 
-        // Check if code_b subsumes code_a (B is ancestor of A)
-        let subsumed_by: Option<(bool,)> = sqlx::query_as(
-            "SELECT TRUE FROM closure_table
-             WHERE code_system_id = $1 AND ancestor_code = $2 AND descendant_code = $3",
+    async fn find_concepts(
+        &self,
+        code_system_id: &Uuid,
+        filters: &[PropertyFilter],
+        limit: i64,
+    ) -> Result<Vec<Concept>, AppError> {
+        let mut query = QueryBuilder::new("SELECT * FROM concepts WHERE code_system_id = ");
+        query.push_bind(code_system_id);
+
+        for filter in filters {
+            query
+                .push(
+                    " AND EXISTS (SELECT 1 FROM jsonb_array_elements(\
+                     CASE WHEN jsonb_typeof(properties) = 'array' THEN properties \
+                     ELSE '[]'::jsonb END) p WHERE p->>'code' = ",
+                )
+                .push_bind(filter.code.clone())
+                .push(" AND ");
+            let comparator = filter.comparator.sql();
+            match filter.kind {
+                PropertyValueKind::Text => {
+                    query.push(
+                        "COALESCE(p->>'valueCode', p->>'valueString', p->>'valueBoolean', \
+                         p->'valueCoding'->>'code') = ",
+                    );
+                    query.push_bind(filter.value.clone());
+                }
+                // Values that are not numbers never match rather than failing the cast
+                PropertyValueKind::Number => {
+                    query.push(
+                        "CASE WHEN jsonb_typeof(COALESCE(p->'valueInteger', p->'valueDecimal')) \
+                         = 'number' THEN (COALESCE(p->>'valueInteger', p->>'valueDecimal'))\
+                         ::numeric ",
+                    );
+                    query
+                        .push(comparator)
+                        .push(" ")
+                        .push_bind(filter.value.clone())
+                        .push("::numeric END");
+                }
+                // ISO 8601 values of the same precision order as text; other text never
+                // matches
+                PropertyValueKind::DateTime => {
+                    query
+                        .push(
+                            "CASE WHEN p->>'valueDateTime' ~ '^[0-9]{4}(-[0-9]{2}(-[0-9]{2}\
+                             (T[0-9]{2}:[0-9]{2}:[0-9]{2}(\\.[0-9]+)?\
+                             (Z|[+-][0-9]{2}:[0-9]{2}))?)?)?$' THEN p->>'valueDateTime' ",
+                        )
+                        .push(comparator)
+                        .push(" ");
+                    query.push_bind(filter.value.clone()).push(" END");
+                }
+            }
+            query.push(")");
+        }
+
+        query
+            .push(" ORDER BY ordinal, code LIMIT ")
+            .push_bind(limit);
+
+        let concepts = query
+            .build_query_as::<Concept>()
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(concepts)
+    }
+
+    async fn get_concept_versions(
+        &self,
+        url: &str,
+        code: &str,
+    ) -> Result<Vec<(Option<String>, Option<String>)>, AppError> {
+        let urls = self.url_candidates(url);
+        let versions = sqlx::query_as::<_, (Option<String>, Option<String>)>(
+            "SELECT cs.version, c.display FROM concepts c
+             JOIN code_systems cs ON cs.id = c.code_system_id
+             WHERE cs.url = ANY($1) AND c.code = $2
+             ORDER BY cs.updated_at DESC",
         )
+        .bind(&urls)
+        .bind(code)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(versions)
+    }
+
+    async fn get_hierarchy_concepts(
+        &self,
+        code_system_id: &Uuid,
+        operator: HierarchyOperator,
+        code: &str,
+    ) -> Result<Vec<Concept>, AppError> {
+        let condition = match operator {
+            HierarchyOperator::IsA => {
+                "code IN (SELECT descendant_code FROM closure_table
+                          WHERE code_system_id = $1 AND ancestor_code = $2)"
+            }
+            HierarchyOperator::DescendentOf => {
+                "code IN (SELECT descendant_code FROM closure_table
+                          WHERE code_system_id = $1 AND ancestor_code = $2 AND depth > 0)"
+            }
+            HierarchyOperator::IsNotA => {
+                "code NOT IN (SELECT descendant_code FROM closure_table
+                              WHERE code_system_id = $1 AND ancestor_code = $2)"
+            }
+            HierarchyOperator::Generalizes => {
+                "code IN (SELECT ancestor_code FROM closure_table
+                          WHERE code_system_id = $1 AND descendant_code = $2)"
+            }
+        };
+        let concepts = sqlx::query_as::<_, Concept>(&format!(
+            "SELECT * FROM concepts WHERE code_system_id = $1 AND {condition}
+             ORDER BY ordinal, code"
+        ))
         .bind(code_system_id)
-        .bind(code_b)
-        .bind(code_a)
-        .fetch_optional(&self.pool)
+        .bind(code)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(concepts)
+    }
+
+    async fn get_related_concepts(
+        &self,
+        code_system_id: &Uuid,
+        code: &str,
+        parents: bool,
+    ) -> Result<Vec<Concept>, AppError> {
+        let (known, related) = if parents {
+            ("descendant_code", "ancestor_code")
+        } else {
+            ("ancestor_code", "descendant_code")
+        };
+        let concepts = sqlx::query_as::<_, Concept>(&format!(
+            "SELECT * FROM concepts WHERE code_system_id = $1
+               AND code IN (SELECT {related} FROM closure_table
+                            WHERE code_system_id = $1 AND {known} = $2 AND depth = 1)
+             ORDER BY ordinal, code"
+        ))
+        .bind(code_system_id)
+        .bind(code)
+        .fetch_all(&self.pool)
         .await?;
 
-        if subsumed_by.is_some() {
-            return Ok(Some(false));
+        Ok(concepts)
+    }
+
+    async fn get_concepts(&self, code_system_id: &uuid::Uuid) -> Result<Vec<Concept>, AppError> {
+        let concepts = sqlx::query_as::<_, Concept>(
+            "SELECT * FROM concepts WHERE code_system_id = $1 ORDER BY ordinal, code",
+        )
+        .bind(code_system_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(concepts)
+    }
+
+    async fn count_concepts(&self, code_system_id: &uuid::Uuid) -> Result<i64, AppError> {
+        let count: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM concepts WHERE code_system_id = $1")
+                .bind(code_system_id)
+                .fetch_one(&self.pool)
+                .await?;
+
+        Ok(count)
+    }
+
+    async fn count_concepts_bounded(
+        &self,
+        code_system_id: &uuid::Uuid,
+    ) -> Result<Option<i64>, AppError> {
+        if self.count_limit <= 0 {
+            return self.count_concepts(code_system_id).await.map(Some);
         }
 
-        // No relationship
-        Ok(None)
+        // Counting one row past the limit tells an exact count from one that was cut off
+        let count: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM (
+                SELECT 1 FROM concepts WHERE code_system_id = $1 LIMIT $2
+             ) AS bounded",
+        )
+        .bind(code_system_id)
+        .bind(self.count_limit + 1)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok((count <= self.count_limit).then_some(count))
+    }
+
+    async fn get_concepts_range(
+        &self,
+        code_system_id: &uuid::Uuid,
+        offset: i64,
+        limit: i64,
+    ) -> Result<Vec<Concept>, AppError> {
+        let concepts = sqlx::query_as::<_, Concept>(
+            "SELECT * FROM concepts WHERE code_system_id = $1
+             ORDER BY ordinal, code LIMIT $2 OFFSET $3",
+        )
+        .bind(code_system_id)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(concepts)
+    }
+
+    async fn get_concepts_page(
+        &self,
+        code_system_id: &uuid::Uuid,
+        after: Option<(i32, &str)>,
+        limit: i64,
+    ) -> Result<Vec<Concept>, AppError> {
+        let concepts = sqlx::query_as::<_, Concept>(
+            r#"
+            SELECT * FROM concepts
+            WHERE code_system_id = $1 AND ($2::int IS NULL OR (ordinal, code) > ($2, $3))
+            ORDER BY ordinal, code
+            LIMIT $4
+            "#,
+        )
+        .bind(code_system_id)
+        .bind(after.map(|(ordinal, _)| ordinal))
+        .bind(after.map(|(_, code)| code))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(concepts)
+    }
+
+    async fn check_subsumption(
+        &self,
+        code_system_id: &uuid::Uuid,
+        code_a: &str,
+        code_b: &str,
+    ) -> Result<Option<bool>, AppError> {
+        match self
+            .closure_subsumption(code_system_id, code_a, code_b)
+            .await
+        {
+            Ok(outcome) => Ok(outcome),
+            Err(sqlx::Error::Database(e)) if e.code().as_deref() == Some(QUERY_CANCELED) => {
+                tracing::warn!(
+                    "Closure query for '{}'/'{}' timed out after {}ms, walking parents instead",
+                    code_a,
+                    code_b,
+                    self.closure_timeout_ms
+                );
+                if self.parent_walk(code_system_id, code_a, code_b).await? {
+                    Ok(Some(true))
+                } else if self.parent_walk(code_system_id, code_b, code_a).await? {
+                    Ok(Some(false))
+                } else {
+                    Ok(None)
+                }
+            }
+            Err(e) => Err(e.into()),
+        }
     }
 
     async fn get_value_set_expansion(
         &self,
         value_set_id: &uuid::Uuid,
-    ) -> Result<Option<Vec<serde_json::Value>>, AppError> {
-        let expansion = sqlx::query_as::<_, (sqlx::types::Json<serde_json::Value>,)>(
-            "SELECT expansion FROM value_set_expansions WHERE value_set_id = $1",
+    ) -> Result<Option<StoredExpansion>, AppError> {
+        let expansion = sqlx::query_as::<
+            _,
+            (
+                sqlx::types::Json<serde_json::Value>,
+                chrono::DateTime<chrono::Utc>,
+            ),
+        >(
+            "SELECT expansion_data, created_at FROM value_set_expansions
+             WHERE value_set_id = $1 ORDER BY created_at DESC LIMIT 1",
         )
         .bind(value_set_id)
         .fetch_optional(&self.pool)
         .await?;
 
-        Ok(expansion.map(|(json,)| {
+        Ok(expansion.map(|(json, created_at)| StoredExpansion {
             // Return the expansion as an array of contains elements
-            if let Some(contains) = json.0.get("contains").and_then(|c| c.as_array()) {
-                contains.clone()
-            } else {
-                vec![]
+            contains: json
+                .0
+                .get("contains")
+                .and_then(|c| c.as_array())
+                .cloned()
+                .unwrap_or_default(),
+            created_at,
+        }))
+    }
+
+    async fn store_value_set_expansion(
+        &self,
+        value_set_id: &uuid::Uuid,
+        expansion: serde_json::Value,
+    ) -> Result<bool, AppError> {
+        let mut tx = self.pool.begin().await?;
+
+        let replaced = sqlx::query("DELETE FROM value_set_expansions WHERE value_set_id = $1")
+            .bind(value_set_id)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected()
+            > 0;
+
+        sqlx::query(
+            "INSERT INTO value_set_expansions (value_set_id, expansion_data) VALUES ($1, $2)",
+        )
+        .bind(value_set_id)
+        .bind(sqlx::types::Json(expansion))
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(replaced)
+    }
+
+    async fn get_expansion_status(
+        &self,
+        value_set_id: &Uuid,
+    ) -> Result<Option<ExpansionStatus>, AppError> {
+        let computed_at: Option<chrono::DateTime<chrono::Utc>> = sqlx::query_scalar(
+            "SELECT MAX(created_at) FROM value_set_expansions WHERE value_set_id = $1",
+        )
+        .bind(value_set_id)
+        .fetch_one(&self.pool)
+        .await?;
+        let Some(computed_at) = computed_at else {
+            return Ok(None);
+        };
+
+        let mut newer_code_systems = Vec::new();
+        for (system, version) in self.get_value_set_systems(value_set_id).await? {
+            let newer = sqlx::query_as::<_, (String, Option<String>)>(
+                "SELECT url, version FROM code_systems
+                 WHERE url = ANY($1) AND ($2::text IS NULL OR version = $2)
+                   AND updated_at > $3
+                 ORDER BY url, version",
+            )
+            .bind(self.url_candidates(&system))
+            .bind(version)
+            .bind(computed_at)
+            .fetch_all(&self.pool)
+            .await?;
+            for code_system in newer {
+                if !newer_code_systems.contains(&code_system) {
+                    newer_code_systems.push(code_system);
+                }
             }
+        }
+
+        Ok(Some(ExpansionStatus {
+            computed_at,
+            newer_code_systems,
         }))
     }
+
+    // ========== Maintenance ==========
+
+    async fn exists_by_id(
+        &self,
+        resource_type: ResourceType,
+        id: &Uuid,
+    ) -> Result<Option<chrono::DateTime<chrono::Utc>>, AppError> {
+        let updated_at = sqlx::query_scalar(&format!(
+            "SELECT updated_at FROM {} WHERE id = $1",
+            resource_type.table()
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(updated_at)
+    }
+
+    async fn reindex_resources(
+        &self,
+        resource_type: ResourceType,
+        id: Option<&uuid::Uuid>,
+    ) -> Result<u64, AppError> {
+        // Each derived column with the value it should hold according to the stored content;
+        // status falls back to 'unknown' when the content has none allowed by the CHECK
+        let mut columns = vec![
+            (
+                "status",
+                "CASE WHEN content->>'status' IN ('draft', 'active', 'retired', 'unknown') \
+                 THEN content->>'status' ELSE 'unknown' END",
+            ),
+            ("name", "content->>'name'"),
+            ("title", "content->>'title'"),
+            (
+                "fhir_version",
+                "COALESCE(content->>'fhirVersion', fhir_version)",
+            ),
+        ];
+        let table = match resource_type {
+            ResourceType::CodeSystem => "code_systems",
+            ResourceType::ValueSet => "value_sets",
+            ResourceType::ConceptMap => {
+                columns.push((
+                    "source_uri",
+                    "COALESCE(content->>'sourceUri', content->>'sourceCanonical')",
+                ));
+                columns.push((
+                    "target_uri",
+                    "COALESCE(content->>'targetUri', content->>'targetCanonical')",
+                ));
+                "concept_maps"
+            }
+        };
+
+        let join = |f: &dyn Fn(&(&str, &str)) -> String| {
+            columns.iter().map(f).collect::<Vec<_>>().join(", ")
+        };
+        let assignments = join(&|(column, _)| format!("{column} = d.{column}"));
+        let derived = join(&|(column, expr)| format!("{expr} AS {column}"));
+        let current = join(&|(column, _)| format!("r.{column}"));
+        let expected = join(&|(column, _)| format!("d.{column}"));
+
+        // Only rows that actually drifted are touched, so the count reflects repairs
+        let query = format!(
+            "UPDATE {table} AS r SET {assignments}
+             FROM (SELECT id, {derived} FROM {table} WHERE $1::uuid IS NULL OR id = $1) AS d
+             WHERE r.id = d.id AND ({current}) IS DISTINCT FROM ({expected})"
+        );
+
+        let result = sqlx::query(&query).bind(id).execute(&self.pool).await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+/// Build a JSONB containment document for the `_tag`, `_profile` and `_security` search
+/// parameters, to be matched with `content -> 'meta' @> $n`
+fn meta_filter(params: &SearchParams) -> Option<serde_json::Value> {
+    let mut meta = serde_json::Map::new();
+
+    if let Some(tag) = &params.tag {
+        meta.insert("tag".to_string(), serde_json::json!([token_coding(tag)]));
+    }
+    if let Some(profile) = &params.profile {
+        meta.insert("profile".to_string(), serde_json::json!([profile]));
+    }
+    if let Some(security) = &params.security {
+        meta.insert(
+            "security".to_string(),
+            serde_json::json!([token_coding(security)]),
+        );
+    }
+
+    (!meta.is_empty()).then_some(serde_json::Value::Object(meta))
+}
+
+/// Parse a FHIR token search value (`system|code`, `system|`, `|code` or `code`) into
+/// the Coding fields it constrains. That a `|code` Coding has no system is checked by
+/// [`PostgresStore::search_query`].
+fn token_coding(token: &str) -> serde_json::Value {
+    let (system, code) = match token.split_once('|') {
+        Some((system, code)) => (system, code),
+        None => ("", token),
+    };
+
+    let mut coding = serde_json::Map::new();
+    if !system.is_empty() {
+        coding.insert("system".to_string(), system.into());
+    }
+    if !code.is_empty() {
+        coding.insert("code".to_string(), code.into());
+    }
+    serde_json::Value::Object(coding)
 }
@@ -1,7 +1,11 @@
 use crate::error::AppError;
-use crate::models::{CodeSystem, Concept, ConceptMap, SearchParams, ValueSet};
+use crate::models::{
+    ClosureCoverage, CodeSystem, Concept, ConceptMap, ExpansionStatus, HierarchyOperator,
+    ImportRecord, PropertyFilter, ResourceType, SearchParams, StoredExpansion, ValueSet,
+};
 use async_trait::async_trait;
 use serde_json::Value;
+use std::collections::HashMap;
 
 /// Trait for terminology storage backend
 /// This allows for pluggable storage implementations (PostgreSQL, SQLite, in-memory, etc.)
@@ -16,6 +20,14 @@ pub trait TerminologyStore: Send + Sync {
         version: Option<&str>,
     ) -> Result<Option<CodeSystem>, AppError>;
     async fn get_code_system_by_id(&self, id: &uuid::Uuid) -> Result<Option<CodeSystem>, AppError>;
+    /// Installed versions of the CodeSystem `url` with their last update, the default
+    /// version (the one selected when no version is given) first
+    async fn list_versions(
+        &self,
+        url: &str,
+    ) -> Result<Vec<(Option<String>, chrono::DateTime<chrono::Utc>)>, AppError>;
+    /// The supplements linked to `code_system`, either for its version or for any version
+    async fn get_supplements(&self, code_system: &CodeSystem) -> Result<Vec<CodeSystem>, AppError>;
     async fn update_code_system(&self, cs: CodeSystem) -> Result<CodeSystem, AppError>;
     async fn delete_code_system(&self, url: &str, version: Option<&str>) -> Result<(), AppError>;
     async fn search_code_systems(&self, params: &SearchParams)
@@ -28,7 +40,19 @@ pub trait TerminologyStore: Send + Sync {
         url: &str,
         version: Option<&str>,
     ) -> Result<Option<ValueSet>, AppError>;
+    /// The ValueSet version most recently updated before `before`
+    async fn get_value_set_as_of(
+        &self,
+        url: &str,
+        before: chrono::DateTime<chrono::Utc>,
+    ) -> Result<Option<ValueSet>, AppError>;
     async fn get_value_set_by_id(&self, id: &uuid::Uuid) -> Result<Option<ValueSet>, AppError>;
+    /// Every `(system, version)` a ValueSet's `compose.include` references, following
+    /// included ValueSets transitively; each ValueSet is visited once
+    async fn get_value_set_systems(
+        &self,
+        value_set_id: &uuid::Uuid,
+    ) -> Result<Vec<(String, Option<String>)>, AppError>;
     async fn update_value_set(&self, vs: ValueSet) -> Result<ValueSet, AppError>;
     async fn delete_value_set(&self, url: &str, version: Option<&str>) -> Result<(), AppError>;
     async fn search_value_sets(&self, params: &SearchParams) -> Result<Vec<ValueSet>, AppError>;
@@ -41,22 +65,104 @@ pub trait TerminologyStore: Send + Sync {
         version: Option<&str>,
     ) -> Result<Option<ConceptMap>, AppError>;
     async fn get_concept_map_by_id(&self, id: &uuid::Uuid) -> Result<Option<ConceptMap>, AppError>;
+    /// ConceptMaps with a group whose `source` (or `target`, when `reverse`) is `system`
+    async fn find_concept_maps_for_system(
+        &self,
+        system: &str,
+        reverse: bool,
+    ) -> Result<Vec<ConceptMap>, AppError>;
     async fn update_concept_map(&self, cm: ConceptMap) -> Result<ConceptMap, AppError>;
     async fn delete_concept_map(&self, url: &str, version: Option<&str>) -> Result<(), AppError>;
     async fn search_concept_maps(&self, params: &SearchParams)
         -> Result<Vec<ConceptMap>, AppError>;
 
+    /// Url and version of every installed CodeSystem, ordered by url and version
+    async fn list_code_system_versions(&self) -> Result<Vec<(String, Option<String>)>, AppError>;
+
+    /// Problems with the database schema: missing tables or unapplied migrations.
+    /// Empty when the schema is complete.
+    async fn check_schema(&self) -> Result<Vec<String>, AppError>;
+
     // Statistics
     async fn count_code_systems(&self) -> Result<i64, AppError>;
     async fn count_value_sets(&self) -> Result<i64, AppError>;
     async fn count_concept_maps(&self) -> Result<i64, AppError>;
+    /// Closure table row count of every CodeSystem, zero for those without closure
+    async fn closure_coverage(&self) -> Result<Vec<ClosureCoverage>, AppError>;
+    /// Most recent package imports, newest first
+    async fn recent_imports(&self, limit: i64) -> Result<Vec<ImportRecord>, AppError>;
 
     // Concept operations (for $lookup and $validate-code)
+    /// The concept `code` of a CodeSystem. Codes match exactly, or ignoring case when the
+    /// CodeSystem is `caseSensitive: false`.
     async fn get_concept(
         &self,
         code_system_id: &uuid::Uuid,
         code: &str,
     ) -> Result<Option<Concept>, AppError>;
+    /// The stored concept of each of `codes` in a CodeSystem, keyed by the code as given
+    /// and matched like [`TerminologyStore::get_concept`], in one query. Codes that are not
+    /// defined are left out.
+    async fn get_concepts_by_codes(
+        &self,
+        code_system_id: &uuid::Uuid,
+        codes: &[&str],
+    ) -> Result<HashMap<String, Concept>, AppError>;
+    /// The version and display of `code` in every installed version of the CodeSystem
+    /// `url` that defines it, most recently updated first
+    async fn get_concept_versions(
+        &self,
+        url: &str,
+        code: &str,
+    ) -> Result<Vec<(Option<String>, Option<String>)>, AppError>;
+    /// Up to `limit` concepts of a CodeSystem, in CodeSystem order, having a property
+    /// matching each of `filters`
+    async fn find_concepts(
+        &self,
+        code_system_id: &uuid::Uuid,
+        filters: &[PropertyFilter],
+        limit: i64,
+    ) -> Result<Vec<Concept>, AppError>;
+    /// The concepts of a CodeSystem related to `code` by `operator` in its closure table,
+    /// in CodeSystem order
+    async fn get_hierarchy_concepts(
+        &self,
+        code_system_id: &uuid::Uuid,
+        operator: HierarchyOperator,
+        code: &str,
+    ) -> Result<Vec<Concept>, AppError>;
+    /// The direct parents (`parents`) or else the direct children of `code` in a
+    /// CodeSystem's closure table, in CodeSystem order
+    async fn get_related_concepts(
+        &self,
+        code_system_id: &uuid::Uuid,
+        code: &str,
+        parents: bool,
+    ) -> Result<Vec<Concept>, AppError>;
+    /// All concepts of a CodeSystem, in CodeSystem order
+    async fn get_concepts(&self, code_system_id: &uuid::Uuid) -> Result<Vec<Concept>, AppError>;
+    async fn count_concepts(&self, code_system_id: &uuid::Uuid) -> Result<i64, AppError>;
+    /// The number of concepts of a CodeSystem, or None when it has more than the
+    /// configured counting limit and the exact count would be too expensive
+    async fn count_concepts_bounded(
+        &self,
+        code_system_id: &uuid::Uuid,
+    ) -> Result<Option<i64>, AppError>;
+    /// Up to `limit` concepts in CodeSystem order, skipping the first `offset`
+    async fn get_concepts_range(
+        &self,
+        code_system_id: &uuid::Uuid,
+        offset: i64,
+        limit: i64,
+    ) -> Result<Vec<Concept>, AppError>;
+    /// Up to `limit` concepts in CodeSystem order, starting after the concept at
+    /// `after` = `(ordinal, code)` (keyset paging)
+    async fn get_concepts_page(
+        &self,
+        code_system_id: &uuid::Uuid,
+        after: Option<(i32, &str)>,
+        limit: i64,
+    ) -> Result<Vec<Concept>, AppError>;
 
     // Subsumption operations (for $subsumes)
     /// Returns Some(true) if code_a subsumes code_b, Some(false) if code_b subsumes code_a, None if no relationship
@@ -71,5 +177,35 @@ pub trait TerminologyStore: Send + Sync {
     async fn get_value_set_expansion(
         &self,
         value_set_id: &uuid::Uuid,
-    ) -> Result<Option<Vec<Value>>, AppError>;
+    ) -> Result<Option<StoredExpansion>, AppError>;
+    /// Replaces any stored expansion; returns true if a previous expansion was replaced
+    async fn store_value_set_expansion(
+        &self,
+        value_set_id: &uuid::Uuid,
+        expansion: Value,
+    ) -> Result<bool, AppError>;
+    /// When the stored expansion was computed and which referenced CodeSystems changed
+    /// since, or None when the ValueSet has no stored expansion
+    async fn get_expansion_status(
+        &self,
+        value_set_id: &uuid::Uuid,
+    ) -> Result<Option<ExpansionStatus>, AppError>;
+
+    /// The last update time of the resource with `id`, or None when it does not exist.
+    /// Does not load the resource content.
+    async fn exists_by_id(
+        &self,
+        resource_type: ResourceType,
+        id: &uuid::Uuid,
+    ) -> Result<Option<chrono::DateTime<chrono::Utc>>, AppError>;
+
+    // Maintenance operations
+    /// Re-derive the indexed columns (status, name, title, fhir_version and, for ConceptMaps,
+    /// source_uri/target_uri) from `content` for one resource, or all of the type when `id`
+    /// is None. Returns the number of rows whose columns had drifted and were updated.
+    async fn reindex_resources(
+        &self,
+        resource_type: ResourceType,
+        id: Option<&uuid::Uuid>,
+    ) -> Result<u64, AppError>;
 }
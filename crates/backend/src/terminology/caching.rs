@@ -0,0 +1,83 @@
+use crate::models::{CodeSystem, ValueSet};
+
+/// The resource version an operation result was computed from, with the other resource
+/// versions it also depends on, such as the CodeSystems a ValueSet includes
+#[derive(Debug, Clone)]
+pub struct ResolvedVersion {
+    pub url: String,
+    pub version: Option<String>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+    /// True when the request (or, for a dependency, the depending resource) selected a
+    /// concrete version rather than the latest one
+    pub pinned: bool,
+    pub dependencies: Vec<ResolvedVersion>,
+}
+
+impl ResolvedVersion {
+    pub fn code_system(code_system: &CodeSystem, pinned: bool) -> Self {
+        Self {
+            url: code_system.url.clone(),
+            version: code_system.version.clone(),
+            updated_at: code_system.updated_at,
+            pinned,
+            dependencies: Vec::new(),
+        }
+    }
+
+    pub fn value_set(value_set: &ValueSet, pinned: bool) -> Self {
+        Self {
+            url: value_set.url.clone(),
+            version: value_set.version.clone(),
+            updated_at: value_set.updated_at,
+            pinned,
+            dependencies: Vec::new(),
+        }
+    }
+
+    /// Record that the result also depends on `other` and everything it depends on
+    pub fn depends_on(&mut self, mut other: ResolvedVersion) {
+        let nested = std::mem::take(&mut other.dependencies);
+        for dependency in std::iter::once(other).chain(nested) {
+            let known = self.dependencies.iter().any(|d| {
+                d.url == dependency.url
+                    && d.version == dependency.version
+                    && d.updated_at == dependency.updated_at
+            });
+            if !known {
+                self.dependencies.push(dependency);
+            }
+        }
+    }
+
+    /// Whether this and every dependency is a concrete, pinned version, so the result
+    /// cannot change without a new version being selected
+    pub fn fully_pinned(&self) -> bool {
+        std::iter::once(self)
+            .chain(&self.dependencies)
+            .all(|resolved| resolved.pinned && resolved.version.is_some())
+    }
+}
+
+/// An operation result tagged with the resource version it was computed from, if any
+#[derive(Clone)]
+pub struct Cacheable<T> {
+    pub body: T,
+    pub resolved: Option<ResolvedVersion>,
+}
+
+impl<T> Cacheable<T> {
+    pub fn new(body: T, resolved: ResolvedVersion) -> Self {
+        Self {
+            body,
+            resolved: Some(resolved),
+        }
+    }
+
+    /// A result that was not computed from a stored resource and must not be cached
+    pub fn uncached(body: T) -> Self {
+        Self {
+            body,
+            resolved: None,
+        }
+    }
+}
@@ -0,0 +1,1762 @@
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashSet;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use super::{
+    localized_designation, property_codes, split_canonical, Cacheable, ParameterIssues, Parameters,
+    ResolvedVersion,
+};
+use crate::error::AppError;
+use crate::models::{Concept, HierarchyOperator, ValueSet};
+use crate::store::TerminologyStore;
+
+/// Options of an `$expand` request, parsed the same way from the query string and from a
+/// POST Parameters body. Identical options produce identical expansions, so they also key
+/// the coalescing of concurrent requests.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ExpandOptions {
+    pub url: Option<String>,
+    pub value_set_version: Option<String>,
+    pub date: Option<String>,
+    pub system_versions: SystemVersions,
+    pub filter: Option<String>,
+    pub display_language: Option<String>,
+    /// Also true when filtering, so that matching children are not lost with
+    /// non-matching parents
+    pub exclude_nested: bool,
+    pub active_only: bool,
+    pub exclude_not_for_ui: bool,
+    pub include_designations: bool,
+    pub properties: Vec<String>,
+    /// Levels of hierarchical nesting returned, roots being level 1; entries whose
+    /// children are cut off are flagged with [`HAS_CHILDREN_EXTENSION`]
+    pub depth: Option<usize>,
+    pub offset: usize,
+    pub count: usize,
+}
+
+/// Extension flagging a hierarchical expansion entry whose children were left out by
+/// `depth`
+pub const HAS_CHILDREN_EXTENSION: &str =
+    "https://github.com/octofhir/term-squid/StructureDefinition/expansion-has-children";
+
+impl ExpandOptions {
+    /// Whether producing the expansion needs to look at every concept rather than a page
+    fn inspects_concepts(&self) -> bool {
+        self.filter.is_some() || self.active_only || self.exclude_not_for_ui
+    }
+
+    /// `expansion.parameter` entries echoing the options
+    fn parameters(&self) -> Vec<serde_json::Value> {
+        let mut parameters = vec![
+            json!({ "name": "excludeNested", "valueBoolean": self.exclude_nested }),
+            json!({ "name": "activeOnly", "valueBoolean": self.active_only }),
+            json!({ "name": "excludeNotForUI", "valueBoolean": self.exclude_not_for_ui }),
+            json!({ "name": "includeDesignations", "valueBoolean": self.include_designations }),
+        ];
+        parameters.extend(
+            self.properties
+                .iter()
+                .map(|code| json!({ "name": "property", "valueString": code })),
+        );
+        parameters.extend(self.system_versions.parameters());
+        if let Some(depth) = self.depth {
+            parameters.push(json!({ "name": "depth", "valueInteger": depth }));
+        }
+        parameters.push(json!({ "name": "offset", "valueInteger": self.offset }));
+        parameters.push(json!({ "name": "count", "valueInteger": self.count }));
+        if let Some(language) = &self.display_language {
+            parameters.push(json!({ "name": "displayLanguage", "valueCode": language }));
+        }
+        if let (None, Some(date)) = (&self.value_set_version, &self.date) {
+            parameters.push(json!({ "name": "date", "valueDateTime": date }));
+        }
+        parameters
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ExpandParams {
+    pub url: Option<String>,
+    #[serde(rename = "valueSetVersion")]
+    pub value_set_version: Option<String>,
+    /// Expand the ValueSet version current at this FHIR dateTime (ignored with
+    /// `valueSetVersion`)
+    pub date: Option<String>,
+    pub filter: Option<String>,
+    #[serde(rename = "displayLanguage")]
+    pub display_language: Option<String>,
+    /// Flatten hierarchical (`contains.contains`) expansions into a single list
+    #[serde(rename = "excludeNested")]
+    pub exclude_nested: Option<bool>,
+    /// Leave out concepts marked `inactive`
+    #[serde(rename = "activeOnly")]
+    pub active_only: Option<bool>,
+    /// Leave abstract (not selectable) concepts out of flat expansions; hierarchical
+    /// expansions keep them as grouping structure
+    #[serde(rename = "excludeNotForUI")]
+    pub exclude_not_for_ui: Option<bool>,
+    /// `system|version` used for includes of the system that pin no version, and for
+    /// implicit (`{system}?fhir_vs`) ValueSets
+    #[serde(rename = "system-version")]
+    pub system_version: Option<String>,
+    /// `system|version` that includes of the system must pin, if they pin one
+    #[serde(rename = "check-system-version")]
+    pub check_system_version: Option<String>,
+    /// `system|version` used for includes of the system whatever they pin
+    #[serde(rename = "force-system-version")]
+    pub force_system_version: Option<String>,
+    /// Return each concept's designations in `contains.designation`
+    #[serde(rename = "includeDesignations")]
+    pub include_designations: Option<bool>,
+    /// Comma-separated property codes returned in `contains.property`
+    pub property: Option<String>,
+    /// Levels of hierarchical nesting returned; deeper children are left out and their
+    /// parents flagged as having children. Only imported expansions can be hierarchical; a
+    /// `depth` on a flat one is rejected.
+    pub depth: Option<i64>,
+    /// Entries skipped: the root entries of a hierarchical (imported) expansion, each with
+    /// its subtree, else concepts. `expansion.total` counts every concept either way.
+    pub offset: Option<i64>,
+    /// Entries returned, counted like `offset`
+    pub count: Option<i64>,
+}
+
+/// Reading the options from a request
+impl ExpandOptions {
+    /// Apply defaults to query-string parameters, rejecting a negative `offset` or `count`
+    /// and a `depth` below 1
+    pub fn from_query(params: ExpandParams) -> Result<Self, AppError> {
+        let mut issues = ParameterIssues::default();
+        let depth = match params.depth {
+            Some(depth) if depth < 1 => {
+                issues.invalid(format!("depth must be at least 1, got {depth}"));
+                None
+            }
+            depth => depth.map(|depth| depth as usize),
+        };
+        let mut non_negative = |name: &str, value: Option<i64>, default: usize| match value {
+            Some(value) if value < 0 => {
+                issues.invalid(format!("{name} must not be negative, got {value}"));
+                default
+            }
+            Some(value) => value as usize,
+            None => default,
+        };
+        let offset = non_negative("offset", params.offset, 0);
+        let count = non_negative("count", params.count, 100);
+        issues.check()?;
+
+        // A piped `url|version` selects the ValueSet version like `valueSetVersion`
+        let (url, value_set_version) = match params.url.as_deref() {
+            Some(canonical) => {
+                let (url, version) =
+                    split_canonical(canonical, params.value_set_version.as_deref())?;
+                (Some(url.to_string()), version.map(str::to_string))
+            }
+            None => (None, params.value_set_version),
+        };
+
+        Ok(Self {
+            properties: property_codes(params.property.as_deref())
+                .into_iter()
+                .map(str::to_string)
+                .collect(),
+            exclude_nested: params.exclude_nested.unwrap_or(false) || params.filter.is_some(),
+            active_only: params.active_only.unwrap_or(false),
+            exclude_not_for_ui: params.exclude_not_for_ui.unwrap_or(false),
+            include_designations: params.include_designations.unwrap_or(false),
+            depth,
+            url,
+            value_set_version,
+            date: params.date,
+            system_versions: SystemVersions {
+                default: params.system_version,
+                check: params.check_system_version,
+                force: params.force_system_version,
+            },
+            filter: params.filter,
+            display_language: params.display_language,
+            offset,
+            count,
+        })
+    }
+
+    /// Read the same options from a Parameters body
+    pub fn from_parameters(params: &Parameters) -> Result<Self, AppError> {
+        let string = |name: &str| params.get_string(name).map(str::to_string);
+        let canonical = |name: &str| {
+            params
+                .get_canonical(name)
+                .or_else(|| params.get_uri(name))
+                .or_else(|| params.get_string(name))
+                .map(str::to_string)
+        };
+        let properties = params.get_all_codes("property");
+
+        Self::from_query(ExpandParams {
+            url: params
+                .get_uri("url")
+                .or_else(|| params.get_string("url"))
+                .map(str::to_string),
+            value_set_version: string("valueSetVersion"),
+            date: params.get_date_time("date").map(str::to_string),
+            filter: string("filter"),
+            display_language: params.get_code("displayLanguage").map(str::to_string),
+            exclude_nested: params.get_boolean("excludeNested"),
+            active_only: params.get_boolean("activeOnly"),
+            exclude_not_for_ui: params.get_boolean("excludeNotForUI"),
+            system_version: canonical("system-version"),
+            check_system_version: canonical("check-system-version"),
+            force_system_version: canonical("force-system-version"),
+            include_designations: params.get_boolean("includeDesignations"),
+            property: (!properties.is_empty()).then(|| properties.join(",")),
+            depth: params.get_integer("depth"),
+            offset: params.get_integer("offset"),
+            count: params.get_integer("count"),
+        })
+    }
+}
+
+/// The `$expand` parameters choosing CodeSystem versions, each a `system|version`
+/// canonical: `system-version` for includes of the system that pin no version,
+/// `check-system-version` to reject includes pinning a different version, and
+/// `force-system-version` to override whatever an include pins
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct SystemVersions {
+    pub default: Option<String>,
+    pub check: Option<String>,
+    pub force: Option<String>,
+}
+
+impl SystemVersions {
+    /// The version of `system` to expand an include with, given the version it pins.
+    /// `check-system-version` also supplies the version when nothing is pinned.
+    pub fn resolve<'a>(
+        &'a self,
+        system: &str,
+        pinned: Option<&'a str>,
+    ) -> Result<Option<&'a str>, AppError> {
+        let for_system = |canonical: &'a Option<String>| {
+            canonical
+                .as_deref()
+                .and_then(|canonical| canonical.split_once('|'))
+                .filter(|(s, _)| *s == system)
+                .map(|(_, version)| version)
+        };
+
+        if let Some(forced) = for_system(&self.force) {
+            return Ok(Some(forced));
+        }
+        match (pinned, for_system(&self.check)) {
+            (Some(pinned), Some(checked)) if pinned != checked => {
+                Err(AppError::BadRequest(format!(
+                    "The ValueSet uses version '{pinned}' of '{system}', \
+                     but check-system-version requires '{checked}'"
+                )))
+            }
+            (Some(pinned), _) => Ok(Some(pinned)),
+            (None, Some(checked)) => Ok(Some(checked)),
+            (None, None) => Ok(for_system(&self.default)),
+        }
+    }
+
+    /// `expansion.parameter` entries echoing the given parameters
+    fn parameters(&self) -> Vec<serde_json::Value> {
+        [
+            ("system-version", &self.default),
+            ("check-system-version", &self.check),
+            ("force-system-version", &self.force),
+        ]
+        .into_iter()
+        .filter_map(|(name, canonical)| {
+            Some(json!({ "name": name, "valueCanonical": canonical.as_ref()? }))
+        })
+        .collect()
+    }
+}
+
+/// Expand a ValueSet. The entries are processed in this order, so the options compose:
+/// properties are selected while the hierarchy is intact (`parent` comes from it),
+/// inactive concepts are dropped with their active children moved up, the result is
+/// flattened, abstract and non-matching concepts are dropped, displays are localized,
+/// designations are removed unless requested and finally the paged hierarchy is cut off
+/// below `depth`.
+pub async fn expand(
+    store: Arc<dyn TerminologyStore>,
+    url: &str,
+    options: ExpandOptions,
+) -> Result<Cacheable<serde_json::Value>, AppError> {
+    let source = match implicit_value_set_system(url) {
+        Some(system) => implicit_source(&store, url, system, &options).await?,
+        None => stored_source(&store, url, &options).await?,
+    };
+
+    let (offset, count) = (options.offset, options.count);
+
+    let strategy = plan_expansion(&source, &options);
+    tracing::debug!("Expanding ValueSet '{url}' with the {strategy:?} strategy");
+
+    let ExpansionSource {
+        content,
+        precomputed,
+        mut resolved,
+        parameters: source_parameters,
+    } = source;
+
+    // The CodeSystem versions the entries are read from
+    let mut dependencies = Vec::new();
+
+    // Entries still to be filtered and paged, or an already paged window with its total,
+    // which is None when the system is too large to count
+    let (mut expansion_entries, paged_total) = match strategy {
+        ExpansionStrategy::Precomputed => (precomputed.unwrap_or_default(), None),
+        ExpansionStrategy::Compose => {
+            let compose = content.get("compose");
+            let entries =
+                compose_entries(&store, compose, &options.system_versions, &mut dependencies)
+                    .await?;
+            (entries, None)
+        }
+        ExpansionStrategy::SystemPage => {
+            let include = whole_system_include(&content)
+                .ok_or_else(|| anyhow::anyhow!("planned a system page without a system include"))?;
+            let (entries, total) = system_page(
+                &store,
+                include,
+                &options.system_versions,
+                offset,
+                count,
+                &mut dependencies,
+            )
+            .await?;
+            (entries, Some(total))
+        }
+    };
+
+    let properties: Vec<&str> = options.properties.iter().map(String::as_str).collect();
+    select_properties(&mut expansion_entries, &properties, None);
+
+    if options.active_only {
+        retain_active(&mut expansion_entries);
+    }
+
+    if options.exclude_nested {
+        expansion_entries = flatten_contains(expansion_entries);
+    }
+
+    // Hierarchical expansions keep abstract concepts as the parents of their children
+    let flat = !expansion_entries
+        .iter()
+        .any(|entry| entry.get("contains").is_some());
+    if options.exclude_not_for_ui && flat {
+        expansion_entries.retain(|entry| entry.get("abstract") != Some(&json!(true)));
+    }
+
+    // Only a stored hierarchical expansion has levels for depth to cut off
+    if options.depth.is_some() && flat {
+        return Err(AppError::BadRequest(
+            "depth applies only to hierarchical expansions, and this expansion is flat".to_string(),
+        ));
+    }
+
+    // Apply filter if provided: every whitespace-separated term must match
+    if let Some(filter_text) = &options.filter {
+        let terms: Vec<String> = filter_text
+            .split_whitespace()
+            .map(str::to_lowercase)
+            .collect();
+        expansion_entries.retain(|entry| matches_filter(entry, &terms));
+    }
+
+    // Show displays in the requested language where the entry carries a matching designation
+    if let Some(language) = &options.display_language {
+        localize_contains(&mut expansion_entries, language);
+    }
+
+    if !options.include_designations {
+        remove_designations(&mut expansion_entries);
+    }
+
+    // The total counts every concept, including those nested under a parent. Hierarchical
+    // expansions are paged over their root entries, each root keeping its full subtree
+    let (total, mut paginated_entries) = match paged_total {
+        Some(total) => (total, expansion_entries),
+        None => (
+            Some(count_contains(&expansion_entries)),
+            expansion_entries
+                .into_iter()
+                .skip(offset)
+                .take(count)
+                .collect(),
+        ),
+    };
+
+    if let Some(depth) = options.depth {
+        truncate_contains(&mut paginated_entries, depth);
+    }
+
+    let mut expansion_parameters = options.parameters();
+    expansion_parameters.extend(source_parameters);
+    if total.is_none() {
+        // Rather than a misleading number, say the total was not computed
+        expansion_parameters.push(json!({ "name": "total-unknown", "valueBoolean": true }));
+    }
+
+    // Build ValueSet with expansion
+    let mut expansion = json!({
+        "identifier": format!("urn:uuid:{}", Uuid::new_v4()),
+        "timestamp": chrono::Utc::now().to_rfc3339(),
+        "offset": offset,
+        "parameter": expansion_parameters,
+        "contains": paginated_entries
+    });
+    if let Some(total) = total {
+        expansion["total"] = json!(total);
+    }
+
+    // Extract the base ValueSet content and add expansion
+    let mut result = content;
+    if let Some(obj) = result.as_object_mut() {
+        obj.insert("expansion".to_string(), expansion);
+    }
+
+    for dependency in dependencies {
+        resolved.depends_on(dependency);
+    }
+    Ok(Cacheable::new(result, resolved))
+}
+
+/// Whether `system`/`code` is a member of the ValueSet `canonical` (`url` or `url|version`),
+/// with the resource versions read to decide. A usable stored expansion is searched for
+/// the code; otherwise the compose rules are evaluated for this one code rather than
+/// expanding the whole ValueSet.
+pub async fn value_set_contains(
+    store: Arc<dyn TerminologyStore>,
+    canonical: &str,
+    system: &str,
+    code: &str,
+) -> Result<Cacheable<bool>, AppError> {
+    let (url, value_set_version) = split_canonical(canonical, None)?;
+    let options = ExpandOptions {
+        url: Some(url.to_string()),
+        value_set_version: value_set_version.map(String::from),
+        date: None,
+        system_versions: SystemVersions::default(),
+        filter: None,
+        display_language: None,
+        exclude_nested: true,
+        active_only: false,
+        exclude_not_for_ui: false,
+        include_designations: false,
+        properties: Vec::new(),
+        depth: None,
+        offset: 0,
+        count: 0,
+    };
+    let source = match implicit_value_set_system(url) {
+        Some(implicit) => implicit_source(&store, url, implicit, &options).await?,
+        None => stored_source(&store, url, &options).await?,
+    };
+
+    let strategy = plan_expansion(&source, &options);
+    let ExpansionSource {
+        content,
+        precomputed,
+        mut resolved,
+        ..
+    } = source;
+
+    let contained = match strategy {
+        ExpansionStrategy::Precomputed => {
+            let coding = (system.to_string(), code.to_string());
+            flatten_contains(precomputed.unwrap_or_default())
+                .iter()
+                .any(|entry| coding_key(entry) == coding)
+        }
+        ExpansionStrategy::Compose | ExpansionStrategy::SystemPage => {
+            let mut dependencies = Vec::new();
+            let contained = compose_contains(
+                &store,
+                content.get("compose"),
+                &options.system_versions,
+                (system, code),
+                &mut dependencies,
+            )
+            .await?;
+            for dependency in dependencies {
+                resolved.depends_on(dependency);
+            }
+            contained
+        }
+    };
+    Ok(Cacheable::new(contained, resolved))
+}
+
+/// The ValueSet an expansion is built from
+struct ExpansionSource {
+    content: serde_json::Value,
+    /// The stored expansion's entries, when one exists that is usable
+    precomputed: Option<Vec<serde_json::Value>>,
+    resolved: ResolvedVersion,
+    /// Extra `expansion.parameter` entries describing how the source was resolved
+    parameters: Vec<serde_json::Value>,
+}
+
+/// How [`expand`] produces the expansion entries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ExpansionStrategy {
+    /// Serve the stored expansion
+    Precomputed,
+    /// Read only the requested page of a single whole-system include, with the total
+    /// counted in the database
+    SystemPage,
+    /// Expand the compose definition in memory, then filter and page
+    Compose,
+}
+
+/// Pick the cheapest way to produce the expansion: a usable stored expansion, else a
+/// database-side page when the ValueSet is one whole CodeSystem and no parameter needs to
+/// inspect every concept, else the in-memory compose expansion. A stored expansion was
+/// made with the default system versions, so it is only used with system version
+/// parameters when the compose cannot be expanded instead.
+fn plan_expansion(source: &ExpansionSource, options: &ExpandOptions) -> ExpansionStrategy {
+    let default_versions = options.system_versions == SystemVersions::default();
+    if source.precomputed.is_some() && (default_versions || !compose_supported(&source.content)) {
+        return ExpansionStrategy::Precomputed;
+    }
+
+    if !options.inspects_concepts() && whole_system_include(&source.content).is_some() {
+        ExpansionStrategy::SystemPage
+    } else {
+        ExpansionStrategy::Compose
+    }
+}
+
+/// The include of a compose that is a single whole CodeSystem, without concepts, filters,
+/// ValueSet references or excludes
+fn whole_system_include(content: &serde_json::Value) -> Option<&serde_json::Value> {
+    let compose = content.get("compose")?;
+    if compose.get("exclude").is_some() {
+        return None;
+    }
+
+    match compose.get("include")?.as_array()?.as_slice() {
+        [include]
+            if include.get("system").is_some()
+                && ["concept", "filter", "valueSet"]
+                    .iter()
+                    .all(|key| include.get(key).is_none()) =>
+        {
+            Some(include)
+        }
+        _ => None,
+    }
+}
+
+/// Whether every include and exclude of a compose can be expanded by [`compose_entries`]
+fn compose_supported(content: &serde_json::Value) -> bool {
+    let Some(compose) = content.get("compose") else {
+        return false;
+    };
+
+    ["include", "exclude"]
+        .iter()
+        .filter_map(|key| compose.get(key).and_then(|sets| sets.as_array()))
+        .flatten()
+        .all(|set| {
+            set.get("system").is_some()
+                && set.get("valueSet").is_none()
+                && set
+                    .get("filter")
+                    .and_then(|f| f.as_array())
+                    .into_iter()
+                    .flatten()
+                    .all(|filter| hierarchy_filter(filter).is_ok())
+        })
+}
+
+/// The operator and code of a compose filter on the `concept` hierarchy; other filters are
+/// a bad request naming them
+fn hierarchy_filter(filter: &serde_json::Value) -> Result<(HierarchyOperator, &str), AppError> {
+    let element = |name| {
+        filter
+            .get(name)
+            .and_then(|v| v.as_str())
+            .unwrap_or_default()
+    };
+    let (property, op, value) = (element("property"), element("op"), element("value"));
+
+    match HierarchyOperator::from_code(op) {
+        Some(operator) if property == "concept" && !value.is_empty() => Ok((operator, value)),
+        _ => Err(AppError::BadRequest(format!(
+            "compose filter '{property} {op} {value}' is not supported; \
+             only concept is-a, descendent-of, is-not-a and generalizes are"
+        ))),
+    }
+}
+
+/// One page of a whole-system include read straight from the database, with the number of
+/// concepts in the system when it is within the counting limit. Nothing is read for
+/// `count=0`.
+async fn system_page(
+    store: &Arc<dyn TerminologyStore>,
+    include: &serde_json::Value,
+    versions: &SystemVersions,
+    offset: usize,
+    count: usize,
+    dependencies: &mut Vec<ResolvedVersion>,
+) -> Result<(Vec<serde_json::Value>, Option<usize>), AppError> {
+    let system = include
+        .get("system")
+        .and_then(|s| s.as_str())
+        .unwrap_or_default();
+    let version = versions.resolve(system, include.get("version").and_then(|v| v.as_str()))?;
+    let code_system = store
+        .get_code_system(system, version)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("CodeSystem '{system}' not found")))?;
+    dependencies.push(ResolvedVersion::code_system(
+        &code_system,
+        version.is_some(),
+    ));
+
+    let total = store
+        .count_concepts_bounded(&code_system.id)
+        .await?
+        .map(|total| total as usize);
+    if count == 0 || total.is_some_and(|total| offset >= total) {
+        return Ok((Vec::new(), total));
+    }
+
+    let entries = store
+        .get_concepts_range(&code_system.id, offset as i64, count as i64)
+        .await?
+        .iter()
+        .map(|concept| {
+            concept_entry(
+                system,
+                code_system.version.as_deref(),
+                &concept.code,
+                concept.display.as_deref(),
+                Some(concept),
+            )
+        })
+        .collect();
+
+    Ok((entries, total))
+}
+
+/// A ValueSet by explicit version or else as of the requested date, with its stored
+/// expansion when that is up to date or the compose cannot be expanded instead
+async fn stored_source(
+    store: &Arc<dyn TerminologyStore>,
+    url: &str,
+    options: &ExpandOptions,
+) -> Result<ExpansionSource, AppError> {
+    let value_set = match (&options.value_set_version, &options.date) {
+        (None, Some(date)) => {
+            let before = date_upper_bound(date).ok_or_else(|| {
+                AppError::BadRequest(format!("date must be a FHIR dateTime, got '{date}'"))
+            })?;
+            store
+                .get_value_set_as_of(url, before)
+                .await?
+                .ok_or_else(|| {
+                    AppError::NotFound(format!("ValueSet '{url}' has no version as of {date}"))
+                })?
+        }
+        (version, _) => store
+            .get_value_set(url, version.as_deref())
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("ValueSet '{url}' not found")))?,
+    };
+
+    // A stored expansion older than the ValueSet is stale, unless nothing can replace it
+    let stored = store
+        .get_value_set_expansion(&value_set.id)
+        .await?
+        .filter(|stored| {
+            stored.created_at >= value_set.updated_at || !compose_supported(&value_set.content)
+        });
+
+    // Recomputing the stored expansion changes the result like updating the ValueSet does
+    let mut resolved = ResolvedVersion::value_set(&value_set, options.value_set_version.is_some());
+    if let Some(stored) = &stored {
+        resolved.updated_at = resolved.updated_at.max(stored.created_at);
+    }
+
+    Ok(ExpansionSource {
+        precomputed: stored.map(|stored| stored.contains),
+        resolved,
+        content: value_set.content.0,
+        parameters: Vec::new(),
+    })
+}
+
+/// The CodeSystem URL of an implicit "all concepts" ValueSet, `{system}?fhir_vs`
+fn implicit_value_set_system(url: &str) -> Option<&str> {
+    url.strip_suffix("?fhir_vs")
+        .filter(|system| !system.is_empty())
+}
+
+/// A ValueSet including every concept of `system`, in the version named by a matching
+/// `force-system-version`, `check-system-version` or `system-version` parameter, else the
+/// default (latest) version
+async fn implicit_source(
+    store: &Arc<dyn TerminologyStore>,
+    url: &str,
+    system: &str,
+    options: &ExpandOptions,
+) -> Result<ExpansionSource, AppError> {
+    let requested_version = options.system_versions.resolve(system, None)?;
+
+    let code_system = store
+        .get_code_system(system, requested_version)
+        .await?
+        .ok_or_else(|| AppError::UnknownCodeSystem(system.to_string()))?;
+
+    let mut include = json!({ "system": system });
+    if let Some(version) = &code_system.version {
+        include["version"] = json!(version);
+    }
+
+    let mut parameters = Vec::new();
+    let used = match &code_system.version {
+        Some(version) => format!("{system}|{version}"),
+        None => system.to_string(),
+    };
+    parameters.push(json!({ "name": "version", "valueUri": used }));
+
+    Ok(ExpansionSource {
+        content: json!({
+            "resourceType": "ValueSet",
+            "url": url,
+            "status": "active",
+            "compose": { "include": [include] }
+        }),
+        precomputed: None,
+        resolved: ResolvedVersion::code_system(&code_system, requested_version.is_some()),
+        parameters,
+    })
+}
+
+/// The exclusive upper bound of a FHIR dateTime: the instant itself for a full
+/// timestamp, else the start of the following year, month or day for partial dates
+fn date_upper_bound(date: &str) -> Option<chrono::DateTime<chrono::Utc>> {
+    use chrono::{DateTime, Months, NaiveDate, Utc};
+
+    if let Ok(instant) = DateTime::parse_from_rfc3339(date) {
+        return Some(instant.with_timezone(&Utc));
+    }
+
+    let end = match date.len() {
+        4 => NaiveDate::parse_from_str(&format!("{date}-01-01"), "%Y-%m-%d")
+            .ok()?
+            .checked_add_months(Months::new(12))?,
+        7 => NaiveDate::parse_from_str(&format!("{date}-01"), "%Y-%m-%d")
+            .ok()?
+            .checked_add_months(Months::new(1))?,
+        10 => NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .ok()?
+            .succ_opt()?,
+        _ => return None,
+    };
+
+    Some(end.and_hms_opt(0, 0, 0)?.and_utc())
+}
+
+/// Expand a ValueSet from its `compose` definition using the stored concepts
+pub async fn compose_expansion(
+    store: &Arc<dyn TerminologyStore>,
+    value_set: &ValueSet,
+) -> Result<Vec<serde_json::Value>, AppError> {
+    compose_entries(
+        store,
+        value_set.content.get("compose"),
+        &SystemVersions::default(),
+        &mut Vec::new(),
+    )
+    .await
+}
+
+/// Expand a `compose` definition using the stored concepts, in the system versions chosen
+/// by `versions`, adding each CodeSystem version read to `dependencies`
+async fn compose_entries(
+    store: &Arc<dyn TerminologyStore>,
+    compose: Option<&serde_json::Value>,
+    versions: &SystemVersions,
+    dependencies: &mut Vec<ResolvedVersion>,
+) -> Result<Vec<serde_json::Value>, AppError> {
+    let mut entries = Vec::new();
+    let mut seen = HashSet::new();
+
+    if let Some(includes) = compose
+        .and_then(|c| c.get("include"))
+        .and_then(|i| i.as_array())
+    {
+        for include in includes {
+            for entry in expand_compose_set(store, include, versions, dependencies).await? {
+                if seen.insert(coding_key(&entry)) {
+                    entries.push(entry);
+                }
+            }
+        }
+    }
+
+    if let Some(excludes) = compose
+        .and_then(|c| c.get("exclude"))
+        .and_then(|e| e.as_array())
+    {
+        let mut excluded = HashSet::new();
+        for exclude in excludes {
+            for entry in expand_compose_set(store, exclude, versions, dependencies).await? {
+                excluded.insert(coding_key(&entry));
+            }
+        }
+        entries.retain(|entry| !excluded.contains(&coding_key(entry)));
+    }
+
+    Ok(entries)
+}
+
+/// Resolve a single `compose.include`/`compose.exclude` entry to expansion entries: its
+/// listed concepts or else the whole system, narrowed by its hierarchy filters
+async fn expand_compose_set(
+    store: &Arc<dyn TerminologyStore>,
+    set: &serde_json::Value,
+    versions: &SystemVersions,
+    dependencies: &mut Vec<ResolvedVersion>,
+) -> Result<Vec<serde_json::Value>, AppError> {
+    let system = set.get("system").and_then(|s| s.as_str()).ok_or_else(|| {
+        AppError::BadRequest("compose entries without a system are not supported".to_string())
+    })?;
+
+    if set.get("valueSet").is_some() {
+        return Err(AppError::BadRequest(format!(
+            "compose valueSet references are not supported for system '{system}'"
+        )));
+    }
+    let filters = set
+        .get("filter")
+        .and_then(|f| f.as_array())
+        .into_iter()
+        .flatten()
+        .map(hierarchy_filter)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let version = versions.resolve(system, set.get("version").and_then(|v| v.as_str()))?;
+    let code_system = store
+        .get_code_system(system, version)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("CodeSystem '{system}' not found")))?;
+    dependencies.push(ResolvedVersion::code_system(
+        &code_system,
+        version.is_some(),
+    ));
+
+    let entry = |code: &str, display: Option<&str>, stored: Option<&Concept>| {
+        concept_entry(
+            system,
+            code_system.version.as_deref(),
+            code,
+            display,
+            stored,
+        )
+    };
+
+    // Every filter must hold, so the concepts they select are intersected
+    let mut filtered: Option<Vec<Concept>> = None;
+    for (operator, code) in filters {
+        let selected = store
+            .get_hierarchy_concepts(&code_system.id, operator, code)
+            .await?;
+        filtered = Some(match filtered {
+            None => selected,
+            Some(mut concepts) => {
+                let codes: HashSet<&str> = selected.iter().map(|c| c.code.as_str()).collect();
+                concepts.retain(|concept| codes.contains(concept.code.as_str()));
+                concepts
+            }
+        });
+    }
+
+    let mut entries = Vec::new();
+
+    if let Some(concepts) = set.get("concept").and_then(|c| c.as_array()) {
+        // Enumerated concepts, looked up together, preferring the display given in the
+        // compose
+        let codes: Vec<&str> = concepts
+            .iter()
+            .filter_map(|concept| concept.get("code")?.as_str())
+            .collect();
+        let stored = store.get_concepts_by_codes(&code_system.id, &codes).await?;
+        for concept in concepts {
+            let Some(code) = concept.get("code").and_then(|c| c.as_str()) else {
+                continue;
+            };
+            let stored = stored.get(code);
+            let display = concept
+                .get("display")
+                .and_then(|d| d.as_str())
+                .or_else(|| stored.and_then(|c| c.display.as_deref()));
+            entries.push(entry(code, display, stored));
+        }
+        if let Some(filtered) = &filtered {
+            let codes: HashSet<&str> = filtered.iter().map(|c| c.code.as_str()).collect();
+            entries.retain(|entry| {
+                entry
+                    .get("code")
+                    .and_then(|c| c.as_str())
+                    .is_some_and(|code| codes.contains(code))
+            });
+        }
+    } else if let Some(filtered) = filtered {
+        for concept in &filtered {
+            entries.push(entry(
+                &concept.code,
+                concept.display.as_deref(),
+                Some(concept),
+            ));
+        }
+    } else {
+        // No concepts listed - include the whole system
+        for concept in store.get_concepts(&code_system.id).await? {
+            entries.push(entry(
+                &concept.code,
+                concept.display.as_deref(),
+                Some(&concept),
+            ));
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Whether a `compose` includes the `(system, code)` coding: some include selects it and
+/// no exclude does, each evaluated for this code alone
+async fn compose_contains(
+    store: &Arc<dyn TerminologyStore>,
+    compose: Option<&serde_json::Value>,
+    versions: &SystemVersions,
+    coding: (&str, &str),
+    dependencies: &mut Vec<ResolvedVersion>,
+) -> Result<bool, AppError> {
+    let sets = |key| {
+        compose
+            .and_then(|c| c.get(key))
+            .and_then(|s| s.as_array())
+            .into_iter()
+            .flatten()
+    };
+
+    let mut included = false;
+    for include in sets("include") {
+        if compose_set_selects(store, include, versions, coding, dependencies).await? {
+            included = true;
+            break;
+        }
+    }
+    if !included {
+        return Ok(false);
+    }
+
+    for exclude in sets("exclude") {
+        if compose_set_selects(store, exclude, versions, coding, dependencies).await? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Whether a single `compose.include`/`compose.exclude` entry selects the `(system, code)`
+/// coding, as [`expand_compose_set`] would: the code must be listed in its concepts, or be
+/// a concept of the system when none are listed, and satisfy each hierarchy filter
+async fn compose_set_selects(
+    store: &Arc<dyn TerminologyStore>,
+    set: &serde_json::Value,
+    versions: &SystemVersions,
+    (system, code): (&str, &str),
+    dependencies: &mut Vec<ResolvedVersion>,
+) -> Result<bool, AppError> {
+    let set_system = set.get("system").and_then(|s| s.as_str()).ok_or_else(|| {
+        AppError::BadRequest("compose entries without a system are not supported".to_string())
+    })?;
+    if set.get("valueSet").is_some() {
+        return Err(AppError::BadRequest(format!(
+            "compose valueSet references are not supported for system '{set_system}'"
+        )));
+    }
+    let filters = set
+        .get("filter")
+        .and_then(|f| f.as_array())
+        .into_iter()
+        .flatten()
+        .map(hierarchy_filter)
+        .collect::<Result<Vec<_>, _>>()?;
+    if set_system != system {
+        return Ok(false);
+    }
+
+    let version = versions.resolve(system, set.get("version").and_then(|v| v.as_str()))?;
+    let code_system = store
+        .get_code_system(system, version)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("CodeSystem '{system}' not found")))?;
+    dependencies.push(ResolvedVersion::code_system(
+        &code_system,
+        version.is_some(),
+    ));
+
+    // Compared by the stored code, so case-insensitive systems match in any case
+    let concept = store.get_concept(&code_system.id, code).await?;
+    let code = concept.as_ref().map_or(code, |c| c.code.as_str());
+
+    if let Some(concepts) = set.get("concept").and_then(|c| c.as_array()) {
+        let listed: Vec<&str> = concepts
+            .iter()
+            .filter_map(|concept| concept.get("code")?.as_str())
+            .collect();
+        let stored = store
+            .get_concepts_by_codes(&code_system.id, &listed)
+            .await?;
+        let is_listed = listed
+            .iter()
+            .any(|listed| stored.get(*listed).map_or(*listed, |c| c.code.as_str()) == code);
+        if !is_listed {
+            return Ok(false);
+        }
+    } else if concept.is_none() {
+        return Ok(false);
+    }
+
+    for (operator, value) in filters {
+        let subsumption = store
+            .check_subsumption(&code_system.id, value, code)
+            .await?;
+        let selected = match operator {
+            HierarchyOperator::IsA => code == value || subsumption == Some(true),
+            HierarchyOperator::DescendentOf => code != value && subsumption == Some(true),
+            HierarchyOperator::IsNotA => code != value && subsumption != Some(true),
+            HierarchyOperator::Generalizes => code == value || subsumption == Some(false),
+        };
+        if !selected {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// An expansion entry for `code`, flagged abstract or inactive from the stored concept and
+/// carrying its designations and properties (trimmed later to what was requested)
+fn concept_entry(
+    system: &str,
+    version: Option<&str>,
+    code: &str,
+    display: Option<&str>,
+    stored: Option<&Concept>,
+) -> serde_json::Value {
+    let mut entry = json!({ "system": system, "code": code });
+    if stored.is_some_and(concept_abstract) {
+        entry["abstract"] = json!(true);
+    }
+    if stored.is_some_and(concept_inactive) {
+        entry["inactive"] = json!(true);
+    }
+    if let Some(version) = version {
+        entry["version"] = json!(version);
+    }
+    if let Some(display) = display {
+        entry["display"] = json!(display);
+    }
+    let non_empty = |value: &Option<sqlx::types::Json<serde_json::Value>>| {
+        value
+            .as_ref()
+            .filter(|v| v.as_array().is_some_and(|a| !a.is_empty()))
+            .map(|v| v.0.clone())
+    };
+    if let Some(designations) = stored.and_then(|c| non_empty(&c.designations)) {
+        entry["designation"] = designations;
+    }
+    if let Some(properties) = stored.and_then(|c| non_empty(&c.properties)) {
+        entry["property"] = properties;
+    }
+    entry
+}
+
+/// Whether a stored concept is inactive according to its `inactive` or `status` property
+fn concept_inactive(concept: &Concept) -> bool {
+    let Some(properties) = concept.properties.as_ref().and_then(|p| p.as_array()) else {
+        return false;
+    };
+
+    properties.iter().any(
+        |property| match property.get("code").and_then(|c| c.as_str()) {
+            Some("inactive") => property.get("valueBoolean") == Some(&json!(true)),
+            Some("status") => property
+                .get("valueCode")
+                .and_then(|v| v.as_str())
+                .is_some_and(|status| matches!(status, "retired" | "inactive")),
+            _ => false,
+        },
+    )
+}
+
+/// Whether a stored concept is abstract according to its `notSelectable` or `abstract`
+/// property
+pub fn concept_abstract(concept: &Concept) -> bool {
+    let Some(properties) = concept.properties.as_ref().and_then(|p| p.as_array()) else {
+        return false;
+    };
+
+    properties.iter().any(|property| {
+        matches!(
+            property.get("code").and_then(|c| c.as_str()),
+            Some("notSelectable" | "abstract")
+        ) && property.get("valueBoolean") == Some(&json!(true))
+    })
+}
+
+/// Drop entries marked `inactive`; their active descendants take their place
+fn retain_active(entries: &mut Vec<serde_json::Value>) {
+    let mut retained = Vec::with_capacity(entries.len());
+    for mut entry in entries.drain(..) {
+        let mut children = match entry.as_object_mut().and_then(|e| e.remove("contains")) {
+            Some(serde_json::Value::Array(children)) => children,
+            _ => Vec::new(),
+        };
+        retain_active(&mut children);
+
+        if entry.get("inactive") == Some(&json!(true)) {
+            retained.extend(children);
+        } else {
+            if !children.is_empty() {
+                entry["contains"] = json!(children);
+            }
+            retained.push(entry);
+        }
+    }
+    *entries = retained;
+}
+
+/// Keep only the requested `contains.property` entries. A requested `parent` missing
+/// from the concept's own properties is taken from the enclosing entry.
+fn select_properties(entries: &mut [serde_json::Value], codes: &[&str], parent: Option<&str>) {
+    for entry in entries {
+        let mut properties: Vec<serde_json::Value> =
+            match entry.as_object_mut().and_then(|e| e.remove("property")) {
+                Some(serde_json::Value::Array(properties)) => properties
+                    .into_iter()
+                    .filter(|p| {
+                        p.get("code")
+                            .and_then(|c| c.as_str())
+                            .is_some_and(|code| codes.contains(&code))
+                    })
+                    .collect(),
+                _ => Vec::new(),
+            };
+        if let Some(parent) = parent.filter(|_| codes.contains(&"parent")) {
+            if !properties
+                .iter()
+                .any(|p| p.get("code") == Some(&json!("parent")))
+            {
+                properties.push(json!({ "code": "parent", "valueCode": parent }));
+            }
+        }
+        if !properties.is_empty() {
+            entry["property"] = json!(properties);
+        }
+
+        let code = entry
+            .get("code")
+            .and_then(|c| c.as_str())
+            .map(str::to_string);
+        if let Some(children) = entry.get_mut("contains").and_then(|c| c.as_array_mut()) {
+            select_properties(children, codes, code.as_deref());
+        }
+    }
+}
+
+/// Remove `contains.designation` from every entry, including nested ones
+fn remove_designations(entries: &mut [serde_json::Value]) {
+    for entry in entries {
+        if let Some(entry) = entry.as_object_mut() {
+            entry.remove("designation");
+        }
+        if let Some(children) = entry.get_mut("contains").and_then(|c| c.as_array_mut()) {
+            remove_designations(children);
+        }
+    }
+}
+
+/// Flatten nested `contains` entries depth-first, parents before their children
+fn flatten_contains(entries: Vec<serde_json::Value>) -> Vec<serde_json::Value> {
+    let mut flattened = Vec::new();
+    let mut stack: Vec<serde_json::Value> = entries.into_iter().rev().collect();
+
+    while let Some(mut entry) = stack.pop() {
+        let children = entry
+            .as_object_mut()
+            .and_then(|e| e.remove("contains"))
+            .and_then(|c| match c {
+                serde_json::Value::Array(children) => Some(children),
+                _ => None,
+            })
+            .unwrap_or_default();
+        flattened.push(entry);
+        stack.extend(children.into_iter().rev());
+    }
+
+    flattened
+}
+
+/// Drop nested `contains` entries below `depth` levels (the given entries being level 1),
+/// flagging each entry that lost its children with [`HAS_CHILDREN_EXTENSION`]
+fn truncate_contains(entries: &mut [serde_json::Value], depth: usize) {
+    for entry in entries {
+        let Some(object) = entry.as_object_mut() else {
+            continue;
+        };
+        if depth > 1 {
+            if let Some(children) = object.get_mut("contains").and_then(|c| c.as_array_mut()) {
+                truncate_contains(children, depth - 1);
+            }
+        } else if object.remove("contains").is_some() {
+            let extension = object.entry("extension").or_insert_with(|| json!([]));
+            if let Some(extensions) = extension.as_array_mut() {
+                extensions.push(json!({ "url": HAS_CHILDREN_EXTENSION, "valueBoolean": true }));
+            }
+        }
+    }
+}
+
+/// Number of entries including all nested `contains` descendants
+pub(super) fn count_contains(entries: &[serde_json::Value]) -> usize {
+    entries
+        .iter()
+        .map(|entry| {
+            1 + entry
+                .get("contains")
+                .and_then(|c| c.as_array())
+                .map_or(0, |children| count_contains(children))
+        })
+        .sum()
+}
+
+/// Whether each term is contained in the entry's display (its code when it has no
+/// display) or one of its designations, in any order
+fn matches_filter(entry: &serde_json::Value, terms: &[String]) -> bool {
+    let label = entry
+        .get("display")
+        .or_else(|| entry.get("code"))
+        .and_then(|v| v.as_str());
+    let designations = entry.get("designation").and_then(|d| d.as_array());
+    let texts: Vec<String> = label
+        .into_iter()
+        .chain(
+            designations
+                .into_iter()
+                .flatten()
+                .filter_map(|d| d.get("value").and_then(|v| v.as_str())),
+        )
+        .map(str::to_lowercase)
+        .collect();
+
+    !texts.is_empty()
+        && terms
+            .iter()
+            .all(|term| texts.iter().any(|text| text.contains(term.as_str())))
+}
+
+/// Replace displays, including nested ones, with the designation in `language` if present
+fn localize_contains(entries: &mut [serde_json::Value], language: &str) {
+    for entry in entries {
+        let localized = entry
+            .get("designation")
+            .and_then(|designations| localized_designation(designations, language, false));
+        if let Some((_, display)) = localized {
+            entry["display"] = json!(display);
+        }
+        if let Some(children) = entry.get_mut("contains").and_then(|c| c.as_array_mut()) {
+            localize_contains(children, language);
+        }
+    }
+}
+
+/// The `(system, code)` identifying an expansion entry
+fn coding_key(entry: &serde_json::Value) -> (String, String) {
+    let element = |name| {
+        entry
+            .get(name)
+            .and_then(|v| v.as_str())
+            .unwrap_or_default()
+            .to_string()
+    };
+    (element("system"), element("code"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+    use crate::terminology::Parameter;
+    use serde_json::Value;
+
+    const SYSTEM: &str = "http://example.org/animals";
+
+    fn store(compose: Value) -> Arc<dyn TerminologyStore> {
+        Arc::new(
+            MemoryStore::default()
+                .with_code_system(json!({
+                    "url": SYSTEM,
+                    "version": "1",
+                    "concept": [{
+                        "code": "animal",
+                        "concept": [
+                            { "code": "dog", "concept": [{ "code": "puppy" }] },
+                            { "code": "cat" }
+                        ]
+                    }, {
+                        "code": "plant"
+                    }]
+                }))
+                .with_value_set(json!({
+                    "url": "http://example.org/ValueSet/test",
+                    "compose": compose
+                })),
+        )
+    }
+
+    async fn expanded_codes(compose: Value) -> Result<Vec<String>, AppError> {
+        let options = ExpandOptions::from_query(ExpandParams {
+            url: Some("http://example.org/ValueSet/test".to_string()),
+            ..Default::default()
+        })?;
+        let expansion = expand(store(compose), "http://example.org/ValueSet/test", options)
+            .await?
+            .body;
+        let mut codes = Vec::new();
+        let mut pending: Vec<&Value> = expansion
+            .pointer("/expansion/contains")
+            .and_then(|c| c.as_array())
+            .into_iter()
+            .flatten()
+            .collect();
+        while let Some(entry) = pending.pop() {
+            codes.push(entry["code"].as_str().unwrap().to_string());
+            pending.extend(
+                entry
+                    .get("contains")
+                    .and_then(|c| c.as_array())
+                    .into_iter()
+                    .flatten(),
+            );
+        }
+        codes.sort();
+        Ok(codes)
+    }
+
+    fn filter(op: &str, value: &str) -> Value {
+        json!({ "system": SYSTEM, "filter": [{ "property": "concept", "op": op, "value": value }] })
+    }
+
+    const STORED: &str = "http://example.org/ValueSet/stored";
+
+    /// The `expansion` of a ValueSet whose stored expansion is `contains`
+    async fn expand_stored(contains: Value, params: ExpandParams) -> Result<Value, AppError> {
+        let store: Arc<dyn TerminologyStore> = Arc::new(
+            MemoryStore::default()
+                .with_value_set(json!({ "url": STORED }))
+                .with_expansion(contains),
+        );
+        let options = ExpandOptions::from_query(ExpandParams {
+            url: Some(STORED.to_string()),
+            ..params
+        })?;
+        let mut result = expand(store, STORED, options).await?.body;
+        Ok(result["expansion"].take())
+    }
+
+    /// The codes of the top-level `contains` entries of an expansion
+    fn root_codes(expansion: &Value) -> Vec<&str> {
+        expansion["contains"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| entry["code"].as_str().unwrap())
+            .collect()
+    }
+
+    fn entry(code: &str, children: &[Value]) -> Value {
+        let mut entry = json!({ "system": SYSTEM, "code": code });
+        if !children.is_empty() {
+            entry["contains"] = json!(children);
+        }
+        entry
+    }
+
+    #[test]
+    fn query_and_parameters_parse_to_the_same_options() {
+        let from_query = ExpandOptions::from_query(ExpandParams {
+            url: Some("http://example.org/ValueSet/test|2".to_string()),
+            filter: Some("dog".to_string()),
+            display_language: Some("de".to_string()),
+            active_only: Some(true),
+            system_version: Some(format!("{SYSTEM}|1")),
+            property: Some("parent, inactive".to_string()),
+            depth: Some(2),
+            offset: Some(10),
+            count: Some(5),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let from_parameters = ExpandOptions::from_parameters(&Parameters::with_parameters(vec![
+            Parameter::uri("url", "http://example.org/ValueSet/test|2"),
+            Parameter::string("filter", "dog"),
+            Parameter::code("displayLanguage", "de"),
+            Parameter::boolean("activeOnly", true),
+            Parameter::uri("system-version", format!("{SYSTEM}|1")),
+            Parameter::code("property", "parent"),
+            Parameter::code("property", "inactive"),
+            Parameter::integer("depth", 2),
+            Parameter::integer("offset", 10),
+            Parameter::integer("count", 5),
+        ]))
+        .unwrap();
+
+        assert_eq!(from_query, from_parameters);
+        assert_eq!(
+            from_query.url.as_deref(),
+            Some("http://example.org/ValueSet/test")
+        );
+        assert_eq!(from_query.value_set_version.as_deref(), Some("2"));
+        assert_eq!(from_query.properties, ["parent", "inactive"]);
+        // Filtering flattens the expansion
+        assert!(from_query.exclude_nested);
+    }
+
+    #[test]
+    fn options_default_to_the_first_page_of_100() {
+        let options = ExpandOptions::from_query(ExpandParams::default()).unwrap();
+        assert_eq!((options.offset, options.count), (0, 100));
+        assert!(!options.exclude_nested);
+        assert_eq!(options.depth, None);
+    }
+
+    #[test]
+    fn invalid_paging_and_depth_are_reported_together() {
+        let error = ExpandOptions::from_query(ExpandParams {
+            offset: Some(-1),
+            count: Some(-5),
+            depth: Some(0),
+            ..Default::default()
+        })
+        .unwrap_err();
+        let AppError::InvalidParameters(issues) = error else {
+            panic!("expected invalid parameters, got {error:?}");
+        };
+        assert_eq!(issues.len(), 3, "{issues:?}");
+    }
+
+    #[test]
+    fn conflicting_piped_and_separate_versions_are_rejected() {
+        let result = ExpandOptions::from_query(ExpandParams {
+            url: Some("http://example.org/ValueSet/test|2".to_string()),
+            value_set_version: Some("3".to_string()),
+            ..Default::default()
+        });
+        assert!(matches!(result, Err(AppError::InvalidParameters(_))));
+    }
+
+    #[test]
+    fn system_versions_force_over_pinned_over_default() {
+        let versions =
+            |default: Option<&str>, check: Option<&str>, force: Option<&str>| SystemVersions {
+                default: default.map(|v| format!("{SYSTEM}|{v}")),
+                check: check.map(|v| format!("{SYSTEM}|{v}")),
+                force: force.map(|v| format!("{SYSTEM}|{v}")),
+            };
+
+        let default = versions(Some("1"), None, None);
+        assert_eq!(default.resolve(SYSTEM, None).unwrap(), Some("1"));
+        assert_eq!(default.resolve(SYSTEM, Some("2")).unwrap(), Some("2"));
+        // Canonicals for other systems do not apply
+        assert_eq!(
+            default.resolve("http://example.org/other", None).unwrap(),
+            None
+        );
+
+        let forced = versions(Some("1"), None, Some("3"));
+        assert_eq!(forced.resolve(SYSTEM, Some("2")).unwrap(), Some("3"));
+
+        let checked = versions(None, Some("2"), None);
+        assert_eq!(checked.resolve(SYSTEM, None).unwrap(), Some("2"));
+        assert_eq!(checked.resolve(SYSTEM, Some("2")).unwrap(), Some("2"));
+        assert!(matches!(
+            checked.resolve(SYSTEM, Some("1")),
+            Err(AppError::BadRequest(_))
+        ));
+    }
+
+    #[test]
+    fn date_upper_bound_is_the_end_of_a_partial_date() {
+        let bound = |date| date_upper_bound(date).map(|d| d.to_rfc3339());
+        assert_eq!(bound("2024").as_deref(), Some("2025-01-01T00:00:00+00:00"));
+        assert_eq!(
+            bound("2024-12").as_deref(),
+            Some("2025-01-01T00:00:00+00:00")
+        );
+        assert_eq!(
+            bound("2024-02-28").as_deref(),
+            Some("2024-02-29T00:00:00+00:00")
+        );
+        assert_eq!(
+            bound("2024-06-01T12:30:00+02:00").as_deref(),
+            Some("2024-06-01T10:30:00+00:00")
+        );
+        assert_eq!(bound("2024-13"), None);
+        assert_eq!(bound("yesterday"), None);
+    }
+
+    #[tokio::test]
+    async fn hierarchy_filters_follow_the_closure() {
+        let codes = |op, value| async move {
+            expanded_codes(json!({ "include": [filter(op, value)] }))
+                .await
+                .unwrap()
+        };
+        assert_eq!(codes("is-a", "dog").await, ["dog", "puppy"]);
+        assert_eq!(
+            codes("descendent-of", "animal").await,
+            ["cat", "dog", "puppy"]
+        );
+        assert_eq!(codes("is-not-a", "animal").await, ["plant"]);
+        assert_eq!(
+            codes("generalizes", "puppy").await,
+            ["animal", "dog", "puppy"]
+        );
+    }
+
+    #[tokio::test]
+    async fn filters_combine_with_concept_lists_and_excludes() {
+        let mut include = filter("is-a", "animal");
+        include["concept"] = json!([{ "code": "dog" }, { "code": "plant" }]);
+        let codes = expanded_codes(json!({
+            "include": [include],
+            "exclude": [{ "system": SYSTEM, "concept": [{ "code": "dog" }] }]
+        }))
+        .await
+        .unwrap();
+        assert!(codes.is_empty(), "{codes:?}");
+
+        let codes = expanded_codes(json!({
+            "include": [filter("is-a", "animal")],
+            "exclude": [filter("is-a", "dog")]
+        }))
+        .await
+        .unwrap();
+        assert_eq!(codes, ["animal", "cat"]);
+    }
+
+    #[tokio::test]
+    async fn abstract_concepts_are_only_kept_as_parents_of_nested_entries() {
+        let mut animal = entry("animal", &[entry("dog", &[]), entry("cat", &[])]);
+        animal["abstract"] = json!(true);
+        let not_for_ui = || ExpandParams {
+            exclude_not_for_ui: Some(true),
+            ..Default::default()
+        };
+
+        let nested = expand_stored(json!([animal]), not_for_ui()).await.unwrap();
+        assert_eq!(root_codes(&nested), ["animal"]);
+
+        let flattened = expand_stored(
+            json!([animal]),
+            ExpandParams {
+                exclude_nested: Some(true),
+                ..not_for_ui()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(root_codes(&flattened), ["dog", "cat"]);
+
+        // A stored flat expansion drops them without excludeNested
+        animal.as_object_mut().unwrap().remove("contains");
+        let flat = expand_stored(json!([animal, entry("dog", &[])]), not_for_ui())
+            .await
+            .unwrap();
+        assert_eq!(root_codes(&flat), ["dog"]);
+    }
+
+    #[tokio::test]
+    async fn membership_is_decided_per_code_from_the_compose() {
+        let store = store(json!({
+            "include": [
+                filter("is-a", "animal"),
+                { "system": SYSTEM, "concept": [{ "code": "plant" }] }
+            ],
+            "exclude": [filter("descendent-of", "dog")]
+        }));
+        let contains = |code| {
+            let store = store.clone();
+            async move {
+                value_set_contains(store, "http://example.org/ValueSet/test", SYSTEM, code)
+                    .await
+                    .unwrap()
+                    .body
+            }
+        };
+        for code in ["animal", "dog", "cat", "plant"] {
+            assert!(contains(code).await, "{code}");
+        }
+        for code in ["puppy", "unknown"] {
+            assert!(!contains(code).await, "{code}");
+        }
+        let other_system =
+            value_set_contains(store, "http://example.org/ValueSet/test", "http://x", "dog");
+        assert!(!other_system.await.unwrap().body);
+    }
+
+    #[tokio::test]
+    async fn membership_searches_a_stored_expansion() {
+        let store: Arc<dyn TerminologyStore> = Arc::new(
+            MemoryStore::default()
+                .with_value_set(json!({ "url": STORED }))
+                .with_expansion(json!([entry("animal", &[entry("dog", &[])])])),
+        );
+        let contains = |code| value_set_contains(store.clone(), STORED, SYSTEM, code);
+        assert!(contains("dog").await.unwrap().body);
+        assert!(!contains("cat").await.unwrap().body);
+    }
+
+    #[tokio::test]
+    async fn depth_cuts_off_nested_levels_and_flags_their_parents() {
+        let contains = json!([
+            entry(
+                "animal",
+                &[entry("dog", &[entry("puppy", &[])]), entry("cat", &[])]
+            ),
+            entry("plant", &[])
+        ]);
+        let expansion = expand_stored(
+            contains,
+            ExpandParams {
+                depth: Some(1),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(root_codes(&expansion), ["animal", "plant"]);
+        let animal = &expansion["contains"][0];
+        assert!(animal.get("contains").is_none());
+        assert_eq!(
+            animal["extension"],
+            json!([{ "url": HAS_CHILDREN_EXTENSION, "valueBoolean": true }])
+        );
+        assert!(expansion["contains"][1].get("extension").is_none());
+    }
+
+    #[tokio::test]
+    async fn hierarchical_expansions_count_every_concept_and_page_over_roots() {
+        let contains = json!([
+            entry("animal", &[entry("dog", &[]), entry("cat", &[])]),
+            entry("plant", &[])
+        ]);
+        let page = |offset, count| {
+            expand_stored(
+                contains.clone(),
+                ExpandParams {
+                    offset: Some(offset),
+                    count: Some(count),
+                    ..Default::default()
+                },
+            )
+        };
+
+        let first = page(0, 1).await.unwrap();
+        assert_eq!(first["total"], 4);
+        assert_eq!(root_codes(&first), ["animal"]);
+        assert_eq!(
+            first["contains"][0]["contains"].as_array().unwrap().len(),
+            2
+        );
+
+        let second = page(1, 1).await.unwrap();
+        assert_eq!(second["total"], 4);
+        assert_eq!(root_codes(&second), ["plant"]);
+    }
+
+    #[tokio::test]
+    async fn depth_on_a_flat_expansion_is_rejected() {
+        let options = ExpandOptions::from_query(ExpandParams {
+            url: Some("http://example.org/ValueSet/test".to_string()),
+            depth: Some(1),
+            ..Default::default()
+        })
+        .unwrap();
+        let store = store(json!({ "include": [{ "system": SYSTEM }] }));
+        let result = expand(store, "http://example.org/ValueSet/test", options).await;
+        assert!(matches!(result, Err(AppError::BadRequest(_))));
+    }
+
+    #[tokio::test]
+    async fn unsupported_filter_operator_is_rejected() {
+        let result = expanded_codes(json!({ "include": [filter("regex", "d.*")] })).await;
+        assert!(matches!(result, Err(AppError::BadRequest(_))), "{result:?}");
+    }
+
+    async fn composed(compose: Value) -> Result<Vec<(String, String)>, AppError> {
+        let store = store(Value::Null);
+        let mut dependencies = Vec::new();
+        let versions = SystemVersions::default();
+        let entries = compose_entries(&store, Some(&compose), &versions, &mut dependencies).await?;
+        Ok(entries
+            .iter()
+            .map(|entry| {
+                let field = |name: &str| entry[name].as_str().unwrap_or_default().to_string();
+                (field("code"), field("display"))
+            })
+            .collect())
+    }
+
+    #[tokio::test]
+    async fn concept_lists_keep_their_order_and_prefer_compose_displays() {
+        let entries = composed(json!({ "include": [{
+            "system": SYSTEM,
+            "concept": [{ "code": "plant" }, { "code": "dog", "display": "Doggo" }]
+        }] }))
+        .await
+        .unwrap();
+        let expected = [("plant", ""), ("dog", "Doggo")]
+            .map(|(code, display)| (code.to_string(), display.to_string()));
+        assert_eq!(entries, expected);
+    }
+
+    #[tokio::test]
+    async fn duplicates_are_included_once_and_excludes_remove_codes() {
+        let entries = composed(json!({
+            "include": [
+                { "system": SYSTEM },
+                { "system": SYSTEM, "concept": [{ "code": "dog" }] }
+            ],
+            "exclude": [{ "system": SYSTEM, "concept": [{ "code": "cat" }] }]
+        }))
+        .await
+        .unwrap();
+        let mut codes: Vec<String> = entries.into_iter().map(|(code, _)| code).collect();
+        codes.sort();
+        assert_eq!(codes, ["animal", "dog", "plant", "puppy"]);
+    }
+
+    #[tokio::test]
+    async fn unknown_systems_are_not_found() {
+        let result =
+            composed(json!({ "include": [{ "system": "http://example.org/none" }] })).await;
+        assert!(matches!(result, Err(AppError::NotFound(_))), "{result:?}");
+    }
+}
@@ -0,0 +1,223 @@
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use super::{count_contains, expand, Cacheable, ExpandOptions, SingleFlight};
+use crate::error::AppError;
+use crate::store::TerminologyStore;
+
+/// Computed expansions shared by all requests, keyed by their options. The least recently
+/// used are dropped once the cached expansions together hold more than a bound of
+/// concepts; concurrent misses with the same options are computed once. Everything is
+/// dropped by [`ExpansionCache::invalidate`] after ValueSets or CodeSystems change.
+#[derive(Clone)]
+pub struct ExpansionCache {
+    lru: Arc<Mutex<Lru>>,
+    inflight: SingleFlight<ExpandOptions, Cacheable<Value>>,
+}
+
+impl ExpansionCache {
+    /// A cache holding at most `max_concepts` expansion entries; `0` caches nothing
+    pub fn new(max_concepts: usize) -> Self {
+        Self {
+            lru: Arc::new(Mutex::new(Lru {
+                max_concepts,
+                concepts: 0,
+                generation: 0,
+                tick: 0,
+                entries: HashMap::new(),
+                order: BTreeMap::new(),
+            })),
+            inflight: SingleFlight::default(),
+        }
+    }
+
+    /// [`expand`] a ValueSet, or the cached expansion for the same options
+    pub async fn expand(
+        &self,
+        store: Arc<dyn TerminologyStore>,
+        url: &str,
+        options: ExpandOptions,
+    ) -> Result<Cacheable<Value>, AppError> {
+        let generation = {
+            let mut lru = self.lru.lock().unwrap();
+            if let Some(expansion) = lru.get(&options) {
+                return Ok(expansion);
+            }
+            lru.generation
+        };
+
+        let expansion = self
+            .inflight
+            .run(options.clone(), || expand(store, url, options.clone()))
+            .await?;
+        self.lru
+            .lock()
+            .unwrap()
+            .insert(options, expansion.clone(), generation);
+        Ok(expansion)
+    }
+
+    /// Drop every cached expansion after ValueSets, their stored expansions or CodeSystems
+    /// changed
+    pub fn invalidate(&self) {
+        let mut lru = self.lru.lock().unwrap();
+        lru.generation += 1;
+        lru.concepts = 0;
+        lru.entries.clear();
+        lru.order.clear();
+    }
+}
+
+struct Lru {
+    max_concepts: usize,
+    /// Expansion entries held by all cached expansions
+    concepts: usize,
+    /// Bumped on every invalidation, so that an expansion computed from data read before
+    /// an invalidation is not kept
+    generation: u64,
+    /// Source of the recency stamps in `order`
+    tick: u64,
+    entries: HashMap<ExpandOptions, CachedExpansion>,
+    /// Cached options by the stamp of their last use, least recent first
+    order: BTreeMap<u64, ExpandOptions>,
+}
+
+struct CachedExpansion {
+    expansion: Cacheable<Value>,
+    concepts: usize,
+    last_used: u64,
+}
+
+impl Lru {
+    fn get(&mut self, options: &ExpandOptions) -> Option<Cacheable<Value>> {
+        self.tick += 1;
+        let entry = self.entries.get_mut(options)?;
+        self.order.remove(&entry.last_used);
+        entry.last_used = self.tick;
+        self.order.insert(self.tick, options.clone());
+        Some(entry.expansion.clone())
+    }
+
+    fn insert(&mut self, options: ExpandOptions, expansion: Cacheable<Value>, generation: u64) {
+        let concepts = expansion
+            .body
+            .pointer("/expansion/contains")
+            .and_then(|c| c.as_array())
+            .map_or(0, |contains| count_contains(contains))
+            .max(1);
+        if generation != self.generation || concepts > self.max_concepts {
+            return;
+        }
+
+        self.remove(&options);
+        while self.concepts + concepts > self.max_concepts {
+            let Some((_, oldest)) = self.order.pop_first() else {
+                break;
+            };
+            self.remove(&oldest);
+        }
+
+        self.tick += 1;
+        self.concepts += concepts;
+        self.order.insert(self.tick, options.clone());
+        self.entries.insert(
+            options,
+            CachedExpansion {
+                expansion,
+                concepts,
+                last_used: self.tick,
+            },
+        );
+    }
+
+    fn remove(&mut self, options: &ExpandOptions) {
+        if let Some(entry) = self.entries.remove(options) {
+            self.order.remove(&entry.last_used);
+            self.concepts -= entry.concepts;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terminology::ExpandParams;
+    use serde_json::json;
+
+    fn options(url: &str) -> ExpandOptions {
+        ExpandOptions::from_query(ExpandParams {
+            url: Some(url.to_string()),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    /// An expansion with `concepts` entries
+    fn expansion(concepts: usize) -> Cacheable<Value> {
+        let contains: Vec<_> = (0..concepts)
+            .map(|i| json!({ "code": i.to_string() }))
+            .collect();
+        Cacheable::uncached(json!({ "expansion": { "contains": contains } }))
+    }
+
+    fn lru(max_concepts: usize) -> Lru {
+        let ExpansionCache { lru, .. } = ExpansionCache::new(max_concepts);
+        Arc::into_inner(lru).unwrap().into_inner().unwrap()
+    }
+
+    #[test]
+    fn least_recently_used_are_evicted_past_the_concept_bound() {
+        let mut lru = lru(10);
+        lru.insert(options("a"), expansion(4), 0);
+        lru.insert(options("b"), expansion(4), 0);
+        // Using `a` makes `b` the least recently used
+        assert!(lru.get(&options("a")).is_some());
+
+        lru.insert(options("c"), expansion(4), 0);
+        assert!(lru.get(&options("b")).is_none());
+        assert!(lru.get(&options("a")).is_some());
+        assert!(lru.get(&options("c")).is_some());
+        assert_eq!(lru.concepts, 8);
+    }
+
+    #[test]
+    fn expansions_larger_than_the_bound_are_not_cached() {
+        let mut lru = lru(3);
+        lru.insert(options("a"), expansion(4), 0);
+        assert!(lru.get(&options("a")).is_none());
+        assert_eq!(lru.concepts, 0);
+
+        let mut disabled = self::lru(0);
+        disabled.insert(options("a"), expansion(0), 0);
+        assert!(disabled.get(&options("a")).is_none());
+    }
+
+    #[test]
+    fn replacing_an_entry_keeps_the_concept_count() {
+        let mut lru = lru(10);
+        lru.insert(options("a"), expansion(4), 0);
+        lru.insert(options("a"), expansion(6), 0);
+        assert_eq!(lru.concepts, 6);
+        assert_eq!(lru.entries.len(), 1);
+        assert_eq!(lru.order.len(), 1);
+    }
+
+    #[test]
+    fn expansions_computed_before_an_invalidation_are_dropped() {
+        let cache = ExpansionCache::new(10);
+        cache
+            .lru
+            .lock()
+            .unwrap()
+            .insert(options("a"), expansion(1), 0);
+        cache.invalidate();
+        let mut lru = cache.lru.lock().unwrap();
+        assert!(lru.get(&options("a")).is_none());
+
+        lru.insert(options("b"), expansion(1), 0);
+        assert!(lru.get(&options("b")).is_none());
+        lru.insert(options("b"), expansion(1), 1);
+        assert!(lru.get(&options("b")).is_some());
+    }
+}
@@ -0,0 +1,337 @@
+use chrono::{DateTime, NaiveDate};
+use std::sync::Arc;
+
+use super::{split_canonical, Coding, Parameter, Parameters};
+use crate::error::AppError;
+use crate::models::{CodeSystem, PropertyComparator, PropertyFilter, PropertyValueKind};
+use crate::store::TerminologyStore;
+
+/// Most concepts returned by one `$find-matches`
+const MAX_MATCHES: i64 = 1000;
+
+/// A `$find-matches` `property` input: the property `code`, its value as text, and an
+/// optional `comparator` code (`eq` when absent)
+#[derive(Debug, Clone)]
+pub struct PropertyCondition<'a> {
+    pub code: &'a str,
+    pub value: String,
+    pub comparator: Option<&'a str>,
+}
+
+/// `$find-matches`: the concepts of `system` whose properties satisfy every condition.
+/// Properties the CodeSystem declares as `integer` or `decimal` compare as numbers and
+/// `dateTime` ones as ISO 8601 text, and stored values of another type never match them;
+/// any other property only supports `eq`.
+pub async fn find_matches(
+    store: Arc<dyn TerminologyStore>,
+    system: &str,
+    version: Option<&str>,
+    conditions: &[PropertyCondition<'_>],
+) -> Result<Parameters, AppError> {
+    let (system, version) = split_canonical(system, version)?;
+    let code_system = store
+        .get_code_system(system, version)
+        .await?
+        .ok_or_else(|| AppError::UnknownCodeSystem(system.to_string()))?;
+
+    let mut issues = Vec::new();
+    let filters: Vec<PropertyFilter> = conditions
+        .iter()
+        .filter_map(|condition| match property_filter(&code_system, condition) {
+            Ok(filter) => Some(filter),
+            Err(issue) => {
+                issues.push(issue);
+                None
+            }
+        })
+        .collect();
+    if !issues.is_empty() {
+        return Err(AppError::InvalidParameters(issues));
+    }
+
+    let concepts = store
+        .find_concepts(&code_system.id, &filters, MAX_MATCHES)
+        .await?;
+
+    let mut result = Vec::new();
+    if concepts.len() as i64 == MAX_MATCHES {
+        result.push(Parameter::string(
+            "message",
+            format!("Only the first {MAX_MATCHES} matching concepts are returned"),
+        ));
+    }
+    for concept in concepts {
+        let mut coding = Coding::new(&code_system.url, concept.code);
+        coding.version = code_system.version.clone();
+        if let Some(display) = concept.display {
+            coding = coding.with_display(display);
+        }
+        result.push(Parameter::part(
+            "match",
+            vec![Parameter::coding("code", coding)],
+        ));
+    }
+
+    Ok(Parameters::with_parameters(result))
+}
+
+/// Type a condition by the property's declared `type`, rejecting comparators the type
+/// cannot be ordered by and values that do not parse as that type
+fn property_filter(
+    code_system: &CodeSystem,
+    condition: &PropertyCondition<'_>,
+) -> Result<PropertyFilter, String> {
+    let code = condition.code;
+    let comparator = match condition.comparator {
+        None => PropertyComparator::Eq,
+        Some(comparator) => PropertyComparator::from_code(comparator).ok_or_else(|| {
+            format!(
+                "Unknown comparator '{comparator}' for property '{code}'; expected eq, gt, \
+                 lt, ge or le"
+            )
+        })?,
+    };
+
+    let declared_type = code_system
+        .content
+        .get("property")
+        .and_then(|p| p.as_array())
+        .into_iter()
+        .flatten()
+        .find(|p| p.get("code").and_then(|c| c.as_str()) == Some(code))
+        .and_then(|p| p.get("type")?.as_str())
+        .unwrap_or("code");
+    let kind = match declared_type {
+        "integer" | "decimal" => PropertyValueKind::Number,
+        "dateTime" => PropertyValueKind::DateTime,
+        _ => PropertyValueKind::Text,
+    };
+
+    if kind == PropertyValueKind::Text && comparator != PropertyComparator::Eq {
+        return Err(format!(
+            "Comparator '{}' is not supported for property '{code}' of type {declared_type}; \
+             only eq is",
+            condition.comparator.unwrap_or_default()
+        ));
+    }
+    if kind == PropertyValueKind::Number
+        && !condition.value.parse::<f64>().is_ok_and(f64::is_finite)
+    {
+        return Err(format!(
+            "Value '{}' of property '{code}' is not a number",
+            condition.value
+        ));
+    }
+
+    if kind == PropertyValueKind::DateTime && !is_date_time(&condition.value) {
+        return Err(format!(
+            "Value '{}' of property '{code}' is not a dateTime",
+            condition.value
+        ));
+    }
+
+    Ok(PropertyFilter {
+        code: code.to_string(),
+        kind,
+        comparator,
+        value: condition.value.clone(),
+    })
+}
+
+/// Whether `value` is a FHIR dateTime: a year, a year and month, a date, or a date and time
+/// with a zone
+fn is_date_time(value: &str) -> bool {
+    match value.len() {
+        4 => value.bytes().all(|b| b.is_ascii_digit()),
+        7 => NaiveDate::parse_from_str(&format!("{value}-01"), "%Y-%m-%d").is_ok(),
+        10 => NaiveDate::parse_from_str(value, "%Y-%m-%d").is_ok(),
+        _ => value.contains('T') && DateTime::parse_from_rfc3339(value).is_ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{MemoryStore, PostgresStore};
+    use serde_json::{json, Value};
+    use sqlx::types::Json;
+    use sqlx::PgPool;
+    use uuid::Uuid;
+
+    /// A CodeSystem declaring a numeric, a dateTime and a code property
+    fn content(url: &str) -> Value {
+        json!({
+            "url": url,
+            "property": [
+                { "code": "weight", "type": "decimal" },
+                { "code": "born", "type": "dateTime" },
+                { "code": "colour", "type": "code" }
+            ]
+        })
+    }
+
+    fn condition<'a>(
+        code: &'a str,
+        value: &str,
+        comparator: Option<&'a str>,
+    ) -> PropertyCondition<'a> {
+        PropertyCondition {
+            code,
+            value: value.to_string(),
+            comparator,
+        }
+    }
+
+    /// The codes of the `match` parts
+    fn matched(result: &Parameters) -> Vec<String> {
+        result
+            .parameter
+            .iter()
+            .flatten()
+            .filter(|p| p.name == "match")
+            .filter_map(|p| {
+                let parts = Parameters::with_parameters(p.part.clone()?);
+                parts.get_coding("code")?.code.clone()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn values_and_comparators_must_fit_the_property_type() {
+        let url = "http://example.org/animals";
+        let store: Arc<dyn TerminologyStore> =
+            Arc::new(MemoryStore::default().with_code_system(content(url)));
+        let conditions = [
+            condition("weight", "heavy", Some("gt")),
+            condition("born", "yesterday", Some("lt")),
+            condition("colour", "brown", Some("gt")),
+            condition("weight", "5", Some("above")),
+        ];
+
+        let Err(AppError::InvalidParameters(issues)) =
+            find_matches(store, url, None, &conditions).await
+        else {
+            panic!("expected the conditions to be rejected");
+        };
+        assert_eq!(
+            issues,
+            [
+                "Value 'heavy' of property 'weight' is not a number",
+                "Value 'yesterday' of property 'born' is not a dateTime",
+                "Comparator 'gt' is not supported for property 'colour' of type code; only eq \
+                 is",
+                "Unknown comparator 'above' for property 'weight'; expected eq, gt, lt, ge or le",
+            ]
+        );
+    }
+
+    /// A CodeSystem in the scratch database named by `TEST_DATABASE_URL` whose concepts
+    /// have the given properties, in order
+    async fn stored(concepts: &[(&str, Value)]) -> (Arc<dyn TerminologyStore>, String) {
+        let database_url = std::env::var("TEST_DATABASE_URL")
+            .expect("TEST_DATABASE_URL must name a scratch database");
+        let pool = PgPool::connect(&database_url).await.unwrap();
+        sqlx::migrate!().run(&pool).await.unwrap();
+        let store = PostgresStore::new(pool.clone());
+
+        let url = format!("http://example.org/CodeSystem/{}", Uuid::new_v4());
+        let now = chrono::Utc::now();
+        let code_system = store
+            .create_code_system(CodeSystem {
+                id: Uuid::nil(),
+                url: url.clone(),
+                version: None,
+                status: "active".to_string(),
+                name: None,
+                title: None,
+                fhir_version: None,
+                content: Json(content(&url)),
+                created_at: now,
+                updated_at: now,
+            })
+            .await
+            .unwrap();
+        for (ordinal, (code, properties)) in concepts.iter().enumerate() {
+            sqlx::query(
+                "INSERT INTO concepts (code_system_id, code, properties, ordinal)
+                 VALUES ($1, $2, $3, $4)",
+            )
+            .bind(code_system.id)
+            .bind(code)
+            .bind(Json(properties))
+            .bind(ordinal as i32)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+        (Arc::new(store), url)
+    }
+
+    #[tokio::test]
+    #[ignore = "needs PostgreSQL: set TEST_DATABASE_URL and run with --ignored"]
+    async fn numbers_compare_by_value_and_uncastable_ones_never_match() {
+        let (store, url) = stored(&[
+            ("light", json!([{ "code": "weight", "valueInteger": 5 }])),
+            ("heavy", json!([{ "code": "weight", "valueDecimal": 12.5 }])),
+            (
+                "unknown",
+                json!([{ "code": "weight", "valueInteger": "lots" }]),
+            ),
+            (
+                "unweighed",
+                json!([{ "code": "colour", "valueCode": "brown" }]),
+            ),
+        ])
+        .await;
+        let matches = |value: &'static str, comparator: &'static str| {
+            let store = store.clone();
+            let url = url.clone();
+            async move {
+                let conditions = [condition("weight", value, Some(comparator))];
+                matched(&find_matches(store, &url, None, &conditions).await.unwrap())
+            }
+        };
+
+        // As text, "12.5" would sort before "5"
+        assert_eq!(matches("5", "gt").await, ["heavy"]);
+        assert_eq!(matches("5", "ge").await, ["light", "heavy"]);
+        assert_eq!(matches("12.5", "eq").await, ["heavy"]);
+        assert_eq!(matches("100", "lt").await, ["light", "heavy"]);
+        assert_eq!(matches("12.5", "le").await, ["light", "heavy"]);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs PostgreSQL: set TEST_DATABASE_URL and run with --ignored"]
+    async fn date_times_compare_in_order_and_other_text_never_matches() {
+        let (store, url) = stored(&[
+            (
+                "old",
+                json!([{ "code": "born", "valueDateTime": "2019-03-01" }]),
+            ),
+            (
+                "young",
+                json!([{ "code": "born", "valueDateTime": "2023-11-20" }]),
+            ),
+            (
+                "unknown",
+                json!([{ "code": "born", "valueDateTime": "unknown" }]),
+            ),
+        ])
+        .await;
+        let matches = |value: &'static str, comparator: &'static str| {
+            let store = store.clone();
+            let url = url.clone();
+            async move {
+                let conditions = [condition("born", value, Some(comparator))];
+                matched(&find_matches(store, &url, None, &conditions).await.unwrap())
+            }
+        };
+
+        assert_eq!(matches("2020-01-01", "gt").await, ["young"]);
+        assert_eq!(matches("2020-01-01", "lt").await, ["old"]);
+        assert_eq!(matches("2023-11-20", "le").await, ["old", "young"]);
+        assert_eq!(matches("2019-03-01", "eq").await, ["old"]);
+        // "unknown" sorts after every year as text, yet is not a dateTime
+        assert_eq!(matches("0001", "ge").await, ["old", "young"]);
+    }
+}
@@ -0,0 +1,62 @@
+use serde_json::Value;
+
+/// Designation `use` codes that mark a designation as a definition
+/// (the SNOMED CT definition type and the plain FHIR `definition` use)
+const DEFINITION_USE_CODES: [&str; 2] = ["900000000000550004", "definition"];
+
+/// Find the designation in `designations` (a FHIR `designation` array) best matching
+/// `language`, among either definition or non-definition designations.
+/// Returns its language and value.
+pub fn localized_designation(
+    designations: &Value,
+    language: &str,
+    definition: bool,
+) -> Option<(String, String)> {
+    let designation = localized_designation_entry(designations, language, definition)?;
+    Some((
+        designation.get("language")?.as_str()?.to_string(),
+        designation.get("value")?.as_str()?.to_string(),
+    ))
+}
+
+/// Like [`localized_designation`], but returns the whole designation, including its `use`
+pub fn localized_designation_entry<'a>(
+    designations: &'a Value,
+    language: &str,
+    definition: bool,
+) -> Option<&'a Value> {
+    let candidates: Vec<(&str, &Value)> = designations
+        .as_array()?
+        .iter()
+        .filter(|d| {
+            let is_definition = d
+                .pointer("/use/code")
+                .and_then(|c| c.as_str())
+                .is_some_and(|c| DEFINITION_USE_CODES.contains(&c));
+            is_definition == definition
+        })
+        .filter(|d| d.get("value").is_some_and(Value::is_string))
+        .filter_map(|d| Some((d.get("language")?.as_str()?, d)))
+        .collect();
+
+    best_language_match(&candidates, language).map(|(_, designation)| designation)
+}
+
+/// Pick the `(language, item)` pair best matching `language`: an exact tag match first,
+/// then a match on the primary subtag (e.g. `de` for `de-CH`)
+fn best_language_match<'a, T: Copy>(
+    candidates: &[(&'a str, T)],
+    language: &str,
+) -> Option<(&'a str, T)> {
+    let primary = |tag: &str| tag.split('-').next().unwrap_or(tag).to_ascii_lowercase();
+
+    candidates
+        .iter()
+        .find(|(l, _)| l.eq_ignore_ascii_case(language))
+        .or_else(|| {
+            candidates
+                .iter()
+                .find(|(l, _)| primary(l) == primary(language))
+        })
+        .copied()
+}
@@ -0,0 +1,445 @@
+use std::sync::Arc;
+
+use super::{
+    localized_designation, localized_designation_entry, split_canonical, Cacheable, Coding,
+    Parameter, ParameterValue, Parameters, ResolvedVersion,
+};
+use crate::error::AppError;
+use crate::store::TerminologyStore;
+
+const SNOMED_CT: &str = "http://snomed.info/sct";
+
+/// SNOMED CT "Definition", the designation use for definitions in the FHIR
+/// designation-use value set
+const DEFINITION_USE_CODE: &str = "900000000000550004";
+
+/// Most property parts returned by one `$lookup`; use `property` to select specific ones
+const MAX_LOOKUP_PROPERTIES: usize = 100;
+
+/// `$lookup`: the name, display, designations and properties of a code, including those
+/// added by supplements, and its direct `parent` and `child` concepts from the closure
+/// table. Only `requested_properties` are returned when any are given.
+///
+/// With `all_versions`, every installed version of the system defining the code is
+/// reported as a `versionMatch` with its display there, and when no version is requested
+/// but the default one lacks the code, the newest version defining it is looked up instead.
+pub async fn lookup(
+    store: Arc<dyn TerminologyStore>,
+    system: &str,
+    code: &str,
+    version: Option<&str>,
+    display_language: Option<&str>,
+    requested_properties: &[&str],
+    all_versions: bool,
+) -> Result<Cacheable<Parameters>, AppError> {
+    let (system, version) = split_canonical(system, version)?;
+
+    // Get the CodeSystem
+    let mut code_system = store
+        .get_code_system(system, version)
+        .await?
+        .ok_or_else(|| AppError::UnknownCodeSystem(system.to_string()))?;
+
+    let code_versions = if all_versions {
+        store.get_concept_versions(system, code).await?
+    } else {
+        Vec::new()
+    };
+
+    // Find the concept in the database
+    let mut concept = store.get_concept(&code_system.id, code).await?;
+    if concept.is_none() && version.is_none() {
+        if let Some((Some(newest), _)) = code_versions.first() {
+            if let Some(fallback) = store.get_code_system(system, Some(newest)).await? {
+                concept = store.get_concept(&fallback.id, code).await?;
+                code_system = fallback;
+            }
+        }
+    }
+    let concept = concept.ok_or_else(|| AppError::UnknownCode {
+        system: system.to_string(),
+        code: code.to_string(),
+    })?;
+
+    // The display in the requested language, when a designation has it
+    let localized_display = display_language
+        .zip(concept.designations.as_ref())
+        .and_then(|(language, designations)| localized_designation(designations, language, false))
+        .map(|(_, value)| value);
+
+    // Build result Parameters
+    let mut result_params = vec![
+        Parameter::string("name", code_system.name.as_deref().unwrap_or("")),
+        Parameter::string(
+            "display",
+            localized_display
+                .or_else(|| concept.display.clone())
+                .unwrap_or_default(),
+        ),
+    ];
+    if all_versions {
+        if let Some(version) = &code_system.version {
+            result_params.push(Parameter::string("version", version.clone()));
+        }
+        for (version, display) in code_versions {
+            let mut parts = Vec::new();
+            if let Some(version) = version {
+                parts.push(Parameter::string("version", version));
+            }
+            if let Some(display) = display {
+                parts.push(Parameter::string("display", display));
+            }
+            result_params.push(Parameter::part("versionMatch", parts));
+        }
+    }
+
+    // Add designation if we have a definition, preferring one in the requested language
+    let localized_definition = display_language
+        .zip(concept.designations.as_ref())
+        .and_then(|(language, designations)| {
+            localized_designation_entry(designations, language, true)
+        });
+    if let Some(designation) = localized_definition {
+        let mut parts = Vec::new();
+        if let Some(language) = designation.get("language").and_then(|l| l.as_str()) {
+            parts.push(Parameter::code("language", language));
+        }
+        parts.push(Parameter::coding("use", designation_use(designation)));
+        parts.push(Parameter::string(
+            "value",
+            designation
+                .get("value")
+                .and_then(|v| v.as_str())
+                .unwrap_or_default(),
+        ));
+        result_params.push(Parameter::part("designation", parts));
+    } else if let Some(definition) = &concept.definition {
+        result_params.push(Parameter::part(
+            "designation",
+            vec![
+                Parameter::coding("use", definition_use()),
+                Parameter::string("value", definition.clone()),
+            ],
+        ));
+    }
+
+    // Supplements add designations and properties to the base concept. Others may be
+    // linked at any time, so a result using them is never pinned.
+    let mut resolved = ResolvedVersion::code_system(&code_system, version.is_some());
+    let mut supplement_concepts = Vec::new();
+    for supplement in store.get_supplements(&code_system).await? {
+        resolved.depends_on(ResolvedVersion::code_system(&supplement, false));
+        if let Some(concept) = store.get_concept(&supplement.id, code).await? {
+            supplement_concepts.push(concept);
+        }
+    }
+    for designation in supplement_concepts
+        .iter()
+        .filter_map(|c| c.designations.as_ref()?.as_array())
+        .flatten()
+    {
+        let Some(value) = designation.get("value").and_then(|v| v.as_str()) else {
+            continue;
+        };
+        let mut parts = Vec::new();
+        if let Some(language) = designation.get("language").and_then(|l| l.as_str()) {
+            parts.push(Parameter::code("language", language));
+        }
+        if let Some(coding) = designation
+            .get("use")
+            .and_then(|u| serde_json::from_value::<Coding>(u.clone()).ok())
+        {
+            parts.push(Parameter::coding("use", coding));
+        }
+        parts.push(Parameter::string("value", value));
+        result_params.push(Parameter::part("designation", parts));
+    }
+
+    // Direct parents and children from the closure table, each described by its display
+    let mut hierarchy = Vec::new();
+    for (property, parents) in [("parent", true), ("child", false)] {
+        for related in store
+            .get_related_concepts(&code_system.id, &concept.code, parents)
+            .await?
+        {
+            hierarchy.push((
+                property.to_string(),
+                ParameterValue::ValueCode(related.code),
+                related.display,
+            ));
+        }
+    }
+
+    // Add the hierarchy and the concept's properties, leaving out stored parents and
+    // children already given, limited to the requested codes and capped in number
+    let stored = std::iter::once(&concept)
+        .chain(&supplement_concepts)
+        .filter_map(|c| c.properties.as_ref())
+        .flat_map(|properties| concept_properties(&properties.0))
+        .filter(|(code, value)| {
+            !hierarchy.iter().any(|(related, related_value, _)| {
+                related == code
+                    && matches!(
+                        (related_value, value),
+                        (ParameterValue::ValueCode(a), ParameterValue::ValueCode(b)) if a == b
+                    )
+            })
+        })
+        .map(|(code, value)| (code, value, None))
+        .collect::<Vec<_>>();
+    let properties: Vec<_> = hierarchy
+        .into_iter()
+        .chain(stored)
+        .filter(|(code, _, _)| {
+            requested_properties.is_empty() || requested_properties.contains(&code.as_str())
+        })
+        .collect();
+    let total_properties = properties.len();
+    for (code, value, description) in properties.into_iter().take(MAX_LOOKUP_PROPERTIES) {
+        let mut parts = vec![
+            Parameter::code("code", code),
+            Parameter {
+                name: "value".to_string(),
+                value: Some(value),
+                part: None,
+                resource: None,
+            },
+        ];
+        if let Some(description) = description {
+            parts.push(Parameter::string("description", description));
+        }
+        result_params.push(Parameter::part("property", parts));
+    }
+    if total_properties > MAX_LOOKUP_PROPERTIES {
+        result_params.push(Parameter::string(
+            "message",
+            format!(
+                "Returned {MAX_LOOKUP_PROPERTIES} of {total_properties} properties; \
+                 use the property parameter to select specific ones"
+            ),
+        ));
+    }
+
+    Ok(Cacheable::new(
+        Parameters::with_parameters(result_params),
+        resolved,
+    ))
+}
+
+/// The SNOMED CT "Definition" designation use
+fn definition_use() -> Coding {
+    Coding::new(SNOMED_CT, DEFINITION_USE_CODE).with_display("Definition")
+}
+
+/// A stored designation's `use` Coding, or the definition use when it lacks system or code
+fn designation_use(designation: &serde_json::Value) -> Coding {
+    designation
+        .get("use")
+        .and_then(|u| serde_json::from_value::<Coding>(u.clone()).ok())
+        .filter(|coding| coding.system.is_some() && coding.code.is_some())
+        .unwrap_or_else(definition_use)
+}
+
+/// The `(code, value)` pairs of a stored concept's properties: a FHIR `property` array of
+/// `{code, value[x]}` entries, or an object of code to value where values that are
+/// Codings (objects with a `code`) become `valueCoding`
+fn concept_properties(properties: &serde_json::Value) -> Vec<(String, ParameterValue)> {
+    if let Some(entries) = properties.as_array() {
+        return entries
+            .iter()
+            .filter_map(|entry| {
+                let entry = entry.as_object()?;
+                let code = entry.get("code")?.as_str()?;
+                let (key, value) = entry.iter().find(|(key, _)| key.starts_with("value"))?;
+                let value = serde_json::from_value(serde_json::json!({ key: value }))
+                    .unwrap_or_else(|_| ParameterValue::ValueString(value.to_string()));
+                Some((code.to_string(), value))
+            })
+            .collect();
+    }
+
+    properties
+        .as_object()
+        .into_iter()
+        .flatten()
+        .map(|(key, value)| {
+            let value = value
+                .get("code")
+                .and_then(|_| serde_json::from_value::<Coding>(value.clone()).ok())
+                .map(ParameterValue::ValueCoding)
+                .unwrap_or_else(|| ParameterValue::ValueString(value.to_string()));
+            (key.clone(), value)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+    use serde_json::json;
+
+    const SYSTEM: &str = "http://example.org/animals";
+
+    fn store() -> Arc<dyn TerminologyStore> {
+        Arc::new(MemoryStore::default().with_code_system(json!({
+            "url": SYSTEM,
+            "version": "1",
+            "name": "Animals",
+            "concept": [{
+                "code": "animal",
+                "display": "Animal",
+                "concept": [{
+                    "code": "dog",
+                    "display": "Dog",
+                    "designation": [{ "language": "de", "value": "Hund" }]
+                }]
+            }]
+        })))
+    }
+
+    /// The `value` codes of the `property` parts with `code`
+    fn property_values(result: &Parameters, code: &str) -> Vec<String> {
+        result
+            .parameter
+            .iter()
+            .flatten()
+            .filter(|p| p.name == "property")
+            .filter_map(|p| {
+                let parts = Parameters::with_parameters(p.part.clone()?);
+                let value = parts.get_code("value").map(str::to_string);
+                (parts.get_code("code") == Some(code)).then_some(value)?
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn display_is_the_designation_in_the_display_language() {
+        let german = lookup(store(), SYSTEM, "dog", None, Some("de-CH"), &[], false)
+            .await
+            .unwrap()
+            .body;
+        assert_eq!(german.get_string("display"), Some("Hund"));
+
+        let french = lookup(store(), SYSTEM, "dog", None, Some("fr"), &[], false)
+            .await
+            .unwrap()
+            .body;
+        assert_eq!(french.get_string("display"), Some("Dog"));
+    }
+
+    #[tokio::test]
+    async fn parent_and_child_come_from_the_hierarchy() {
+        let animal = lookup(store(), SYSTEM, "animal", None, None, &[], false)
+            .await
+            .unwrap()
+            .body;
+        assert_eq!(property_values(&animal, "child"), ["dog"]);
+        assert!(property_values(&animal, "parent").is_empty());
+
+        let dog = lookup(store(), SYSTEM, "dog", None, None, &["parent"], false)
+            .await
+            .unwrap()
+            .body;
+        assert_eq!(property_values(&dog, "parent"), ["animal"]);
+    }
+
+    #[tokio::test]
+    async fn unknown_system_and_code_are_told_apart() {
+        let unknown_system = lookup(
+            store(),
+            "http://example.org/other",
+            "dog",
+            None,
+            None,
+            &[],
+            false,
+        )
+        .await;
+        assert!(matches!(
+            unknown_system,
+            Err(AppError::UnknownCodeSystem(_))
+        ));
+
+        let unknown_code = lookup(store(), SYSTEM, "cat", None, None, &[], false).await;
+        assert!(matches!(unknown_code, Err(AppError::UnknownCode { .. })));
+    }
+
+    #[tokio::test]
+    async fn pinned_version_is_cacheable() {
+        let pinned = lookup(
+            store(),
+            &format!("{SYSTEM}|1"),
+            "dog",
+            None,
+            None,
+            &[],
+            false,
+        )
+        .await
+        .unwrap();
+        assert!(pinned.resolved.unwrap().fully_pinned());
+
+        let latest = lookup(store(), SYSTEM, "dog", None, None, &[], false)
+            .await
+            .unwrap();
+        assert!(!latest.resolved.unwrap().fully_pinned());
+    }
+
+    #[tokio::test]
+    async fn properties_beyond_the_cap_are_reported_not_returned() {
+        let tags: Vec<_> = (0..150)
+            .map(|i| json!({ "code": "tag", "valueCode": format!("t{i}") }))
+            .collect();
+        let store: Arc<dyn TerminologyStore> =
+            Arc::new(MemoryStore::default().with_code_system(json!({
+                "url": SYSTEM,
+                "concept": [{ "code": "dog", "property": tags }]
+            })));
+
+        let capped = lookup(store.clone(), SYSTEM, "dog", None, None, &[], false)
+            .await
+            .unwrap()
+            .body;
+        assert_eq!(property_values(&capped, "tag").len(), MAX_LOOKUP_PROPERTIES);
+        assert_eq!(property_values(&capped, "tag")[0], "t0");
+        assert_eq!(
+            capped.get_string("message"),
+            Some(
+                "Returned 100 of 150 properties; use the property parameter to select specific \
+                 ones"
+            )
+        );
+
+        // Selecting other properties leaves the tags out, so nothing is capped
+        let selected = lookup(store, SYSTEM, "dog", None, None, &["child"], false)
+            .await
+            .unwrap()
+            .body;
+        assert!(property_values(&selected, "tag").is_empty());
+        assert_eq!(selected.get_string("message"), None);
+    }
+
+    #[tokio::test]
+    async fn codes_of_a_case_insensitive_system_match_in_any_case() {
+        let insensitive: Arc<dyn TerminologyStore> =
+            Arc::new(MemoryStore::default().with_code_system(json!({
+                "url": SYSTEM,
+                "caseSensitive": false,
+                "concept": [{
+                    "code": "animal",
+                    "concept": [{ "code": "dog", "display": "Dog" }]
+                }]
+            })));
+
+        let dog = lookup(insensitive, SYSTEM, "DOG", None, None, &[], false)
+            .await
+            .unwrap()
+            .body;
+        assert_eq!(dog.get_string("display"), Some("Dog"));
+        assert_eq!(property_values(&dog, "parent"), ["animal"]);
+
+        let unknown = lookup(store(), SYSTEM, "DOG", None, None, &[], false).await;
+        assert!(matches!(unknown, Err(AppError::UnknownCode { .. })));
+    }
+}
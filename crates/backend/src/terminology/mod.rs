@@ -0,0 +1,27 @@
+//! The terminology operations, independent of HTTP: each takes the store and typed
+//! inputs. The `api` handlers parse requests into these inputs and render the results;
+//! the inputs, the `Parameters` results and their cache metadata are defined here.
+
+mod caching;
+mod expand;
+mod expansion_cache;
+mod find_matches;
+mod language;
+mod lookup;
+mod parameters;
+mod single_flight;
+mod subsumes;
+mod translate;
+mod validate;
+
+pub use caching::*;
+pub use expand::*;
+pub use expansion_cache::*;
+pub use find_matches::*;
+pub use language::*;
+pub use lookup::*;
+pub use parameters::*;
+pub use single_flight::*;
+pub use subsumes::*;
+pub use translate::*;
+pub use validate::*;
@@ -0,0 +1,349 @@
+use serde::{Deserialize, Serialize};
+
+use crate::error::AppError;
+
+/// FHIR Parameters resource for operation inputs/outputs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Parameters {
+    #[serde(rename = "resourceType")]
+    pub resource_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parameter: Option<Vec<Parameter>>,
+}
+
+/// Individual parameter within Parameters resource
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Parameter {
+    pub name: String,
+    #[serde(flatten)]
+    pub value: Option<ParameterValue>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub part: Option<Vec<Parameter>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resource: Option<serde_json::Value>,
+}
+
+/// Parameter value types according to FHIR spec
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[allow(clippy::enum_variant_names)]
+pub enum ParameterValue {
+    ValueString(String),
+    ValueBoolean(bool),
+    ValueInteger(i64),
+    ValueDecimal(f64),
+    ValueDateTime(String),
+    ValueCode(String),
+    ValueUri(String),
+    ValueUrl(String),
+    ValueCanonical(String),
+    ValueCoding(Coding),
+    ValueCodeableConcept(CodeableConcept),
+}
+
+/// The `value[x]` element names accepted for a [`Parameter`], one per [`ParameterValue`]
+pub(crate) const SUPPORTED_VALUE_TYPES: [&str; 11] = [
+    "valueString",
+    "valueBoolean",
+    "valueInteger",
+    "valueDecimal",
+    "valueDateTime",
+    "valueCode",
+    "valueUri",
+    "valueUrl",
+    "valueCanonical",
+    "valueCoding",
+    "valueCodeableConcept",
+];
+
+/// FHIR Coding datatype
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Coding {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display: Option<String>,
+}
+
+/// FHIR CodeableConcept datatype
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CodeableConcept {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub coding: Option<Vec<Coding>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+}
+
+impl Parameters {
+    pub fn new() -> Self {
+        Self {
+            resource_type: "Parameters".to_string(),
+            parameter: None,
+        }
+    }
+
+    pub fn with_parameters(params: Vec<Parameter>) -> Self {
+        Self {
+            resource_type: "Parameters".to_string(),
+            parameter: Some(params),
+        }
+    }
+
+    pub fn get_parameter(&self, name: &str) -> Option<&Parameter> {
+        self.parameter.as_ref()?.iter().find(|p| p.name == name)
+    }
+
+    pub fn get_string(&self, name: &str) -> Option<&str> {
+        match self.get_parameter(name)?.value.as_ref()? {
+            ParameterValue::ValueString(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn get_boolean(&self, name: &str) -> Option<bool> {
+        match self.get_parameter(name)?.value.as_ref()? {
+            ParameterValue::ValueBoolean(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn get_integer(&self, name: &str) -> Option<i64> {
+        match self.get_parameter(name)?.value.as_ref()? {
+            ParameterValue::ValueInteger(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    pub fn get_date_time(&self, name: &str) -> Option<&str> {
+        match self.get_parameter(name)?.value.as_ref()? {
+            ParameterValue::ValueDateTime(d) => Some(d),
+            _ => None,
+        }
+    }
+
+    pub fn get_code(&self, name: &str) -> Option<&str> {
+        match self.get_parameter(name)?.value.as_ref()? {
+            ParameterValue::ValueCode(c) => Some(c),
+            _ => None,
+        }
+    }
+
+    /// Every `valueCode` (or `valueString`) given for a repeatable parameter
+    pub fn get_all_codes(&self, name: &str) -> Vec<&str> {
+        self.parameter
+            .iter()
+            .flatten()
+            .filter(|p| p.name == name)
+            .filter_map(|p| match p.value.as_ref()? {
+                ParameterValue::ValueCode(c) | ParameterValue::ValueString(c) => Some(c.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn get_uri(&self, name: &str) -> Option<&str> {
+        match self.get_parameter(name)?.value.as_ref()? {
+            ParameterValue::ValueUri(u) => Some(u),
+            _ => None,
+        }
+    }
+
+    pub fn get_canonical(&self, name: &str) -> Option<&str> {
+        match self.get_parameter(name)?.value.as_ref()? {
+            ParameterValue::ValueCanonical(c) => Some(c),
+            _ => None,
+        }
+    }
+
+    pub fn get_coding(&self, name: &str) -> Option<&Coding> {
+        match self.get_parameter(name)?.value.as_ref()? {
+            ParameterValue::ValueCoding(c) => Some(c),
+            _ => None,
+        }
+    }
+
+    pub fn get_codeable_concept(&self, name: &str) -> Option<&CodeableConcept> {
+        match self.get_parameter(name)?.value.as_ref()? {
+            ParameterValue::ValueCodeableConcept(cc) => Some(cc),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Parameters {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Parameter {
+    pub fn string(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            value: Some(ParameterValue::ValueString(value.into())),
+            part: None,
+            resource: None,
+        }
+    }
+
+    pub fn boolean(name: impl Into<String>, value: bool) -> Self {
+        Self {
+            name: name.into(),
+            value: Some(ParameterValue::ValueBoolean(value)),
+            part: None,
+            resource: None,
+        }
+    }
+
+    pub fn integer(name: impl Into<String>, value: i64) -> Self {
+        Self {
+            name: name.into(),
+            value: Some(ParameterValue::ValueInteger(value)),
+            part: None,
+            resource: None,
+        }
+    }
+
+    pub fn date_time(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            value: Some(ParameterValue::ValueDateTime(value.into())),
+            part: None,
+            resource: None,
+        }
+    }
+
+    pub fn code(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            value: Some(ParameterValue::ValueCode(value.into())),
+            part: None,
+            resource: None,
+        }
+    }
+
+    pub fn uri(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            value: Some(ParameterValue::ValueUri(value.into())),
+            part: None,
+            resource: None,
+        }
+    }
+
+    pub fn coding(name: impl Into<String>, coding: Coding) -> Self {
+        Self {
+            name: name.into(),
+            value: Some(ParameterValue::ValueCoding(coding)),
+            part: None,
+            resource: None,
+        }
+    }
+
+    pub fn part(name: impl Into<String>, parts: Vec<Parameter>) -> Self {
+        Self {
+            name: name.into(),
+            value: None,
+            part: Some(parts),
+            resource: None,
+        }
+    }
+
+    pub fn resource(name: impl Into<String>, resource: serde_json::Value) -> Self {
+        Self {
+            name: name.into(),
+            value: None,
+            part: None,
+            resource: Some(resource),
+        }
+    }
+}
+
+/// Split a canonical reference `url|version` into its url and version. A version given
+/// separately must agree with a piped one.
+pub fn split_canonical<'a>(
+    canonical: &'a str,
+    version: Option<&'a str>,
+) -> Result<(&'a str, Option<&'a str>), AppError> {
+    let Some((url, piped)) = canonical.split_once('|') else {
+        return Ok((canonical, version));
+    };
+    let piped = Some(piped).filter(|v| !v.is_empty());
+
+    match (piped, version) {
+        (Some(piped), Some(version)) if piped != version => {
+            Err(AppError::InvalidParameters(vec![format!(
+                "version '{version}' conflicts with the version in '{canonical}'"
+            )]))
+        }
+        (piped, version) => Ok((url, piped.or(version))),
+    }
+}
+
+impl Coding {
+    pub fn new(system: impl Into<String>, code: impl Into<String>) -> Self {
+        Self {
+            system: Some(system.into()),
+            version: None,
+            code: Some(code.into()),
+            display: None,
+        }
+    }
+
+    pub fn with_display(mut self, display: impl Into<String>) -> Self {
+        self.display = Some(display.into());
+        self
+    }
+}
+
+/// Split the comma-separated `property` query parameter into codes
+pub fn property_codes(property: Option<&str>) -> Vec<&str> {
+    property
+        .map(|p| {
+            p.split(',')
+                .map(str::trim)
+                .filter(|p| !p.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Collects problems with operation inputs so they can be reported all at once
+#[derive(Debug, Default)]
+pub struct ParameterIssues {
+    issues: Vec<String>,
+}
+
+impl ParameterIssues {
+    /// Pass `value` through, recording `<name> parameter required` when it is missing
+    pub fn require<T>(&mut self, name: &str, value: Option<T>) -> Option<T> {
+        if value.is_none() {
+            self.issues.push(format!("{name} parameter required"));
+        }
+        value
+    }
+
+    /// Record an arbitrary problem with the inputs
+    pub fn invalid(&mut self, message: impl Into<String>) {
+        self.issues.push(message.into());
+    }
+
+    /// Fail with every recorded issue, if any
+    pub fn check(self) -> Result<(), AppError> {
+        if self.issues.is_empty() {
+            Ok(())
+        } else {
+            Err(self.into())
+        }
+    }
+}
+
+impl From<ParameterIssues> for AppError {
+    fn from(issues: ParameterIssues) -> Self {
+        AppError::InvalidParameters(issues.issues)
+    }
+}
@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+
+use crate::error::AppError;
+
+/// Coalesces concurrent computations with the same key: the first caller computes, later
+/// callers wait for its result instead of repeating the work. Only successes are shared;
+/// when the computation fails or its caller goes away, each waiting caller computes
+/// for itself.
+pub struct SingleFlight<K, V> {
+    inflight: Arc<Mutex<HashMap<K, watch::Receiver<Option<V>>>>>,
+}
+
+impl<K, V> Clone for SingleFlight<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            inflight: self.inflight.clone(),
+        }
+    }
+}
+
+impl<K, V> Default for SingleFlight<K, V> {
+    fn default() -> Self {
+        Self {
+            inflight: Arc::default(),
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> SingleFlight<K, V> {
+    pub async fn run<F, Fut>(&self, key: K, compute: F) -> Result<V, AppError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V, AppError>>,
+    {
+        let waiting = {
+            let mut inflight = self.inflight.lock().unwrap();
+            match inflight.get(&key) {
+                Some(receiver) => Err(receiver.clone()),
+                None => {
+                    let (sender, receiver) = watch::channel(None);
+                    inflight.insert(key.clone(), receiver);
+                    Ok(sender)
+                }
+            }
+        };
+
+        match waiting {
+            Ok(sender) => {
+                let flight = InFlight {
+                    inflight: &self.inflight,
+                    key,
+                };
+                let result = compute().await;
+                // Later callers start their own computation from here on
+                drop(flight);
+                if let Ok(value) = &result {
+                    sender.send_replace(Some(value.clone()));
+                }
+                result
+            }
+            Err(mut receiver) => {
+                if receiver.changed().await.is_ok() {
+                    if let Some(value) = receiver.borrow().clone() {
+                        return Ok(value);
+                    }
+                }
+                compute().await
+            }
+        }
+    }
+}
+
+/// Removes the key of a running computation when it finishes or is cancelled
+struct InFlight<'a, K: Hash + Eq, V> {
+    inflight: &'a Mutex<HashMap<K, watch::Receiver<Option<V>>>>,
+    key: K,
+}
+
+impl<K: Hash + Eq, V> Drop for InFlight<'_, K, V> {
+    fn drop(&mut self) {
+        if let Ok(mut inflight) = self.inflight.lock() {
+            inflight.remove(&self.key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::sync::Notify;
+
+    #[tokio::test]
+    async fn concurrent_callers_share_one_computation() {
+        let flight = SingleFlight::<&str, u32>::default();
+        let computed = AtomicUsize::new(0);
+        let release = Notify::new();
+
+        let first = flight.run("key", || async {
+            computed.fetch_add(1, Ordering::SeqCst);
+            release.notified().await;
+            Ok(42)
+        });
+        let second = flight.run("key", || async {
+            computed.fetch_add(1, Ordering::SeqCst);
+            Ok(0)
+        });
+        let releaser = async {
+            tokio::task::yield_now().await;
+            release.notify_one();
+        };
+
+        let (first, second, ()) = tokio::join!(first, second, releaser);
+        assert_eq!(first.unwrap(), 42);
+        assert_eq!(second.unwrap(), 42);
+        assert_eq!(computed.load(Ordering::SeqCst), 1);
+        assert!(flight.inflight.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn waiting_callers_compute_for_themselves_after_a_failure() {
+        let flight = SingleFlight::<&str, u32>::default();
+        let release = Notify::new();
+
+        let first = flight.run("key", || async {
+            release.notified().await;
+            Err(AppError::BadRequest("failed".into()))
+        });
+        let second = flight.run("key", || async { Ok(7) });
+        let releaser = async {
+            tokio::task::yield_now().await;
+            release.notify_one();
+        };
+
+        let (first, second, ()) = tokio::join!(first, second, releaser);
+        assert!(first.is_err());
+        assert_eq!(second.unwrap(), 7);
+    }
+
+    #[tokio::test]
+    async fn different_keys_compute_separately() {
+        let flight = SingleFlight::<&str, u32>::default();
+        let (a, b) = tokio::join!(
+            flight.run("a", || async { Ok(1) }),
+            flight.run("b", || async { Ok(2) }),
+        );
+        assert_eq!((a.unwrap(), b.unwrap()), (1, 2));
+    }
+}
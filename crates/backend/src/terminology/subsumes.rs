@@ -0,0 +1,114 @@
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use super::split_canonical;
+use crate::error::AppError;
+use crate::store::TerminologyStore;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SubsumptionOutcome {
+    Equivalent,
+    Subsumes,
+    SubsumedBy,
+    NotSubsumed,
+}
+
+impl SubsumptionOutcome {
+    /// The FHIR concept-subsumption-outcome code
+    pub fn code(self) -> &'static str {
+        match self {
+            SubsumptionOutcome::Equivalent => "equivalent",
+            SubsumptionOutcome::Subsumes => "subsumes",
+            SubsumptionOutcome::SubsumedBy => "subsumed-by",
+            SubsumptionOutcome::NotSubsumed => "not-subsumed",
+        }
+    }
+}
+
+/// `$subsumes`: how `code_a` relates to `code_b` in the CodeSystem's hierarchy. Both codes
+/// must exist.
+pub async fn subsumes(
+    store: Arc<dyn TerminologyStore>,
+    system: &str,
+    code_a: &str,
+    code_b: &str,
+    version: Option<&str>,
+) -> Result<SubsumptionOutcome, AppError> {
+    let (system, version) = split_canonical(system, version)?;
+
+    // Get the CodeSystem
+    let code_system = store
+        .get_code_system(system, version)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("CodeSystem '{system}' not found")))?;
+
+    // Check if both codes exist; a case-insensitive CodeSystem resolves them to the stored
+    // codes, which are compared from here on
+    let Some(concept_a) = store.get_concept(&code_system.id, code_a).await? else {
+        return Err(AppError::NotFound(format!(
+            "Code '{code_a}' not found in system '{system}'"
+        )));
+    };
+    let Some(concept_b) = store.get_concept(&code_system.id, code_b).await? else {
+        return Err(AppError::NotFound(format!(
+            "Code '{code_b}' not found in system '{system}'"
+        )));
+    };
+
+    // Check for equivalence first
+    if concept_a.code == concept_b.code {
+        return Ok(SubsumptionOutcome::Equivalent);
+    }
+
+    // Query closure table for subsumption relationship
+    let outcome = store
+        .check_subsumption(&code_system.id, &concept_a.code, &concept_b.code)
+        .await?;
+
+    Ok(match outcome {
+        Some(true) => SubsumptionOutcome::Subsumes, // A subsumes B
+        Some(false) => SubsumptionOutcome::SubsumedBy, // A is subsumed by B
+        None => SubsumptionOutcome::NotSubsumed,    // No relationship
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+    use serde_json::json;
+
+    const SYSTEM: &str = "http://example.org/animals";
+
+    fn store(case_sensitive: bool) -> Arc<dyn TerminologyStore> {
+        Arc::new(MemoryStore::default().with_code_system(json!({
+            "url": SYSTEM,
+            "caseSensitive": case_sensitive,
+            "concept": [{
+                "code": "Animal",
+                "concept": [{ "code": "Dog" }]
+            }]
+        })))
+    }
+
+    #[tokio::test]
+    async fn codes_of_a_case_insensitive_system_are_compared_as_stored() {
+        let outcome = subsumes(store(false), SYSTEM, "dog", "DOG", None).await;
+        assert_eq!(outcome.unwrap(), SubsumptionOutcome::Equivalent);
+
+        let outcome = subsumes(store(false), SYSTEM, "ANIMAL", "dog", None).await;
+        assert_eq!(outcome.unwrap(), SubsumptionOutcome::Subsumes);
+        let outcome = subsumes(store(false), SYSTEM, "dog", "animal", None).await;
+        assert_eq!(outcome.unwrap(), SubsumptionOutcome::SubsumedBy);
+    }
+
+    #[tokio::test]
+    async fn codes_of_a_case_sensitive_system_must_match_exactly() {
+        let outcome = subsumes(store(true), SYSTEM, "Dog", "Dog", None).await;
+        assert_eq!(outcome.unwrap(), SubsumptionOutcome::Equivalent);
+
+        let outcome = subsumes(store(true), SYSTEM, "Dog", "dog", None).await;
+        assert!(matches!(outcome, Err(AppError::NotFound(_))));
+    }
+}
@@ -0,0 +1,909 @@
+use std::collections::{BTreeSet, HashSet, VecDeque};
+use std::sync::Arc;
+
+use super::{split_canonical, value_set_contains, Coding, Parameter, ParameterValue, Parameters};
+use crate::error::AppError;
+use crate::fhir_version::FhirVersion;
+use crate::models::ConceptMap;
+use crate::store::TerminologyStore;
+
+/// Most ConceptMaps composed into one chained translation
+const MAX_CHAIN_LENGTH: usize = 3;
+
+/// ValueSets (`url` or `url|version`) a `$translate` is limited to: the source codings must
+/// be members of `source`, and only targets that are members of `target` are returned
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TranslateScope<'a> {
+    pub source: Option<&'a str>,
+    pub target: Option<&'a str>,
+}
+
+/// `$translate`: the targets the source codings map to in the ConceptMap at
+/// `concept_map_url` (any installed map for their system when absent), or with `chain`
+/// through any installed maps into `target_system`, limited to the ValueSets of `scope`.
+/// The match parts are worded for the FHIR `version` being served.
+#[allow(clippy::too_many_arguments)]
+pub async fn translate(
+    store: Arc<dyn TerminologyStore>,
+    version: FhirVersion,
+    concept_map_url: Option<&str>,
+    sources: &[(&str, &str)],
+    target_system: Option<&str>,
+    reverse: bool,
+    chain: bool,
+    scope: TranslateScope<'_>,
+) -> Result<Parameters, AppError> {
+    let in_scope: Vec<(&str, &str)>;
+    let sources = match scope.source {
+        Some(source_value_set) => {
+            let (mut inside, mut outside) = (Vec::new(), Vec::new());
+            for &(system, code) in sources {
+                let membership =
+                    value_set_contains(store.clone(), source_value_set, system, code).await?;
+                match membership.body {
+                    true => inside.push((system, code)),
+                    false => outside.push((system, code)),
+                }
+            }
+            if inside.is_empty() {
+                let (system, code) = outside[0];
+                return Ok(Parameters::with_parameters(vec![
+                    Parameter::boolean("result", false),
+                    Parameter::string(
+                        "message",
+                        format!(
+                            "Code '{code}' in system '{system}' is not in the source ValueSet \
+                             '{source_value_set}'"
+                        ),
+                    ),
+                ]));
+            }
+            in_scope = inside;
+            &in_scope[..]
+        }
+        None => sources,
+    };
+
+    let mut result = translate_codings(
+        store.clone(),
+        version,
+        concept_map_url,
+        sources,
+        target_system,
+        reverse,
+        chain,
+    )
+    .await?;
+
+    if let Some(target_value_set) = scope.target {
+        // Each distinct target is checked on its own rather than expanding the ValueSet
+        let targets: HashSet<(String, String)> = result
+            .parameter
+            .iter()
+            .flatten()
+            .filter_map(match_target)
+            .collect();
+        let mut members = HashSet::new();
+        for (system, code) in targets {
+            if value_set_contains(store.clone(), target_value_set, &system, &code)
+                .await?
+                .body
+            {
+                members.insert((system, code));
+            }
+        }
+        retain_target_members(&mut result, &members, target_value_set);
+    }
+
+    Ok(result)
+}
+
+/// The `(system, code)` of the `concept` of a `match` part
+fn match_target(parameter: &Parameter) -> Option<(String, String)> {
+    if parameter.name != "match" {
+        return None;
+    }
+    let coding = parameter
+        .part
+        .iter()
+        .flatten()
+        .find(|part| part.name == "concept")
+        .and_then(|part| match &part.value {
+            Some(ParameterValue::ValueCoding(coding)) => Some(coding),
+            _ => None,
+        })?;
+    Some((
+        coding.system.clone().unwrap_or_default(),
+        coding.code.clone().unwrap_or_default(),
+    ))
+}
+
+/// Drop the `match` parts whose target concept is not in `members`, the targets that are
+/// in the target ValueSet, and report no result when none is left
+fn retain_target_members(
+    result: &mut Parameters,
+    members: &HashSet<(String, String)>,
+    target_value_set: &str,
+) {
+    let Some(parameters) = result.parameter.as_mut() else {
+        return;
+    };
+    let matched_before = parameters.iter().any(|p| p.name == "match");
+
+    parameters.retain(|parameter| match match_target(parameter) {
+        Some(target) => members.contains(&target),
+        None => true,
+    });
+
+    if matched_before && !parameters.iter().any(|p| p.name == "match") {
+        for parameter in parameters.iter_mut().filter(|p| p.name == "result") {
+            parameter.value = Some(ParameterValue::ValueBoolean(false));
+        }
+        parameters.push(Parameter::string(
+            "message",
+            format!("No translation found in the target ValueSet '{target_value_set}'"),
+        ));
+    }
+}
+
+/// [`translate`] without the ValueSet scope
+async fn translate_codings(
+    store: Arc<dyn TerminologyStore>,
+    version: FhirVersion,
+    concept_map_url: Option<&str>,
+    sources: &[(&str, &str)],
+    target_system: Option<&str>,
+    reverse: bool,
+    chain: bool,
+) -> Result<Parameters, AppError> {
+    if chain {
+        let Some(target_system) = target_system else {
+            return Err(AppError::InvalidParameters(vec![
+                "target is required when chain is true".to_string(),
+            ]));
+        };
+        return perform_chained_translate(
+            store,
+            version,
+            concept_map_url,
+            sources,
+            target_system,
+            reverse,
+        )
+        .await;
+    }
+
+    // The ConceptMap asked for; without one, every map with a group for the source system
+    let requested_map = match concept_map_url {
+        Some(canonical) => {
+            let (url, version) = split_canonical(canonical, None)?;
+            Some(
+                store
+                    .get_concept_map(url, version)
+                    .await?
+                    .ok_or_else(|| AppError::NotFound(format!("ConceptMap '{url}' not found")))?,
+            )
+        }
+        None => None,
+    };
+
+    let mut matches = Vec::new();
+    // Sorted and unique, for the message naming explicitly unmapped codes
+    let mut unmapped = BTreeSet::new();
+    // Maps and groups whose systems matched the request, reported when nothing was found
+    let mut searched_maps: Vec<String> = Vec::new();
+    let mut searched_groups: Vec<(String, String, String)> = Vec::new();
+    // Codings of a CodeableConcept may map to the same target; report each target once
+    let mut seen: HashSet<(String, String, String)> = HashSet::new();
+
+    for &(source_system, source_code) in sources {
+        // The `other-map` rule of a group without an element for the code adds its map
+        let mut pending: VecDeque<ConceptMap> = match &requested_map {
+            Some(concept_map) => VecDeque::from([concept_map.clone()]),
+            None => store
+                .find_concept_maps_for_system(source_system, reverse)
+                .await?
+                .into(),
+        };
+        let mut queued: HashSet<String> = pending.iter().map(|m| m.url.clone()).collect();
+
+        while let Some(concept_map) = pending.pop_front() {
+            if !searched_maps.contains(&concept_map.url) {
+                searched_maps.push(concept_map.url.clone());
+            }
+            let groups = concept_map
+                .content
+                .get("group")
+                .and_then(|g| g.as_array())
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+
+            for group in groups {
+                let group_source = group.get("source").and_then(|s| s.as_str());
+                let group_target = group.get("target").and_then(|t| t.as_str());
+
+                // Check if this group matches our source system
+                let matches_source = if reverse {
+                    group_target == Some(source_system)
+                } else {
+                    group_source == Some(source_system)
+                };
+                if !matches_source {
+                    continue;
+                }
+
+                let target_system_str = if reverse {
+                    group_source.unwrap_or("")
+                } else {
+                    group_target.unwrap_or("")
+                };
+
+                // Filter by target system if specified
+                if target_system.is_some_and(|ts| target_system_str != ts) {
+                    continue;
+                }
+
+                let group_key = (
+                    concept_map.url.clone(),
+                    group_source.unwrap_or("").to_string(),
+                    group_target.unwrap_or("").to_string(),
+                );
+                if !searched_groups.contains(&group_key) {
+                    searched_groups.push(group_key);
+                }
+
+                let mut push_match = |code: &str, display: Option<&str>, equivalence: &str| {
+                    let key = (
+                        equivalence.to_string(),
+                        target_system_str.to_string(),
+                        code.to_string(),
+                    );
+                    if !seen.insert(key) {
+                        return;
+                    }
+                    let mut coding = Coding::new(target_system_str, code);
+                    if let Some(display) = display {
+                        coding = coding.with_display(display);
+                    }
+                    matches.push(Parameter::part(
+                        "match",
+                        vec![
+                            relationship_param(version, equivalence),
+                            Parameter::coding("concept", coding),
+                        ],
+                    ));
+                };
+
+                // Look through elements for our code
+                let elements = group
+                    .get("element")
+                    .and_then(|e| e.as_array())
+                    .map(Vec::as_slice)
+                    .unwrap_or_default();
+                let mut has_element = false;
+                for element in elements {
+                    if element.get("code").and_then(|c| c.as_str()) != Some(source_code) {
+                        continue;
+                    }
+                    has_element = true;
+
+                    let targets = element
+                        .get("target")
+                        .and_then(|t| t.as_array())
+                        .map(Vec::as_slice)
+                        .unwrap_or_default();
+                    let no_map = element
+                        .get("noMap")
+                        .and_then(|n| n.as_bool())
+                        .unwrap_or(false);
+
+                    // The element exists but explicitly maps to nothing
+                    if no_map || targets.is_empty() {
+                        unmapped.insert((source_system, source_code));
+                        continue;
+                    }
+
+                    // Found a match, extract targets
+                    for target in targets {
+                        let equivalence = target
+                            .get("equivalence")
+                            .or_else(|| target.get("relationship"))
+                            .and_then(|e| e.as_str())
+                            .unwrap_or("equivalent");
+
+                        match target.get("code").and_then(|c| c.as_str()) {
+                            Some(target_code) => push_match(
+                                target_code,
+                                target.get("display").and_then(|d| d.as_str()),
+                                equivalence,
+                            ),
+                            None if is_no_map_equivalence(equivalence) => {
+                                unmapped.insert((source_system, source_code));
+                            }
+                            None => {}
+                        }
+                    }
+                }
+
+                // Without an element, the group's `unmapped` rule says what the code maps to.
+                // It describes source codes, so it does not apply to a reverse translation.
+                if has_element || reverse {
+                    continue;
+                }
+                match unmapped_rule(group, source_code) {
+                    Some(UnmappedRule::Target {
+                        code,
+                        display,
+                        equivalence,
+                    }) => push_match(code, display, equivalence),
+                    Some(UnmappedRule::OtherMap(canonical)) => {
+                        let (url, version) = split_canonical(canonical, None)?;
+                        if let Some(other) = store.get_concept_map(url, version).await? {
+                            if queued.insert(other.url.clone()) {
+                                pending.push_back(other);
+                            }
+                        }
+                    }
+                    None => {}
+                }
+            }
+        }
+    }
+
+    if !unmapped.is_empty() {
+        matches.insert(0, no_map_match(version));
+    }
+    let mut result_params = vec![Parameter::boolean("result", !matches.is_empty())];
+
+    if matches.is_empty() {
+        result_params.push(Parameter::string(
+            "message",
+            format!("No translation found for {}", describe_codes(sources)),
+        ));
+
+        // Say which maps and system pairs were searched, to explain the missing translation
+        for url in &searched_maps {
+            result_params.push(Parameter::uri("conceptMap", url));
+        }
+        for (url, source, target) in searched_groups {
+            result_params.push(Parameter::part(
+                "group",
+                vec![
+                    Parameter::uri("conceptMap", url),
+                    Parameter::uri("source", source),
+                    Parameter::uri("target", target),
+                ],
+            ));
+        }
+    } else {
+        if !unmapped.is_empty() {
+            result_params.push(Parameter::string(
+                "message",
+                format!("Explicitly unmapped: {}", describe_codes(&unmapped)),
+            ));
+        }
+        result_params.extend(matches);
+    }
+
+    Ok(Parameters::with_parameters(result_params))
+}
+
+/// `code 'c' in system 's'` for each of the codings, comma separated
+fn describe_codes<'a>(codings: impl IntoIterator<Item = &'a (&'a str, &'a str)>) -> String {
+    codings
+        .into_iter()
+        .map(|(system, code)| format!("code '{code}' in system '{system}'"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// What a ConceptMap group's `unmapped` rule maps a source code without an element to
+enum UnmappedRule<'a> {
+    /// A target code in the group's target system: `fixed` names it, and `use-source-code`
+    /// (R4 `provided`) reuses the source code
+    Target {
+        code: &'a str,
+        display: Option<&'a str>,
+        equivalence: &'a str,
+    },
+    /// `other-map`: the canonical of another ConceptMap to search
+    OtherMap(&'a str),
+}
+
+/// The `unmapped` rule of `group` applied to `source_code`. Without a stated R5
+/// `relationship`, the source code is `equivalent` and a fixed code only `inexact`.
+fn unmapped_rule<'a>(
+    group: &'a serde_json::Value,
+    source_code: &'a str,
+) -> Option<UnmappedRule<'a>> {
+    let unmapped = group.get("unmapped")?;
+    let text = |key: &str| unmapped.get(key).and_then(|v| v.as_str());
+    let relationship = text("relationship");
+
+    match text("mode")? {
+        "use-source-code" | "provided" => Some(UnmappedRule::Target {
+            code: source_code,
+            display: None,
+            equivalence: relationship.unwrap_or("equivalent"),
+        }),
+        "fixed" => Some(UnmappedRule::Target {
+            code: text("code")?,
+            display: text("display"),
+            equivalence: relationship.unwrap_or("inexact"),
+        }),
+        // R5 names the map `otherMap`, R4 `url`
+        "other-map" => text("otherMap")
+            .or_else(|| text("url"))
+            .map(UnmappedRule::OtherMap),
+        _ => None,
+    }
+}
+
+/// One translation step through a ConceptMap group
+struct MapStep {
+    system: String,
+    code: String,
+    display: Option<String>,
+    equivalence: &'static str,
+}
+
+/// A code reached while chaining, with the combined equivalence and the maps used so far
+struct ChainNode {
+    system: String,
+    code: String,
+    display: Option<String>,
+    equivalence: &'static str,
+    maps: Vec<String>,
+}
+
+/// Translate into `target_system` by composing ConceptMaps through intermediate systems.
+///
+/// A breadth-first search over the installed maps, bounded by [`MAX_CHAIN_LENGTH`], returns
+/// the targets reached by the shortest chains. Each chain's equivalence is its weakest link.
+/// When `concept_map_url` is given, the first step must use that map.
+async fn perform_chained_translate(
+    store: Arc<dyn TerminologyStore>,
+    version: FhirVersion,
+    concept_map_url: Option<&str>,
+    sources: &[(&str, &str)],
+    target_system: &str,
+    reverse: bool,
+) -> Result<Parameters, AppError> {
+    let first_map = match concept_map_url {
+        Some(canonical) => {
+            let (url, version) = split_canonical(canonical, None)?;
+            Some(
+                store
+                    .get_concept_map(url, version)
+                    .await?
+                    .ok_or_else(|| AppError::NotFound(format!("ConceptMap '{url}' not found")))?,
+            )
+        }
+        None => None,
+    };
+
+    let mut queue: VecDeque<ChainNode> = sources
+        .iter()
+        .map(|&(system, code)| ChainNode {
+            system: system.to_string(),
+            code: code.to_string(),
+            display: None,
+            equivalence: "equivalent",
+            maps: Vec::new(),
+        })
+        .collect();
+    // Codes already reached, so cycles between maps end the search
+    let mut visited: HashSet<(String, String)> = queue
+        .iter()
+        .map(|node| (node.system.clone(), node.code.clone()))
+        .collect();
+    let mut found: Vec<ChainNode> = Vec::new();
+
+    while let Some(node) = queue.pop_front() {
+        // Only keep the shortest chains, and never compose more than MAX_CHAIN_LENGTH maps
+        if found
+            .first()
+            .is_some_and(|f| node.maps.len() >= f.maps.len())
+            || node.maps.len() >= MAX_CHAIN_LENGTH
+        {
+            continue;
+        }
+
+        let concept_maps = match (&first_map, node.maps.is_empty()) {
+            (Some(concept_map), true) => vec![concept_map.clone()],
+            _ => {
+                store
+                    .find_concept_maps_for_system(&node.system, reverse)
+                    .await?
+            }
+        };
+
+        for concept_map in &concept_maps {
+            for step in map_steps(concept_map, &node.system, &node.code, reverse) {
+                let equivalence = combine_equivalence(node.equivalence, step.equivalence);
+                let mut maps = node.maps.clone();
+                maps.push(concept_map.url.clone());
+                let next = ChainNode {
+                    system: step.system,
+                    code: step.code,
+                    display: step.display,
+                    equivalence,
+                    maps,
+                };
+
+                if next.system == target_system {
+                    let duplicate = found
+                        .iter()
+                        .any(|f| f.code == next.code && f.equivalence == next.equivalence);
+                    if !duplicate {
+                        found.push(next);
+                    }
+                } else if visited.insert((next.system.clone(), next.code.clone())) {
+                    queue.push_back(next);
+                }
+            }
+        }
+    }
+
+    let mut result_params = vec![Parameter::boolean("result", !found.is_empty())];
+    if found.is_empty() {
+        let described = describe_codes(sources);
+        result_params.push(Parameter::string(
+            "message",
+            format!(
+                "No translation found for {described} into '{target_system}' \
+                 through at most {MAX_CHAIN_LENGTH} ConceptMaps"
+            ),
+        ));
+    }
+
+    for node in found {
+        let mut coding = Coding::new(target_system, node.code);
+        if let Some(display) = node.display {
+            coding = coding.with_display(display);
+        }
+
+        let mut parts = vec![
+            relationship_param(version, node.equivalence),
+            Parameter::coding("concept", coding),
+        ];
+        // The ConceptMaps composed, in order
+        parts.extend(
+            node.maps
+                .into_iter()
+                .map(|url| Parameter::uri("source", url)),
+        );
+        result_params.push(Parameter::part("match", parts));
+    }
+
+    Ok(Parameters::with_parameters(result_params))
+}
+
+/// The targets `code` in `system` maps to in `concept_map`, skipping unmapped elements
+fn map_steps(concept_map: &ConceptMap, system: &str, code: &str, reverse: bool) -> Vec<MapStep> {
+    let groups = concept_map
+        .content
+        .get("group")
+        .and_then(|g| g.as_array())
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+
+    let mut steps = Vec::new();
+    for group in groups {
+        let group_source = group.get("source").and_then(|s| s.as_str());
+        let group_target = group.get("target").and_then(|t| t.as_str());
+        let (from, to) = if reverse {
+            (group_target, group_source)
+        } else {
+            (group_source, group_target)
+        };
+        let (Some(from), Some(to)) = (from, to) else {
+            continue;
+        };
+        if from != system {
+            continue;
+        }
+
+        let elements = group
+            .get("element")
+            .and_then(|e| e.as_array())
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        for element in elements {
+            if element.get("code").and_then(|c| c.as_str()) != Some(code) {
+                continue;
+            }
+            let targets = element
+                .get("target")
+                .and_then(|t| t.as_array())
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            for target in targets {
+                let Some(target_code) = target.get("code").and_then(|c| c.as_str()) else {
+                    continue;
+                };
+                let equivalence = target
+                    .get("equivalence")
+                    .or_else(|| target.get("relationship"))
+                    .and_then(|e| e.as_str())
+                    .unwrap_or("equivalent");
+                let Some(equivalence) = equivalence_strength(equivalence) else {
+                    continue;
+                };
+                steps.push(MapStep {
+                    system: to.to_string(),
+                    code: target_code.to_string(),
+                    display: target
+                        .get("display")
+                        .and_then(|d| d.as_str())
+                        .map(str::to_string),
+                    equivalence,
+                });
+            }
+        }
+    }
+
+    steps
+}
+
+/// Normalize an R4 equivalence or R5 relationship to the R4 code used for chaining, or None
+/// when it states there is no usable mapping
+fn equivalence_strength(equivalence: &str) -> Option<&'static str> {
+    match equivalence {
+        "equal" | "equivalent" => Some("equivalent"),
+        "wider" | "subsumes" | "source-is-narrower-than-target" => Some("wider"),
+        "narrower" | "specializes" | "source-is-broader-than-target" => Some("narrower"),
+        "relatedto" | "inexact" | "related-to" => Some("inexact"),
+        _ => None,
+    }
+}
+
+/// The equivalence of two composed steps: the weakest link, where mixing `wider` and
+/// `narrower` only leaves an inexact relationship
+fn combine_equivalence(first: &'static str, second: &'static str) -> &'static str {
+    match (first, second) {
+        ("equivalent", other) | (other, "equivalent") => other,
+        (a, b) if a == b => a,
+        _ => "inexact",
+    }
+}
+
+/// Whether a target equivalence (R4) or relationship (R5) states that no mapping exists
+fn is_no_map_equivalence(equivalence: &str) -> bool {
+    matches!(equivalence, "unmatched" | "not-related-to")
+}
+
+/// A `match` part recording that the source code is explicitly unmapped
+fn no_map_match(version: FhirVersion) -> Parameter {
+    Parameter::part("match", vec![relationship_param(version, "unmatched")])
+}
+
+/// The part stating how a target relates to its source: an R4 `equivalence` or, from R5,
+/// a `relationship`, converting codes stored in the other release's vocabulary
+fn relationship_param(version: FhirVersion, equivalence: &str) -> Parameter {
+    let code = match version {
+        FhirVersion::R4 => match equivalence {
+            "source-is-narrower-than-target" => "wider",
+            "source-is-broader-than-target" => "narrower",
+            "related-to" => "relatedto",
+            "not-related-to" => "unmatched",
+            other => other,
+        },
+        FhirVersion::R5 | FhirVersion::R6 => match equivalence {
+            "equal" | "equivalent" => "equivalent",
+            "wider" | "subsumes" => "source-is-narrower-than-target",
+            "narrower" | "specializes" => "source-is-broader-than-target",
+            "relatedto" | "inexact" => "related-to",
+            "unmatched" | "disjoint" => "not-related-to",
+            other => other,
+        },
+    };
+    Parameter::code(version.relationship_element(), code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+    use serde_json::json;
+
+    const SOURCE: &str = "http://example.org/source";
+    const TARGET: &str = "http://example.org/target";
+
+    fn store(unmapped: serde_json::Value) -> Arc<dyn TerminologyStore> {
+        Arc::new(MemoryStore::default().with_concept_map(json!({
+            "url": "http://example.org/map",
+            "group": [{
+                "source": SOURCE,
+                "target": TARGET,
+                "element": [
+                    { "code": "a", "target": [{ "code": "x", "equivalence": "equivalent" }] },
+                    { "code": "b", "noMap": true }
+                ],
+                "unmapped": unmapped
+            }]
+        })))
+    }
+
+    async fn translate_code(store: Arc<dyn TerminologyStore>, code: &str) -> Parameters {
+        translate(
+            store,
+            FhirVersion::R4,
+            None,
+            &[(SOURCE, code)],
+            None,
+            false,
+            false,
+            TranslateScope::default(),
+        )
+        .await
+        .unwrap()
+    }
+
+    /// The `(equivalence, target code)` of each `match` with a concept
+    fn matched(result: &Parameters) -> Vec<(String, String)> {
+        result
+            .parameter
+            .iter()
+            .flatten()
+            .filter(|p| p.name == "match")
+            .filter_map(|p| {
+                let parts = Parameters::with_parameters(p.part.clone()?);
+                let coding = parts.get_coding("concept")?;
+                Some((
+                    parts.get_code("equivalence")?.to_string(),
+                    coding.code.clone()?,
+                ))
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn installed_maps_are_searched_without_a_url() {
+        let result = translate_code(store(json!(null)), "a").await;
+        assert_eq!(result.get_boolean("result"), Some(true));
+        assert_eq!(
+            matched(&result),
+            [("equivalent".to_string(), "x".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn no_map_element_is_explicitly_unmapped() {
+        let result = translate_code(store(json!(null)), "b").await;
+        assert_eq!(result.get_boolean("result"), Some(true));
+        assert!(matched(&result).is_empty());
+        assert_eq!(
+            result.get_string("message"),
+            Some(format!("Explicitly unmapped: code 'b' in system '{SOURCE}'").as_str())
+        );
+    }
+
+    #[tokio::test]
+    async fn unmapped_rule_covers_codes_without_an_element() {
+        let source_code = store(json!({ "mode": "use-source-code" }));
+        assert_eq!(
+            matched(&translate_code(source_code, "c").await),
+            [("equivalent".to_string(), "c".to_string())]
+        );
+
+        let fixed = store(json!({ "mode": "fixed", "code": "other" }));
+        assert_eq!(
+            matched(&translate_code(fixed, "c").await),
+            [("inexact".to_string(), "other".to_string())]
+        );
+
+        let none = translate_code(store(json!(null)), "c").await;
+        assert_eq!(none.get_boolean("result"), Some(false));
+    }
+
+    #[tokio::test]
+    async fn other_map_is_searched_for_codes_without_an_element() {
+        let store = MemoryStore::default()
+            .with_concept_map(json!({
+                "url": "http://example.org/map",
+                "group": [{
+                    "source": SOURCE,
+                    "target": TARGET,
+                    "element": [{ "code": "a", "target": [{ "code": "x" }] }],
+                    "unmapped": { "mode": "other-map", "url": "http://example.org/fallback" }
+                }]
+            }))
+            .with_concept_map(json!({
+                "url": "http://example.org/fallback",
+                "group": [{
+                    "source": "http://example.org/elsewhere",
+                    "element": []
+                }, {
+                    "source": SOURCE,
+                    "target": TARGET,
+                    "element": [{
+                        "code": "c",
+                        "target": [{ "code": "z", "equivalence": "wider" }]
+                    }]
+                }]
+            }));
+        let result = translate(
+            Arc::new(store),
+            FhirVersion::R4,
+            Some("http://example.org/map"),
+            &[(SOURCE, "c")],
+            None,
+            false,
+            false,
+            TranslateScope::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(matched(&result), [("wider".to_string(), "z".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn source_and_target_value_sets_scope_the_translation() {
+        let store: Arc<dyn TerminologyStore> = Arc::new(
+            MemoryStore::default()
+                .with_code_system(json!({
+                    "url": SOURCE,
+                    "concept": [{ "code": "a" }, { "code": "b" }]
+                }))
+                .with_code_system(json!({
+                    "url": TARGET,
+                    "concept": [{ "code": "x" }, { "code": "y" }]
+                }))
+                .with_value_set(json!({
+                    "url": "http://example.org/ValueSet/sources",
+                    "compose": { "include": [{ "system": SOURCE, "concept": [{ "code": "a" }] }] }
+                }))
+                .with_value_set(json!({
+                    "url": "http://example.org/ValueSet/targets",
+                    "compose": { "include": [{ "system": TARGET, "concept": [{ "code": "x" }] }] }
+                }))
+                .with_concept_map(json!({
+                    "url": "http://example.org/map",
+                    "group": [{
+                        "source": SOURCE,
+                        "target": TARGET,
+                        "element": [
+                            { "code": "a", "target": [
+                                { "code": "x", "equivalence": "equivalent" },
+                                { "code": "y", "equivalence": "wider" }
+                            ] },
+                            { "code": "b", "target": [
+                                { "code": "x", "equivalence": "equivalent" }
+                            ] }
+                        ]
+                    }]
+                })),
+        );
+        let scoped = |code| {
+            let store = store.clone();
+            async move {
+                translate(
+                    store,
+                    FhirVersion::R4,
+                    Some("http://example.org/map"),
+                    &[(SOURCE, code)],
+                    None,
+                    false,
+                    false,
+                    TranslateScope {
+                        source: Some("http://example.org/ValueSet/sources"),
+                        target: Some("http://example.org/ValueSet/targets"),
+                    },
+                )
+                .await
+                .unwrap()
+            }
+        };
+
+        let inside = scoped("a").await;
+        assert_eq!(
+            matched(&inside),
+            [("equivalent".to_string(), "x".to_string())]
+        );
+
+        let outside = scoped("b").await;
+        assert_eq!(outside.get_boolean("result"), Some(false));
+        assert!(matched(&outside).is_empty());
+    }
+}
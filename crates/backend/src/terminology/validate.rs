@@ -0,0 +1,455 @@
+use std::sync::Arc;
+
+use super::{
+    concept_abstract, localized_designation, split_canonical, value_set_contains, Cacheable,
+    Parameter, Parameters, ResolvedVersion,
+};
+use crate::error::AppError;
+use crate::models::ValueSet;
+use crate::store::TerminologyStore;
+
+/// `$validate-code` against a CodeSystem: whether `code` exists, is selectable unless
+/// `abstract_allowed`, and has the given `display`. An unknown system is a `result=false`
+/// warning when `lenient_system`, else an error.
+#[allow(clippy::too_many_arguments)]
+pub async fn validate_code(
+    store: Arc<dyn TerminologyStore>,
+    system: &str,
+    code: &str,
+    version: Option<&str>,
+    display: Option<&str>,
+    display_language: Option<&str>,
+    lenient_system: bool,
+    abstract_allowed: bool,
+) -> Result<Cacheable<Parameters>, AppError> {
+    let (system, version) = split_canonical(system, version)?;
+
+    // Check if CodeSystem exists; unknown systems are a warning unless lenient mode is off
+    let code_system = store.get_code_system(system, version).await?;
+    if code_system.is_none() {
+        let message = format!("CodeSystem '{system}' not found");
+        if !lenient_system {
+            return Err(AppError::NotFound(message));
+        }
+
+        return Ok(Cacheable::uncached(Parameters::with_parameters(vec![
+            Parameter::boolean("result", false),
+            Parameter::string("message", message.clone()),
+            issues("warning", "not-found", &message),
+        ])));
+    }
+
+    let code_system = code_system.unwrap();
+
+    // Check if code exists in the system
+    let concept = store.get_concept(&code_system.id, code).await?;
+
+    // Abstract codes are only valid where the caller allows them
+    let is_abstract = concept.as_ref().is_some_and(concept_abstract);
+    let is_valid = concept.is_some() && (abstract_allowed || !is_abstract);
+    let mut result_params = vec![Parameter::boolean("result", is_valid)];
+
+    // The stored code, which differs in case from `code` in a case-insensitive CodeSystem
+    if let Some(concept) = &concept {
+        result_params.push(Parameter::code("code", concept.code.clone()));
+    }
+    result_params.push(Parameter::uri("system", code_system.url.clone()));
+    if let Some(resolved_version) = &code_system.version {
+        result_params.push(Parameter::string("version", resolved_version.clone()));
+    }
+
+    if let Some(concept) = concept {
+        if is_abstract && abstract_allowed {
+            result_params.push(issues(
+                "warning",
+                "business-rule",
+                &format!("Code '{code}' is abstract"),
+            ));
+        } else if is_abstract {
+            let message = format!("Code '{code}' is abstract and abstract codes are not allowed");
+            result_params.push(Parameter::string("message", message.clone()));
+            result_params.push(issues("error", "business-rule", &message));
+        }
+
+        // Prefer the designation in the requested language as the display
+        let localized_display = display_language
+            .zip(concept.designations.as_ref())
+            .and_then(|(language, designations)| {
+                localized_designation(designations, language, false)
+            })
+            .map(|(_, value)| value);
+
+        // Optionally validate display; the localized display is accepted as well
+        if let Some(expected_display) = display {
+            if let Some(actual_display) = localized_display.as_ref().or(concept.display.as_ref()) {
+                if concept.display.as_deref() != Some(expected_display)
+                    && actual_display != expected_display
+                {
+                    result_params.push(Parameter::string(
+                        "message",
+                        format!(
+                            "Display value '{expected_display}' does not match expected '{actual_display}'"
+                        ),
+                    ));
+                }
+            }
+        }
+
+        if let Some(display) = localized_display.or(concept.display) {
+            result_params.push(Parameter::string("display", display));
+        }
+    } else {
+        result_params.push(Parameter::string(
+            "message",
+            format!("Code '{code}' not found in system '{system}'"),
+        ));
+    }
+    result_params.push(Parameter::boolean("abstract", abstract_allowed));
+
+    Ok(Cacheable::new(
+        Parameters::with_parameters(result_params),
+        ResolvedVersion::code_system(&code_system, version.is_some()),
+    ))
+}
+
+/// `$validate-code` against a ValueSet, checking the code in the system version the
+/// ValueSet uses and then its membership in the ValueSet, decided for this code alone.
+/// Without a `system`, it is inferred from the ValueSet's systems. `value_set_pinned` tells
+/// whether the request selected the ValueSet version rather than the latest one.
+#[allow(clippy::too_many_arguments)]
+pub async fn validate_code_in_value_set(
+    store: Arc<dyn TerminologyStore>,
+    value_set: &ValueSet,
+    value_set_pinned: bool,
+    system: Option<&str>,
+    code: &str,
+    display: Option<&str>,
+    display_language: Option<&str>,
+    lenient_system: bool,
+    abstract_allowed: bool,
+    system_version: Option<&str>,
+) -> Result<Cacheable<Parameters>, AppError> {
+    // First validate the code exists in the version of the system the ValueSet uses,
+    // unless the system is given as `system|version`
+    let inferred;
+    let (system, piped_version) = match system {
+        Some(system) => split_canonical(system, None)?,
+        None => {
+            inferred = infer_system(&store, value_set, code).await?;
+            (inferred.0.as_str(), inferred.1.as_deref())
+        }
+    };
+    let version =
+        piped_version.or_else(|| resolve_system_version(value_set, system, system_version));
+    let code_validation = validate_code(
+        store.clone(),
+        system,
+        code,
+        version,
+        display,
+        display_language,
+        lenient_system,
+        abstract_allowed,
+    )
+    .await?;
+
+    let code_valid = code_validation.body.get_boolean("result").unwrap_or(false);
+
+    if !code_valid {
+        return Ok(code_validation);
+    }
+
+    // The code exists in the system; it must also be a member of the ValueSet, checked for
+    // the stored code with the system as requested or as stored
+    let stored_code = code_validation
+        .body
+        .get_code("code")
+        .unwrap_or(code)
+        .to_string();
+    let stored_system = code_validation.body.get_uri("system").map(str::to_string);
+    let canonical = match &value_set.version {
+        Some(version) => format!("{}|{}", value_set.url, version),
+        None => value_set.url.clone(),
+    };
+    let mut systems = vec![system];
+    systems.extend(stored_system.as_deref().filter(|stored| *stored != system));
+    let mut is_member = false;
+    let mut members_resolved = Vec::new();
+    for system in systems {
+        let membership =
+            value_set_contains(store.clone(), &canonical, system, &stored_code).await?;
+        members_resolved.extend(membership.resolved);
+        if membership.body {
+            is_member = true;
+            break;
+        }
+    }
+
+    // Keep the resolved coding, issues and echoed settings of the code validation
+    let carried = code_validation
+        .body
+        .parameter
+        .into_iter()
+        .flatten()
+        .filter(|p| {
+            matches!(
+                p.name.as_str(),
+                "code" | "system" | "version" | "display" | "issues" | "abstract"
+            )
+        });
+    let mut result_params = if is_member {
+        vec![Parameter::boolean("result", true)]
+    } else {
+        let message = format!(
+            "Code {code} from system {system} is not in value set {}",
+            value_set.url
+        );
+        vec![
+            Parameter::boolean("result", false),
+            Parameter::string("message", message.clone()),
+            issues("error", "code-invalid", &message),
+        ]
+    };
+    result_params.extend(carried);
+
+    // The result depends on the ValueSet, the CodeSystem the code was validated in and
+    // those its expansion read
+    let mut resolved = ResolvedVersion::value_set(value_set, value_set_pinned);
+    for dependency in code_validation.resolved.into_iter().chain(members_resolved) {
+        resolved.depends_on(dependency);
+    }
+
+    Ok(Cacheable::new(
+        Parameters::with_parameters(result_params),
+        resolved,
+    ))
+}
+
+/// The version of `system` to validate against: the version pinned by a `compose.include`
+/// of the ValueSet, else the one given by a `system-version` (`system|version`) parameter,
+/// else None for the latest version
+fn resolve_system_version<'a>(
+    value_set: &'a ValueSet,
+    system: &str,
+    system_version: Option<&'a str>,
+) -> Option<&'a str> {
+    let pinned = value_set
+        .content
+        .pointer("/compose/include")
+        .and_then(|i| i.as_array())
+        .into_iter()
+        .flatten()
+        .filter(|include| include.get("system").and_then(|s| s.as_str()) == Some(system))
+        .find_map(|include| include.get("version").and_then(|v| v.as_str()));
+
+    pinned.or_else(|| {
+        system_version
+            .and_then(|canonical| canonical.split_once('|'))
+            .filter(|(s, _)| *s == system)
+            .map(|(_, version)| version)
+    })
+}
+
+/// The system and version for `inferSystem`: the single system referenced by the ValueSet,
+/// directly or through included ValueSets, that defines `code`
+async fn infer_system(
+    store: &Arc<dyn TerminologyStore>,
+    value_set: &ValueSet,
+    code: &str,
+) -> Result<(String, Option<String>), AppError> {
+    let mut candidates: Vec<(String, Option<String>)> = Vec::new();
+    for (system, version) in store.get_value_set_systems(&value_set.id).await? {
+        let Some(code_system) = store.get_code_system(&system, version.as_deref()).await? else {
+            continue;
+        };
+        if store.get_concept(&code_system.id, code).await?.is_some()
+            && !candidates.iter().any(|(s, _)| *s == system)
+        {
+            candidates.push((system, version));
+        }
+    }
+
+    match candidates.len() {
+        1 => Ok(candidates.remove(0)),
+        0 => Err(AppError::InvalidParameters(vec![format!(
+            "Unable to infer a system: no system referenced by ValueSet '{}' defines code '{code}'",
+            value_set.url
+        )])),
+        _ => Err(AppError::InvalidParameters(vec![format!(
+            "Unable to infer a system: code '{code}' is defined by {} systems referenced by \
+             ValueSet '{}'",
+            candidates.len(),
+            value_set.url
+        )])),
+    }
+}
+
+/// An `issues` output parameter holding a single-issue OperationOutcome
+fn issues(severity: &str, code: &str, message: &str) -> Parameter {
+    Parameter::resource(
+        "issues",
+        serde_json::json!({
+            "resourceType": "OperationOutcome",
+            "issue": [{
+                "severity": severity,
+                "code": code,
+                "details": { "text": message }
+            }]
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+    use serde_json::json;
+
+    const SYSTEM: &str = "http://example.org/animals";
+    const VALUE_SET: &str = "http://example.org/ValueSet/dogs";
+
+    fn store() -> Arc<dyn TerminologyStore> {
+        Arc::new(
+            MemoryStore::default()
+                .with_code_system(json!({
+                    "url": SYSTEM,
+                    "version": "1",
+                    "concept": [{
+                        "code": "animal",
+                        "display": "Animal",
+                        "concept": [
+                            { "code": "dog", "display": "Dog" },
+                            { "code": "cat" }
+                        ]
+                    }]
+                }))
+                .with_value_set(json!({
+                    "url": VALUE_SET,
+                    "version": "1",
+                    "compose": {
+                        "include": [{ "system": SYSTEM, "concept": [{ "code": "dog" }] }]
+                    }
+                })),
+        )
+    }
+
+    async fn validate_in_value_set(system: Option<&str>, code: &str) -> Parameters {
+        let store = store();
+        let value_set = store.get_value_set(VALUE_SET, None).await.unwrap().unwrap();
+        validate_code_in_value_set(
+            store, &value_set, true, system, code, None, None, false, false, None,
+        )
+        .await
+        .unwrap()
+        .body
+    }
+
+    #[tokio::test]
+    async fn member_of_the_value_set_is_valid() {
+        let result = validate_in_value_set(Some(SYSTEM), "dog").await;
+        assert_eq!(result.get_boolean("result"), Some(true));
+        assert_eq!(result.get_string("display"), Some("Dog"));
+    }
+
+    #[tokio::test]
+    async fn code_of_the_system_outside_the_value_set_is_invalid() {
+        let result = validate_in_value_set(Some(SYSTEM), "animal").await;
+        assert_eq!(result.get_boolean("result"), Some(false));
+        assert_eq!(
+            result.get_string("message"),
+            Some(
+                format!("Code animal from system {SYSTEM} is not in value set {VALUE_SET}")
+                    .as_str()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn wrong_display_is_reported_without_failing() {
+        let result = validate_code(
+            store(),
+            SYSTEM,
+            "dog",
+            None,
+            Some("Cat"),
+            None,
+            false,
+            false,
+        )
+        .await
+        .unwrap()
+        .body;
+        assert_eq!(result.get_boolean("result"), Some(true));
+        assert!(result.get_string("message").unwrap().contains("Cat"));
+    }
+
+    #[tokio::test]
+    async fn inferred_system_and_its_version_are_returned() {
+        let result = validate_in_value_set(None, "dog").await;
+        assert_eq!(result.get_boolean("result"), Some(true));
+        assert_eq!(result.get_uri("system"), Some(SYSTEM));
+        assert_eq!(result.get_string("version"), Some("1"));
+    }
+
+    #[tokio::test]
+    async fn concept_without_display_returns_no_display() {
+        let result = validate_code(store(), SYSTEM, "cat", None, None, None, false, false)
+            .await
+            .unwrap()
+            .body;
+        assert_eq!(result.get_boolean("result"), Some(true));
+        assert!(result
+            .parameter
+            .iter()
+            .flatten()
+            .all(|parameter| parameter.name != "display"));
+    }
+
+    #[tokio::test]
+    async fn codes_of_a_case_insensitive_system_resolve_to_the_stored_code() {
+        let store: Arc<dyn TerminologyStore> = Arc::new(
+            MemoryStore::default()
+                .with_code_system(json!({
+                    "url": SYSTEM,
+                    "caseSensitive": false,
+                    "concept": [{ "code": "dog", "display": "Dog" }]
+                }))
+                .with_value_set(json!({
+                    "url": VALUE_SET,
+                    "compose": {
+                        "include": [{ "system": SYSTEM, "concept": [{ "code": "dog" }] }]
+                    }
+                })),
+        );
+
+        let result = validate_code(store.clone(), SYSTEM, "Dog", None, None, None, false, false)
+            .await
+            .unwrap()
+            .body;
+        assert_eq!(result.get_boolean("result"), Some(true));
+        assert_eq!(result.get_code("code"), Some("dog"));
+
+        let value_set = store.get_value_set(VALUE_SET, None).await.unwrap().unwrap();
+        let result = validate_code_in_value_set(
+            store,
+            &value_set,
+            false,
+            Some(SYSTEM),
+            "DOG",
+            None,
+            None,
+            false,
+            false,
+            None,
+        )
+        .await
+        .unwrap()
+        .body;
+        assert_eq!(result.get_boolean("result"), Some(true));
+        assert_eq!(result.get_code("code"), Some("dog"));
+
+        // A case-sensitive system knows the code in its own case only
+        let result = validate_in_value_set(Some(SYSTEM), "DOG").await;
+        assert_eq!(result.get_boolean("result"), Some(false));
+    }
+}
@@ -0,0 +1,200 @@
+use anyhow::Result;
+use serde_json::Value;
+use sqlx::PgConnection;
+use std::collections::{HashMap, HashSet, VecDeque};
+use term_squid_backend::store::hierarchy_properties;
+use tracing::info;
+use uuid::Uuid;
+
+/// Closure rows written per statement
+const CLOSURE_BATCH_SIZE: usize = 10_000;
+
+/// Derive the transitive closure of a CodeSystem's hierarchy and insert it into
+/// `closure_table`. Parents come from nested `concept.concept` and from the concepts'
+/// [`hierarchy_properties`] (`valueCode`, `valueString` or `valueCoding.code`); every
+/// concept is also its own ancestor at depth 0.
+pub async fn build_closure(
+    conn: &mut PgConnection,
+    code_system_id: &Uuid,
+    url: &str,
+    code_system: &Value,
+    concepts: &[&Value],
+) -> Result<()> {
+    let hierarchy = hierarchy_properties(url, code_system);
+    let rows = closure_rows(concepts, &hierarchy);
+
+    for batch in rows.chunks(CLOSURE_BATCH_SIZE) {
+        let ancestors: Vec<&str> = batch.iter().map(|(a, _, _)| *a).collect();
+        let descendants: Vec<&str> = batch.iter().map(|(_, d, _)| *d).collect();
+        let depths: Vec<i32> = batch.iter().map(|(_, _, depth)| *depth).collect();
+        sqlx::query(
+            "INSERT INTO closure_table (code_system_id, ancestor_code, descendant_code, depth)
+             SELECT $1, * FROM UNNEST($2::text[], $3::text[], $4::int[])
+             ON CONFLICT (code_system_id, ancestor_code, descendant_code) DO NOTHING",
+        )
+        .bind(code_system_id)
+        .bind(&ancestors)
+        .bind(&descendants)
+        .bind(&depths)
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    info!("Built {} closure rows for {}", rows.len(), url);
+    Ok(())
+}
+
+/// The codes each concept is nested under in `concept.concept`, by concept code. A code
+/// nested under several concepts has all of them, each once.
+pub fn nested_parents<'a>(concepts: &[&'a Value]) -> HashMap<&'a str, Vec<&'a str>> {
+    let mut parents: HashMap<&str, Vec<&str>> = HashMap::new();
+    for concept in concepts {
+        let Some(code) = concept.get("code").and_then(|c| c.as_str()) else {
+            continue;
+        };
+        let children = concept
+            .get("concept")
+            .and_then(|c| c.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|child| child.get("code")?.as_str());
+        for child in children {
+            let child_parents = parents.entry(child).or_default();
+            if !child_parents.contains(&code) {
+                child_parents.push(code);
+            }
+        }
+    }
+    parents
+}
+
+/// `(ancestor, descendant, depth)` for every concept and each of its ancestors, at the
+/// shortest distance between them. Cycles and parents that are not concepts are ignored.
+fn closure_rows<'a>(
+    concepts: &[&'a Value],
+    hierarchy: &HashSet<&str>,
+) -> Vec<(&'a str, &'a str, i32)> {
+    let codes: HashSet<&str> = concepts
+        .iter()
+        .filter_map(|c| c.get("code")?.as_str())
+        .collect();
+
+    let mut parents = nested_parents(concepts);
+    for concept in concepts {
+        let Some(code) = concept.get("code").and_then(|c| c.as_str()) else {
+            continue;
+        };
+        let property_parents = concept
+            .get("property")
+            .and_then(|p| p.as_array())
+            .into_iter()
+            .flatten()
+            .filter(|p| {
+                p.get("code")
+                    .and_then(|c| c.as_str())
+                    .is_some_and(|c| hierarchy.contains(c))
+            })
+            .filter_map(|p| {
+                p.get("valueCode")
+                    .or_else(|| p.get("valueString"))
+                    .or_else(|| p.pointer("/valueCoding/code"))?
+                    .as_str()
+            })
+            .filter(|parent| codes.contains(parent));
+        parents.entry(code).or_default().extend(property_parents);
+    }
+
+    let mut rows = Vec::new();
+    for &code in &codes {
+        // Breadth-first so each ancestor is reached first at its shortest depth
+        let mut seen = HashSet::from([code]);
+        let mut queue = VecDeque::from([(code, 0)]);
+        while let Some((current, depth)) = queue.pop_front() {
+            rows.push((current, code, depth));
+            for &parent in parents.get(current).into_iter().flatten() {
+                if seen.insert(parent) {
+                    queue.push_back((parent, depth + 1));
+                }
+            }
+        }
+    }
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn rows(concepts: &Value, hierarchy: &[&str]) -> Vec<(String, String, i32)> {
+        let concepts: Vec<&Value> = concepts.as_array().unwrap().iter().collect();
+        let hierarchy = hierarchy.iter().copied().collect();
+        let mut rows: Vec<_> = closure_rows(&concepts, &hierarchy)
+            .into_iter()
+            .map(|(a, d, depth)| (a.to_string(), d.to_string(), depth))
+            .collect();
+        rows.sort();
+        rows
+    }
+
+    fn row(ancestor: &str, descendant: &str, depth: i32) -> (String, String, i32) {
+        (ancestor.to_string(), descendant.to_string(), depth)
+    }
+
+    #[test]
+    fn ancestors_are_reached_at_their_shortest_depth() {
+        // c is both a grandchild of a through b and a direct child of a
+        let concepts = json!([
+            { "code": "a", "concept": [{ "code": "b" }] },
+            { "code": "b" },
+            { "code": "c", "property": [
+                { "code": "parent", "valueCode": "b" },
+                { "code": "subsumedBy", "valueCoding": { "code": "a" } }
+            ] }
+        ]);
+        assert_eq!(
+            rows(&concepts, &["parent", "subsumedBy"]),
+            [
+                row("a", "a", 0),
+                row("a", "b", 1),
+                row("a", "c", 1),
+                row("b", "b", 0),
+                row("b", "c", 1),
+                row("c", "c", 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn only_hierarchy_properties_naming_concepts_are_followed() {
+        let concepts = json!([
+            { "code": "a" },
+            { "code": "b", "property": [
+                { "code": "116680003", "valueCode": "a" },
+                { "code": "parent", "valueCode": "missing" },
+                { "code": "related", "valueCode": "a" }
+            ] }
+        ]);
+        assert_eq!(
+            rows(&concepts, &["parent", "116680003"]),
+            [row("a", "a", 0), row("a", "b", 1), row("b", "b", 0)]
+        );
+    }
+
+    #[test]
+    fn cycles_end_the_walk() {
+        let concepts = json!([
+            { "code": "a", "property": [{ "code": "parent", "valueCode": "b" }] },
+            { "code": "b", "property": [{ "code": "parent", "valueCode": "a" }] }
+        ]);
+        assert_eq!(
+            rows(&concepts, &["parent"]),
+            [
+                row("a", "a", 0),
+                row("a", "b", 1),
+                row("b", "a", 1),
+                row("b", "b", 0),
+            ]
+        );
+    }
+}
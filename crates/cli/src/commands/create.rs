@@ -1,11 +1,19 @@
 use anyhow::{Context, Result};
 use sqlx::PgPool;
 use std::fs;
+use term_squid_backend::models::ResourceType;
 use tracing::info;
 use uuid::Uuid;
 
 /// Create a CodeSystem from a FHIR JSON file
-pub async fn create_code_system(pool: PgPool, file_path: String) -> Result<()> {
+pub async fn create_code_system(
+    pool: PgPool,
+    file_path: String,
+    max_concept_depth: usize,
+    reject_suspicious: bool,
+    strict_json: bool,
+    dry_run: bool,
+) -> Result<()> {
     info!("Creating CodeSystem from file: {}", file_path);
 
     // Read and parse the JSON file
@@ -23,11 +31,13 @@ pub async fn create_code_system(pool: PgPool, file_path: String) -> Result<()> {
         anyhow::bail!("Expected resourceType 'CodeSystem', got '{resource_type}'");
     }
 
+    super::import::check_resource(&json, reject_suspicious, strict_json)?;
+
     // Extract required fields
     let url = json["url"]
         .as_str()
-        .context("Missing required field 'url'")?
-        .to_string();
+        .map(super::import::stored_canonical)
+        .context("Missing required field 'url'")?;
 
     let status = json["status"]
         .as_str()
@@ -39,33 +49,28 @@ pub async fn create_code_system(pool: PgPool, file_path: String) -> Result<()> {
     let title = json["title"].as_str().map(|s| s.to_string());
     let fhir_version = json["fhirVersion"].as_str().map(|s| s.to_string());
 
-    // Check if already exists
-    let exists: bool = if let Some(v) = &version {
-        sqlx::query_scalar(
-            "SELECT EXISTS(SELECT 1 FROM code_systems WHERE url = $1 AND version = $2)",
-        )
-        .bind(&url)
-        .bind(v)
-        .fetch_one(&pool)
-        .await?
-    } else {
-        sqlx::query_scalar(
-            "SELECT EXISTS(SELECT 1 FROM code_systems WHERE url = $1 AND version IS NULL)",
-        )
-        .bind(&url)
-        .fetch_one(&pool)
-        .await?
+    // Check the concepts before anything is written
+    let concepts = match json.get("concept").and_then(|c| c.as_array()) {
+        Some(concepts) => super::import::flatten_concepts(concepts, max_concept_depth)?,
+        None => Vec::new(),
+    };
+    let limit = super::import::ConceptLimit {
+        max: super::import::max_concepts_from_env()?,
+        warn_only: false,
     };
+    limit.check(&url, concepts.len())?;
 
-    if exists {
-        anyhow::bail!("CodeSystem with url '{url}' and version '{version:?}' already exists");
+    if dry_run {
+        let concepts = format!("Concepts: {}", concepts.len());
+        return report_dry_run(&pool, ResourceType::CodeSystem, &url, version, &[concepts]).await;
     }
 
     // Insert CodeSystem
     let id = Uuid::new_v4();
-    sqlx::query(
+    let inserted = sqlx::query(
         "INSERT INTO code_systems (id, url, version, status, name, title, fhir_version, content, created_at, updated_at)
-         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, NOW(), NOW())",
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, NOW(), NOW())
+         ON CONFLICT (url, COALESCE(version, '')) DO NOTHING",
     )
     .bind(id)
     .bind(&url)
@@ -76,35 +81,36 @@ pub async fn create_code_system(pool: PgPool, file_path: String) -> Result<()> {
     .bind(&fhir_version)
     .bind(sqlx::types::Json(&json))
     .execute(&pool)
-    .await?;
+    .await?
+    .rows_affected();
+
+    if inserted == 0 {
+        anyhow::bail!("CodeSystem with url '{url}' and version '{version:?}' already exists");
+    }
+
+    let mut conn = pool.acquire().await?;
+    super::import::link_supplement(&mut conn, &id, &json).await?;
 
     // Import concepts if present
-    if let Some(concepts) = json.get("concept").and_then(|c| c.as_array()) {
-        let concept_count = concepts.len();
-        info!("Importing {} concepts...", concept_count);
-
-        for concept in concepts {
-            let code = concept
-                .get("code")
-                .and_then(|c| c.as_str())
-                .context("Concept must have a code")?;
-            let display = concept.get("display").and_then(|d| d.as_str());
-            let definition = concept.get("definition").and_then(|d| d.as_str());
-            let properties = concept.get("property");
-
-            sqlx::query(
-                "INSERT INTO concepts (code_system_id, code, display, definition, properties)
-                 VALUES ($1, $2, $3, $4, $5)
-                 ON CONFLICT (code_system_id, code) DO NOTHING",
+    if !concepts.is_empty() {
+        info!("Importing {} concepts...", concepts.len());
+
+        let coding_properties = super::import::CodingProperties::of(&url, &json);
+        let language = json.get("language").and_then(|l| l.as_str());
+        let stored_concepts = super::import::with_nested_parents(&concepts);
+        for (ordinal, concept) in stored_concepts.iter().enumerate() {
+            super::import::insert_concept(
+                &mut conn,
+                &id,
+                concept,
+                ordinal,
+                Default::default(),
+                &coding_properties,
+                language,
             )
-            .bind(id)
-            .bind(code)
-            .bind(display)
-            .bind(definition)
-            .bind(properties.map(sqlx::types::Json))
-            .execute(&pool)
             .await?;
         }
+        crate::closure::build_closure(&mut conn, &id, &url, &json, &concepts).await?;
     }
 
     println!("✅ CodeSystem created successfully!");
@@ -118,7 +124,13 @@ pub async fn create_code_system(pool: PgPool, file_path: String) -> Result<()> {
 }
 
 /// Create a ValueSet from a FHIR JSON file
-pub async fn create_value_set(pool: PgPool, file_path: String) -> Result<()> {
+pub async fn create_value_set(
+    pool: PgPool,
+    file_path: String,
+    reject_suspicious: bool,
+    strict_json: bool,
+    dry_run: bool,
+) -> Result<()> {
     info!("Creating ValueSet from file: {}", file_path);
 
     // Read and parse the JSON file
@@ -136,11 +148,13 @@ pub async fn create_value_set(pool: PgPool, file_path: String) -> Result<()> {
         anyhow::bail!("Expected resourceType 'ValueSet', got '{resource_type}'");
     }
 
+    super::import::check_resource(&json, reject_suspicious, strict_json)?;
+
     // Extract required fields
     let url = json["url"]
         .as_str()
-        .context("Missing required field 'url'")?
-        .to_string();
+        .map(super::import::stored_canonical)
+        .context("Missing required field 'url'")?;
 
     let status = json["status"]
         .as_str()
@@ -152,33 +166,16 @@ pub async fn create_value_set(pool: PgPool, file_path: String) -> Result<()> {
     let title = json["title"].as_str().map(|s| s.to_string());
     let fhir_version = json["fhirVersion"].as_str().map(|s| s.to_string());
 
-    // Check if already exists
-    let exists: bool = if let Some(v) = &version {
-        sqlx::query_scalar(
-            "SELECT EXISTS(SELECT 1 FROM value_sets WHERE url = $1 AND version = $2)",
-        )
-        .bind(&url)
-        .bind(v)
-        .fetch_one(&pool)
-        .await?
-    } else {
-        sqlx::query_scalar(
-            "SELECT EXISTS(SELECT 1 FROM value_sets WHERE url = $1 AND version IS NULL)",
-        )
-        .bind(&url)
-        .fetch_one(&pool)
-        .await?
-    };
-
-    if exists {
-        anyhow::bail!("ValueSet with url '{url}' and version '{version:?}' already exists");
+    if dry_run {
+        return report_dry_run(&pool, ResourceType::ValueSet, &url, version, &[]).await;
     }
 
     // Insert ValueSet
     let id = Uuid::new_v4();
-    sqlx::query(
+    let inserted = sqlx::query(
         "INSERT INTO value_sets (id, url, version, status, name, title, fhir_version, content, created_at, updated_at)
-         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, NOW(), NOW())",
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, NOW(), NOW())
+         ON CONFLICT (url, COALESCE(version, '')) DO NOTHING",
     )
     .bind(id)
     .bind(&url)
@@ -189,7 +186,16 @@ pub async fn create_value_set(pool: PgPool, file_path: String) -> Result<()> {
     .bind(&fhir_version)
     .bind(sqlx::types::Json(&json))
     .execute(&pool)
-    .await?;
+    .await?
+    .rows_affected();
+
+    if inserted == 0 {
+        anyhow::bail!("ValueSet with url '{url}' and version '{version:?}' already exists");
+    }
+
+    // Store an embedded expansion so $expand can serve it directly
+    let expansion_size =
+        super::import::store_inline_expansion(&mut *pool.acquire().await?, &id, &json).await?;
 
     println!("✅ ValueSet created successfully!");
     println!("  ID: {id}");
@@ -197,12 +203,22 @@ pub async fn create_value_set(pool: PgPool, file_path: String) -> Result<()> {
     if let Some(v) = version {
         println!("  Version: {v}");
     }
+    if expansion_size > 0 {
+        println!("  Expansion: {expansion_size} codes");
+    }
 
     Ok(())
 }
 
 /// Create a ConceptMap from a FHIR JSON file
-pub async fn create_concept_map(pool: PgPool, file_path: String) -> Result<()> {
+pub async fn create_concept_map(
+    pool: PgPool,
+    file_path: String,
+    strict: bool,
+    reject_suspicious: bool,
+    strict_json: bool,
+    dry_run: bool,
+) -> Result<()> {
     info!("Creating ConceptMap from file: {}", file_path);
 
     // Read and parse the JSON file
@@ -220,11 +236,13 @@ pub async fn create_concept_map(pool: PgPool, file_path: String) -> Result<()> {
         anyhow::bail!("Expected resourceType 'ConceptMap', got '{resource_type}'");
     }
 
+    super::import::check_resource(&json, reject_suspicious, strict_json)?;
+
     // Extract required fields
     let url = json["url"]
         .as_str()
-        .context("Missing required field 'url'")?
-        .to_string();
+        .map(super::import::stored_canonical)
+        .context("Missing required field 'url'")?;
 
     let status = json["status"]
         .as_str()
@@ -246,33 +264,24 @@ pub async fn create_concept_map(pool: PgPool, file_path: String) -> Result<()> {
         .map(|s| s.to_string())
         .or_else(|| json["targetCanonical"].as_str().map(|s| s.to_string()));
 
-    // Check if already exists
-    let exists: bool = if let Some(v) = &version {
-        sqlx::query_scalar(
-            "SELECT EXISTS(SELECT 1 FROM concept_maps WHERE url = $1 AND version = $2)",
-        )
-        .bind(&url)
-        .bind(v)
-        .fetch_one(&pool)
-        .await?
-    } else {
-        sqlx::query_scalar(
-            "SELECT EXISTS(SELECT 1 FROM concept_maps WHERE url = $1 AND version IS NULL)",
-        )
-        .bind(&url)
-        .fetch_one(&pool)
-        .await?
-    };
+    // Check that the systems referenced by the groups are installed
+    super::import::check_concept_map_systems(&mut *pool.acquire().await?, &url, &json, strict)
+        .await?;
 
-    if exists {
-        anyhow::bail!("ConceptMap with url '{url}' and version '{version:?}' already exists");
+    if dry_run {
+        let elements = format!(
+            "Mapping elements: {}",
+            super::import::map_elements(&json).total
+        );
+        return report_dry_run(&pool, ResourceType::ConceptMap, &url, version, &[elements]).await;
     }
 
     // Insert ConceptMap
     let id = Uuid::new_v4();
-    sqlx::query(
-        "INSERT INTO concept_maps (id, url, version, status, name, title, fhir_version, source_uri, target_uri, content, created_at, updated_at)
-         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, NOW(), NOW())",
+    let inserted = sqlx::query(
+        "INSERT INTO concept_maps (id, url, version, status, name, title, fhir_version, source_uri, target_uri, content, element_count, created_at, updated_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, concept_map_element_count($10), NOW(), NOW())
+         ON CONFLICT (url, COALESCE(version, '')) DO NOTHING",
     )
     .bind(id)
     .bind(&url)
@@ -285,7 +294,15 @@ pub async fn create_concept_map(pool: PgPool, file_path: String) -> Result<()> {
     .bind(&target_uri)
     .bind(sqlx::types::Json(&json))
     .execute(&pool)
-    .await?;
+    .await?
+    .rows_affected();
+
+    if inserted == 0 {
+        anyhow::bail!("ConceptMap with url '{url}' and version '{version:?}' already exists");
+    }
+
+    let elements = super::import::map_elements(&json);
+    elements.warn_incomplete(&url);
 
     println!("✅ ConceptMap created successfully!");
     println!("  ID: {id}");
@@ -293,6 +310,46 @@ pub async fn create_concept_map(pool: PgPool, file_path: String) -> Result<()> {
     if let Some(v) = version {
         println!("  Version: {v}");
     }
+    println!("  Mapping elements: {}", elements.total);
+
+    Ok(())
+}
+
+/// Print what a `--dry-run` create would write, after the file passed the same checks as a
+/// real create. A `url` and `version` that already exist fail like the create would.
+async fn report_dry_run(
+    pool: &PgPool,
+    resource_type: ResourceType,
+    url: &str,
+    version: Option<String>,
+    details: &[String],
+) -> Result<()> {
+    let exists: bool = sqlx::query_scalar(&format!(
+        "SELECT EXISTS (SELECT 1 FROM {} WHERE url = $1 AND COALESCE(version, '') = $2)",
+        resource_type.table()
+    ))
+    .bind(url)
+    .bind(version.as_deref().unwrap_or_default())
+    .fetch_one(pool)
+    .await?;
+
+    info!("Dry run mode - no changes will be made");
+    println!("🔎 Would create {}:", resource_type.as_str());
+    println!("  URL: {url}");
+    if let Some(v) = &version {
+        println!("  Version: {v}");
+    }
+    for detail in details {
+        println!("  {detail}");
+    }
+
+    if exists {
+        anyhow::bail!(
+            "{} with url '{url}' and version '{version:?}' already exists",
+            resource_type.as_str()
+        );
+    }
+    println!("✅ No conflicting resource exists");
 
     Ok(())
 }
@@ -0,0 +1,52 @@
+use anyhow::Result;
+use sqlx::PgPool;
+
+/// Resource tables whose unversioned rows must be unique per url
+const RESOURCE_TABLES: [(&str, &str); 3] = [
+    ("CodeSystem", "code_systems"),
+    ("ValueSet", "value_sets"),
+    ("ConceptMap", "concept_maps"),
+];
+
+/// Delete duplicate unversioned resources left by earlier racing imports, keeping the
+/// oldest row of each url, so the unique url/version indexes can be created. With
+/// `dry_run` the duplicates are only listed.
+pub async fn run(pool: PgPool, dry_run: bool) -> Result<()> {
+    println!("\n🧬 Duplicate unversioned resources:");
+
+    let mut total = 0;
+    for (resource_type, table) in RESOURCE_TABLES {
+        let duplicates: Vec<(String, i64)> = sqlx::query_as(&format!(
+            "SELECT url, COUNT(*) FROM {table} WHERE version IS NULL
+             GROUP BY url HAVING COUNT(*) > 1 ORDER BY url"
+        ))
+        .fetch_all(&pool)
+        .await?;
+
+        for (url, copies) in &duplicates {
+            println!("  {resource_type} {url}: {copies} rows");
+            total += copies - 1;
+        }
+
+        if !dry_run && !duplicates.is_empty() {
+            // Deleting a CodeSystem or ValueSet cascades to its concepts and expansions
+            sqlx::query(&format!(
+                "DELETE FROM {table} a USING {table} b
+                 WHERE a.version IS NULL AND b.version IS NULL AND a.url = b.url
+                   AND (a.created_at, a.id) > (b.created_at, b.id)"
+            ))
+            .execute(&pool)
+            .await?;
+        }
+    }
+
+    if total == 0 {
+        println!("  (none)");
+    } else if dry_run {
+        println!("\nDry run - {total} duplicate rows would be deleted");
+    } else {
+        println!("\n✅ Deleted {total} duplicate rows, keeping the oldest of each url");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,59 @@
+use anyhow::Result;
+use sqlx::PgPool;
+use std::sync::Arc;
+use term_squid_backend::store::{PostgresStore, TerminologyStore};
+use term_squid_backend::terminology::{self, ExpandOptions, ExpandParams};
+
+/// Expand a ValueSet with the server's `$expand` logic and write the expanded ValueSet as
+/// JSON to `output`, or to stdout
+pub async fn run(
+    pool: PgPool,
+    url: String,
+    filter: Option<String>,
+    output: Option<String>,
+) -> Result<()> {
+    let store: Arc<dyn TerminologyStore> = Arc::new(PostgresStore::new(pool));
+    // One unbounded page holds every concept
+    let options = ExpandOptions::from_query(ExpandParams {
+        url: Some(url.clone()),
+        filter,
+        count: Some(i64::MAX),
+        ..Default::default()
+    })?;
+    let value_set_url = options.url.clone().unwrap_or_default();
+    let mut value_set = terminology::expand(store, &value_set_url, options)
+        .await?
+        .body;
+    // The expansion is not paged, so the unbounded count is not reported
+    if let Some(parameters) = value_set
+        .pointer_mut("/expansion/parameter")
+        .and_then(|p| p.as_array_mut())
+    {
+        parameters.retain(|p| p.get("name").and_then(|n| n.as_str()) != Some("count"));
+    }
+    // Systems too large to count omit the total; the page then holds all their concepts
+    let total = value_set
+        .pointer("/expansion/total")
+        .and_then(|t| t.as_u64())
+        .or_else(|| {
+            let contains = value_set.pointer("/expansion/contains")?.as_array()?;
+            Some(contains.len() as u64)
+        })
+        .unwrap_or_default();
+    let json = serde_json::to_string_pretty(&value_set)?;
+
+    match output {
+        Some(path) => {
+            std::fs::write(&path, json)?;
+            println!("✅ Expanded {url} to {path}");
+            println!("  Total: {total} concepts");
+        }
+        None => {
+            println!("{json}");
+            // Keep stdout a valid JSON document
+            eprintln!("Total: {total} concepts");
+        }
+    }
+
+    Ok(())
+}
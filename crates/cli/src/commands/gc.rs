@@ -0,0 +1,61 @@
+use anyhow::Result;
+use sqlx::PgPool;
+use tracing::info;
+
+/// Rows deleted per statement, so no single delete holds its locks for long
+const GC_BATCH_SIZE: i64 = 10_000;
+
+/// Tables whose rows belong to a parent row: (table, foreign key column, parent table)
+const OWNED_ROWS: [(&str, &str, &str); 4] = [
+    ("concepts", "code_system_id", "code_systems"),
+    ("closure_table", "code_system_id", "code_systems"),
+    ("value_set_expansions", "value_set_id", "value_sets"),
+    ("code_system_supplements", "supplement_id", "code_systems"),
+];
+
+/// Delete rows whose parent CodeSystem or ValueSet no longer exists, e.g. left behind by
+/// databases created before deletes cascaded. With `dry_run` the orphans are only counted.
+pub async fn run(pool: PgPool, dry_run: bool) -> Result<()> {
+    println!("\n🧹 Orphaned rows:");
+
+    let mut total = 0;
+    for (table, column, parent) in OWNED_ROWS {
+        let orphan = format!("NOT EXISTS (SELECT 1 FROM {parent} p WHERE p.id = t.{column})");
+
+        let removed = if dry_run {
+            sqlx::query_scalar::<_, i64>(&format!("SELECT COUNT(*) FROM {table} t WHERE {orphan}"))
+                .fetch_one(&pool)
+                .await? as u64
+        } else {
+            let delete = format!(
+                "DELETE FROM {table} WHERE ctid IN
+                     (SELECT t.ctid FROM {table} t WHERE {orphan} LIMIT $1)"
+            );
+            let mut removed = 0;
+            loop {
+                let deleted = sqlx::query(&delete)
+                    .bind(GC_BATCH_SIZE)
+                    .execute(&pool)
+                    .await?
+                    .rows_affected();
+                removed += deleted;
+                if deleted < GC_BATCH_SIZE as u64 {
+                    break;
+                }
+                info!("Deleted {} orphaned rows from {} so far", removed, table);
+            }
+            removed
+        };
+
+        println!("  {table}: {removed}");
+        total += removed;
+    }
+
+    if dry_run {
+        println!("\nDry run - {total} orphaned rows would be deleted");
+    } else {
+        println!("\n✅ Deleted {total} orphaned rows");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,47 @@
+use anyhow::Result;
+use sqlx::PgPool;
+
+type HistoryRow = (
+    String,
+    String,
+    Option<String>,
+    i32,
+    i32,
+    i32,
+    i32,
+    bool,
+    chrono::DateTime<chrono::Utc>,
+);
+
+pub async fn run(pool: PgPool, limit: i64) -> Result<()> {
+    let history: Vec<HistoryRow> = sqlx::query_as(
+        "SELECT package_name, package_version, registry, resources, imported, skipped, errors,
+                dry_run, imported_at
+         FROM import_history ORDER BY imported_at DESC LIMIT $1",
+    )
+    .bind(limit)
+    .fetch_all(&pool)
+    .await?;
+
+    println!("\n📜 Import History:");
+
+    if history.is_empty() {
+        println!("  (no imports recorded)");
+    }
+
+    for (name, version, registry, resources, imported, skipped, errors, dry_run, imported_at) in
+        history
+    {
+        let source = registry.as_deref().unwrap_or("local file");
+        let mode = if dry_run { " (dry run)" } else { "" };
+        println!(
+            "  - {} {name}@{version} from {source}{mode}: {resources} resources, \
+             {imported} imported, {skipped} skipped, {errors} errors",
+            imported_at.format("%Y-%m-%d %H:%M:%S")
+        );
+    }
+
+    println!();
+
+    Ok(())
+}
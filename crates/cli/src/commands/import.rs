@@ -1,35 +1,281 @@
 use anyhow::{Context, Result};
 use indicatif::{ProgressBar, ProgressStyle};
-use sqlx::{PgPool, Postgres, Transaction};
+use serde_json::Value;
+use sqlx::{PgConnection, PgPool, Postgres, Transaction};
+use std::borrow::Cow;
+use std::collections::HashSet;
 use std::path::Path;
+use std::time::Instant;
+use term_squid_backend::store::normalize_canonical;
 use tracing::{info, warn};
 use uuid::Uuid;
 
-use crate::package::{FhirResource, PackageDownloader};
+use crate::closure::{build_closure, nested_parents};
+use crate::package::{FhirPackage, FhirResource, PackageDownloader};
 
+/// Default cap on `concept.concept` nesting accepted during import
+pub const DEFAULT_MAX_CONCEPT_DEPTH: usize = 100;
+
+/// Options controlling how package resources are written
+#[derive(Debug, Clone, Copy)]
+pub struct ImportOptions {
+    /// Reject ConceptMaps that reference CodeSystems which are not installed
+    pub strict: bool,
+    /// Maximum nesting depth accepted for `concept.concept` hierarchies
+    pub max_concept_depth: usize,
+    /// Reject resources flagged by [`validate_resource`] instead of only warning
+    pub reject_suspicious: bool,
+    /// Reject resources with top-level elements not known for their type
+    pub strict_json: bool,
+    /// Concepts printed per CodeSystem during a dry run
+    pub preview_concepts: usize,
+    /// Size guard applied to each concept's `property` array
+    pub property_limit: PropertyLimit,
+    /// Cap on the number of concepts of a single CodeSystem
+    pub concept_limit: ConceptLimit,
+    /// Refresh planner statistics after an import of at least [`ANALYZE_MIN_CONCEPTS`]
+    pub analyze: bool,
+}
+
+/// Concepts a package must hold (nested ones included) for its import to be followed by
+/// an `ANALYZE` of the terminology tables
+pub const ANALYZE_MIN_CONCEPTS: usize = 10_000;
+
+/// Tables an import writes to, whose statistics are refreshed after a large import
+const ANALYZED_TABLES: &str =
+    "code_systems, concepts, closure_table, value_sets, value_set_expansions, concept_maps";
+
+/// Cap on the concepts of one CodeSystem, guarding a shared server against an accidental
+/// import of a huge terminology. Over the cap the CodeSystem is rejected, or with
+/// `warn_only` imported with a warning.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConceptLimit {
+    pub max: Option<usize>,
+    pub warn_only: bool,
+}
+
+impl ConceptLimit {
+    /// Check the number of concepts of the CodeSystem `url` against the cap
+    pub fn check(&self, url: &str, concepts: usize) -> Result<()> {
+        let Some(max) = self.max.filter(|max| concepts > *max) else {
+            return Ok(());
+        };
+        let message = format!(
+            "CodeSystem {url} has {concepts} concepts, more than the limit of {max}. \
+             Consider publishing it with `content: not-present` and serving it from a \
+             dedicated terminology server, or raise --max-concepts"
+        );
+        if self.warn_only {
+            warn!("{}", message);
+            Ok(())
+        } else {
+            anyhow::bail!(message)
+        }
+    }
+}
+
+/// The concept cap set by the `MAX_CONCEPTS_PER_CODESYSTEM` environment variable
+pub fn max_concepts_from_env() -> Result<Option<usize>> {
+    match std::env::var("MAX_CONCEPTS_PER_CODESYSTEM") {
+        Ok(max) if !max.is_empty() => Ok(Some(
+            max.parse()
+                .context("MAX_CONCEPTS_PER_CODESYSTEM must be a number")?,
+        )),
+        _ => Ok(None),
+    }
+}
+
+/// Default size above which a concept's serialized `property` array is reported
+pub const DEFAULT_MAX_PROPERTY_BYTES: usize = 64 * 1024;
+
+/// Size guard for concept properties: oversized property arrays are logged and,
+/// with `truncate`, reduced to the essential properties plus whatever else fits
+#[derive(Debug, Clone, Copy)]
+pub struct PropertyLimit {
+    pub max_bytes: usize,
+    pub truncate: bool,
+}
+
+impl Default for PropertyLimit {
+    fn default() -> Self {
+        Self {
+            max_bytes: DEFAULT_MAX_PROPERTY_BYTES,
+            truncate: false,
+        }
+    }
+}
+
+/// The concept properties a CodeSystem declares with `type` `Coding`, whose values are
+/// stored as `valueCoding` so that they are compared by system and code
+#[derive(Debug)]
+pub struct CodingProperties<'a> {
+    /// The CodeSystem's url, taken as the system of bare codes
+    system: &'a str,
+    codes: HashSet<&'a str>,
+}
+
+impl<'a> CodingProperties<'a> {
+    /// The Coding-typed properties of a CodeSystem stored under `url`
+    pub fn of(url: &'a str, code_system: &'a Value) -> Self {
+        let codes = code_system
+            .get("property")
+            .and_then(|p| p.as_array())
+            .into_iter()
+            .flatten()
+            .filter(|p| p.get("type").and_then(|t| t.as_str()) == Some("Coding"))
+            .filter_map(|p| p.get("code")?.as_str())
+            .collect();
+        Self { system: url, codes }
+    }
+
+    /// A concept's `property` array with the values of Coding-typed properties given as
+    /// `valueCode` or `valueString` (`code` or `system|code`) rewritten to `valueCoding`
+    fn normalize(&self, properties: &Value) -> Value {
+        let Some(entries) = properties.as_array().filter(|_| !self.codes.is_empty()) else {
+            return properties.clone();
+        };
+
+        let entries = entries
+            .iter()
+            .map(|entry| {
+                let coded = entry
+                    .get("code")
+                    .and_then(|c| c.as_str())
+                    .is_some_and(|code| self.codes.contains(code));
+                let Some(value) = entry
+                    .get("valueCode")
+                    .or_else(|| entry.get("valueString"))
+                    .and_then(|v| v.as_str())
+                    .filter(|_| coded)
+                else {
+                    return entry.clone();
+                };
+
+                let (system, code) = value.split_once('|').unwrap_or((self.system, value));
+                serde_json::json!({
+                    "code": entry["code"],
+                    "valueCoding": { "system": system, "code": code }
+                })
+            })
+            .collect();
+        Value::Array(entries)
+    }
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self {
+            strict: false,
+            max_concept_depth: DEFAULT_MAX_CONCEPT_DEPTH,
+            reject_suspicious: false,
+            strict_json: false,
+            preview_concepts: 0,
+            property_limit: PropertyLimit::default(),
+            concept_limit: ConceptLimit::default(),
+            analyze: true,
+        }
+    }
+}
+
+/// Whether the `STRICT_JSON` environment variable enables strict JSON checking
+pub fn strict_json_from_env() -> bool {
+    std::env::var("STRICT_JSON").is_ok_and(|v| v == "true" || v == "1")
+}
+
+/// The `url` column value for a canonical: normalized with [`normalize_canonical`] unless
+/// the `STRICT_CANONICALS` environment variable keeps canonicals exactly as authored
+pub fn stored_canonical(url: &str) -> String {
+    if std::env::var("STRICT_CANONICALS").is_ok_and(|v| v == "true" || v == "1") {
+        url.to_string()
+    } else {
+        normalize_canonical(url)
+    }
+}
+
+/// The `url` column values a canonical matches: as given and as [`stored_canonical`]
+/// stores it, like the server's canonical lookups
+pub fn canonical_candidates(url: &str) -> Vec<String> {
+    let stored = stored_canonical(url);
+    if stored == url {
+        vec![stored]
+    } else {
+        vec![url.to_string(), stored]
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn run(
     pool: PgPool,
     package: String,
     version: Option<String>,
-    registry: String,
+    registries: Vec<String>,
     dry_run: bool,
     yes: bool,
+    stream: bool,
+    options: ImportOptions,
 ) -> Result<()> {
     info!("Starting package import...");
 
-    let downloader = PackageDownloader::new(registry);
+    let downloader = PackageDownloader::new(registries).with_strict(options.strict);
+    let fetched = fetch_package(&downloader, &package, version.as_deref(), stream).await?;
+
+    import_package(&pool, fetched, dry_run, yes, options).await
+}
 
+/// A package read from disk or a registry and parsed, ready to be imported
+pub struct FetchedPackage {
+    pub package: FhirPackage,
+    /// Registry the package was downloaded from; None for local files
+    pub registry: Option<String>,
+}
+
+/// Download (unless `package` is a local archive) and extract a package. With `stream`,
+/// a registry download is parsed as it arrives instead of going through a temporary file.
+pub async fn fetch_package(
+    downloader: &PackageDownloader,
+    package: &str,
+    version: Option<&str>,
+    stream: bool,
+) -> Result<FetchedPackage> {
     // Determine if package is a local file or needs to be downloaded
-    let package_path = if package.ends_with(".tgz") || package.ends_with(".tar.gz") {
+    let is_local = [".tgz", ".tar.gz", ".tar", ".zip"]
+        .iter()
+        .any(|ext| package.ends_with(ext));
+    if is_local {
         info!("Using local package file: {}", package);
-        Path::new(&package).to_path_buf()
+        let package = downloader.extract_package(Path::new(package))?;
+        return Ok(FetchedPackage {
+            package,
+            registry: None,
+        });
+    }
+
+    let version = version.context("Version is required when downloading from registry")?;
+    let (package, registry) = if stream {
+        downloader.download_streamed(package, version).await?
     } else {
-        let version = version.context("Version is required when downloading from registry")?;
-        downloader.download(&package, &version).await?
+        let (path, registry) = downloader.download(package, version).await?;
+        (downloader.extract_package(&path)?, registry)
     };
 
-    // Extract and parse package
-    let fhir_package = downloader.extract_package(&package_path)?;
+    Ok(FetchedPackage {
+        package,
+        registry: Some(registry.to_string()),
+    })
+}
+
+/// Summarize, confirm and import a fetched package in its own transaction
+pub async fn import_package(
+    pool: &PgPool,
+    fetched: FetchedPackage,
+    dry_run: bool,
+    yes: bool,
+    options: ImportOptions,
+) -> Result<()> {
+    let FetchedPackage {
+        package: fhir_package,
+        registry,
+    } = fetched;
 
     info!("Package: {} v{}", fhir_package.name, fhir_package.version);
 
@@ -57,10 +303,28 @@ pub async fn run(
     println!("    - CodeSystems: {code_systems}");
     println!("    - ValueSets: {value_sets}");
     println!("    - ConceptMaps: {concept_maps}");
+    let mapping_elements: usize = fhir_package
+        .resources
+        .iter()
+        .filter(|r| r.resource_type == "ConceptMap")
+        .map(|r| map_elements(&r.content).total)
+        .sum();
+    println!("      Mapping elements: {mapping_elements}");
     println!("    - Total: {}\n", fhir_package.resources.len());
 
+    let source = ImportSource {
+        name: &fhir_package.name,
+        version: &fhir_package.version,
+        registry: registry.as_deref(),
+        resources: fhir_package.resources.len(),
+    };
+
     if dry_run {
         info!("Dry run mode - no changes will be made");
+        if options.preview_concepts > 0 {
+            preview_concepts(&fhir_package.resources, options)?;
+        }
+        record_history(pool, &source, ImportCounts::default(), true).await?;
         return Ok(());
     }
 
@@ -79,15 +343,118 @@ pub async fn run(
         }
     }
 
+    let concepts: usize = fhir_package
+        .resources
+        .iter()
+        .filter(|r| r.resource_type == "CodeSystem")
+        .filter_map(|r| r.content.get("concept")?.as_array())
+        .map(|c| count_concepts(c))
+        .sum();
+
     // Import resources with transaction
-    import_resources(&pool, fhir_package.resources).await?;
+    let counts = import_resources(pool, fhir_package.resources, options).await?;
+    record_history(pool, &source, counts, false).await?;
+
+    if counts.errors > 0 {
+        anyhow::bail!("Import failed due to errors");
+    }
+
+    if options.analyze && concepts >= ANALYZE_MIN_CONCEPTS {
+        analyze(pool).await?;
+    }
 
     println!("\n✅ Import completed successfully!");
 
     Ok(())
 }
 
-async fn import_resources(pool: &PgPool, resources: Vec<FhirResource>) -> Result<()> {
+/// Concepts in a `concept` array, nested ones included
+fn count_concepts(concepts: &[Value]) -> usize {
+    let mut stack: Vec<&Value> = concepts.iter().collect();
+    let mut count = 0;
+    while let Some(concept) = stack.pop() {
+        count += 1;
+        if let Some(children) = concept.get("concept").and_then(|c| c.as_array()) {
+            stack.extend(children);
+        }
+    }
+    count
+}
+
+/// Refresh the planner statistics of [`ANALYZED_TABLES`], so queries right after a large
+/// import do not run on plans made for the tables before it
+async fn analyze(pool: &PgPool) -> Result<()> {
+    info!("Analyzing {}", ANALYZED_TABLES);
+    let started = Instant::now();
+
+    sqlx::query(&format!("ANALYZE {ANALYZED_TABLES}"))
+        .execute(pool)
+        .await?;
+
+    info!("Analyze completed in {:.1?}", started.elapsed());
+    Ok(())
+}
+
+/// Print the first concepts (nested ones included, in document order) of every
+/// CodeSystem in the package
+fn preview_concepts(resources: &[FhirResource], options: ImportOptions) -> Result<()> {
+    let code_systems = resources.iter().filter(|r| r.resource_type == "CodeSystem");
+
+    for code_system in code_systems {
+        let Some(concepts) = code_system
+            .content
+            .get("concept")
+            .and_then(|c| c.as_array())
+        else {
+            continue;
+        };
+        let concepts = flatten_concepts(concepts, options.max_concept_depth)?;
+        let url = code_system.url.as_deref().unwrap_or("(no url)");
+
+        println!("🔎 {url}:");
+        for concept in concepts.iter().take(options.preview_concepts) {
+            let code = concept.get("code").and_then(|c| c.as_str()).unwrap_or("");
+            match concept.get("display").and_then(|d| d.as_str()) {
+                Some(display) => println!("    {code} - {display}"),
+                None => println!("    {code}"),
+            }
+        }
+        println!(
+            "  Previewed {} of {} concepts\n",
+            concepts.len().min(options.preview_concepts),
+            concepts.len()
+        );
+    }
+
+    Ok(())
+}
+
+/// Outcome of importing a package's resources
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ImportCounts {
+    pub imported: usize,
+    pub skipped: usize,
+    pub errors: usize,
+}
+
+/// The package an import run read from, as recorded in `import_history`
+struct ImportSource<'a> {
+    name: &'a str,
+    version: &'a str,
+    /// Registry the package was downloaded from; None for local files
+    registry: Option<&'a str>,
+    resources: usize,
+}
+
+/// Import all resources in one transaction, which is rolled back if any resource fails
+async fn import_resources(
+    pool: &PgPool,
+    mut resources: Vec<FhirResource>,
+    options: ImportOptions,
+) -> Result<ImportCounts> {
+    // Import ConceptMaps last so the systems they reference from the same package are visible
+    resources.sort_by_key(|r| r.resource_type == "ConceptMap");
+
     let pb = ProgressBar::new(resources.len() as u64);
     pb.set_style(
         ProgressStyle::default_bar()
@@ -109,7 +476,7 @@ async fn import_resources(pool: &PgPool, resources: Vec<FhirResource>) -> Result
             resource.url.as_deref().unwrap_or("unknown")
         ));
 
-        match import_resource(&mut tx, &resource).await {
+        match import_resource(&mut tx, &resource, options).await {
             Ok(true) => imported += 1,
             Ok(false) => skipped += 1,
             Err(e) => {
@@ -129,7 +496,6 @@ async fn import_resources(pool: &PgPool, resources: Vec<FhirResource>) -> Result
             imported, skipped, errors
         );
         tx.rollback().await?;
-        anyhow::bail!("Import failed due to errors");
     } else {
         tx.commit().await?;
         info!(
@@ -138,17 +504,54 @@ async fn import_resources(pool: &PgPool, resources: Vec<FhirResource>) -> Result
         );
     }
 
+    Ok(ImportCounts {
+        imported,
+        skipped,
+        errors,
+    })
+}
+
+/// Append an audit record of an import run to `import_history`
+async fn record_history(
+    pool: &PgPool,
+    source: &ImportSource<'_>,
+    counts: ImportCounts,
+    dry_run: bool,
+) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO import_history
+            (package_name, package_version, registry, resources, imported, skipped, errors, dry_run)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+    )
+    .bind(source.name)
+    .bind(source.version)
+    .bind(source.registry)
+    .bind(source.resources as i32)
+    .bind(counts.imported as i32)
+    .bind(counts.skipped as i32)
+    .bind(counts.errors as i32)
+    .bind(dry_run)
+    .execute(pool)
+    .await?;
+
     Ok(())
 }
 
-async fn import_resource(
+pub async fn import_resource(
     tx: &mut Transaction<'_, Postgres>,
     resource: &FhirResource,
+    options: ImportOptions,
 ) -> Result<bool> {
+    check_resource(
+        &resource.content,
+        options.reject_suspicious,
+        options.strict_json,
+    )?;
+
     match resource.resource_type.as_str() {
-        "CodeSystem" => import_code_system(tx, resource).await,
+        "CodeSystem" => import_code_system(tx, resource, options).await,
         "ValueSet" => import_value_set(tx, resource).await,
-        "ConceptMap" => import_concept_map(tx, resource).await,
+        "ConceptMap" => import_concept_map(tx, resource, options.strict).await,
         _ => Ok(false),
     }
 }
@@ -156,11 +559,13 @@ async fn import_resource(
 async fn import_code_system(
     tx: &mut Transaction<'_, Postgres>,
     resource: &FhirResource,
+    options: ImportOptions,
 ) -> Result<bool> {
     let url = resource
         .url
         .as_ref()
         .context("CodeSystem must have a url")?;
+    let url = &stored_canonical(url);
     let version = resource.content.get("version").and_then(|v| v.as_str());
     let status = resource
         .content
@@ -170,33 +575,12 @@ async fn import_code_system(
     let name = resource.content.get("name").and_then(|v| v.as_str());
     let title = resource.content.get("title").and_then(|v| v.as_str());
 
-    // Check if already exists
-    let exists: bool = if let Some(v) = version {
-        sqlx::query_scalar(
-            "SELECT EXISTS(SELECT 1 FROM code_systems WHERE url = $1 AND version = $2)",
-        )
-        .bind(url)
-        .bind(v)
-        .fetch_one(&mut **tx)
-        .await?
-    } else {
-        sqlx::query_scalar(
-            "SELECT EXISTS(SELECT 1 FROM code_systems WHERE url = $1 AND version IS NULL)",
-        )
-        .bind(url)
-        .fetch_one(&mut **tx)
-        .await?
-    };
-
-    if exists {
-        return Ok(false); // Skip existing
-    }
-
     // Insert CodeSystem
     let id = Uuid::new_v4();
-    sqlx::query(
+    let inserted = sqlx::query(
         "INSERT INTO code_systems (id, url, version, status, name, title, content, created_at, updated_at)
-         VALUES ($1, $2, $3, $4, $5, $6, $7, NOW(), NOW())",
+         VALUES ($1, $2, $3, $4, $5, $6, $7, NOW(), NOW())
+         ON CONFLICT (url, COALESCE(version, '')) DO NOTHING",
     )
     .bind(id)
     .bind(url)
@@ -206,45 +590,312 @@ async fn import_code_system(
     .bind(title)
     .bind(sqlx::types::Json(&resource.content))
     .execute(&mut **tx)
-    .await?;
+    .await?
+    .rows_affected();
+
+    // Another import (or an earlier run) already stored this url/version
+    if inserted == 0 {
+        return Ok(false);
+    }
+
+    link_supplement(tx, &id, &resource.content).await?;
 
     // Import concepts if present
     if let Some(concepts) = resource.content.get("concept").and_then(|c| c.as_array()) {
-        import_concepts(tx, &id, concepts).await?;
+        import_concepts(tx, url, &id, &resource.content, concepts, options).await?;
     }
 
     Ok(true)
 }
 
+/// Record which CodeSystem a supplement extends, from its `supplements` canonical
+/// (`url` or `url|version`). The base is matched by URL at query time, so it may be
+/// imported before or after the supplement.
+pub async fn link_supplement(
+    conn: &mut PgConnection,
+    code_system_id: &Uuid,
+    content: &Value,
+) -> Result<()> {
+    let Some(supplements) = content.get("supplements").and_then(|s| s.as_str()) else {
+        return Ok(());
+    };
+    let (base_url, base_version) = match supplements.split_once('|') {
+        Some((url, version)) => (url, Some(version).filter(|v| !v.is_empty())),
+        None => (supplements, None),
+    };
+
+    sqlx::query(
+        "INSERT INTO code_system_supplements (supplement_id, base_url, base_version)
+         VALUES ($1, $2, $3)",
+    )
+    .bind(code_system_id)
+    .bind(stored_canonical(base_url))
+    .bind(base_version)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
 async fn import_concepts(
     tx: &mut Transaction<'_, Postgres>,
+    url: &str,
     code_system_id: &Uuid,
+    code_system: &Value,
     concepts: &[serde_json::Value],
+    options: ImportOptions,
 ) -> Result<()> {
-    for concept in concepts {
+    // Counted before anything is inserted; the whole import rolls back on failure
+    let concepts = flatten_concepts(concepts, options.max_concept_depth)?;
+    options.concept_limit.check(url, concepts.len())?;
+
+    let coding_properties = CodingProperties::of(url, code_system);
+    let language = code_system.get("language").and_then(|l| l.as_str());
+    for (ordinal, concept) in with_nested_parents(&concepts).iter().enumerate() {
+        insert_concept(
+            tx,
+            code_system_id,
+            concept,
+            ordinal,
+            options.property_limit,
+            &coding_properties,
+            language,
+        )
+        .await?;
+    }
+
+    build_closure(tx, code_system_id, url, code_system, &concepts).await?;
+
+    Ok(())
+}
+
+/// Designation `use` codes that mark a designation as a definition
+/// (the SNOMED CT definition type and the plain FHIR `definition` use)
+const DEFINITION_USE_CODES: [&str; 2] = ["900000000000550004", "definition"];
+
+/// Properties kept first when an oversized property array is truncated, as they drive
+/// hierarchy, status and selectability
+const ESSENTIAL_PROPERTIES: [&str; 7] = [
+    "parent",
+    "child",
+    "inactive",
+    "status",
+    "deprecated",
+    "notSelectable",
+    "abstract",
+];
+
+/// Apply `limit` to a concept's `property` array, returning the (possibly reduced) array
+fn guard_properties(code: &str, properties: &Value, limit: PropertyLimit) -> Value {
+    let size = properties.to_string().len();
+    if size <= limit.max_bytes {
+        return properties.clone();
+    }
+
+    let Some(all) = properties.as_array().filter(|_| limit.truncate) else {
+        warn!(
+            "Concept '{}' has {} bytes of properties (limit {})",
+            code, size, limit.max_bytes
+        );
+        return properties.clone();
+    };
+
+    let is_essential = |p: &&Value| {
+        p.get("code")
+            .and_then(|c| c.as_str())
+            .is_some_and(|c| ESSENTIAL_PROPERTIES.contains(&c))
+    };
+    let mut kept: Vec<Value> = all.iter().filter(is_essential).cloned().collect();
+    let mut kept_size = Value::Array(kept.clone()).to_string().len();
+    for property in all.iter().filter(|p| !is_essential(p)) {
+        let property_size = property.to_string().len() + 1;
+        if kept_size + property_size > limit.max_bytes {
+            continue;
+        }
+        kept_size += property_size;
+        kept.push(property.clone());
+    }
+
+    warn!(
+        "Concept '{}' has {} bytes of properties (limit {}); kept {} of {} properties",
+        code,
+        size,
+        limit.max_bytes,
+        kept.len(),
+        all.len()
+    );
+
+    Value::Array(kept)
+}
+
+/// A concept's designations as stored for `$lookup` and `$expand`: each one with a `value`,
+/// reduced to its `language`, `use`, `additionalUse` and `value`. A designation without a
+/// `language` takes the CodeSystem's `language`, when it has one.
+fn stored_designations(designations: &[Value], language: Option<&str>) -> Option<Value> {
+    let stored: Vec<Value> = designations
+        .iter()
+        .filter(|d| d.get("value").is_some_and(Value::is_string))
+        .map(|d| {
+            let mut stored = serde_json::Map::new();
+            let language = d
+                .get("language")
+                .cloned()
+                .or_else(|| language.map(Value::from));
+            if let Some(language) = language {
+                stored.insert("language".to_string(), language);
+            }
+            for element in ["use", "additionalUse", "value"] {
+                if let Some(value) = d.get(element) {
+                    stored.insert(element.to_string(), value.clone());
+                }
+            }
+            Value::Object(stored)
+        })
+        .collect();
+
+    (!stored.is_empty()).then_some(Value::Array(stored))
+}
+
+/// Insert a single CodeSystem concept at position `ordinal` of the flattened concept list,
+/// keeping its properties (subject to `property_limit`) and [`stored_designations`], whose
+/// default language is the CodeSystem's `language`. When the concept has no `definition`,
+/// the first definition designation is used.
+pub async fn insert_concept(
+    conn: &mut PgConnection,
+    code_system_id: &Uuid,
+    concept: &Value,
+    ordinal: usize,
+    property_limit: PropertyLimit,
+    coding_properties: &CodingProperties<'_>,
+    language: Option<&str>,
+) -> Result<()> {
+    let code = concept
+        .get("code")
+        .and_then(|c| c.as_str())
+        .context("Concept must have a code")?;
+    let display = concept.get("display").and_then(|d| d.as_str());
+    let properties = concept
+        .get("property")
+        .map(|p| guard_properties(code, &coding_properties.normalize(p), property_limit));
+    let designations = concept.get("designation").and_then(|d| d.as_array());
+
+    let definition = concept
+        .get("definition")
+        .and_then(|d| d.as_str())
+        .or_else(|| {
+            designations?
+                .iter()
+                .find(|d| {
+                    d.pointer("/use/code")
+                        .and_then(|c| c.as_str())
+                        .is_some_and(|c| DEFINITION_USE_CODES.contains(&c))
+                })?
+                .get("value")?
+                .as_str()
+        });
+
+    sqlx::query(
+        "INSERT INTO concepts
+             (code_system_id, code, display, definition, properties, designations, ordinal)
+         VALUES ($1, $2, $3, $4, $5, $6, $7)
+         ON CONFLICT (code_system_id, code) DO NOTHING",
+    )
+    .bind(code_system_id)
+    .bind(code)
+    .bind(display)
+    .bind(definition)
+    .bind(properties.map(sqlx::types::Json))
+    .bind(designations.and_then(|d| stored_designations(d, language).map(sqlx::types::Json)))
+    .bind(i32::try_from(ordinal).context("Too many concepts in one CodeSystem")?)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Walk a (possibly nested) `concept` array depth-first, returning every concept in
+/// document order. Fails on a code nested under itself or on nesting deeper than `max_depth`.
+pub fn flatten_concepts(concepts: &[Value], max_depth: usize) -> Result<Vec<&Value>> {
+    let mut flattened = Vec::new();
+
+    // Explicit stack so deep trees cannot overflow; each entry carries its ancestor codes
+    let mut stack: Vec<(&Value, Vec<&str>)> =
+        concepts.iter().rev().map(|c| (c, Vec::new())).collect();
+
+    while let Some((concept, ancestors)) = stack.pop() {
         let code = concept
             .get("code")
             .and_then(|c| c.as_str())
             .context("Concept must have a code")?;
-        let display = concept.get("display").and_then(|d| d.as_str());
-        let definition = concept.get("definition").and_then(|d| d.as_str());
-        let properties = concept.get("property");
-
-        sqlx::query(
-            "INSERT INTO concepts (code_system_id, code, display, definition, properties)
-             VALUES ($1, $2, $3, $4, $5)
-             ON CONFLICT (code_system_id, code) DO NOTHING",
-        )
-        .bind(code_system_id)
-        .bind(code)
-        .bind(display)
-        .bind(definition)
-        .bind(properties.map(sqlx::types::Json))
-        .execute(&mut **tx)
-        .await?;
+
+        if ancestors.contains(&code) {
+            anyhow::bail!(
+                "Concept '{code}' is nested under itself: {} > {code}",
+                ancestors.join(" > ")
+            );
+        }
+        if ancestors.len() >= max_depth {
+            anyhow::bail!("Concept '{code}' exceeds the maximum nesting depth of {max_depth}");
+        }
+
+        flattened.push(concept);
+
+        if let Some(children) = concept.get("concept").and_then(|c| c.as_array()) {
+            let mut path = ancestors;
+            path.push(code);
+            for child in children.iter().rev() {
+                stack.push((child, path.clone()));
+            }
+        }
     }
 
-    Ok(())
+    Ok(flattened)
+}
+
+/// The [`flatten_concepts`] list with a `parent` property (`valueCode`) added to each
+/// nested concept for every concept it is nested under and does not already name as its
+/// `parent`, so the hierarchy survives in the stored properties. A code nested under
+/// several parents, stored once, gets all of them.
+pub fn with_nested_parents<'a>(concepts: &[&'a Value]) -> Vec<Cow<'a, Value>> {
+    let parents = nested_parents(concepts);
+
+    concepts
+        .iter()
+        .map(|&concept| {
+            let nested_parents = concept
+                .get("code")
+                .and_then(|c| c.as_str())
+                .and_then(|code| parents.get(code));
+            let declared: Vec<&str> = concept
+                .get("property")
+                .and_then(|p| p.as_array())
+                .into_iter()
+                .flatten()
+                .filter(|p| p.get("code").and_then(|c| c.as_str()) == Some("parent"))
+                .filter_map(|p| {
+                    p.get("valueCode")
+                        .or_else(|| p.pointer("/valueCoding/code"))?
+                        .as_str()
+                })
+                .collect();
+            let missing: Vec<Value> = nested_parents
+                .into_iter()
+                .flatten()
+                .filter(|parent| !declared.contains(parent))
+                .map(|parent| serde_json::json!({ "code": "parent", "valueCode": parent }))
+                .collect();
+            if missing.is_empty() {
+                return Cow::Borrowed(concept);
+            }
+
+            let mut concept = concept.clone();
+            match concept.get_mut("property").and_then(|p| p.as_array_mut()) {
+                Some(properties) => properties.extend(missing),
+                None => concept["property"] = Value::Array(missing),
+            }
+            Cow::Owned(concept)
+        })
+        .collect()
 }
 
 async fn import_value_set(
@@ -252,6 +903,7 @@ async fn import_value_set(
     resource: &FhirResource,
 ) -> Result<bool> {
     let url = resource.url.as_ref().context("ValueSet must have a url")?;
+    let url = &stored_canonical(url);
     let version = resource.content.get("version").and_then(|v| v.as_str());
     let status = resource
         .content
@@ -261,33 +913,13 @@ async fn import_value_set(
     let name = resource.content.get("name").and_then(|v| v.as_str());
     let title = resource.content.get("title").and_then(|v| v.as_str());
 
-    // Check if already exists
-    let exists: bool = if let Some(v) = version {
-        sqlx::query_scalar(
-            "SELECT EXISTS(SELECT 1 FROM value_sets WHERE url = $1 AND version = $2)",
-        )
-        .bind(url)
-        .bind(v)
-        .fetch_one(&mut **tx)
-        .await?
-    } else {
-        sqlx::query_scalar(
-            "SELECT EXISTS(SELECT 1 FROM value_sets WHERE url = $1 AND version IS NULL)",
-        )
-        .bind(url)
-        .fetch_one(&mut **tx)
-        .await?
-    };
-
-    if exists {
-        return Ok(false);
-    }
-
-    sqlx::query(
+    let id = Uuid::new_v4();
+    let inserted = sqlx::query(
         "INSERT INTO value_sets (id, url, version, status, name, title, content, created_at, updated_at)
-         VALUES ($1, $2, $3, $4, $5, $6, $7, NOW(), NOW())",
+         VALUES ($1, $2, $3, $4, $5, $6, $7, NOW(), NOW())
+         ON CONFLICT (url, COALESCE(version, '')) DO NOTHING",
     )
-    .bind(Uuid::new_v4())
+    .bind(id)
     .bind(url)
     .bind(version)
     .bind(status)
@@ -295,7 +927,15 @@ async fn import_value_set(
     .bind(title)
     .bind(sqlx::types::Json(&resource.content))
     .execute(&mut **tx)
-    .await?;
+    .await?
+    .rows_affected();
+
+    if inserted == 0 {
+        return Ok(false);
+    }
+
+    // Keep any expansion authored upstream so $expand can serve it directly
+    store_inline_expansion(tx, &id, &resource.content).await?;
 
     Ok(true)
 }
@@ -303,11 +943,13 @@ async fn import_value_set(
 async fn import_concept_map(
     tx: &mut Transaction<'_, Postgres>,
     resource: &FhirResource,
+    strict: bool,
 ) -> Result<bool> {
     let url = resource
         .url
         .as_ref()
         .context("ConceptMap must have a url")?;
+    let url = &stored_canonical(url);
     let version = resource.content.get("version").and_then(|v| v.as_str());
     let status = resource
         .content
@@ -315,31 +957,10 @@ async fn import_concept_map(
         .and_then(|v| v.as_str())
         .unwrap_or("unknown");
 
-    // Check if already exists
-    let exists: bool = if let Some(v) = version {
-        sqlx::query_scalar(
-            "SELECT EXISTS(SELECT 1 FROM concept_maps WHERE url = $1 AND version = $2)",
-        )
-        .bind(url)
-        .bind(v)
-        .fetch_one(&mut **tx)
-        .await?
-    } else {
-        sqlx::query_scalar(
-            "SELECT EXISTS(SELECT 1 FROM concept_maps WHERE url = $1 AND version IS NULL)",
-        )
-        .bind(url)
-        .fetch_one(&mut **tx)
-        .await?
-    };
-
-    if exists {
-        return Ok(false);
-    }
-
-    sqlx::query(
-        "INSERT INTO concept_maps (id, url, version, status, content, created_at, updated_at)
-         VALUES ($1, $2, $3, $4, $5, NOW(), NOW())",
+    let inserted = sqlx::query(
+        "INSERT INTO concept_maps (id, url, version, status, content, element_count, created_at, updated_at)
+         VALUES ($1, $2, $3, $4, $5, concept_map_element_count($5), NOW(), NOW())
+         ON CONFLICT (url, COALESCE(version, '')) DO NOTHING",
     )
     .bind(Uuid::new_v4())
     .bind(url)
@@ -347,7 +968,467 @@ async fn import_concept_map(
     .bind(status)
     .bind(sqlx::types::Json(&resource.content))
     .execute(&mut **tx)
-    .await?;
+    .await?
+    .rows_affected();
+
+    if inserted == 0 {
+        return Ok(false);
+    }
+
+    check_concept_map_systems(tx, url, &resource.content, strict).await?;
+
+    let elements = map_elements(&resource.content);
+    info!("ConceptMap {}: {} mapping elements", url, elements.total);
+    elements.warn_incomplete(url);
 
     Ok(true)
 }
+
+/// The mapping elements (`group.element`) of a ConceptMap
+pub struct MapElements<'a> {
+    pub total: usize,
+    /// Source codes of elements with no target, no `noMap` flag and no `unmapped` rule on
+    /// their group
+    pub incomplete: Vec<&'a str>,
+}
+
+impl MapElements<'_> {
+    /// Warn about elements that say nothing about where their code maps to
+    pub fn warn_incomplete(&self, url: &str) {
+        if self.incomplete.is_empty() {
+            return;
+        }
+        let shown: Vec<&str> = self.incomplete.iter().take(10).copied().collect();
+        warn!(
+            "ConceptMap {}: {} of {} elements have no target and no unmapped rule: {}{}",
+            url,
+            self.incomplete.len(),
+            self.total,
+            shown.join(", "),
+            if self.incomplete.len() > shown.len() {
+                ", ..."
+            } else {
+                ""
+            }
+        );
+    }
+}
+
+/// Count the mapping elements of a ConceptMap and find the incomplete ones
+pub fn map_elements(content: &Value) -> MapElements<'_> {
+    let mut elements = MapElements {
+        total: 0,
+        incomplete: Vec::new(),
+    };
+
+    let groups = content.get("group").and_then(|g| g.as_array());
+    for group in groups.into_iter().flatten() {
+        let has_unmapped = group.get("unmapped").is_some();
+        let group_elements = group.get("element").and_then(|e| e.as_array());
+        for element in group_elements.into_iter().flatten() {
+            elements.total += 1;
+
+            let has_target = element
+                .get("target")
+                .and_then(|t| t.as_array())
+                .is_some_and(|targets| !targets.is_empty());
+            let no_map = element.get("noMap").and_then(|n| n.as_bool()) == Some(true);
+            if !(has_target || no_map || has_unmapped) {
+                let code = element.get("code").and_then(|c| c.as_str());
+                elements.incomplete.push(code.unwrap_or("(no code)"));
+            }
+        }
+    }
+
+    elements
+}
+
+/// Store the `expansion` embedded in a ValueSet resource, if it has any `contains` entries.
+/// Returns the number of stored entries.
+pub async fn store_inline_expansion(
+    conn: &mut PgConnection,
+    value_set_id: &Uuid,
+    content: &Value,
+) -> Result<usize> {
+    let Some(expansion) = content.get("expansion") else {
+        return Ok(0);
+    };
+    let Some(contains) = expansion.get("contains").and_then(|c| c.as_array()) else {
+        return Ok(0);
+    };
+
+    sqlx::query("INSERT INTO value_set_expansions (value_set_id, expansion_data) VALUES ($1, $2)")
+        .bind(value_set_id)
+        .bind(sqlx::types::Json(expansion))
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(contains.len())
+}
+
+/// The distinct `group.source`/`group.target` systems referenced by a ConceptMap, in order
+fn concept_map_systems(content: &Value) -> Vec<&str> {
+    let mut systems: Vec<&str> = Vec::new();
+
+    if let Some(groups) = content.get("group").and_then(|g| g.as_array()) {
+        for group in groups {
+            for key in ["source", "target"] {
+                if let Some(system) = group.get(key).and_then(|s| s.as_str()) {
+                    if !systems.contains(&system) {
+                        systems.push(system);
+                    }
+                }
+            }
+        }
+    }
+
+    systems
+}
+
+/// Collect the `group.source`/`group.target` systems referenced by a ConceptMap
+/// that have no matching CodeSystem installed
+async fn missing_concept_map_systems(
+    conn: &mut PgConnection,
+    content: &Value,
+) -> Result<Vec<String>> {
+    let mut missing = Vec::new();
+    for system in concept_map_systems(content) {
+        let exists: bool =
+            sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM code_systems WHERE url = ANY($1))")
+                .bind(canonical_candidates(system))
+                .fetch_one(&mut *conn)
+                .await?;
+
+        if !exists {
+            missing.push(system.to_string());
+        }
+    }
+
+    Ok(missing)
+}
+
+/// Warn about (or, in strict mode, reject) ConceptMaps whose group systems are not installed
+pub async fn check_concept_map_systems(
+    conn: &mut PgConnection,
+    url: &str,
+    content: &Value,
+    strict: bool,
+) -> Result<()> {
+    let missing = missing_concept_map_systems(conn, content).await?;
+
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    let missing = missing.join(", ");
+    if strict {
+        anyhow::bail!(
+            "ConceptMap '{url}' references CodeSystems that are not installed: {missing}"
+        );
+    }
+
+    warn!(
+        "ConceptMap '{}' references CodeSystems that are not installed: {}",
+        url, missing
+    );
+
+    Ok(())
+}
+
+/// Structural checks for common authoring mistakes that would otherwise import silently,
+/// e.g. a CodeSystem claiming complete content without any concepts. Returns one message
+/// per problem found.
+pub fn validate_resource(content: &Value) -> Vec<String> {
+    let resource_type = content
+        .get("resourceType")
+        .and_then(|t| t.as_str())
+        .unwrap_or_default();
+    let is_empty = |field: &str| {
+        content
+            .get(field)
+            .and_then(|v| v.as_array())
+            .is_none_or(|a| a.is_empty())
+    };
+
+    let content_mode = content.get("content").and_then(|c| c.as_str());
+    let problem = match resource_type {
+        "CodeSystem" => (content_mode == Some("complete") && is_empty("concept"))
+            .then_some("declares content 'complete' but has no concepts"),
+        "ValueSet" => (content.get("compose").is_none() && content.get("expansion").is_none())
+            .then_some("has neither a compose nor an expansion"),
+        "ConceptMap" => is_empty("group").then_some("has no groups"),
+        _ => None,
+    };
+
+    problem.into_iter().map(str::to_string).collect()
+}
+
+/// Top-level elements shared by the canonical resources (R4 and R5)
+const CANONICAL_ELEMENTS: &[&str] = &[
+    "resourceType",
+    "id",
+    "meta",
+    "implicitRules",
+    "language",
+    "text",
+    "contained",
+    "extension",
+    "modifierExtension",
+    "url",
+    "identifier",
+    "version",
+    "versionAlgorithmString",
+    "versionAlgorithmCoding",
+    "name",
+    "title",
+    "status",
+    "experimental",
+    "date",
+    "publisher",
+    "contact",
+    "description",
+    "useContext",
+    "jurisdiction",
+    "purpose",
+    "copyright",
+    "copyrightLabel",
+    "approvalDate",
+    "lastReviewDate",
+    "effectivePeriod",
+    "topic",
+    "author",
+    "editor",
+    "reviewer",
+    "endorser",
+    "relatedArtifact",
+];
+
+/// Top-level elements specific to each supported resource type (R4 and R5)
+fn type_elements(resource_type: &str) -> &'static [&'static str] {
+    match resource_type {
+        "CodeSystem" => &[
+            "caseSensitive",
+            "valueSet",
+            "hierarchyMeaning",
+            "compositional",
+            "versionNeeded",
+            "content",
+            "supplements",
+            "count",
+            "filter",
+            "property",
+            "concept",
+        ],
+        "ValueSet" => &["immutable", "compose", "expansion", "scope"],
+        "ConceptMap" => &[
+            "sourceUri",
+            "sourceCanonical",
+            "targetUri",
+            "targetCanonical",
+            "sourceScopeUri",
+            "sourceScopeCanonical",
+            "targetScopeUri",
+            "targetScopeCanonical",
+            "property",
+            "additionalAttribute",
+            "group",
+        ],
+        _ => &[],
+    }
+}
+
+/// Top-level elements of `content` that are not known for its resource type. Primitive
+/// extension elements (`_status`) are accepted for known elements.
+pub fn unknown_elements(content: &Value) -> Vec<String> {
+    let Some(fields) = content.as_object() else {
+        return Vec::new();
+    };
+    let specific = type_elements(
+        content
+            .get("resourceType")
+            .and_then(|t| t.as_str())
+            .unwrap_or_default(),
+    );
+
+    fields
+        .keys()
+        .filter(|field| {
+            let element = field.strip_prefix('_').unwrap_or(field);
+            !CANONICAL_ELEMENTS.contains(&element) && !specific.contains(&element)
+        })
+        .cloned()
+        .collect()
+}
+
+/// Warn about (or, when `reject` is set, fail on) problems found by [`validate_resource`].
+/// With `strict_json`, unknown top-level elements (see [`unknown_elements`]) always fail.
+pub fn check_resource(content: &Value, reject: bool, strict_json: bool) -> Result<()> {
+    let resource_type = content
+        .get("resourceType")
+        .and_then(|t| t.as_str())
+        .unwrap_or("Resource");
+    let url = content
+        .get("url")
+        .and_then(|u| u.as_str())
+        .unwrap_or("unknown");
+
+    if strict_json {
+        let unknown = unknown_elements(content);
+        if !unknown.is_empty() {
+            anyhow::bail!(
+                "{resource_type} '{url}' has unknown elements: {}",
+                unknown.join(", ")
+            );
+        }
+    }
+
+    let problems = validate_resource(content);
+    if problems.is_empty() {
+        return Ok(());
+    }
+
+    let problems = problems.join("; ");
+
+    if reject {
+        anyhow::bail!("{resource_type} '{url}' looks malformed: {problems}");
+    }
+
+    warn!("{} '{}' looks malformed: {}", resource_type, url, problems);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn concept_map_systems_are_the_distinct_group_sources_and_targets() {
+        let concept_map = json!({
+            "resourceType": "ConceptMap",
+            "group": [
+                { "source": "http://a", "target": "http://b" },
+                { "source": "http://b", "target": "http://c" },
+                { "source": "http://a" },
+                { "element": [] }
+            ]
+        });
+        assert_eq!(
+            concept_map_systems(&concept_map),
+            ["http://a", "http://b", "http://c"]
+        );
+        assert!(concept_map_systems(&json!({ "resourceType": "ConceptMap" })).is_empty());
+    }
+
+    fn codes<'a>(concepts: &[&'a Value]) -> Vec<&'a str> {
+        concepts
+            .iter()
+            .map(|c| c["code"].as_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn nested_concepts_are_flattened_in_document_order() {
+        let concepts = json!([
+            { "code": "a", "concept": [
+                { "code": "a1", "concept": [{ "code": "a1x" }] },
+                { "code": "a2" }
+            ] },
+            { "code": "b" }
+        ]);
+        let flattened = flatten_concepts(concepts.as_array().unwrap(), 10).unwrap();
+        assert_eq!(codes(&flattened), ["a", "a1", "a1x", "a2", "b"]);
+    }
+
+    #[test]
+    fn a_code_nested_under_itself_is_rejected() {
+        let concepts = json!([
+            { "code": "a", "concept": [{ "code": "b", "concept": [{ "code": "a" }] }] }
+        ]);
+        let error = flatten_concepts(concepts.as_array().unwrap(), 10).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Concept 'a' is nested under itself: a > b > a"
+        );
+
+        // The same code in separate branches is not a cycle
+        let concepts = json!([
+            { "code": "a", "concept": [{ "code": "c" }] },
+            { "code": "b", "concept": [{ "code": "c" }] }
+        ]);
+        assert!(flatten_concepts(concepts.as_array().unwrap(), 10).is_ok());
+    }
+
+    #[test]
+    fn nesting_deeper_than_the_limit_is_rejected() {
+        let concepts = json!([{ "code": "a", "concept": [{ "code": "b", "concept": [
+            { "code": "c" }
+        ] }] }]);
+        assert!(flatten_concepts(concepts.as_array().unwrap(), 3).is_ok());
+        let error = flatten_concepts(concepts.as_array().unwrap(), 2).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Concept 'c' exceeds the maximum nesting depth of 2"
+        );
+    }
+
+    /// A pool on the scratch database named by `TEST_DATABASE_URL`, migrated
+    async fn test_pool() -> PgPool {
+        let url = std::env::var("TEST_DATABASE_URL")
+            .expect("TEST_DATABASE_URL must name a scratch database");
+        let pool = PgPool::connect(&url).await.unwrap();
+        sqlx::migrate!("../backend/migrations")
+            .run(&pool)
+            .await
+            .unwrap();
+        pool
+    }
+
+    #[tokio::test]
+    #[ignore = "needs PostgreSQL: set TEST_DATABASE_URL and run with --ignored"]
+    async fn concurrent_imports_of_a_resource_store_it_once() {
+        let pool = test_pool().await;
+        // Unversioned, so only the url/COALESCE(version, '') index tells the copies apart
+        let url = format!("http://example.org/CodeSystem/{}", Uuid::new_v4());
+        let resource = FhirResource {
+            resource_type: "CodeSystem".to_string(),
+            url: Some(url.clone()),
+            content: json!({
+                "resourceType": "CodeSystem",
+                "url": url,
+                "status": "active",
+                "content": "complete",
+                "concept": [{ "code": "a" }]
+            }),
+        };
+        let options = ImportOptions {
+            strict: false,
+            max_concept_depth: DEFAULT_MAX_CONCEPT_DEPTH,
+            reject_suspicious: false,
+            strict_json: false,
+            preview_concepts: 0,
+            property_limit: PropertyLimit::default(),
+            concept_limit: ConceptLimit::default(),
+            analyze: false,
+        };
+        let import = || async {
+            let mut tx = pool.begin().await?;
+            let imported = import_resource(&mut tx, &resource, options).await?;
+            tx.commit().await?;
+            anyhow::Ok(imported)
+        };
+
+        let (first, second) = tokio::join!(import(), import());
+        let mut imported = [first.unwrap(), second.unwrap()];
+        imported.sort();
+        assert_eq!(imported, [false, true]);
+
+        let stored: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM code_systems WHERE url = $1")
+            .bind(&url)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(stored, 1);
+    }
+}
@@ -1,8 +1,28 @@
 use anyhow::Result;
 use sqlx::PgPool;
-use tracing::info;
+use std::time::Instant;
+use tokio::sync::mpsc;
+use tracing::{info, warn};
 
-pub async fn run(pool: PgPool, version: String, dry_run: bool, yes: bool) -> Result<()> {
+use super::import::{
+    fetch_package, import_package, max_concepts_from_env, strict_json_from_env, ConceptLimit,
+    ImportOptions,
+};
+use crate::package::PackageDownloader;
+
+/// Packages fetched ahead of the importer when pipelining. With a depth of one,
+/// at most one download runs alongside one import transaction.
+const PIPELINE_DEPTH: usize = 1;
+
+pub async fn run(
+    pool: PgPool,
+    version: String,
+    registries: Vec<String>,
+    dry_run: bool,
+    yes: bool,
+    pipeline: bool,
+    stream: bool,
+) -> Result<()> {
     info!("Import defaults for version: {}", version);
 
     let packages = match version.to_lowercase().as_str() {
@@ -17,19 +37,118 @@ pub async fn run(pool: PgPool, version: String, dry_run: bool, yes: bool) -> Res
         _ => anyhow::bail!("Invalid version: {version}. Must be r4, r5, r6, or all"),
     };
 
-    for (package_name, package_version) in packages {
-        info!("Importing {} v{}", package_name, package_version);
-
-        super::import::run(
-            pool.clone(),
-            package_name.to_string(),
-            Some(package_version.to_string()),
-            "https://packages.fhir.org".to_string(),
-            dry_run,
-            yes,
-        )
-        .await?;
+    let options = ImportOptions {
+        strict_json: strict_json_from_env(),
+        concept_limit: ConceptLimit {
+            max: max_concepts_from_env()?,
+            warn_only: false,
+        },
+        ..Default::default()
+    };
+    let started = Instant::now();
+
+    let results = if pipeline {
+        run_pipelined(&pool, packages, registries, dry_run, yes, stream, options).await?
+    } else {
+        let downloader = PackageDownloader::new(registries);
+        let mut results = Vec::new();
+        for (package_name, package_version) in packages {
+            info!("Importing {} v{}", package_name, package_version);
+
+            let imported = async {
+                let fetched =
+                    fetch_package(&downloader, package_name, Some(package_version), stream).await?;
+                import_package(&pool, fetched, dry_run, yes, options).await
+            }
+            .await;
+            results.push((package_name, package_version, imported));
+        }
+        results
+    };
+
+    report(&results, started, pipeline)
+}
+
+/// Log the outcome of every package. An error is returned only when none imported,
+/// so one unreachable release does not undo or skip the others.
+fn report(
+    results: &[(&'static str, &'static str, Result<()>)],
+    started: Instant,
+    pipeline: bool,
+) -> Result<()> {
+    let failed = results.iter().filter(|(_, _, r)| r.is_err()).count();
+    let imported = results.len() - failed;
+
+    for (package_name, package_version, result) in results {
+        match result {
+            Ok(()) => info!("  Imported {} v{}", package_name, package_version),
+            Err(e) => warn!("  Failed {} v{}: {:#}", package_name, package_version, e),
+        }
+    }
+
+    info!(
+        "Imported {} of {} package(s) in {:.1?}{}",
+        imported,
+        results.len(),
+        started.elapsed(),
+        if pipeline { " (pipelined)" } else { "" }
+    );
+
+    if imported == 0 {
+        anyhow::bail!("All {failed} package import(s) failed");
+    }
+    if failed > 0 {
+        warn!("{} package import(s) failed; re-run to retry them", failed);
     }
 
     Ok(())
 }
+
+/// Download packages on a separate task while the current one is imported.
+/// The bounded channel caps how far downloads run ahead of the database.
+/// A failed download or import is recorded and the remaining packages still run.
+async fn run_pipelined(
+    pool: &PgPool,
+    packages: Vec<(&'static str, &'static str)>,
+    registries: Vec<String>,
+    dry_run: bool,
+    yes: bool,
+    stream: bool,
+    options: ImportOptions,
+) -> Result<Vec<(&'static str, &'static str, Result<()>)>> {
+    let (tx, mut rx) = mpsc::channel(PIPELINE_DEPTH);
+
+    let downloader = tokio::spawn(async move {
+        let downloader = PackageDownloader::new(registries);
+        for (package_name, package_version) in packages {
+            info!("Downloading {} v{}", package_name, package_version);
+
+            let fetched =
+                fetch_package(&downloader, package_name, Some(package_version), stream).await;
+            // A closed channel means the importer stopped; nothing left to do
+            if tx
+                .send((package_name, package_version, fetched))
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+    });
+
+    let mut results = Vec::new();
+    while let Some((package_name, package_version, fetched)) = rx.recv().await {
+        let imported = match fetched {
+            Ok(fetched) => {
+                info!("Importing {} v{}", package_name, package_version);
+                import_package(pool, fetched, dry_run, yes, options).await
+            }
+            Err(e) => Err(e),
+        };
+        results.push((package_name, package_version, imported));
+    }
+
+    downloader.await?;
+
+    Ok(results)
+}
@@ -1,44 +1,141 @@
 use anyhow::Result;
+use clap::ValueEnum;
+use serde_json::json;
 use sqlx::PgPool;
 
-pub async fn run(pool: PgPool) -> Result<()> {
-    println!("\n📚 Installed CodeSystems:");
+/// Resource types that can be listed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ResourceType {
+    CodeSystem,
+    ValueSet,
+    ConceptMap,
+}
 
-    let code_systems: Vec<(String, Option<String>, String)> =
-        sqlx::query_as("SELECT url, version, status FROM code_systems ORDER BY url, version")
-            .fetch_all(&pool)
-            .await?;
+impl ResourceType {
+    const ALL: [ResourceType; 3] = [Self::CodeSystem, Self::ValueSet, Self::ConceptMap];
 
-    for (url, version, status) in code_systems {
-        let version_str = version.as_deref().unwrap_or("(no version)");
-        println!("  - {url} [{version_str}] - {status}");
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::CodeSystem => "CodeSystem",
+            Self::ValueSet => "ValueSet",
+            Self::ConceptMap => "ConceptMap",
+        }
     }
 
-    println!("\n📚 Installed ValueSets:");
+    fn table(self) -> &'static str {
+        match self {
+            Self::CodeSystem => "code_systems",
+            Self::ValueSet => "value_sets",
+            Self::ConceptMap => "concept_maps",
+        }
+    }
+}
+
+/// How `list` prints its results
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// One page of installed resources of a single type
+struct Page {
+    resource_type: ResourceType,
+    total: i64,
+    rows: Vec<(String, Option<String>, String)>,
+}
+
+/// List installed resources, `limit` per type starting at `offset`, optionally only of one type
+pub async fn run(
+    pool: PgPool,
+    resource_type: Option<ResourceType>,
+    limit: i64,
+    offset: i64,
+    format: OutputFormat,
+) -> Result<()> {
+    let types = match resource_type {
+        Some(resource_type) => vec![resource_type],
+        None => ResourceType::ALL.to_vec(),
+    };
+
+    let mut pages = Vec::new();
+    for resource_type in types {
+        let table = resource_type.table();
 
-    let value_sets: Vec<(String, Option<String>, String)> =
-        sqlx::query_as("SELECT url, version, status FROM value_sets ORDER BY url, version")
-            .fetch_all(&pool)
+        let total: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {table}"))
+            .fetch_one(&pool)
             .await?;
 
-    for (url, version, status) in value_sets {
-        let version_str = version.as_deref().unwrap_or("(no version)");
-        println!("  - {url} [{version_str}] - {status}");
+        let rows: Vec<(String, Option<String>, String)> = sqlx::query_as(&format!(
+            "SELECT url, version, status FROM {table} ORDER BY url, version LIMIT $1 OFFSET $2"
+        ))
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&pool)
+        .await?;
+
+        pages.push(Page {
+            resource_type,
+            total,
+            rows,
+        });
     }
 
-    println!("\n📚 Installed ConceptMaps:");
+    match format {
+        OutputFormat::Text => print_text(&pages, offset),
+        OutputFormat::Json => print_json(&pages, offset)?,
+    }
 
-    let concept_maps: Vec<(String, Option<String>, String)> =
-        sqlx::query_as("SELECT url, version, status FROM concept_maps ORDER BY url, version")
-            .fetch_all(&pool)
-            .await?;
+    Ok(())
+}
 
-    for (url, version, status) in concept_maps {
-        let version_str = version.as_deref().unwrap_or("(no version)");
-        println!("  - {url} [{version_str}] - {status}");
+fn print_text(pages: &[Page], offset: i64) {
+    for page in pages {
+        println!("\n📚 Installed {}s:", page.resource_type.as_str());
+
+        for (url, version, status) in &page.rows {
+            let version_str = version.as_deref().unwrap_or("(no version)");
+            println!("  - {url} [{version_str}] - {status}");
+        }
+
+        if page.rows.is_empty() {
+            println!("  Showing 0 of {}", page.total);
+        } else {
+            println!(
+                "  Showing {}-{} of {}",
+                offset + 1,
+                offset + page.rows.len() as i64,
+                page.total
+            );
+        }
     }
 
     println!();
+}
+
+fn print_json(pages: &[Page], offset: i64) -> Result<()> {
+    let output: serde_json::Map<String, serde_json::Value> = pages
+        .iter()
+        .map(|page| {
+            let items: Vec<_> = page
+                .rows
+                .iter()
+                .map(|(url, version, status)| {
+                    json!({ "url": url, "version": version, "status": status })
+                })
+                .collect();
+            let listing = json!({
+                "total": page.total,
+                "offset": offset,
+                "count": items.len(),
+                "items": items
+            });
+            (page.resource_type.as_str().to_string(), listing)
+        })
+        .collect();
+
+    println!("{}", serde_json::to_string_pretty(&output)?);
 
     Ok(())
 }
@@ -0,0 +1,138 @@
+use anyhow::Result;
+use serde_json::Value;
+use sqlx::types::Json;
+use sqlx::PgPool;
+use std::collections::{BTreeMap, BTreeSet};
+use uuid::Uuid;
+
+use super::import::canonical_candidates;
+
+/// The mapped source codes of a ConceptMap's groups for one source/target pair
+#[derive(Default)]
+struct PairCodes<'a> {
+    source_version: Option<&'a str>,
+    codes: BTreeSet<&'a str>,
+}
+
+/// For each ConceptMap (or only those with `url`), print per source/target system pair how
+/// many concepts of the installed source CodeSystem have an element with at least one target
+pub async fn run(pool: PgPool, url: Option<String>) -> Result<()> {
+    let concept_maps: Vec<(String, Option<String>, Json<Value>)> = sqlx::query_as(
+        "SELECT url, version, content FROM concept_maps
+         WHERE $1::text[] IS NULL OR url = ANY($1)
+         ORDER BY url, version",
+    )
+    .bind(url.as_deref().map(canonical_candidates))
+    .fetch_all(&pool)
+    .await?;
+
+    println!("\n🗺️  ConceptMap Coverage:");
+    if concept_maps.is_empty() {
+        println!("  (no ConceptMaps found)");
+    }
+
+    for (url, version, Json(content)) in &concept_maps {
+        match version {
+            Some(version) => println!("  - {url}|{version}"),
+            None => println!("  - {url}"),
+        }
+
+        let pairs = mapped_codes(content);
+        if pairs.is_empty() {
+            println!("      (no groups with a source system)");
+        }
+        for ((source, target), pair) in pairs {
+            let (source, source_version) = match source.split_once('|') {
+                Some((source, version)) => (source, Some(version)),
+                None => (source, pair.source_version),
+            };
+            let Some(code_system_id) = resolve_code_system(&pool, source, source_version).await?
+            else {
+                println!("      {source} → {target}: source CodeSystem not installed");
+                continue;
+            };
+
+            let codes: Vec<&str> = pair.codes.into_iter().collect();
+            let (concepts, mapped): (i64, i64) = sqlx::query_as(
+                "SELECT COUNT(*), COUNT(*) FILTER (WHERE code = ANY($2))
+                 FROM concepts WHERE code_system_id = $1",
+            )
+            .bind(code_system_id)
+            .bind(&codes)
+            .fetch_one(&pool)
+            .await?;
+
+            let coverage = if concepts == 0 {
+                0.0
+            } else {
+                mapped as f64 * 100.0 / concepts as f64
+            };
+            println!(
+                "      {source} → {target}: {mapped} of {concepts} source concepts mapped \
+                 ({coverage:.1}%)"
+            );
+        }
+    }
+    println!();
+
+    Ok(())
+}
+
+/// The codes of elements with at least one target, by `(group.source, group.target)`
+fn mapped_codes(content: &Value) -> BTreeMap<(&str, &str), PairCodes<'_>> {
+    let mut pairs: BTreeMap<(&str, &str), PairCodes> = BTreeMap::new();
+
+    let groups = content
+        .get("group")
+        .and_then(|g| g.as_array())
+        .into_iter()
+        .flatten();
+    for group in groups {
+        let Some(source) = group.get("source").and_then(|s| s.as_str()) else {
+            continue;
+        };
+        let target = group
+            .get("target")
+            .and_then(|t| t.as_str())
+            .unwrap_or("(no target system)");
+
+        let pair = pairs.entry((source, target)).or_default();
+        pair.source_version = pair
+            .source_version
+            .or_else(|| group.get("sourceVersion").and_then(|v| v.as_str()));
+        let codes = group
+            .get("element")
+            .and_then(|e| e.as_array())
+            .into_iter()
+            .flatten()
+            .filter(|element| {
+                element
+                    .get("target")
+                    .and_then(|t| t.as_array())
+                    .is_some_and(|targets| !targets.is_empty())
+            })
+            .filter_map(|element| element.get("code")?.as_str());
+        pair.codes.extend(codes);
+    }
+
+    pairs
+}
+
+/// The installed CodeSystem `url`, in `version` or else its most recently updated version
+async fn resolve_code_system(
+    pool: &PgPool,
+    url: &str,
+    version: Option<&str>,
+) -> Result<Option<Uuid>> {
+    let id = sqlx::query_scalar(
+        "SELECT id FROM code_systems
+         WHERE url = ANY($1) AND ($2::text IS NULL OR version = $2)
+         ORDER BY updated_at DESC LIMIT 1",
+    )
+    .bind(canonical_candidates(url))
+    .bind(version)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(id)
+}
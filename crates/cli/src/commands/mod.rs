@@ -1,5 +1,11 @@
 pub mod create;
+pub mod dedupe;
+pub mod expand;
+pub mod gc;
+pub mod history;
 pub mod import;
 pub mod import_defaults;
 pub mod list;
+pub mod map_coverage;
+pub mod self_test;
 pub mod stats;
@@ -0,0 +1,226 @@
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use super::import::{import_resource, ImportOptions};
+use crate::package::FhirResource;
+
+/// Tables the server and CLI expect the migrations to have created
+const REQUIRED_TABLES: [&str; 8] = [
+    "code_systems",
+    "value_sets",
+    "concept_maps",
+    "concepts",
+    "closure_table",
+    "value_set_expansions",
+    "import_history",
+    "code_system_supplements",
+];
+
+/// Check the schema, then import a throwaway CodeSystem, ValueSet and ConceptMap and run
+/// the queries behind $lookup, $validate-code, $translate and $subsumes against them.
+/// Everything happens in a transaction that is rolled back.
+pub async fn run(pool: PgPool) -> Result<()> {
+    let mut report = Vec::new();
+
+    for table in REQUIRED_TABLES {
+        let exists: bool = sqlx::query_scalar("SELECT to_regclass($1) IS NOT NULL")
+            .bind(table)
+            .fetch_one(&pool)
+            .await?;
+        let result = if exists {
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!("table is missing, run the migrations"))
+        };
+        report.push((format!("table {table}"), result));
+    }
+
+    // Only exercise the operations against a complete schema
+    if report.iter().all(|(_, result)| result.is_ok()) {
+        let system = format!("urn:uuid:{}", Uuid::new_v4());
+        let mut tx = pool.begin().await?;
+        report.push((
+            "import".to_string(),
+            import_fixtures(&mut tx, &system).await,
+        ));
+        report.push(("$lookup".to_string(), check_lookup(&mut tx, &system).await));
+        report.push((
+            "$validate-code".to_string(),
+            check_validate_code(&mut tx, &system).await,
+        ));
+        report.push((
+            "$translate".to_string(),
+            check_translate(&mut tx, &system).await,
+        ));
+        report.push((
+            "$subsumes".to_string(),
+            check_subsumes(&mut tx, &system).await,
+        ));
+        tx.rollback().await?;
+    }
+
+    println!("\n🩺 Self-test:");
+
+    let mut failures = 0;
+    for (check, result) in &report {
+        match result {
+            Ok(()) => println!("  ✅ {check}"),
+            Err(e) => {
+                failures += 1;
+                println!("  ❌ {check}: {e:#}");
+            }
+        }
+    }
+
+    println!();
+
+    if failures > 0 {
+        anyhow::bail!("{failures} self-test check(s) failed");
+    }
+
+    Ok(())
+}
+
+/// Import the fixtures through the regular import path, which also derives the closure
+async fn import_fixtures(tx: &mut Transaction<'_, Postgres>, system: &str) -> Result<()> {
+    let resources = [
+        fixture(
+            "CodeSystem",
+            json!({
+                "resourceType": "CodeSystem",
+                "url": system,
+                "status": "active",
+                "content": "complete",
+                "concept": [{
+                    "code": "parent",
+                    "display": "Parent",
+                    "concept": [{ "code": "child", "display": "Child" }]
+                }]
+            }),
+        ),
+        fixture(
+            "ValueSet",
+            json!({
+                "resourceType": "ValueSet",
+                "url": format!("{system}/vs"),
+                "status": "active",
+                "expansion": {
+                    "timestamp": "2026-01-01T00:00:00Z",
+                    "contains": [{ "system": system, "code": "child", "display": "Child" }]
+                }
+            }),
+        ),
+        fixture(
+            "ConceptMap",
+            json!({
+                "resourceType": "ConceptMap",
+                "url": format!("{system}/cm"),
+                "status": "active",
+                "group": [{
+                    "source": system,
+                    "target": system,
+                    "element": [{
+                        "code": "child",
+                        "target": [{ "code": "parent", "equivalence": "wider" }]
+                    }]
+                }]
+            }),
+        ),
+    ];
+
+    for resource in &resources {
+        let imported = import_resource(tx, resource, ImportOptions::default()).await?;
+        anyhow::ensure!(imported, "{} was not imported", resource.resource_type);
+    }
+
+    Ok(())
+}
+
+fn fixture(resource_type: &str, content: Value) -> FhirResource {
+    FhirResource {
+        resource_type: resource_type.to_string(),
+        url: content
+            .get("url")
+            .and_then(|u| u.as_str())
+            .map(str::to_string),
+        content,
+    }
+}
+
+async fn check_lookup(tx: &mut Transaction<'_, Postgres>, system: &str) -> Result<()> {
+    let display: Option<String> = sqlx::query_scalar(
+        "SELECT c.display FROM concepts c
+         JOIN code_systems cs ON cs.id = c.code_system_id
+         WHERE cs.url = $1 AND c.code = 'child'",
+    )
+    .bind(system)
+    .fetch_optional(&mut **tx)
+    .await?
+    .flatten();
+
+    anyhow::ensure!(
+        display.as_deref() == Some("Child"),
+        "expected display 'Child', got {display:?}"
+    );
+    Ok(())
+}
+
+async fn check_validate_code(tx: &mut Transaction<'_, Postgres>, system: &str) -> Result<()> {
+    let contains: Option<sqlx::types::Json<Value>> = sqlx::query_scalar(
+        "SELECT e.expansion_data -> 'contains' FROM value_set_expansions e
+         JOIN value_sets vs ON vs.id = e.value_set_id
+         WHERE vs.url = $1 ORDER BY e.created_at DESC LIMIT 1",
+    )
+    .bind(format!("{system}/vs"))
+    .fetch_optional(&mut **tx)
+    .await?;
+
+    let contains = contains.context("ValueSet expansion was not stored")?;
+    let found = contains.as_array().is_some_and(|entries| {
+        entries.iter().any(|e| {
+            e.get("system").and_then(|s| s.as_str()) == Some(system)
+                && e.get("code").and_then(|c| c.as_str()) == Some("child")
+        })
+    });
+
+    anyhow::ensure!(found, "code 'child' missing from the stored expansion");
+    Ok(())
+}
+
+async fn check_translate(tx: &mut Transaction<'_, Postgres>, system: &str) -> Result<()> {
+    let targets: Vec<String> = sqlx::query_scalar(
+        "SELECT t ->> 'code' FROM concept_maps,
+                jsonb_array_elements(content -> 'group') g,
+                jsonb_array_elements(g -> 'element') e,
+                jsonb_array_elements(e -> 'target') t
+         WHERE url = $1 AND g ->> 'source' = $2 AND e ->> 'code' = 'child'",
+    )
+    .bind(format!("{system}/cm"))
+    .bind(system)
+    .fetch_all(&mut **tx)
+    .await?;
+
+    anyhow::ensure!(
+        targets == ["parent"],
+        "expected translation to 'parent', got {targets:?}"
+    );
+    Ok(())
+}
+
+async fn check_subsumes(tx: &mut Transaction<'_, Postgres>, system: &str) -> Result<()> {
+    let subsumes: bool = sqlx::query_scalar(
+        "SELECT EXISTS (
+             SELECT 1 FROM closure_table ct
+             JOIN code_systems cs ON cs.id = ct.code_system_id
+             WHERE cs.url = $1 AND ct.ancestor_code = 'parent' AND ct.descendant_code = 'child'
+         )",
+    )
+    .bind(system)
+    .fetch_one(&mut **tx)
+    .await?;
+
+    anyhow::ensure!(subsumes, "'parent' does not subsume 'child'");
+    Ok(())
+}
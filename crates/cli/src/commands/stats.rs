@@ -14,6 +14,11 @@ pub async fn run(pool: PgPool) -> Result<()> {
         .fetch_one(&pool)
         .await?;
 
+    let mapping_elements: i64 =
+        sqlx::query_scalar("SELECT COALESCE(SUM(element_count), 0) FROM concept_maps")
+            .fetch_one(&pool)
+            .await?;
+
     let concepts: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM concepts")
         .fetch_one(&pool)
         .await?;
@@ -21,8 +26,30 @@ pub async fn run(pool: PgPool) -> Result<()> {
     println!("\n📊 Terminology Server Statistics:");
     println!("  CodeSystems: {code_systems}");
     println!("  ValueSets: {value_sets}");
-    println!("  ConceptMaps: {concept_maps}");
+    println!("  ConceptMaps: {concept_maps} ({mapping_elements} mapping elements)");
     println!("  Concepts: {concepts}");
+
+    // Closure rows back $subsumes; systems without any always answer not-subsumed
+    let coverage: Vec<(String, Option<String>, i64)> = sqlx::query_as(
+        "SELECT cs.url, cs.version, COUNT(ct.id)
+         FROM code_systems cs
+         LEFT JOIN closure_table ct ON ct.code_system_id = cs.id
+         GROUP BY cs.id, cs.url, cs.version
+         ORDER BY cs.url, cs.version",
+    )
+    .fetch_all(&pool)
+    .await?;
+
+    let without_closure = coverage.iter().filter(|(_, _, rows)| *rows == 0).count();
+
+    println!("\n🌳 Closure Coverage:");
+    println!("  CodeSystems without closure: {without_closure}");
+    for (url, version, rows) in coverage.iter().filter(|(_, _, rows)| *rows > 0) {
+        match version {
+            Some(version) => println!("  - {url}|{version}: {rows} closure rows"),
+            None => println!("  - {url}: {rows} closure rows"),
+        }
+    }
     println!();
 
     Ok(())
@@ -1,3 +1,4 @@
+mod closure;
 mod commands;
 mod package;
 
@@ -25,7 +26,7 @@ struct Cli {
 enum Commands {
     /// Import a FHIR package from a registry or local file
     Import {
-        /// Package name (e.g., hl7.fhir.r4.core) or path to local .tgz file
+        /// Package name (e.g., hl7.fhir.r4.core) or path to a local .tgz, .tar or .zip file
         package: String,
 
         /// Package version (e.g., 4.0.1). Not required for local files.
@@ -36,9 +37,56 @@ enum Commands {
         #[arg(long)]
         dry_run: bool,
 
+        /// With --dry-run, print the first N concepts of each CodeSystem
+        #[arg(long, value_name = "N", default_value_t = 0, requires = "dry_run")]
+        preview_concepts: usize,
+
         /// Skip confirmation prompt
         #[arg(short = 'y', long)]
         yes: bool,
+
+        /// Fail when a ConceptMap references CodeSystems that are not installed, a package
+        /// file is not valid UTF-8, or package.json lacks a name or version (otherwise these
+        /// are warned about and skipped or worked around)
+        #[arg(long)]
+        strict: bool,
+
+        /// Maximum nesting depth accepted for nested CodeSystem concepts
+        #[arg(long, default_value_t = commands::import::DEFAULT_MAX_CONCEPT_DEPTH)]
+        max_concept_depth: usize,
+
+        /// Warn about concepts whose properties exceed this many bytes (as JSON)
+        #[arg(long, default_value_t = commands::import::DEFAULT_MAX_PROPERTY_BYTES)]
+        max_property_bytes: usize,
+
+        /// Drop non-essential properties of concepts over --max-property-bytes
+        #[arg(long)]
+        truncate_properties: bool,
+
+        /// Reject resources that look malformed (e.g. complete content without concepts)
+        #[arg(long)]
+        reject_suspicious: bool,
+
+        /// Reject resources with unknown top-level elements (also enabled by STRICT_JSON=true)
+        #[arg(long)]
+        strict_json: bool,
+
+        /// Reject CodeSystems with more concepts than this (default:
+        /// MAX_CONCEPTS_PER_CODESYSTEM, else no limit)
+        #[arg(long, value_name = "N")]
+        max_concepts: Option<usize>,
+
+        /// Only warn about CodeSystems over --max-concepts
+        #[arg(long)]
+        warn_max_concepts: bool,
+
+        /// Parse the package as it downloads instead of writing it to a temporary file
+        #[arg(long)]
+        stream: bool,
+
+        /// Skip the ANALYZE of the terminology tables after importing a large package
+        #[arg(long)]
+        no_analyze: bool,
     },
 
     /// Import default FHIR packages (R4, R5, R6 core definitions)
@@ -54,31 +102,149 @@ enum Commands {
         /// Skip confirmation prompt
         #[arg(short = 'y', long)]
         yes: bool,
+
+        /// Download the next package while the current one is imported
+        #[arg(long)]
+        pipeline: bool,
+
+        /// Parse the package as it downloads instead of writing it to a temporary file
+        #[arg(long)]
+        stream: bool,
     },
 
     /// Create a CodeSystem from a FHIR JSON file
     CreateCodeSystem {
         /// Path to FHIR CodeSystem JSON file
         file: String,
+
+        /// Maximum nesting depth accepted for nested concepts
+        #[arg(long, default_value_t = commands::import::DEFAULT_MAX_CONCEPT_DEPTH)]
+        max_concept_depth: usize,
+
+        /// Reject resources that look malformed (e.g. complete content without concepts)
+        #[arg(long)]
+        reject_suspicious: bool,
+
+        /// Reject resources with unknown top-level elements (also enabled by STRICT_JSON=true)
+        #[arg(long)]
+        strict_json: bool,
+
+        /// Validate the file and report what would be created without writing anything
+        #[arg(long)]
+        dry_run: bool,
     },
 
     /// Create a ValueSet from a FHIR JSON file
     CreateValueSet {
         /// Path to FHIR ValueSet JSON file
         file: String,
+
+        /// Reject resources that look malformed (e.g. complete content without concepts)
+        #[arg(long)]
+        reject_suspicious: bool,
+
+        /// Reject resources with unknown top-level elements (also enabled by STRICT_JSON=true)
+        #[arg(long)]
+        strict_json: bool,
+
+        /// Validate the file and report what would be created without writing anything
+        #[arg(long)]
+        dry_run: bool,
     },
 
     /// Create a ConceptMap from a FHIR JSON file
     CreateConceptMap {
         /// Path to FHIR ConceptMap JSON file
         file: String,
+
+        /// Fail when the ConceptMap references CodeSystems that are not installed
+        #[arg(long)]
+        strict: bool,
+
+        /// Reject resources that look malformed (e.g. complete content without concepts)
+        #[arg(long)]
+        reject_suspicious: bool,
+
+        /// Reject resources with unknown top-level elements (also enabled by STRICT_JSON=true)
+        #[arg(long)]
+        strict_json: bool,
+
+        /// Validate the file and report what would be created without writing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Expand a ValueSet from the installed resources and write the expanded ValueSet
+    Expand {
+        /// Canonical URL of the ValueSet, optionally with a piped version (`url|version`)
+        url: String,
+
+        /// Only keep concepts matching every whitespace-separated term
+        #[arg(long)]
+        filter: Option<String>,
+
+        /// Write the expanded ValueSet JSON to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<String>,
     },
 
     /// List installed packages
-    List,
+    List {
+        /// Only list resources of this type
+        #[arg(short = 't', long = "type", value_enum)]
+        resource_type: Option<commands::list::ResourceType>,
+
+        /// Maximum number of resources listed per type
+        #[arg(
+            short = 'n',
+            long,
+            default_value_t = 100,
+            value_parser = clap::value_parser!(i64).range(0..)
+        )]
+        limit: i64,
+
+        /// Number of resources skipped per type
+        #[arg(long, default_value_t = 0, value_parser = clap::value_parser!(i64).range(0..))]
+        offset: i64,
+
+        /// Output format
+        #[arg(short, long, value_enum, default_value_t = commands::list::OutputFormat::Text)]
+        format: commands::list::OutputFormat,
+    },
 
     /// Show package statistics
     Stats,
+
+    /// Show how many source concepts each ConceptMap maps, per source and target system
+    MapCoverage {
+        /// Only report the ConceptMap with this canonical URL
+        #[arg(long)]
+        url: Option<String>,
+    },
+
+    /// Check the schema and exercise the terminology operations against throwaway data
+    SelfTest,
+
+    /// Show recent package imports
+    History {
+        /// Number of imports to show
+        #[arg(short = 'n', long, default_value_t = 20)]
+        limit: i64,
+    },
+
+    /// Delete concepts, closure rows and expansions whose CodeSystem or ValueSet is gone
+    Gc {
+        /// Only count the orphaned rows
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Delete duplicate unversioned resources, keeping the oldest row of each url
+    Dedupe {
+        /// Only list the duplicates
+        #[arg(long)]
+        dry_run: bool,
+    },
 }
 
 #[tokio::main]
@@ -113,34 +279,137 @@ async fn main() -> anyhow::Result<()> {
             package,
             version,
             dry_run,
+            preview_concepts,
             yes,
+            strict,
+            max_concept_depth,
+            max_property_bytes,
+            truncate_properties,
+            reject_suspicious,
+            strict_json,
+            max_concepts,
+            warn_max_concepts,
+            stream,
+            no_analyze,
         } => {
-            // Use default FHIR package registry
-            let registry = "https://packages.fhir.org".to_string();
-            commands::import::run(pool, package, version, registry, dry_run, yes).await?;
+            let registries = package::registries_from_env();
+            let max_concepts = match max_concepts {
+                Some(max) => Some(max),
+                None => commands::import::max_concepts_from_env()?,
+            };
+            let options = commands::import::ImportOptions {
+                strict,
+                max_concept_depth,
+                reject_suspicious,
+                strict_json: strict_json || commands::import::strict_json_from_env(),
+                preview_concepts,
+                property_limit: commands::import::PropertyLimit {
+                    max_bytes: max_property_bytes,
+                    truncate: truncate_properties,
+                },
+                concept_limit: commands::import::ConceptLimit {
+                    max: max_concepts,
+                    warn_only: warn_max_concepts,
+                },
+                analyze: !no_analyze,
+            };
+            commands::import::run(
+                pool, package, version, registries, dry_run, yes, stream, options,
+            )
+            .await?;
         }
         Commands::ImportDefaults {
             version,
             dry_run,
             yes,
+            pipeline,
+            stream,
         } => {
-            commands::import_defaults::run(pool, version, dry_run, yes).await?;
+            let registries = package::registries_from_env();
+            commands::import_defaults::run(
+                pool, version, registries, dry_run, yes, pipeline, stream,
+            )
+            .await?;
         }
-        Commands::CreateCodeSystem { file } => {
-            commands::create::create_code_system(pool, file).await?;
+        Commands::CreateCodeSystem {
+            file,
+            max_concept_depth,
+            reject_suspicious,
+            strict_json,
+            dry_run,
+        } => {
+            let strict_json = strict_json || commands::import::strict_json_from_env();
+            commands::create::create_code_system(
+                pool,
+                file,
+                max_concept_depth,
+                reject_suspicious,
+                strict_json,
+                dry_run,
+            )
+            .await?;
         }
-        Commands::CreateValueSet { file } => {
-            commands::create::create_value_set(pool, file).await?;
+        Commands::CreateValueSet {
+            file,
+            reject_suspicious,
+            strict_json,
+            dry_run,
+        } => {
+            let strict_json = strict_json || commands::import::strict_json_from_env();
+            commands::create::create_value_set(pool, file, reject_suspicious, strict_json, dry_run)
+                .await?;
         }
-        Commands::CreateConceptMap { file } => {
-            commands::create::create_concept_map(pool, file).await?;
+        Commands::CreateConceptMap {
+            file,
+            strict,
+            reject_suspicious,
+            strict_json,
+            dry_run,
+        } => {
+            let strict_json = strict_json || commands::import::strict_json_from_env();
+            commands::create::create_concept_map(
+                pool,
+                file,
+                strict,
+                reject_suspicious,
+                strict_json,
+                dry_run,
+            )
+            .await?;
         }
-        Commands::List => {
-            commands::list::run(pool).await?;
+        Commands::Expand {
+            url,
+            filter,
+            output,
+        } => {
+            commands::expand::run(pool, url, filter, output).await?;
+        }
+        Commands::List {
+            resource_type,
+            limit,
+            offset,
+            format,
+        } => {
+            commands::list::run(pool, resource_type, limit, offset, format).await?;
         }
         Commands::Stats => {
             commands::stats::run(pool).await?;
         }
+        Commands::MapCoverage { url } => {
+            commands::map_coverage::run(pool, url).await?;
+        }
+        Commands::SelfTest => {
+            commands::self_test::run(pool).await?;
+        }
+        Commands::History { limit } => {
+            commands::history::run(pool, limit).await?;
+        }
+        Commands::Gc { dry_run } => {
+            commands::gc::run(pool, dry_run).await?;
+        }
+        Commands::Dedupe { dry_run } => {
+            commands::dedupe::run(pool, dry_run).await?;
+        }
     }
 
     Ok(())
@@ -1,16 +1,43 @@
 use anyhow::{Context, Result};
 use flate2::read::GzDecoder;
+use futures_util::StreamExt;
 use indicatif::{ProgressBar, ProgressStyle};
 use serde_json::Value;
 use std::fs::File;
-use std::io::Read;
+use std::future::Future;
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
 use tar::Archive;
-use tracing::{debug, info};
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+use zip::ZipArchive;
+
+/// Registry used when `FHIR_REGISTRIES` is not set
+pub const DEFAULT_REGISTRY: &str = "https://packages.fhir.org";
+
+/// Package registries to download from, in the order they are tried. Read from the
+/// comma-separated `FHIR_REGISTRIES` environment variable, defaulting to the public registry.
+pub fn registries_from_env() -> Vec<String> {
+    let registries: Vec<String> = std::env::var("FHIR_REGISTRIES")
+        .unwrap_or_default()
+        .split(',')
+        .map(|r| r.trim().trim_end_matches('/').to_string())
+        .filter(|r| !r.is_empty())
+        .collect();
+
+    if registries.is_empty() {
+        vec![DEFAULT_REGISTRY.to_string()]
+    } else {
+        registries
+    }
+}
 
 pub struct PackageDownloader {
     client: reqwest::Client,
-    registry_url: String,
+    registries: Vec<String>,
+    /// Reject a package file that is not valid UTF-8 instead of skipping it, and a
+    /// package.json without a name or version instead of falling back
+    strict: bool,
 }
 
 pub struct FhirPackage {
@@ -26,16 +53,87 @@ pub struct FhirResource {
 }
 
 impl PackageDownloader {
-    pub fn new(registry_url: String) -> Self {
+    pub fn new(registries: Vec<String>) -> Self {
         Self {
             client: reqwest::Client::new(),
-            registry_url,
+            registries,
+            strict: false,
         }
     }
 
-    /// Download a package from the FHIR registry
-    pub async fn download(&self, package_name: &str, version: &str) -> Result<PathBuf> {
-        let url = format!("{}/{}/{}", self.registry_url, package_name, version);
+    /// Fail extraction on a package file that is not valid UTF-8, naming the file, or on
+    /// missing package.json metadata, rather than warning and working around them
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Download a package, trying each registry in order until one serves it.
+    /// Returns the downloaded file and the registry it came from.
+    pub async fn download(&self, package_name: &str, version: &str) -> Result<(PathBuf, &str)> {
+        self.first_serving(package_name, version, |registry| {
+            self.download_from(registry, package_name, version)
+        })
+        .await
+    }
+
+    /// Download and parse a package without writing it to disk: the archive is decoded
+    /// as it arrives. Registries are tried in order as with [`PackageDownloader::download`].
+    pub async fn download_streamed(
+        &self,
+        package_name: &str,
+        version: &str,
+    ) -> Result<(FhirPackage, &str)> {
+        self.first_serving(package_name, version, |registry| {
+            self.stream_from(registry, package_name, version)
+        })
+        .await
+    }
+
+    /// The first successful `fetch` from the registries, in order, with the registry
+    async fn first_serving<'a, T, F>(
+        &'a self,
+        package_name: &str,
+        version: &str,
+        fetch: impl Fn(&'a str) -> F,
+    ) -> Result<(T, &'a str)>
+    where
+        F: Future<Output = Result<T>>,
+    {
+        let mut last_error = None;
+
+        for registry in &self.registries {
+            match fetch(registry).await {
+                Ok(fetched) => {
+                    info!(
+                        "Package {}#{} served by {}",
+                        package_name, version, registry
+                    );
+                    return Ok((fetched, registry));
+                }
+                Err(e) => {
+                    warn!("Registry {} failed: {:#}", registry, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        Err(last_error
+            .unwrap_or_else(|| anyhow::anyhow!("No package registries configured"))
+            .context(format!(
+                "Package {package_name}#{version} is not available from any registry"
+            )))
+    }
+
+    /// Request a package from a single FHIR registry, with a progress bar sized by the
+    /// response's content length
+    async fn request(
+        &self,
+        registry: &str,
+        package_name: &str,
+        version: &str,
+    ) -> Result<(reqwest::Response, ProgressBar)> {
+        let url = format!("{registry}/{package_name}/{version}");
         info!("Downloading package from: {}", url);
 
         let response = self
@@ -59,6 +157,18 @@ impl PackageDownloader {
                 .progress_chars("#>-"),
         );
 
+        Ok((response, pb))
+    }
+
+    /// Download a package from a single FHIR registry
+    async fn download_from(
+        &self,
+        registry: &str,
+        package_name: &str,
+        version: &str,
+    ) -> Result<PathBuf> {
+        let (response, pb) = self.request(registry, package_name, version).await?;
+
         // Create temp file
         let temp_dir = std::env::temp_dir();
         let file_path = temp_dir.join(format!("{package_name}-{version}.tgz"));
@@ -67,7 +177,6 @@ impl PackageDownloader {
         // Download with progress
         let mut downloaded = 0u64;
         let mut stream = response.bytes_stream();
-        use futures_util::StreamExt;
 
         while let Some(chunk) = stream.next().await {
             let chunk = chunk?;
@@ -82,16 +191,65 @@ impl PackageDownloader {
         Ok(file_path)
     }
 
-    /// Extract and parse a package file
+    /// Download a package from a single FHIR registry, handing the body to a blocking
+    /// parser as it arrives. The download's progress bar is the only one shown.
+    async fn stream_from(
+        &self,
+        registry: &str,
+        package_name: &str,
+        version: &str,
+    ) -> Result<FhirPackage> {
+        let (response, pb) = self.request(registry, package_name, version).await?;
+
+        let (sender, chunks) = mpsc::channel(STREAM_CHUNKS_AHEAD);
+        let strict = self.strict;
+        let requested = PackageId {
+            name: Some(package_name.to_string()),
+            version: Some(version.to_string()),
+            source: "the requested package",
+        };
+        let parser = tokio::task::spawn_blocking(move || {
+            let reader = ChunkReader {
+                chunks,
+                current: Cursor::new(Vec::new()),
+            };
+            read_stream(reader, strict, requested)
+        });
+
+        let mut downloaded = 0u64;
+        let mut stream = response.bytes_stream();
+
+        while let Some(chunk) = stream.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => {
+                    downloaded += chunk.len() as u64;
+                    pb.set_position(downloaded);
+                    Ok(chunk.to_vec())
+                }
+                Err(e) => Err(io::Error::other(e)),
+            };
+            let failed = chunk.is_err();
+            // A closed channel means the parser stopped early; its error is returned below
+            if sender.send(chunk).await.is_err() || failed {
+                break;
+            }
+        }
+        drop(sender);
+
+        let package = parser.await??;
+        pb.finish_with_message("Downloaded");
+        Ok(package)
+    }
+
+    /// Extract and parse a package file. The container (gzip-compressed tar, plain tar
+    /// or zip) is detected from the file's magic bytes.
     pub fn extract_package(&self, package_path: &Path) -> Result<FhirPackage> {
         info!("Extracting package: {:?}", package_path);
 
-        let file = File::open(package_path)?;
-        let decoder = GzDecoder::new(file);
-        let mut archive = Archive::new(decoder);
-
-        let mut package_json: Option<Value> = None;
-        let mut resources = Vec::new();
+        let mut file = File::open(package_path)?;
+        let mut magic = [0u8; 4];
+        let read = file.read(&mut magic)?;
+        file.seek(SeekFrom::Start(0))?;
 
         let pb = ProgressBar::new_spinner();
         pb.set_style(
@@ -101,71 +259,353 @@ impl PackageDownloader {
         );
         pb.set_message("Parsing package...");
 
+        let mut contents = PackageContents::new(pb, self.strict);
+
+        match PackageFormat::detect(&magic[..read]) {
+            PackageFormat::TarGz => contents.read_tar(Archive::new(GzDecoder::new(file)))?,
+            PackageFormat::Tar => contents.read_tar(Archive::new(file))?,
+            PackageFormat::Zip => contents.read_zip(ZipArchive::new(file)?)?,
+        }
+
+        contents.finish(PackageId::from_file_name(package_path))
+    }
+}
+
+/// Parse a package archive read front to back. Gzip-compressed and plain tar are
+/// supported; zip needs to seek to its central directory and is rejected. `requested`
+/// names the package when its package.json does not.
+fn read_stream(mut reader: impl Read, strict: bool, requested: PackageId) -> Result<FhirPackage> {
+    let mut magic = [0u8; 4];
+    let mut read = 0;
+    while read < magic.len() {
+        match reader.read(&mut magic[read..])? {
+            0 => break,
+            n => read += n,
+        }
+    }
+    let reader = Cursor::new(magic[..read].to_vec()).chain(reader);
+
+    let mut contents = PackageContents::new(ProgressBar::hidden(), strict);
+
+    match PackageFormat::detect(&magic[..read]) {
+        PackageFormat::TarGz => contents.read_tar(Archive::new(GzDecoder::new(reader)))?,
+        PackageFormat::Tar => contents.read_tar(Archive::new(reader))?,
+        PackageFormat::Zip => anyhow::bail!("Zip packages cannot be streamed; download them"),
+    }
+
+    contents.finish(requested)
+}
+
+/// Download chunks sent ahead of the blocking parser
+const STREAM_CHUNKS_AHEAD: usize = 16;
+
+/// A blocking reader over download chunks sent from the async side
+struct ChunkReader {
+    chunks: mpsc::Receiver<io::Result<Vec<u8>>>,
+    current: Cursor<Vec<u8>>,
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = self.current.read(buf)?;
+            if read > 0 || buf.is_empty() {
+                return Ok(read);
+            }
+            match self.chunks.blocking_recv() {
+                Some(Ok(chunk)) => self.current = Cursor::new(chunk),
+                Some(Err(e)) => return Err(e),
+                None => return Ok(0),
+            }
+        }
+    }
+}
+
+/// Container formats a FHIR package can be distributed in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PackageFormat {
+    TarGz,
+    Tar,
+    Zip,
+}
+
+impl PackageFormat {
+    fn detect(magic: &[u8]) -> Self {
+        if magic.starts_with(&[0x1f, 0x8b]) {
+            PackageFormat::TarGz
+        } else if magic.starts_with(b"PK\x03\x04") {
+            PackageFormat::Zip
+        } else {
+            PackageFormat::Tar
+        }
+    }
+}
+
+/// Accumulates package metadata and terminology resources while walking an archive
+struct PackageContents {
+    package_json: Option<Value>,
+    /// The `packageId` and `version` of an ImplementationGuide resource in the package
+    implementation_guide: PackageId,
+    resources: Vec<FhirResource>,
+    pb: ProgressBar,
+    strict: bool,
+}
+
+/// The UTF-8 byte order mark some editors write at the start of a file
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+impl PackageContents {
+    fn new(pb: ProgressBar, strict: bool) -> Self {
+        Self {
+            package_json: None,
+            implementation_guide: PackageId::default(),
+            resources: Vec::new(),
+            pb,
+            strict,
+        }
+    }
+
+    fn read_tar<R: Read>(&mut self, mut archive: Archive<R>) -> Result<()> {
         for entry in archive.entries()? {
             let mut entry = entry?;
             let path = entry.path()?.to_path_buf();
             let file_name = path.file_name().and_then(|s| s.to_str()).unwrap_or("");
 
-            debug!("Processing file: {}", file_name);
-
-            // Read package.json for metadata
-            if file_name == "package.json" {
-                let mut contents = String::new();
-                entry.read_to_string(&mut contents)?;
-                package_json = Some(serde_json::from_str(&contents)?);
+            if !file_name.ends_with(".json") {
                 continue;
             }
 
-            // Only process JSON files in the package directory
-            if !file_name.ends_with(".json") || file_name == "package.json" {
+            let mut contents = Vec::new();
+            entry.read_to_end(&mut contents)?;
+            self.add_bytes(file_name, &contents)?;
+        }
+
+        Ok(())
+    }
+
+    fn read_zip<R: Read + Seek>(&mut self, mut archive: ZipArchive<R>) -> Result<()> {
+        for index in 0..archive.len() {
+            let mut entry = archive.by_index(index)?;
+            let file_name = Path::new(entry.name())
+                .file_name()
+                .and_then(|s| s.to_str())
+                .unwrap_or("")
+                .to_string();
+
+            if !entry.is_file() || !file_name.ends_with(".json") {
                 continue;
             }
 
-            // Parse FHIR resource
-            let mut contents = String::new();
-            entry.read_to_string(&mut contents)?;
-
-            if let Ok(resource_json) = serde_json::from_str::<Value>(&contents) {
-                if let Some(resource_type) =
-                    resource_json.get("resourceType").and_then(|v| v.as_str())
-                {
-                    // Only process terminology resources
-                    if matches!(resource_type, "CodeSystem" | "ValueSet" | "ConceptMap") {
-                        let resource = FhirResource {
-                            resource_type: resource_type.to_string(),
-                            url: resource_json
-                                .get("url")
-                                .and_then(|v| v.as_str())
-                                .map(String::from),
-                            content: resource_json,
-                        };
-                        resources.push(resource);
-                        pb.set_message(format!("Found {} resources...", resources.len()));
-                    }
+            let mut contents = Vec::new();
+            entry.read_to_end(&mut contents)?;
+            self.add_bytes(&file_name, &contents)?;
+        }
+
+        Ok(())
+    }
+
+    /// Decode a file as UTF-8, dropping a leading byte order mark. A file that is not
+    /// UTF-8 is skipped with a warning, or rejected when `strict`.
+    fn add_bytes(&mut self, file_name: &str, contents: &[u8]) -> Result<()> {
+        let contents = contents.strip_prefix(UTF8_BOM).unwrap_or(contents);
+        match std::str::from_utf8(contents) {
+            Ok(contents) => self.add_file(file_name, contents),
+            Err(e) if self.strict => {
+                anyhow::bail!("Package file {file_name} is not valid UTF-8: {e}")
+            }
+            Err(e) => {
+                warn!(
+                    "Skipping package file {}: not valid UTF-8 ({})",
+                    file_name, e
+                );
+                Ok(())
+            }
+        }
+    }
+
+    fn add_file(&mut self, file_name: &str, contents: &str) -> Result<()> {
+        debug!("Processing file: {}", file_name);
+
+        // Read package.json for metadata
+        if file_name == "package.json" {
+            self.package_json = Some(serde_json::from_str(contents)?);
+            return Ok(());
+        }
+
+        // Parse FHIR resource
+        if let Ok(resource_json) = serde_json::from_str::<Value>(contents) {
+            if let Some(resource_type) = resource_json.get("resourceType").and_then(|v| v.as_str())
+            {
+                if resource_type == "ImplementationGuide" {
+                    let element = |name| Some(resource_json.get(name)?.as_str()?.to_string());
+                    self.implementation_guide = PackageId {
+                        name: element("packageId"),
+                        version: element("version"),
+                        source: "the ImplementationGuide resource",
+                    };
+                }
+
+                // Only process terminology resources
+                if matches!(resource_type, "CodeSystem" | "ValueSet" | "ConceptMap") {
+                    let resource = FhirResource {
+                        resource_type: resource_type.to_string(),
+                        url: resource_json
+                            .get("url")
+                            .and_then(|v| v.as_str())
+                            .map(String::from),
+                        content: resource_json,
+                    };
+                    self.resources.push(resource);
+                    self.pb
+                        .set_message(format!("Found {} resources...", self.resources.len()));
                 }
             }
         }
 
-        pb.finish_with_message(format!("Extracted {} resources", resources.len()));
+        Ok(())
+    }
+
+    /// The package, named by package.json. A name or version missing there is taken from
+    /// an ImplementationGuide resource, else from `fallback`, with a warning; under
+    /// `strict` it is an error.
+    fn finish(self, fallback: PackageId) -> Result<FhirPackage> {
+        self.pb
+            .finish_with_message(format!("Extracted {} resources", self.resources.len()));
 
-        let package_metadata = package_json.context("package.json not found in archive")?;
-        let name = package_metadata
-            .get("name")
-            .and_then(|v| v.as_str())
-            .context("Package name not found")?
-            .to_string();
-        let version = package_metadata
-            .get("version")
-            .and_then(|v| v.as_str())
-            .context("Package version not found")?
-            .to_string();
+        if self.package_json.is_none() {
+            if self.strict {
+                anyhow::bail!("package.json not found in archive");
+            }
+            warn!("package.json not found in archive");
+        }
+        let ig = &self.implementation_guide;
+        let name = self.metadata(
+            "name",
+            [
+                (ig.name.clone(), ig.source),
+                (fallback.name, fallback.source),
+            ],
+        )?;
+        let version = self.metadata(
+            "version",
+            [
+                (ig.version.clone(), ig.source),
+                (fallback.version, fallback.source),
+            ],
+        )?;
 
         Ok(FhirPackage {
             name,
             version,
-            resources,
+            resources: self.resources,
         })
     }
+
+    /// The package.json `element` (`name` or `version`), else the first known of
+    /// `fallbacks`, each given with where it came from
+    fn metadata(&self, element: &str, fallbacks: [(Option<String>, &str); 2]) -> Result<String> {
+        let declared = self
+            .package_json
+            .as_ref()
+            .and_then(|package| package.get(element)?.as_str());
+        if let Some(value) = declared {
+            return Ok(value.to_string());
+        }
+        if self.strict {
+            anyhow::bail!("Package {element} not found");
+        }
+
+        let (value, source) = fallbacks
+            .into_iter()
+            .find_map(|(value, source)| Some((value?, source)))
+            .with_context(|| format!("Package {element} not found"))?;
+        warn!(
+            "Package {} not found in package.json; using '{}' from {}",
+            element, value, source
+        );
+        Ok(value)
+    }
+}
+
+/// A package name and version known apart from package.json, and where they came from
+#[derive(Default)]
+struct PackageId {
+    name: Option<String>,
+    version: Option<String>,
+    source: &'static str,
+}
+
+impl PackageId {
+    /// The name and version in an archive's file name, `name#version.tgz` or
+    /// `name-version.tgz` with the version starting at the first `-` before a digit
+    fn from_file_name(path: &Path) -> Self {
+        let file_name = path.file_name().and_then(|f| f.to_str()).unwrap_or("");
+        let stem = [".tar.gz", ".tgz", ".tar", ".zip"]
+            .iter()
+            .find_map(|extension| file_name.strip_suffix(extension))
+            .unwrap_or(file_name);
+
+        let (name, version) = stem
+            .split_once('#')
+            .or_else(|| {
+                let (index, _) = stem.char_indices().find(|&(i, c)| {
+                    c == '-' && stem[i + 1..].starts_with(|next: char| next.is_ascii_digit())
+                })?;
+                Some((&stem[..index], &stem[index + 1..]))
+            })
+            .map_or((stem, None), |(name, version)| (name, Some(version)));
+
+        Self {
+            name: Some(name).filter(|n| !n.is_empty()).map(String::from),
+            version: version.filter(|v| !v.is_empty()).map(String::from),
+            source: "the file name",
+        }
+    }
 }
 
 use std::io::Write;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file_name_id(file_name: &str) -> (Option<String>, Option<String>) {
+        let id = PackageId::from_file_name(Path::new("/downloads").join(file_name).as_path());
+        (id.name, id.version)
+    }
+
+    fn id(name: &str, version: &str) -> (Option<String>, Option<String>) {
+        (Some(name.to_string()), Some(version.to_string()))
+    }
+
+    #[test]
+    fn name_and_version_are_split_at_a_hash() {
+        assert_eq!(
+            file_name_id("hl7.fhir.r4.core#4.0.1.tgz"),
+            id("hl7.fhir.r4.core", "4.0.1")
+        );
+        assert_eq!(
+            file_name_id("my-package#1.0.0-ballot.tar.gz"),
+            id("my-package", "1.0.0-ballot")
+        );
+    }
+
+    #[test]
+    fn the_version_starts_at_the_first_dash_before_a_digit() {
+        assert_eq!(
+            file_name_id("hl7.terminology.r4-5.0.0.tgz"),
+            id("hl7.terminology.r4", "5.0.0")
+        );
+        assert_eq!(
+            file_name_id("us-core-6.1.0-snapshot.zip"),
+            id("us-core", "6.1.0-snapshot")
+        );
+        assert_eq!(file_name_id("package.tar"), (Some("package".into()), None));
+    }
+
+    #[test]
+    fn empty_parts_are_unknown() {
+        assert_eq!(file_name_id("#1.0.0.tgz"), (None, Some("1.0.0".into())));
+        assert_eq!(file_name_id("package#.tgz"), (Some("package".into()), None));
+    }
+}